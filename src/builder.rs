@@ -0,0 +1,450 @@
+use crate::command::{Command, Edge, Flow, Timeout};
+use crate::library::Library;
+use crate::node::{self, Node, NodeType};
+use crate::{check_with, enums, Design, Diagnostic, Options};
+use std::ops::Range;
+
+/// Builds a [`Design`] in code instead of formatting `.asmc` text.
+///
+/// Declarations may appear anywhere in the chain; node commands (`then`,
+/// `check`, `yes`, `no`, `transfer`) go to the most recently started node.
+///
+/// ```
+/// use asm_to_verilog_compiler::DesignBuilder;
+///
+/// let (design, options) = DesignBuilder::new("counter")
+///     .input("go", 1)
+///     .reg("r0", 4)
+///     .state("idle")
+///     .then("test")
+///     .decision("test")
+///     .check("go")
+///     .yes("count")
+///     .no("idle")
+///     .state("count")
+///     .transfer("r0", "r0 + 1")
+///     .then("idle")
+///     .build()
+///     .unwrap();
+/// assert_eq!(design.nodes.len(), 3);
+/// assert_eq!(options.module_name, "counter");
+/// ```
+#[derive(Debug, Clone)]
+pub struct DesignBuilder {
+    options: Options,
+    design: Design,
+    /// Machine of the nodes started from now on.
+    machine: String,
+    errors: Vec<Diagnostic>,
+}
+
+/// `[n - 1:0]`, stored msb first the way the parser stores ranges.
+fn bit_range(n: u8) -> Range<u8> {
+    n.saturating_sub(1)..0
+}
+
+impl DesignBuilder {
+    pub fn new(module_name: &str) -> Self {
+        DesignBuilder {
+            options: Options {
+                module_name: module_name.to_string(),
+                ..Options::default()
+            },
+            design: Design {
+                commands: vec![],
+                nodes: vec![],
+            },
+            machine: String::new(),
+            errors: vec![],
+        }
+    }
+
+    fn declare(mut self, cmd: Command) -> Self {
+        self.design.commands.push(cmd);
+        self
+    }
+
+    pub fn input(self, name: &str, width: u8) -> Self {
+        self.declare(Command::Input {
+            pin_name: name.to_string(),
+            bits: bit_range(width),
+            array: 0..0,
+            enum_name: None,
+            asynchronous: false,
+        })
+    }
+    /// `name => input[width - 1:0] async;`, read through a synchronizer.
+    pub fn async_input(self, name: &str, width: u8) -> Self {
+        self.declare(Command::Input {
+            pin_name: name.to_string(),
+            bits: bit_range(width),
+            array: 0..0,
+            enum_name: None,
+            asynchronous: true,
+        })
+    }
+    pub fn output(self, name: &str, width: u8) -> Self {
+        self.declare(Command::Output {
+            pin_name: name.to_string(),
+            bits: bit_range(width),
+            array: 0..0,
+            enum_name: None,
+        })
+    }
+    pub fn inout(self, name: &str, width: u8) -> Self {
+        self.declare(Command::Inout {
+            pin_name: name.to_string(),
+            bits: bit_range(width),
+            array: 0..0,
+            registered: false,
+        })
+    }
+    /// `name => inout[width - 1:0] registered;`, read as sampled at the
+    /// last clock edge.
+    pub fn registered_inout(self, name: &str, width: u8) -> Self {
+        self.declare(Command::Inout {
+            pin_name: name.to_string(),
+            bits: bit_range(width),
+            array: 0..0,
+            registered: true,
+        })
+    }
+    pub fn reg(self, name: &str, width: u8) -> Self {
+        self.declare(Command::Register {
+            reg_name: name.to_string(),
+            bits: bit_range(width),
+            array: 0..0,
+            enum_name: None,
+        })
+    }
+    /// A register file of `depth` entries, `name[0]` to `name[depth - 1]`.
+    pub fn reg_array(self, name: &str, depth: u8, width: u8) -> Self {
+        self.declare(Command::Register {
+            reg_name: name.to_string(),
+            bits: bit_range(width),
+            array: bit_range(depth),
+            enum_name: None,
+        })
+    }
+
+    /// `name => memory[width - 1:0] depth depth ports ports latency latency;`,
+    /// filled from the `$readmemh` file `init` if there is one.
+    pub fn memory(
+        self,
+        name: &str,
+        width: u8,
+        depth: u32,
+        (ports, latency): (u8, u8),
+        init: Option<&str>,
+    ) -> Self {
+        self.declare(Command::Memory {
+            mem_name: name.to_string(),
+            bits: bit_range(width),
+            depth,
+            ports,
+            latency,
+            init: init.map(str::to_string),
+        })
+    }
+
+    /// `name => fifo[width] depth depth;`
+    pub fn fifo(self, name: &str, width: u8, depth: u32) -> Self {
+        self.declare(Command::Fifo {
+            fifo_name: name.to_string(),
+            width,
+            depth,
+        })
+    }
+    /// `name => counter[width];`
+    pub fn counter(self, name: &str, width: u8) -> Self {
+        self.declare(Command::Counter {
+            counter_name: name.to_string(),
+            width,
+        })
+    }
+
+    /// `name => stream flow[width - 1:0];`, the ports `name_valid`,
+    /// `name_ready` and `name_data`.
+    pub fn stream(self, name: &str, flow: Flow, width: u8) -> Self {
+        self.declare(Command::Stream {
+            stream_name: name.to_string(),
+            flow,
+            bits: bit_range(width),
+        })
+    }
+
+    /// `enum name { members }`; the members are 0, 1, ... in order.
+    pub fn enumeration(self, name: &str, members: &[&str]) -> Self {
+        self.declare(Command::Enum {
+            name: name.to_string(),
+            members: members.iter().map(|member| member.to_string()).collect(),
+        })
+    }
+    /// `name => input enum_name;`, sized once the design is built.
+    pub fn typed_input(self, name: &str, enum_name: &str) -> Self {
+        self.declare(Command::Input {
+            pin_name: name.to_string(),
+            bits: 0..0,
+            array: 0..0,
+            enum_name: Some(enum_name.to_string()),
+            asynchronous: false,
+        })
+    }
+    /// `name => output enum_name;`, sized once the design is built.
+    pub fn typed_output(self, name: &str, enum_name: &str) -> Self {
+        self.declare(Command::Output {
+            pin_name: name.to_string(),
+            bits: 0..0,
+            array: 0..0,
+            enum_name: Some(enum_name.to_string()),
+        })
+    }
+    /// `name => reg enum_name;`, sized once the design is built.
+    pub fn typed_reg(self, name: &str, enum_name: &str) -> Self {
+        self.declare(Command::Register {
+            reg_name: name.to_string(),
+            bits: 0..0,
+            array: 0..0,
+            enum_name: Some(enum_name.to_string()),
+        })
+    }
+
+    /// `name => wire[width - 1:0] = value;`
+    pub fn wire(self, name: &str, width: u8, value: &str) -> Self {
+        self.declare(Command::Wire {
+            wire_name: name.to_string(),
+            bits: bit_range(width),
+            value: value.to_string(),
+        })
+    }
+
+    /// `name => instance module;`; `module` is looked up in the library
+    /// given to [`DesignBuilder::library`].
+    pub fn instance(self, name: &str, module: &str) -> Self {
+        self.declare(Command::Instance {
+            instance_name: name.to_string(),
+            module_name: module.to_string(),
+        })
+    }
+    pub fn library(mut self, library: Library) -> Self {
+        self.options.library = library;
+        self
+    }
+
+    /// Puts the nodes started after this call in the machine `name`, like a
+    /// `machine => name;` directive.
+    pub fn machine(mut self, name: &str) -> Self {
+        self.machine = name.to_string();
+        self
+    }
+    /// `priority => main, bus;`
+    pub fn priority(self, machines: &[&str]) -> Self {
+        self.declare(Command::Priority {
+            machines: machines.iter().map(|name| name.to_string()).collect(),
+        })
+    }
+    /// `clock machine => clock edge reset reset;` for the machine of the
+    /// nodes started after this call.
+    pub fn clock(self, clock: &str, edge: Edge, reset: &str, enable: Option<&str>) -> Self {
+        let machine = self.machine.clone();
+        self.declare(Command::Clock {
+            machine,
+            clock_name: clock.to_string(),
+            edge,
+            reset: reset.to_string(),
+            enable: enable.map(str::to_string),
+        })
+    }
+
+    fn node(mut self, name: &str, node_type: NodeType) -> Self {
+        self.design.nodes.push(Node {
+            node_name: name.to_string(),
+            node_type,
+            commands: vec![],
+            machine: self.machine.clone(),
+            origin: None,
+            id: 0,
+        });
+        self
+    }
+
+    pub fn state(self, name: &str) -> Self {
+        self.node(name, NodeType::State)
+    }
+    pub fn decision(self, name: &str) -> Self {
+        self.node(name, NodeType::Decision)
+    }
+    pub fn conditional(self, name: &str) -> Self {
+        self.node(name, NodeType::Conditional)
+    }
+
+    fn command(mut self, cmd: Command) -> Self {
+        match self.design.nodes.last_mut() {
+            Some(node) => node.commands.push(cmd),
+            None => self.errors.push(Diagnostic::error(format!(
+                "`{}` was added before any node was started",
+                cmd
+            ))),
+        }
+        self
+    }
+
+    pub fn then(self, next_node: &str) -> Self {
+        self.command(Command::Then {
+            next_node: next_node.to_string(),
+        })
+    }
+    pub fn check(self, check: &str) -> Self {
+        self.command(Command::Check {
+            check: check.to_string(),
+        })
+    }
+    pub fn yes(self, next_node: &str) -> Self {
+        self.command(Command::Yes {
+            next_node: next_node.to_string(),
+        })
+    }
+    pub fn no(self, next_node: &str) -> Self {
+        self.command(Command::No {
+            next_node: next_node.to_string(),
+        })
+    }
+    /// `target => value;`, e.g. `.transfer("r0[3]", "r1 + 1")`.
+    pub fn transfer(self, target: &str, value: &str) -> Self {
+        self.command(Command::RegisterTransfer {
+            reg_name: target.to_string(),
+            reg_value: value.to_string(),
+        })
+    }
+
+    /// `drive => pin;`
+    pub fn drive(self, pin: &str) -> Self {
+        self.command(Command::Drive {
+            pin_name: pin.to_string(),
+        })
+    }
+    /// `release => pin;`
+    pub fn release(self, pin: &str) -> Self {
+        self.command(Command::Release {
+            pin_name: pin.to_string(),
+        })
+    }
+
+    /// `fifo.push(value);`
+    pub fn push(self, fifo: &str, value: &str) -> Self {
+        self.act(fifo, "push", Some(value))
+    }
+    /// `fifo.pop();`
+    pub fn pop(self, fifo: &str) -> Self {
+        self.act(fifo, "pop", None)
+    }
+    /// `counter.clear();`
+    pub fn clear(self, counter: &str) -> Self {
+        self.act(counter, "clear", None)
+    }
+    fn act(self, name: &str, action: &str, value: Option<&str>) -> Self {
+        self.command(Command::Action {
+            name: name.to_string(),
+            action: action.to_string(),
+            value: value.map(str::to_string),
+        })
+    }
+
+    /// `call => node;`; the node's `then` names the state to return to.
+    pub fn call(self, node: &str) -> Self {
+        self.command(Command::Call {
+            node_name: node.to_string(),
+        })
+    }
+    /// `return;`
+    pub fn ret(self) -> Self {
+        self.command(Command::Return)
+    }
+    /// `wait => cycles;`
+    pub fn wait(self, cycles: u32) -> Self {
+        self.command(Command::Wait { cycles })
+    }
+    /// `wait_until => check;`, or with `Some((50, "error"))`
+    /// `wait_until => check timeout 50 => error;`.
+    pub fn wait_until(self, check: &str, timeout: Option<(u32, &str)>) -> Self {
+        self.command(Command::WaitUntil {
+            check: check.to_string(),
+            timeout: timeout.map(|(cycles, next_node)| Timeout {
+                cycles,
+                next_node: next_node.to_string(),
+            }),
+        })
+    }
+    /// `call_depth => depth;`
+    pub fn call_depth(self, depth: u8) -> Self {
+        self.declare(Command::CallDepth { depth })
+    }
+
+    /// Finishes the design and runs [`check_with`] on it. Fails with every
+    /// diagnostic if any of them is an error; warnings alone do not fail.
+    pub fn build(mut self) -> Result<(Design, Options), Vec<Diagnostic>> {
+        node::number_states(&mut self.design.nodes);
+        enums::size(&mut self.design);
+        let mut diagnostics = self.errors;
+        diagnostics.extend(check_with(&self.design, &self.options));
+        if diagnostics.iter().any(|diagnostic| diagnostic.is_error()) {
+            return Err(diagnostics);
+        }
+        Ok((self.design, self.options))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{emit_verilog, parse};
+
+    #[test]
+    fn matches_parser_test() {
+        let (built, options) = DesignBuilder::new("Top")
+            .input("go", 1)
+            .reg("r0", 4)
+            .state("idle")
+            .then("check")
+            .decision("check")
+            .check("go")
+            .yes("busy")
+            .no("idle")
+            .state("busy")
+            .transfer("r0", "r0 + 1")
+            .then("idle")
+            .build()
+            .unwrap();
+        let parsed = parse(
+            "
+go => input;
+r0 => reg[3:0];
+.idle : state { then => check; }
+.check : decision { check => go; yes => busy; no => idle; }
+.busy : state { r0 => r0 + 1; then => idle; }
+",
+        )
+        .unwrap();
+        assert_eq!(built, parsed);
+        assert_eq!(
+            emit_verilog(&built, &options).unwrap(),
+            emit_verilog(&parsed, &options).unwrap()
+        );
+    }
+
+    #[test]
+    fn build_errors_test() {
+        let errors = DesignBuilder::new("Top")
+            .then("idle")
+            .state("idle")
+            .transfer("r9", "1")
+            .then("idle")
+            .build()
+            .unwrap_err();
+        assert_eq!(errors.len(), 2);
+        assert_eq!(
+            errors[1].to_string(),
+            "error: `r9` is not declared (in node `idle`)"
+        );
+    }
+}
@@ -0,0 +1,323 @@
+use crate::command::Command;
+use crate::naming::Names;
+use crate::node::{Node, NodeType};
+use crate::Design;
+use std::collections::HashMap;
+
+/// Entries in each machine's return stack, set with `call_depth => n;`.
+pub fn call_depth(design: &Design) -> u8 {
+    design
+        .commands
+        .iter()
+        .find_map(|cmd| match cmd {
+            Command::CallDepth { depth } => Some(*depth),
+            _ => None,
+        })
+        .unwrap_or(1)
+}
+
+fn uses_calls(nodes: &[Node]) -> bool {
+    nodes
+        .iter()
+        .flat_map(|node| node.commands.iter())
+        .any(|cmd| matches!(cmd, Command::Call { .. } | Command::Return))
+}
+
+fn return_register(machine: &str, depth: u8) -> String {
+    let name = if depth > 1 {
+        "return_stack"
+    } else {
+        "return_state"
+    };
+    match machine {
+        "" => name.to_string(),
+        machine => format!("{}_{}", machine, name),
+    }
+}
+
+/// Return state `level` calls below the innermost one in `register`.
+fn return_slot(register: &str, depth: u8, level: u8) -> String {
+    match depth {
+        0 | 1 => register.to_string(),
+        _ => format!("{}[{}]", register, level),
+    }
+}
+
+/// The registers [`lower`] declares, named by `names`: for every machine
+/// that calls, a state encoding per stack entry.
+fn return_registers(design: &Design, names: &mut Names) -> Vec<(String, Command)> {
+    let depth = call_depth(design);
+    let mut out = vec![];
+    for machine in design.machines() {
+        let nodes: Vec<Node> = design
+            .nodes
+            .iter()
+            .filter(|node| node.machine == machine)
+            .cloned()
+            .collect();
+        if !uses_calls(&nodes) {
+            continue;
+        }
+        let state_count = nodes
+            .iter()
+            .filter(|node| node.node_type == NodeType::State)
+            .count() as u32;
+        out.push((
+            machine.to_string(),
+            Command::Register {
+                reg_name: names.fresh(&return_register(machine, depth)),
+                bits: state_count.max(1).ilog2() as u8..0,
+                array: if depth > 1 { depth - 1..0 } else { 0..0 },
+                enum_name: None,
+            },
+        ));
+    }
+    out
+}
+
+/// Rewrites `call` and `return` into what every backend already handles.
+/// A call shifts the state after it onto the machine's return stack and
+/// moves to the callee; a return shifts the stack back and picks the
+/// return state with a chain of decisions over the machine's return points.
+/// The stack has no pointer, so a reset mid-call leaves nothing stale.
+pub fn lower(design: &Design, names: &mut Names) -> Design {
+    let mut lowered = design.clone();
+    if !uses_calls(&design.nodes) {
+        return lowered;
+    }
+    let depth = call_depth(design);
+    let mut registers = HashMap::new();
+    for (machine, cmd) in return_registers(design, names) {
+        if let Command::Register { reg_name, .. } = &cmd {
+            registers.insert(machine, reg_name.clone());
+        }
+        lowered.commands.push(cmd);
+    }
+
+    let ids: HashMap<&str, u32> = design
+        .nodes
+        .iter()
+        .filter(|node| node.node_type == NodeType::State)
+        .map(|node| (node.node_name.as_str(), node.id))
+        .collect();
+    let mut return_points: HashMap<&str, Vec<&str>> = HashMap::new();
+    for node in design.nodes.iter() {
+        if node
+            .commands
+            .iter()
+            .any(|cmd| matches!(cmd, Command::Call { .. }))
+        {
+            let points = return_points.entry(&node.machine).or_default();
+            for cmd in node.commands.iter() {
+                if let Command::Then { next_node } = cmd {
+                    if !points.contains(&next_node.as_str()) {
+                        points.push(next_node);
+                    }
+                }
+            }
+        }
+    }
+
+    let mut chains = vec![];
+    for node in lowered.nodes.iter_mut() {
+        let machine = node.machine.clone();
+        let register = registers
+            .get(&machine)
+            .map(String::as_str)
+            .unwrap_or_default();
+        let callee = node.commands.iter().find_map(|cmd| match cmd {
+            Command::Call { node_name } => Some(node_name.clone()),
+            _ => None,
+        });
+        let returns = node.commands.contains(&Command::Return);
+        if callee.is_none() && !returns {
+            continue;
+        }
+        let mut commands = vec![];
+        for cmd in node.commands.drain(..) {
+            match cmd {
+                Command::Call { .. } => {}
+                Command::Then { next_node } if callee.is_some() => {
+                    for level in (1..depth).rev() {
+                        commands.push(Command::RegisterTransfer {
+                            reg_name: return_slot(register, depth, level),
+                            reg_value: return_slot(register, depth, level - 1),
+                        });
+                    }
+                    commands.push(Command::RegisterTransfer {
+                        reg_name: return_slot(register, depth, 0),
+                        reg_value: ids
+                            .get(next_node.as_str())
+                            .copied()
+                            .unwrap_or(0)
+                            .to_string(),
+                    });
+                    commands.push(Command::Then {
+                        next_node: callee.clone().unwrap(),
+                    });
+                }
+                Command::Return => {
+                    for level in 1..depth {
+                        commands.push(Command::RegisterTransfer {
+                            reg_name: return_slot(register, depth, level - 1),
+                            reg_value: return_slot(register, depth, level),
+                        });
+                    }
+                    let points = return_points
+                        .get(machine.as_str())
+                        .map(Vec::as_slice)
+                        .unwrap_or_default();
+                    let decision = |idx: usize| format!("{}_return_{}", node.node_name, idx);
+                    let target = |idx: usize| {
+                        if idx + 1 == points.len() {
+                            points[idx].to_string()
+                        } else {
+                            decision(idx)
+                        }
+                    };
+                    if !points.is_empty() {
+                        commands.push(Command::Then {
+                            next_node: target(0),
+                        });
+                    }
+                    for (idx, point) in points
+                        .iter()
+                        .enumerate()
+                        .take(points.len().saturating_sub(1))
+                    {
+                        chains.push(Node {
+                            node_name: decision(idx),
+                            node_type: NodeType::Decision,
+                            commands: vec![
+                                Command::Check {
+                                    check: format!(
+                                        "{} == {}",
+                                        return_slot(register, depth, 0),
+                                        ids.get(point).copied().unwrap_or(0)
+                                    ),
+                                },
+                                Command::Yes {
+                                    next_node: point.to_string(),
+                                },
+                                Command::No {
+                                    next_node: target(idx + 1),
+                                },
+                            ],
+                            machine: machine.clone(),
+                            origin: None,
+                            id: 0,
+                        });
+                    }
+                }
+                cmd => commands.push(cmd),
+            }
+        }
+        node.commands = commands;
+    }
+    lowered.nodes.extend(chains);
+    lowered
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::DEFAULT;
+    use crate::{parse, Naming};
+
+    fn names(design: &Design, naming: &Naming) -> Names {
+        Names::new(naming, &design.commands, &DEFAULT, &[], false)
+    }
+
+    #[test]
+    fn lower_test() {
+        let design = parse(
+            "
+call_depth => 2;
+.idle : state {
+    call => send;
+    then => again;
+}
+.again : state {
+    call => send;
+    then => idle;
+}
+.send : state {
+    return;
+}
+",
+        )
+        .unwrap();
+        let lowered = lower(&design, &mut names(&design, &Naming::default()));
+        assert_eq!(
+            lowered.to_string(),
+            "call_depth => 2;
+return_stack => reg[1:0][1:0];
+
+.idle : state {
+    return_stack[1] => return_stack[0];
+    return_stack[0] => 1;
+    then => send;
+}
+
+.again : state {
+    return_stack[1] => return_stack[0];
+    return_stack[0] => 0;
+    then => send;
+}
+
+.send : state {
+    return_stack[0] => return_stack[1];
+    then => send_return_0;
+}
+
+.send_return_0 : decision {
+    check => return_stack[0] == 1;
+    yes => again;
+    no => idle;
+}
+"
+        );
+    }
+
+    #[test]
+    fn register_name_test() {
+        let design = parse(
+            "
+return_state => reg;
+.idle : state {
+    call => send;
+    then => idle;
+}
+.send : state {
+    return;
+}
+",
+        )
+        .unwrap();
+        let lowered = lower(&design, &mut names(&design, &Naming::default()));
+        assert_eq!(
+            lowered.to_string(),
+            "return_state => reg;
+return_state_2 => reg[1:0];
+
+.idle : state {
+    return_state_2 => 0;
+    then => send;
+}
+
+.send : state {
+    then => idle;
+}
+"
+        );
+        let naming = Naming {
+            prefix: String::new(),
+            suffix: "_r".to_string(),
+        };
+        let lowered = lower(&design, &mut names(&design, &naming));
+        assert_eq!(
+            lowered.commands[1].to_string(),
+            "return_state_r => reg[1:0]"
+        );
+    }
+}
@@ -0,0 +1,2358 @@
+use crate::command::{split_target, Command, Timeout};
+use crate::expr::Expr;
+use crate::library::{instances, ports, Direction, Library};
+use crate::naming::{Names, VERILOG_KEYWORDS};
+use crate::node::{Node, NodeType};
+use crate::primitive::{Kind, Primitive};
+use crate::transition::collect_transitions;
+use crate::{call, clock, enums, memory, stream};
+use crate::{Design, Diagnostic, Options};
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+
+#[derive(PartialEq)]
+enum Role {
+    Signal,
+    /// `instance.port`, a port of the named module.
+    Port(String),
+    /// The instance itself; `false` if its module is not in the library.
+    Instance(String, bool),
+    /// A member of the named enum.
+    Member(String),
+    /// A `wire`, computed from other names.
+    Wire,
+    /// An inout pin, which `drive` and `release` apply to.
+    Inout,
+    /// A `memory`, reached one word at a time.
+    Memory,
+    /// A `fifo` or `counter`, changed through its actions.
+    Primitive(Kind),
+    /// `q.empty`, a status of the `fifo` or `counter` before the dot.
+    Status(Kind),
+}
+
+/// What a declared name may be used for.
+struct Declared {
+    writable: bool,
+    width: u32,
+    is_array: bool,
+    role: Role,
+    /// The enum whose members the name holds or is one of.
+    enum_name: Option<String>,
+}
+
+impl Declared {
+    fn new(writable: bool, bits: &Range<u8>, array: &Range<u8>) -> Self {
+        Declared {
+            writable,
+            width: bits.start.abs_diff(bits.end) as u32 + 1,
+            is_array: array.start != array.end || array.start != 0,
+            role: Role::Signal,
+            enum_name: None,
+        }
+    }
+    /// Records the enum of a typed declaration, if there is one by that name.
+    fn typed(
+        mut self,
+        enum_name: &Option<String>,
+        enums: &[(&str, &[String])],
+        out: &mut Vec<Diagnostic>,
+    ) -> Self {
+        if let Some(enum_name) = enum_name {
+            if enums.iter().any(|(name, _)| name == enum_name) {
+                self.enum_name = Some(enum_name.clone());
+            } else {
+                out.push(Diagnostic::error(format!("`{}` is not an enum", enum_name)));
+            }
+        }
+        self
+    }
+    fn instance(module: &str, resolved: bool) -> Self {
+        Declared {
+            writable: false,
+            width: 0,
+            is_array: false,
+            role: Role::Instance(module.to_string(), resolved),
+            enum_name: None,
+        }
+    }
+}
+
+/// Whether `module`, or anything it instantiates, is `design` itself.
+fn contains(library: &Library, module: &str, design: &Design, seen: &mut HashSet<String>) -> bool {
+    let Some(sub) = library.get(module) else {
+        return false;
+    };
+    sub == design
+        || instances(sub).any(|(_, inner)| {
+            seen.insert(inner.to_string()) && contains(library, inner, design, seen)
+        })
+}
+
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn declared_names(
+    design: &Design,
+    library: &Library,
+    out: &mut Vec<Diagnostic>,
+) -> HashMap<String, Declared> {
+    let mut names = HashMap::new();
+    let mut module_named = false;
+    let enums = enums::enums(design);
+    let mut enum_names = HashSet::new();
+    for cmd in design.commands.iter() {
+        let (name, declared) = match cmd {
+            Command::Instance {
+                instance_name,
+                module_name,
+            } => {
+                let resolved = match library.get(module_name) {
+                    None => {
+                        out.push(Diagnostic::error(format!(
+                            "module `{}` of instance `{}` is not known, compile its chart along with this one",
+                            module_name, instance_name
+                        )));
+                        false
+                    }
+                    Some(_) if contains(library, module_name, design, &mut HashSet::new()) => {
+                        out.push(Diagnostic::error(format!(
+                            "instance `{}` of `{}` would make the module contain itself",
+                            instance_name, module_name
+                        )));
+                        false
+                    }
+                    Some(sub) => {
+                        for port in ports(sub) {
+                            let name = format!("{}.{}", instance_name, port.name);
+                            if port.direction == Direction::Inout {
+                                out.push(Diagnostic::error(format!(
+                                    "`{}` is an inout, which instances cannot connect",
+                                    name
+                                )));
+                            }
+                            let mut declared = Declared::new(
+                                port.direction == Direction::Input,
+                                &port.bits,
+                                &port.array,
+                            );
+                            declared.role = Role::Port(module_name.clone());
+                            names.insert(name, declared);
+                        }
+                        true
+                    }
+                };
+                (instance_name, Declared::instance(module_name, resolved))
+            }
+            Command::Module { module_name } => {
+                if module_named {
+                    out.push(Diagnostic::error(
+                        "the module is named more than once".to_string(),
+                    ));
+                }
+                if !is_identifier(module_name) {
+                    out.push(Diagnostic::error(format!(
+                        "`{}` is not a valid module name",
+                        module_name
+                    )));
+                }
+                module_named = true;
+                continue;
+            }
+            Command::Priority { .. } | Command::CallDepth { .. } | Command::Clock { .. } => {
+                continue
+            }
+            Command::Include { .. } | Command::Import { .. } => {
+                out.push(Diagnostic::error(format!(
+                    "`{}` has not been resolved, read the chart with `load`",
+                    cmd
+                )));
+                continue;
+            }
+            Command::Template { name, .. } => {
+                out.push(Diagnostic::error(format!(
+                    "template `{}` has not been expanded, read the chart with `load`",
+                    name
+                )));
+                continue;
+            }
+            Command::Use { .. } => {
+                out.push(Diagnostic::error(format!(
+                    "`{}` has not been expanded, read the chart with `load`",
+                    cmd
+                )));
+                continue;
+            }
+            Command::Enum { name, members } => {
+                if !is_identifier(name) {
+                    out.push(Diagnostic::error(format!(
+                        "`{}` is not a valid enum name",
+                        name
+                    )));
+                }
+                if !enum_names.insert(name) {
+                    out.push(Diagnostic::error(format!(
+                        "enum `{}` is declared more than once",
+                        name
+                    )));
+                }
+                if members.is_empty() {
+                    out.push(Diagnostic::error(format!("enum `{}` has no members", name)));
+                }
+                for member in members.iter() {
+                    if !is_identifier(member) {
+                        out.push(Diagnostic::error(format!(
+                            "`{}` in enum `{}` is not a valid name",
+                            member, name
+                        )));
+                    }
+                    let declared = Declared {
+                        writable: false,
+                        width: enums::width(members.len()),
+                        is_array: false,
+                        role: Role::Member(name.clone()),
+                        enum_name: Some(name.clone()),
+                    };
+                    if names.insert(member.clone(), declared).is_some() {
+                        out.push(Diagnostic::error(format!(
+                            "`{}` is declared more than once",
+                            member
+                        )));
+                    }
+                }
+                continue;
+            }
+            Command::Input {
+                pin_name,
+                bits,
+                array,
+                enum_name,
+                ..
+            } => (
+                pin_name,
+                Declared::new(false, bits, array).typed(enum_name, &enums, out),
+            ),
+            Command::Output {
+                pin_name,
+                bits,
+                array,
+                enum_name,
+            } => (
+                pin_name,
+                Declared::new(true, bits, array).typed(enum_name, &enums, out),
+            ),
+            Command::Inout {
+                pin_name,
+                bits,
+                array,
+                ..
+            } => {
+                let mut declared = Declared::new(true, bits, array);
+                declared.role = Role::Inout;
+                (pin_name, declared)
+            }
+            Command::Stream {
+                stream_name,
+                flow,
+                bits,
+            } => {
+                for port in stream::ports(stream_name, *flow, bits) {
+                    let (name, declared) = match &port {
+                        Command::Input { pin_name, bits, .. } => {
+                            (pin_name, Declared::new(false, bits, &(0..0)))
+                        }
+                        Command::Output { pin_name, bits, .. } => {
+                            (pin_name, Declared::new(true, bits, &(0..0)))
+                        }
+                        _ => unreachable!("streams are made of inputs and outputs"),
+                    };
+                    if names.insert(name.clone(), declared).is_some() {
+                        out.push(Diagnostic::error(format!(
+                            "`{}` clashes with the port generated for stream `{}`",
+                            name, stream_name
+                        )));
+                    }
+                }
+                continue;
+            }
+            Command::Wire {
+                wire_name, bits, ..
+            } => {
+                let mut declared = Declared::new(false, bits, &(0..0));
+                declared.role = Role::Wire;
+                (wire_name, declared)
+            }
+            Command::Register {
+                reg_name,
+                bits,
+                array,
+                enum_name,
+            } => (
+                reg_name,
+                Declared::new(true, bits, array).typed(enum_name, &enums, out),
+            ),
+            Command::Memory {
+                mem_name,
+                bits,
+                depth,
+                ports,
+                ..
+            } => {
+                if *depth == 0 {
+                    out.push(Diagnostic::error(format!(
+                        "memory `{}` needs a depth of at least 1",
+                        mem_name
+                    )));
+                }
+                if *ports == 0 {
+                    out.push(Diagnostic::error(format!(
+                        "memory `{}` needs at least one port",
+                        mem_name
+                    )));
+                }
+                let mut declared = Declared::new(true, bits, &(0..0));
+                declared.is_array = true;
+                declared.role = Role::Memory;
+                (mem_name, declared)
+            }
+            Command::Fifo {
+                fifo_name: name, ..
+            }
+            | Command::Counter {
+                counter_name: name, ..
+            } => {
+                let primitive = Primitive::of(cmd).unwrap();
+                let kind = primitive.kind.name();
+                if primitive.width == 0 {
+                    out.push(Diagnostic::error(format!(
+                        "{} `{}` needs a width of at least 1",
+                        kind, name
+                    )));
+                }
+                if primitive.kind == Kind::Fifo && primitive.depth == 0 {
+                    out.push(Diagnostic::error(format!(
+                        "{} `{}` needs a depth of at least 1",
+                        kind, name
+                    )));
+                }
+                for (status, width) in primitive.statuses() {
+                    let declared = Declared {
+                        writable: false,
+                        width,
+                        is_array: false,
+                        role: Role::Status(primitive.kind),
+                        enum_name: None,
+                    };
+                    names.insert(format!("{}.{}", name, status), declared);
+                }
+                let declared = Declared {
+                    writable: false,
+                    width: primitive.width as u32,
+                    is_array: false,
+                    role: Role::Primitive(primitive.kind),
+                    enum_name: None,
+                };
+                (name, declared)
+            }
+            Command::RegisterTransfer {
+                reg_name,
+                reg_value,
+            } => {
+                out.push(Diagnostic::error(format!(
+                    "`{} => {}` is not a declaration",
+                    reg_name, reg_value
+                )));
+                continue;
+            }
+            _ => {
+                out.push(Diagnostic::error(format!(
+                    "`{}` is only allowed inside a node",
+                    cmd
+                )));
+                continue;
+            }
+        };
+        if names.insert(name.clone(), declared).is_some() {
+            out.push(Diagnostic::error(format!(
+                "`{}` is declared more than once",
+                name
+            )));
+        }
+    }
+    names
+}
+
+/// Why `ident` cannot be read or written, if it cannot. Ports of instances
+/// whose module is unknown are skipped, that is reported once already.
+fn misuse(ident: &str, names: &HashMap<String, Declared>) -> Option<String> {
+    let instance = ident
+        .split_once('.')
+        .and_then(|(instance, port)| Some((names.get(instance)?, port)));
+    match (names.get(ident), instance) {
+        (
+            Some(Declared {
+                role: Role::Instance(..),
+                ..
+            }),
+            _,
+        ) => Some(format!("`{}` is an instance, use one of its ports", ident)),
+        (
+            Some(Declared {
+                role: Role::Primitive(kind),
+                ..
+            }),
+            _,
+        ) => Some(format!(
+            "`{}` is a {}, read {}",
+            ident,
+            kind.name(),
+            statuses(ident, *kind)
+        )),
+        (Some(_), _) => None,
+        (
+            None,
+            Some((
+                Declared {
+                    role: Role::Instance(module, resolved),
+                    ..
+                },
+                port,
+            )),
+        ) => resolved.then(|| format!("`{}` has no port `{}`", module, port)),
+        (
+            None,
+            Some((
+                Declared {
+                    role: Role::Primitive(kind),
+                    ..
+                },
+                status,
+            )),
+        ) => Some(format!(
+            "{} `{}` has no `{}`, read {}",
+            kind.name(),
+            &ident[..ident.len() - status.len() - 1],
+            status,
+            statuses(&ident[..ident.len() - status.len() - 1], *kind)
+        )),
+        (None, _) => Some(format!("`{}` is not declared", ident)),
+    }
+}
+
+/// `` `q.data`, `q.empty` or `q.full` ``, the statuses nodes can read.
+fn statuses(name: &str, kind: Kind) -> String {
+    let listed: Vec<String> = kind
+        .statuses()
+        .iter()
+        .map(|status| format!("`{}.{}`", name, status))
+        .collect();
+    match listed.split_last() {
+        Some((last, rest)) if !rest.is_empty() => format!("{} or {}", rest.join(", "), last),
+        _ => listed.concat(),
+    }
+}
+
+/// Where a diagnostic was found, such as the node it is in.
+type At<'a> = &'a dyn Fn(Diagnostic) -> Diagnostic;
+
+fn check_expr(
+    text: &str,
+    names: &HashMap<String, Declared>,
+    at: At,
+    out: &mut Vec<Diagnostic>,
+) -> Option<Expr> {
+    match text.parse::<Expr>() {
+        Ok(expr) => {
+            for ident in expr.idents() {
+                if let Some(message) = misuse(ident, names) {
+                    out.push(at(Diagnostic::error(message)));
+                }
+            }
+            check_comparisons(&expr, names, at, out);
+            Some(expr)
+        }
+        Err(err) => {
+            out.push(at(Diagnostic::error(format!("{} in `{}`", err, text))));
+            None
+        }
+    }
+}
+
+/// The enum whose members `expr` holds, if it is a typed name, a member or
+/// a choice between two of the same enum.
+fn enum_of<'n>(expr: &Expr, names: &'n HashMap<String, Declared>) -> Option<&'n str> {
+    match expr {
+        Expr::Ident(name) => names.get(name)?.enum_name.as_deref(),
+        Expr::Ternary(_, yes, no) => {
+            let yes = enum_of(yes, names)?;
+            (enum_of(no, names)? == yes).then_some(yes)
+        }
+        _ => None,
+    }
+}
+
+/// Reports comparisons between members of two different enums.
+fn check_comparisons(
+    expr: &Expr,
+    names: &HashMap<String, Declared>,
+    at: At,
+    out: &mut Vec<Diagnostic>,
+) {
+    match expr {
+        Expr::Binary(op, lhs, rhs) => {
+            if let (true, Some(left), Some(right)) =
+                (op.is_boolean(), enum_of(lhs, names), enum_of(rhs, names))
+            {
+                if left != right {
+                    out.push(at(Diagnostic::error(format!(
+                        "`{}` and `{}` hold different enums, `{}` and `{}`",
+                        lhs, rhs, left, right
+                    ))));
+                }
+            }
+            check_comparisons(lhs, names, at, out);
+            check_comparisons(rhs, names, at, out);
+        }
+        Expr::Unary(_, inner) | Expr::Slice(inner, _, _) => {
+            check_comparisons(inner, names, at, out)
+        }
+        Expr::Index(base, index) => {
+            check_comparisons(base, names, at, out);
+            check_comparisons(index, names, at, out);
+        }
+        Expr::Ternary(cond, yes, no) => {
+            for inner in [cond, yes, no] {
+                check_comparisons(inner, names, at, out);
+            }
+        }
+        Expr::Concat(parts) => {
+            for part in parts.iter() {
+                check_comparisons(part, names, at, out);
+            }
+        }
+        Expr::Ident(_) | Expr::Number { .. } => {}
+    }
+}
+
+/// Reports transfers of another enum's members, or of numbers without a
+/// member, to a typed name.
+fn check_enum_transfer(
+    target: &Expr,
+    value: &Expr,
+    names: &HashMap<String, Declared>,
+    node: &Node,
+    out: &mut Vec<Diagnostic>,
+) {
+    let Expr::Ident(name) = target else {
+        return;
+    };
+    let Some(expected) = names
+        .get(name)
+        .and_then(|declared| declared.enum_name.as_ref())
+    else {
+        return;
+    };
+    match (enum_of(value, names), value) {
+        (Some(actual), _) if actual != expected => out.push(
+            Diagnostic::error(format!(
+                "`{}` holds `{}`, not `{}` like `{}`",
+                name, expected, actual, value
+            ))
+            .in_node(&node.node_name),
+        ),
+        (None, Expr::Number { value, .. }) => {
+            let count = names
+                .values()
+                .filter(|declared| declared.role == Role::Member(expected.clone()))
+                .count() as u64;
+            if *value >= count {
+                out.push(
+                    Diagnostic::warning(format!(
+                        "`{}` holds `{}`, which has no member for {}",
+                        name, expected, value
+                    ))
+                    .in_node(&node.node_name),
+                );
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Warns when a transfer to or from an instance port drops bits, since a
+/// mismatch there usually means the two charts disagree on the port.
+fn check_port_width(
+    target: &Expr,
+    value: &Expr,
+    names: &HashMap<String, Declared>,
+    node: &Node,
+    out: &mut Vec<Diagnostic>,
+) {
+    let involves_port = target
+        .idents()
+        .iter()
+        .chain(value.idents().iter())
+        .any(|ident| {
+            names
+                .get(*ident)
+                .is_some_and(|declared| matches!(declared.role, Role::Port(_)))
+        });
+    if !involves_port {
+        return;
+    }
+    let lookup = |name: &str| {
+        names
+            .get(name)
+            .map(|declared| (declared.width, declared.is_array))
+    };
+    if let (Ok(target_width), Ok(value_width)) = (target.width(&lookup), value.value_width(&lookup))
+    {
+        if value_width > target_width {
+            out.push(
+                Diagnostic::warning(format!(
+                    "`{}` is {} bits wide but `{}` only holds {}",
+                    value, value_width, target, target_width
+                ))
+                .in_node(&node.node_name),
+            );
+        }
+    }
+}
+
+/// Warns about inputs of instances that no node writes, which the
+/// instance would read as undefined.
+fn check_instance_inputs(design: &Design, library: &Library, out: &mut Vec<Diagnostic>) {
+    let written: HashSet<&str> = design
+        .nodes
+        .iter()
+        .flat_map(|node| node.commands.iter())
+        .filter_map(|cmd| match cmd {
+            Command::RegisterTransfer { reg_name, .. } => Some(split_target(reg_name).0),
+            _ => None,
+        })
+        .collect();
+    for (instance, module) in instances(design) {
+        let Some(sub) = library.get(module) else {
+            continue;
+        };
+        for port in ports(sub) {
+            let name = format!("{}.{}", instance, port.name);
+            if port.direction == Direction::Input && !written.contains(name.as_str()) {
+                out.push(Diagnostic::warning(format!(
+                    "`{}` is an input of `{}` that no node writes",
+                    name, module
+                )));
+            }
+        }
+    }
+}
+
+/// Checks the expressions of the wires and that no wire depends on itself,
+/// which would be a combinational loop.
+fn check_wires(design: &Design, names: &HashMap<String, Declared>, out: &mut Vec<Diagnostic>) {
+    let mut reads: HashMap<&str, Vec<String>> = HashMap::new();
+    for cmd in design.commands.iter() {
+        let Command::Wire {
+            wire_name,
+            bits,
+            value,
+        } = cmd
+        else {
+            continue;
+        };
+        let at = |mut diagnostic: Diagnostic| {
+            diagnostic.message = format!("in wire `{}`: {}", wire_name, diagnostic.message);
+            diagnostic
+        };
+        let Some(expr) = check_expr(value, names, &at, out) else {
+            continue;
+        };
+        let lookup = |name: &str| {
+            names
+                .get(name)
+                .map(|declared| (declared.width, declared.is_array))
+        };
+        let width = bits.start.abs_diff(bits.end) as u32 + 1;
+        if let Ok(value_width) = expr.value_width(&lookup) {
+            if value_width > width {
+                out.push(at(Diagnostic::warning(format!(
+                    "`{}` is {} bits wide but the wire only holds {}",
+                    expr, value_width, width
+                ))));
+            }
+        }
+        reads.insert(
+            wire_name,
+            expr.idents()
+                .into_iter()
+                .filter(|ident| {
+                    names
+                        .get(*ident)
+                        .is_some_and(|declared| declared.role == Role::Wire)
+                })
+                .map(str::to_string)
+                .collect(),
+        );
+    }
+
+    // Depth-first from every wire; a wire met again on the current path
+    // closes a loop, reported once from the wire declared first.
+    let mut done: HashSet<&str> = HashSet::new();
+    for cmd in design.commands.iter() {
+        let Command::Wire { wire_name, .. } = cmd else {
+            continue;
+        };
+        let mut path = vec![wire_name.as_str()];
+        let mut stack = vec![0];
+        while let Some(next) = stack.last_mut() {
+            let current = path[path.len() - 1];
+            let Some(read) = reads.get(current).and_then(|read| read.get(*next)) else {
+                done.insert(current);
+                path.pop();
+                stack.pop();
+                continue;
+            };
+            *next += 1;
+            if let Some(start) = path.iter().position(|wire| wire == read) {
+                if start == 0 && !done.contains(read.as_str()) {
+                    let mut cycle: Vec<String> =
+                        path.iter().map(|wire| format!("`{}`", wire)).collect();
+                    cycle.push(format!("`{}`", read));
+                    out.push(Diagnostic::error(format!(
+                        "combinational loop: {}",
+                        cycle.join(" reads ")
+                    )));
+                }
+            } else if !done.contains(read.as_str()) {
+                path.push(read);
+                stack.push(0);
+            }
+        }
+    }
+}
+
+/// Memories named on their own instead of through one of their words.
+fn whole_memories<'e>(expr: &'e Expr, memories: &HashMap<&str, u8>, out: &mut Vec<&'e str>) {
+    match expr {
+        Expr::Ident(name) if memories.contains_key(name.as_str()) => out.push(name),
+        Expr::Index(_, address) if memory::word(expr, memories).is_some() => {
+            whole_memories(address, memories, out)
+        }
+        Expr::Ident(_) | Expr::Number { .. } => {}
+        Expr::Unary(_, inner) | Expr::Slice(inner, _, _) => whole_memories(inner, memories, out),
+        Expr::Binary(_, lhs, rhs) | Expr::Index(lhs, rhs) => {
+            whole_memories(lhs, memories, out);
+            whole_memories(rhs, memories, out);
+        }
+        Expr::Ternary(cond, yes, no) => {
+            whole_memories(cond, memories, out);
+            whole_memories(yes, memories, out);
+            whole_memories(no, memories, out);
+        }
+        Expr::Concat(parts) => parts
+            .iter()
+            .for_each(|part| whole_memories(part, memories, out)),
+    }
+}
+
+/// Memories are reached a word at a time through their ports, so a state
+/// may access as many words of a memory as it has ports, a memory with a
+/// read latency can only be read into a register, and wires, which belong
+/// to no state, cannot read memories at all.
+fn check_memories(design: &Design, names: &HashMap<String, Declared>, out: &mut Vec<Diagnostic>) {
+    let memories = memory::latencies(&design.commands);
+    if memories.is_empty() {
+        return;
+    }
+    for cmd in design.commands.iter() {
+        if let Command::Wire {
+            wire_name, value, ..
+        } = cmd
+        {
+            let Ok(expr) = value.parse::<Expr>() else {
+                continue;
+            };
+            for ident in expr.idents() {
+                if memories.contains_key(ident) {
+                    out.push(Diagnostic::error(format!(
+                        "in wire `{}`: memory `{}` can only be read by nodes",
+                        wire_name, ident
+                    )));
+                }
+            }
+        }
+    }
+
+    let mut owners: HashMap<String, &str> = HashMap::new();
+    for node in design.nodes.iter() {
+        let error = |message: String| Diagnostic::error(message).in_node(&node.node_name);
+        for cmd in node.commands.iter() {
+            let (target, reads) = match cmd {
+                Command::RegisterTransfer {
+                    reg_name,
+                    reg_value,
+                } => (
+                    reg_name.parse::<Expr>().ok(),
+                    reg_value.parse::<Expr>().ok(),
+                ),
+                Command::Check { check } | Command::WaitUntil { check, .. } => {
+                    (None, check.parse::<Expr>().ok())
+                }
+                _ => continue,
+            };
+            let mut whole = vec![];
+            let mut words = vec![];
+            match (
+                &target,
+                target
+                    .as_ref()
+                    .and_then(|target| memory::word(target, &memories)),
+            ) {
+                (Some(Expr::Index(_, address)), Some(_)) => {
+                    whole_memories(address, &memories, &mut whole);
+                    memory::reads(address, &memories, &mut words);
+                }
+                (Some(target), None) => {
+                    whole_memories(target, &memories, &mut whole);
+                    if let Command::RegisterTransfer { reg_name, .. } = cmd {
+                        let (base, _) = split_target(reg_name);
+                        if memories.contains_key(base) {
+                            out.push(error(format!(
+                                "write whole words of memory `{}`, such as `{}[address] => value`",
+                                base, base
+                            )));
+                            whole.retain(|name| *name != base);
+                        }
+                    }
+                }
+                _ => {}
+            }
+            if let Some(reads) = &reads {
+                whole_memories(reads, &memories, &mut whole);
+                memory::reads(reads, &memories, &mut words);
+            }
+            for name in whole {
+                out.push(error(format!(
+                    "memory `{}` is read one word at a time, such as `{}[address]`",
+                    name, name
+                )));
+            }
+            let loaded = memory::load(cmd, &memories).is_some_and(|(_, target)| {
+                names.get(&target).is_some_and(|declared| {
+                    declared.writable && declared.role == Role::Signal && !declared.is_array
+                })
+            });
+            let mut late: HashSet<&str> = HashSet::new();
+            for (mem, address) in words.iter() {
+                let latency = memories[mem.as_str()];
+                // Once per memory, however many of its words the command reads.
+                if latency > 0 && !loaded && late.insert(mem) {
+                    out.push(error(format!(
+                        "memory `{}` has a read latency of {}, so it can only be read into a register, such as `r0 => {}[address]`",
+                        mem, latency, mem
+                    )));
+                }
+                let mut nested = vec![];
+                if let Ok(address) = address.parse::<Expr>() {
+                    memory::reads(&address, &memories, &mut nested);
+                }
+                if !nested.is_empty() {
+                    out.push(error(format!(
+                        "the address of a word of memory `{}` cannot itself read a memory",
+                        mem
+                    )));
+                }
+            }
+            for (mem, _) in memory::accesses(cmd, &memories) {
+                let owner = *owners.entry(mem.clone()).or_insert(&node.machine);
+                if owner != node.machine {
+                    out.push(error(format!(
+                        "memory `{}` is used by {} and {}, but belongs to one machine",
+                        mem,
+                        machine_label(owner),
+                        machine_label(&node.machine)
+                    )));
+                }
+            }
+        }
+    }
+
+    let node_map: HashMap<&str, &Node> = design
+        .nodes
+        .iter()
+        .map(|node| (node.node_name.as_str(), node))
+        .collect();
+    for node in design.nodes.iter() {
+        if node.node_type != NodeType::State {
+            continue;
+        }
+        let accesses = memory::state_accesses(node, &node_map, &memories);
+        for cmd in design.commands.iter() {
+            let Command::Memory {
+                mem_name, ports, ..
+            } = cmd
+            else {
+                continue;
+            };
+            let addresses: Vec<String> = accesses
+                .iter()
+                .filter(|(mem, _)| mem == mem_name)
+                .map(|(_, address)| format!("`{}`", address))
+                .collect();
+            if *ports > 0 && addresses.len() > *ports as usize {
+                out.push(
+                    Diagnostic::error(format!(
+                        "memory `{}` has {} port{} but this state accesses {} of its words: {}",
+                        mem_name,
+                        ports,
+                        if *ports == 1 { "" } else { "s" },
+                        addresses.len(),
+                        addresses.join(", ")
+                    ))
+                    .in_node(&node.node_name),
+                );
+            }
+        }
+    }
+}
+
+/// A fifo or counter has one set of inputs, driven from the state of the
+/// one machine that acts on it.
+fn check_primitives(design: &Design, out: &mut Vec<Diagnostic>) {
+    let mut owners: HashMap<&str, &str> = HashMap::new();
+    for node in design.nodes.iter() {
+        for cmd in node.commands.iter() {
+            let Command::Action { name, .. } = cmd else {
+                continue;
+            };
+            let owner = *owners.entry(name).or_insert(&node.machine);
+            if owner != node.machine {
+                out.push(
+                    Diagnostic::error(format!(
+                        "`{}` is used by {} and {}, but belongs to one machine",
+                        name,
+                        machine_label(owner),
+                        machine_label(&node.machine)
+                    ))
+                    .in_node(&node.node_name),
+                );
+            }
+        }
+    }
+}
+
+fn check_node(
+    node: &Node,
+    names: &HashMap<String, Declared>,
+    node_names: &HashSet<&str>,
+    out: &mut Vec<Diagnostic>,
+) {
+    let at = |diagnostic: Diagnostic| diagnostic.in_node(&node.node_name);
+    let error = |message: String| Diagnostic::error(message).in_node(&node.node_name);
+    let (mut checks, mut yes, mut no, mut then) = (0, 0, 0, 0);
+    let (mut calls, mut returns, mut waits) = (0, 0, 0);
+    for cmd in node.commands.iter() {
+        match cmd {
+            Command::Wait { cycles } => {
+                waits += 1;
+                if *cycles == 0 {
+                    out.push(error("`wait` needs at least one cycle".to_string()));
+                }
+            }
+            Command::WaitUntil { check, timeout } => {
+                waits += 1;
+                check_expr(check, names, &at, out);
+                if let Some(timeout) = timeout {
+                    if timeout.cycles == 0 {
+                        out.push(error("a timeout needs at least one cycle".to_string()));
+                    }
+                    if !node_names.contains(timeout.next_node.as_str()) {
+                        out.push(error(format!("`{}` is not a node", timeout.next_node)));
+                    }
+                }
+            }
+            Command::Call { node_name } => {
+                calls += 1;
+                if !node_names.contains(node_name.as_str()) {
+                    out.push(error(format!("`{}` is not a node", node_name)));
+                }
+            }
+            Command::Return => returns += 1,
+            Command::Drive { pin_name } | Command::Release { pin_name } => {
+                let action = match cmd {
+                    Command::Drive { .. } => "driven",
+                    _ => "released",
+                };
+                match names.get(pin_name) {
+                    Some(Declared {
+                        role: Role::Inout, ..
+                    }) => {}
+                    Some(_) => out.push(error(format!(
+                        "`{}` is not an inout and cannot be {}",
+                        pin_name, action
+                    ))),
+                    None => out.push(error(format!("`{}` is not declared", pin_name))),
+                }
+                if action == "driven"
+                    && node.commands.contains(&Command::Release {
+                        pin_name: pin_name.clone(),
+                    })
+                {
+                    out.push(error(format!("`{}` is both driven and released", pin_name)));
+                }
+            }
+            Command::Check { check } => {
+                checks += 1;
+                check_expr(check, names, &at, out);
+            }
+            Command::Yes { next_node }
+            | Command::No { next_node }
+            | Command::Then { next_node } => {
+                match cmd {
+                    Command::Yes { .. } => yes += 1,
+                    Command::No { .. } => no += 1,
+                    _ => then += 1,
+                }
+                if !node_names.contains(next_node.as_str()) {
+                    out.push(error(format!("`{}` is not a node", next_node)));
+                }
+            }
+            Command::RegisterTransfer {
+                reg_name,
+                reg_value,
+            } => {
+                if node.node_type == NodeType::Decision {
+                    out.push(error(format!(
+                        "decisions cannot transfer `{} => {}`",
+                        reg_name, reg_value
+                    )));
+                }
+                let (base, _) = split_target(reg_name);
+                let target = match names.get(base) {
+                    Some(Declared {
+                        role: Role::Status(kind),
+                        ..
+                    }) => {
+                        out.push(error(format!(
+                            "`{}` is a status of {} `{}` and cannot be written",
+                            base,
+                            kind.name(),
+                            base.split_once('.').map_or(base, |(name, _)| name)
+                        )));
+                        None
+                    }
+                    Some(Declared {
+                        role: Role::Wire, ..
+                    }) => {
+                        out.push(error(format!("`{}` is a wire and cannot be written", base)));
+                        None
+                    }
+                    Some(Declared {
+                        role: Role::Member(enum_name),
+                        ..
+                    }) => {
+                        out.push(error(format!(
+                            "`{}` is a member of enum `{}` and cannot be written",
+                            base, enum_name
+                        )));
+                        None
+                    }
+                    Some(Declared {
+                        role: Role::Port(module),
+                        writable: false,
+                        ..
+                    }) => {
+                        out.push(error(format!(
+                            "`{}` is an output of `{}` and cannot be written",
+                            base, module
+                        )));
+                        None
+                    }
+                    Some(declared)
+                        if !declared.writable && !matches!(declared.role, Role::Instance(..)) =>
+                    {
+                        out.push(error(format!(
+                            "`{}` is an input and cannot be written",
+                            base
+                        )));
+                        None
+                    }
+                    Some(declared) if declared.writable => check_expr(reg_name, names, &at, out),
+                    _ => {
+                        if let Some(message) = misuse(base, names) {
+                            out.push(error(message));
+                        }
+                        None
+                    }
+                };
+                let value = check_expr(reg_value, names, &at, out);
+                if let (Some(target), Some(value)) = (target, value) {
+                    check_port_width(&target, &value, names, node, out);
+                    check_enum_transfer(&target, &value, names, node, out);
+                }
+            }
+            Command::Action {
+                name,
+                action,
+                value,
+            } => {
+                if node.node_type == NodeType::Decision {
+                    out.push(error(format!("decisions cannot perform `{}`", cmd)));
+                }
+                match names.get(name) {
+                    Some(Declared {
+                        role: Role::Primitive(kind),
+                        width,
+                        ..
+                    }) => match kind.actions().iter().find(|(known, _)| known == action) {
+                        None => out.push(error(format!(
+                            "{} `{}` has no action `{}`, use {}",
+                            kind.name(),
+                            name,
+                            action,
+                            kind.actions()
+                                .iter()
+                                .map(|(known, _)| format!("`{}.{}()`", name, known))
+                                .collect::<Vec<_>>()
+                                .join(" or ")
+                        ))),
+                        Some((_, true)) if value.is_none() => {
+                            out.push(error(format!("`{}.{}` needs a value", name, action)))
+                        }
+                        Some((_, false)) if value.is_some() => {
+                            out.push(error(format!("`{}.{}` takes no value", name, action)))
+                        }
+                        Some(_) => {
+                            let value = value
+                                .as_ref()
+                                .and_then(|value| check_expr(value, names, &at, out));
+                            let lookup = |name: &str| {
+                                names
+                                    .get(name)
+                                    .map(|declared| (declared.width, declared.is_array))
+                            };
+                            if let Some((value, Ok(value_width))) =
+                                value.map(|value| (value.to_string(), value.value_width(&lookup)))
+                            {
+                                if value_width > *width {
+                                    out.push(
+                                        Diagnostic::warning(format!(
+                                            "`{}` is {} bits wide but {} `{}` only holds {}",
+                                            value,
+                                            value_width,
+                                            kind.name(),
+                                            name,
+                                            width
+                                        ))
+                                        .in_node(&node.node_name),
+                                    );
+                                }
+                            }
+                        }
+                    },
+                    Some(_) => out.push(error(format!(
+                        "`{}` is not a fifo or counter and has no actions",
+                        name
+                    ))),
+                    None => out.push(error(format!("`{}` is not declared", name))),
+                }
+            }
+            _ => out.push(error(format!("`{}` is only allowed at the top level", cmd))),
+        }
+    }
+    if waits > 0 && (node.node_type != NodeType::State || waits > 1 || calls + returns > 0) {
+        out.push(error(
+            "only states can wait, once each and without a `call` or `return`".to_string(),
+        ));
+    }
+    if node.node_type == NodeType::Decision {
+        if checks != 1 || yes != 1 || no != 1 || then + calls + returns != 0 {
+            out.push(error(
+                "decisions need exactly one `check`, one `yes` and one `no`".to_string(),
+            ));
+        }
+    } else if then + returns != 1 || checks + yes + no != 0 {
+        out.push(error(
+            "states and conditional outputs need exactly one `then` or `return`".to_string(),
+        ));
+    } else if calls > 1 || calls > then {
+        out.push(error(
+            "a `call` needs one `then` naming the state to return to".to_string(),
+        ));
+    }
+}
+
+fn machine_label(machine: &str) -> String {
+    match machine {
+        "" => "the unnamed machine".to_string(),
+        machine => format!("machine `{}`", machine),
+    }
+}
+
+/// Transitions must stay inside their machine, and a register written by
+/// more than one machine needs a `priority` rule naming all of them, since
+/// nothing stops the machines from writing it in the same cycle.
+fn check_machines(design: &Design, machines: &[&str], out: &mut Vec<Diagnostic>) {
+    let machine_of: HashMap<&str, &str> = design
+        .nodes
+        .iter()
+        .map(|node| (node.node_name.as_str(), node.machine.as_str()))
+        .collect();
+    let mut writers: Vec<(&str, Vec<&str>)> = vec![];
+    for node in design.nodes.iter() {
+        for cmd in node.commands.iter() {
+            match cmd {
+                Command::Then { next_node }
+                | Command::Yes { next_node }
+                | Command::No { next_node }
+                | Command::Call {
+                    node_name: next_node,
+                }
+                | Command::WaitUntil {
+                    timeout: Some(Timeout { next_node, .. }),
+                    ..
+                } => {
+                    if let Some(machine) = machine_of.get(next_node.as_str()) {
+                        if *machine != node.machine {
+                            out.push(
+                                Diagnostic::error(format!(
+                                    "`{}` belongs to {}, transitions cannot leave {}",
+                                    next_node,
+                                    machine_label(machine),
+                                    machine_label(&node.machine)
+                                ))
+                                .in_node(&node.node_name),
+                            );
+                        }
+                    }
+                }
+                Command::RegisterTransfer { reg_name, .. }
+                | Command::Drive { pin_name: reg_name }
+                | Command::Release { pin_name: reg_name } => {
+                    let (base, _) = split_target(reg_name);
+                    match writers.iter_mut().find(|(name, _)| *name == base) {
+                        Some((_, found)) if found.contains(&node.machine.as_str()) => {}
+                        Some((_, found)) => found.push(&node.machine),
+                        None => writers.push((base, vec![&node.machine])),
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    let mut priority: Option<&Vec<String>> = None;
+    for cmd in design.commands.iter() {
+        if let Command::Priority { machines: listed } = cmd {
+            if priority.is_some() {
+                out.push(Diagnostic::error(
+                    "there is more than one `priority` rule".to_string(),
+                ));
+            }
+            for (idx, machine) in listed.iter().enumerate() {
+                if machine.is_empty() || !machines.contains(&machine.as_str()) {
+                    out.push(Diagnostic::error(format!(
+                        "`{}` in the `priority` rule is not a machine",
+                        machine
+                    )));
+                } else if listed[..idx].contains(machine) {
+                    out.push(Diagnostic::error(format!(
+                        "`{}` is listed more than once in the `priority` rule",
+                        machine
+                    )));
+                }
+            }
+            priority = Some(listed);
+        }
+    }
+    for (name, found) in writers.iter().filter(|(_, found)| found.len() > 1) {
+        let first = clock::domain(&design.commands, found[0]);
+        if found
+            .iter()
+            .any(|machine| !clock::domain(&design.commands, machine).same_clock(&first))
+        {
+            out.push(Diagnostic::error(format!(
+                "`{}` is written by {}, which run on different clocks",
+                name,
+                found
+                    .iter()
+                    .map(|machine| machine_label(machine))
+                    .collect::<Vec<_>>()
+                    .join(" and ")
+            )));
+            continue;
+        }
+        let arbitrated = priority.is_some_and(|listed| {
+            found
+                .iter()
+                .all(|machine| listed.iter().any(|listed| listed == machine))
+        });
+        if !arbitrated {
+            out.push(Diagnostic::error(format!(
+                "`{}` is written by {}, which may happen in the same cycle; list them in a `priority` rule",
+                name,
+                found
+                    .iter()
+                    .map(|machine| machine_label(machine))
+                    .collect::<Vec<_>>()
+                    .join(" and ")
+            )));
+        }
+    }
+}
+
+/// Every `clock` declaration names a machine once, and the clocks, resets
+/// and enables become inputs of the module, so they cannot be declared,
+/// be Verilog keywords or serve two purposes. `defaulted` is `design` with
+/// the configured clock and reset filled in.
+fn check_clocks(
+    design: &Design,
+    defaulted: &Design,
+    names: &HashMap<String, Declared>,
+    machines: &[&str],
+    out: &mut Vec<Diagnostic>,
+) {
+    let mut clocked = HashSet::new();
+    for cmd in design.commands.iter() {
+        match cmd {
+            Command::Clock { machine, .. } => {
+                if !machines.contains(&machine.as_str()) {
+                    out.push(Diagnostic::error(format!(
+                        "`{}` is for {}, which has no nodes",
+                        cmd,
+                        machine_label(machine)
+                    )));
+                } else if !clocked.insert(machine) {
+                    out.push(Diagnostic::error(format!(
+                        "the clock of {} is declared more than once",
+                        machine_label(machine)
+                    )));
+                }
+            }
+            Command::Input {
+                pin_name,
+                array,
+                asynchronous: true,
+                ..
+            } if array.start != array.end || array.start != 0 => {
+                out.push(Diagnostic::error(format!(
+                    "async input `{}` cannot be an array",
+                    pin_name
+                )));
+            }
+            _ => {}
+        }
+    }
+    let mut roles: Vec<(&str, clock::Role)> = vec![];
+    for machine in machines.iter() {
+        let domain = clock::domain(&defaulted.commands, machine);
+        let used = [
+            Some((domain.clock, clock::Role::Clock)),
+            Some((domain.reset, clock::Role::Reset)),
+            domain.enable.map(|enable| (enable, clock::Role::Enable)),
+        ];
+        for (name, role) in used.into_iter().flatten() {
+            match roles.iter().find(|(known, _)| *known == name) {
+                Some((_, known)) if *known != role => out.push(Diagnostic::error(format!(
+                    "`{}` is used both as a {} and as a {}",
+                    name,
+                    known.name(),
+                    role.name()
+                ))),
+                Some(_) => {}
+                None => roles.push((name, role)),
+            }
+        }
+    }
+    for (name, role) in roles {
+        if VERILOG_KEYWORDS.contains(&name) {
+            out.push(Diagnostic::error(format!(
+                "`{}` is a Verilog keyword and cannot name the {} input",
+                name,
+                role.name()
+            )));
+        } else if names.contains_key(name) {
+            out.push(Diagnostic::error(format!(
+                "`{}` is the {} input of the module and cannot also be declared",
+                name,
+                role.name()
+            )));
+        }
+    }
+}
+
+/// How many calls may be pending at once while `callee` runs, counting its
+/// own, or the subroutine that can end up calling itself.
+fn nesting<'d>(
+    callee: &'d str,
+    node_map: &HashMap<&str, &'d Node>,
+    memo: &mut HashMap<&'d str, Option<u32>>,
+) -> Result<u32, &'d str> {
+    match memo.get(callee) {
+        Some(Some(depth)) => return Ok(*depth),
+        Some(None) => return Err(callee),
+        None => {}
+    }
+    memo.insert(callee, None);
+    let mut inner = 0;
+    let mut seen = HashSet::from([callee]);
+    let mut pending = vec![callee];
+    while let Some(name) = pending.pop() {
+        let Some(node) = node_map.get(name) else {
+            continue;
+        };
+        for cmd in node.commands.iter() {
+            if let Command::Call { node_name } = cmd {
+                inner = inner.max(nesting(node_name, node_map, memo)?);
+            }
+        }
+        for cmd in node.commands.iter() {
+            match cmd {
+                // Control comes back to the `then` once the callee returns.
+                Command::Then { next_node }
+                | Command::Yes { next_node }
+                | Command::No { next_node }
+                    if seen.insert(next_node) =>
+                {
+                    pending.push(next_node);
+                }
+                _ => {}
+            }
+        }
+    }
+    memo.insert(callee, Some(inner + 1));
+    Ok(inner + 1)
+}
+
+/// Calls must land on a state and resume at one, since both are encoded
+/// as states in the return stack, and must fit in `call_depth` entries.
+fn check_calls(design: &Design, out: &mut Vec<Diagnostic>) {
+    let mut depth_given = false;
+    for cmd in design.commands.iter() {
+        if let Command::CallDepth { depth } = cmd {
+            if depth_given {
+                out.push(Diagnostic::error(
+                    "the call depth is given more than once".to_string(),
+                ));
+            }
+            if *depth == 0 {
+                out.push(Diagnostic::error(
+                    "`call_depth` must be at least 1".to_string(),
+                ));
+            }
+            depth_given = true;
+        }
+    }
+
+    let node_map: HashMap<&str, &Node> = design
+        .nodes
+        .iter()
+        .map(|node| (node.node_name.as_str(), node))
+        .collect();
+    let is_state = |name: &str| {
+        node_map
+            .get(name)
+            .is_some_and(|node| node.node_type == NodeType::State)
+    };
+    let mut memo = HashMap::new();
+    let mut deepest = 0;
+    for node in design.nodes.iter() {
+        let error = |message: String| Diagnostic::error(message).in_node(&node.node_name);
+        for cmd in node.commands.iter() {
+            match cmd {
+                Command::Call { node_name } => {
+                    if node_map.contains_key(node_name.as_str()) && !is_state(node_name) {
+                        out.push(error(format!(
+                            "`{}` is called but is not a state",
+                            node_name
+                        )));
+                    }
+                    match nesting(node_name, &node_map, &mut memo) {
+                        Ok(depth) => deepest = deepest.max(depth),
+                        Err(callee) => out.push(error(format!(
+                            "`{}` can end up calling itself, which needs an unbounded return stack",
+                            callee
+                        ))),
+                    }
+                }
+                Command::Then { next_node }
+                    if node_map.contains_key(next_node.as_str())
+                        && !is_state(next_node)
+                        && node
+                            .commands
+                            .iter()
+                            .any(|cmd| matches!(cmd, Command::Call { .. })) =>
+                {
+                    out.push(error(format!(
+                        "`{}` is returned to after the `call`, so it must be a state",
+                        next_node
+                    )));
+                }
+                Command::Return
+                    if !design.nodes.iter().any(|other| {
+                        other.machine == node.machine
+                            && other
+                                .commands
+                                .iter()
+                                .any(|cmd| matches!(cmd, Command::Call { .. }))
+                    }) =>
+                {
+                    out.push(error(format!(
+                        "`return` without any `call` in {}",
+                        machine_label(&node.machine)
+                    )));
+                }
+                _ => {}
+            }
+        }
+    }
+    let depth = call::call_depth(design) as u32;
+    if deepest > depth {
+        out.push(Diagnostic::error(format!(
+            "calls nest {} deep but the return stack holds {}, raise `call_depth`",
+            deepest, depth
+        )));
+    }
+}
+
+/// Every problem of `design` when compiled with `options`. Those in nodes
+/// copied from a template name the template and the `use` that copied them.
+pub fn check(design: &Design, options: &Options) -> Vec<Diagnostic> {
+    let mut out = check_design(design, options);
+    for diagnostic in out.iter_mut() {
+        let origin = diagnostic
+            .node
+            .as_ref()
+            .and_then(|name| design.nodes.iter().find(|node| node.node_name == *name))
+            .and_then(|node| node.origin.as_ref());
+        if let Some(origin) = origin {
+            diagnostic.message = format!(
+                "in template `{}` used at `{}`: {}",
+                origin.template, origin.site, diagnostic.message
+            );
+        }
+    }
+    out
+}
+
+fn check_design(design: &Design, options: &Options) -> Vec<Diagnostic> {
+    let mut out = vec![];
+    let library = &options.library;
+    let names = declared_names(design, library, &mut out);
+
+    let mut node_names = HashSet::new();
+    for node in design.nodes.iter() {
+        if !node_names.insert(node.node_name.as_str()) {
+            out.push(Diagnostic::error(format!(
+                "node `{}` is defined more than once",
+                node.node_name
+            )));
+        }
+    }
+    if !design
+        .nodes
+        .iter()
+        .any(|node| node.node_type == NodeType::State)
+    {
+        out.push(Diagnostic::error("the chart has no state node".to_string()));
+        return out;
+    }
+    let machines = design.machines();
+    let mut reset_states = vec![];
+    for machine in machines.iter() {
+        if !machine.is_empty() && !is_identifier(machine) {
+            out.push(Diagnostic::error(format!(
+                "`{}` is not a valid machine name",
+                machine
+            )));
+        }
+        match design
+            .nodes
+            .iter()
+            .find(|node| node.machine == *machine && node.node_type == NodeType::State)
+        {
+            Some(node) => reset_states.push(node.node_name.as_str()),
+            None => out.push(Diagnostic::error(format!(
+                "{} has no state node",
+                machine_label(machine)
+            ))),
+        }
+    }
+    check_wires(design, &names, &mut out);
+    for node in design.nodes.iter() {
+        check_node(node, &names, &node_names, &mut out);
+    }
+    check_instance_inputs(design, library, &mut out);
+    check_memories(design, &names, &mut out);
+    check_primitives(design, &mut out);
+    check_machines(design, &machines, &mut out);
+    // Machines without a `clock` declaration get the configured inputs.
+    let clocked = clock::with_defaults(design, &options.clock, &options.reset);
+    check_clocks(design, &clocked, &names, &machines, &mut out);
+    check_calls(design, &mut out);
+    if out.iter().any(|diagnostic| diagnostic.is_error()) {
+        return out;
+    }
+
+    let mut generated = Names::new(
+        &options.naming,
+        &design.commands,
+        &clock::DEFAULT,
+        &[],
+        false,
+    );
+    match collect_transitions(&crate::lower(design, &mut generated).nodes) {
+        Ok(transitions) => {
+            let mut reached: HashSet<&str> = reset_states.iter().copied().collect();
+            let mut pending = reset_states;
+            while let Some(state) = pending.pop() {
+                for transition in transitions.iter().filter(|t| t.from == state) {
+                    if reached.insert(transition.to.as_str()) {
+                        pending.push(transition.to.as_str());
+                    }
+                }
+            }
+            for node in design.nodes.iter() {
+                if node.node_type == NodeType::State && !reached.contains(node.node_name.as_str()) {
+                    out.push(
+                        Diagnostic::warning("state is unreachable from reset".to_string())
+                            .in_node(&node.node_name),
+                    );
+                }
+            }
+        }
+        Err(err) => out.push(Diagnostic::error(format!(
+            "{}: a path through decisions and conditional outputs never reaches a state",
+            err
+        ))),
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{check, check_with, parse, Command};
+
+    fn messages(source: &str) -> Vec<String> {
+        check(&parse(source).unwrap())
+            .iter()
+            .map(|diagnostic| diagnostic.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn clean_design_test() {
+        let source = std::fs::read_to_string("multiplier.asmc").unwrap();
+        assert!(messages(&source).is_empty());
+    }
+
+    #[test]
+    fn reference_errors_test() {
+        let found = messages(
+            "
+a => input;
+r0 => reg[3:0];
+.main : state {
+    a => 1;
+    r1 => r0;
+    r0 => r0 + b;
+    then => nowhere;
+}
+",
+        );
+        assert_eq!(
+            found,
+            vec![
+                "error: `a` is an input and cannot be written (in node `main`)",
+                "error: `r1` is not declared (in node `main`)",
+                "error: `b` is not declared (in node `main`)",
+                "error: `nowhere` is not a node (in node `main`)",
+            ]
+        );
+    }
+
+    #[test]
+    fn structure_errors_test() {
+        let found = messages(
+            "
+r0 => reg;
+r0 => reg;
+.main : state {
+    then => d;
+}
+.d : decision {
+    check => r0;
+    yes => c;
+}
+.c : conditional {
+    then => d;
+}
+",
+        );
+        assert_eq!(
+            found,
+            vec![
+                "error: `r0` is declared more than once",
+                "error: decisions need exactly one `check`, one `yes` and one `no` (in node `d`)",
+            ]
+        );
+    }
+
+    #[test]
+    fn module_directive_test() {
+        let found = messages(
+            "
+module => mult-2;
+module => mult;
+.main : state {
+    module => other;
+    then => main;
+}
+",
+        );
+        assert_eq!(
+            found,
+            vec![
+                "error: `mult-2` is not a valid module name",
+                "error: the module is named more than once",
+                "error: `module => other` is only allowed at the top level (in node `main`)",
+            ]
+        );
+    }
+
+    #[test]
+    fn instance_test() {
+        let mut options = crate::Options::default();
+        options.library.insert(
+            "multiplier",
+            parse(&std::fs::read_to_string("multiplier.asmc").unwrap()).unwrap(),
+        );
+        let controller = parse(
+            "
+x => input[7:0];
+out => output[3:0];
+m => instance multiplier;
+n => instance divider;
+.idle : state {
+    m.a => x;
+    m.ready => 1;
+    out => m.res;
+    m => 0;
+    n.start => 1;
+    then => test;
+}
+.test : decision {
+    check => m.done;
+    yes => idle;
+    no => idle;
+}
+",
+        )
+        .unwrap();
+        let found: Vec<String> = check_with(&controller, &options)
+            .iter()
+            .map(|diagnostic| diagnostic.to_string())
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                "error: module `divider` of instance `n` is not known, compile its chart along with this one",
+                "warning: `x` is 8 bits wide but `m.a` only holds 4 (in node `idle`)",
+                "error: `m.ready` is an output of `multiplier` and cannot be written (in node `idle`)",
+                "warning: `m.res` is 8 bits wide but `out` only holds 4 (in node `idle`)",
+                "error: `m` is an instance, use one of its ports (in node `idle`)",
+                "error: `multiplier` has no port `done` (in node `test`)",
+                "warning: `m.b` is an input of `multiplier` that no node writes",
+                "warning: `m.start` is an input of `multiplier` that no node writes",
+            ]
+        );
+
+        let multiplier = options.library.get("multiplier").unwrap().clone();
+        let mut looped = multiplier.clone();
+        looped.commands.push(Command::Instance {
+            instance_name: "inner".to_string(),
+            module_name: "multiplier".to_string(),
+        });
+        options.library.insert("multiplier", looped.clone());
+        assert!(check_with(&looped, &options)[0]
+            .message
+            .contains("would make the module contain itself"));
+    }
+
+    #[test]
+    fn machines_test() {
+        let source = "
+r0 => reg;
+.idle : state {
+    r0 => 1;
+    then => idle;
+}
+machine => bus;
+.wait : state {
+    r0 => 0;
+    then => idle;
+}
+machine => spare;
+.test : decision {
+    check => r0;
+    yes => test;
+    no => test;
+}
+";
+        assert_eq!(
+            messages(source),
+            vec![
+                "error: machine `spare` has no state node",
+                "error: `idle` belongs to the unnamed machine, transitions cannot leave machine `bus` (in node `wait`)",
+                "error: `r0` is written by the unnamed machine and machine `bus`, which may happen in the same cycle; list them in a `priority` rule",
+            ]
+        );
+
+        let found = messages(
+            "
+r0 => reg;
+priority => bus, bus, other;
+machine => main;
+.idle : state {
+    r0 => 1;
+    then => idle;
+}
+machine => bus;
+.wait : state {
+    r0 => 0;
+    then => wait;
+}
+",
+        );
+        assert_eq!(
+            found,
+            vec![
+                "error: `bus` is listed more than once in the `priority` rule",
+                "error: `other` in the `priority` rule is not a machine",
+                "error: `r0` is written by machine `main` and machine `bus`, which may happen in the same cycle; list them in a `priority` rule",
+            ]
+        );
+    }
+
+    #[test]
+    fn call_test() {
+        let found = messages(
+            "
+.main : state {
+    call => sub;
+    then => pick;
+}
+.pick : decision {
+    check => 1;
+    yes => main;
+    no => main;
+}
+.sub : state {
+    call => sub;
+    then => main;
+}
+.lonely : state {
+    return;
+    then => main;
+}
+",
+        );
+        assert_eq!(
+            found,
+            vec![
+                "error: states and conditional outputs need exactly one `then` or `return` (in node `lonely`)",
+                "error: `sub` can end up calling itself, which needs an unbounded return stack (in node `main`)",
+                "error: `pick` is returned to after the `call`, so it must be a state (in node `main`)",
+                "error: `sub` can end up calling itself, which needs an unbounded return stack (in node `sub`)",
+            ]
+        );
+
+        let nested = "
+.main : state {
+    call => outer;
+    then => main;
+}
+.outer : state {
+    call => inner;
+    then => back;
+}
+.back : state {
+    return;
+}
+.inner : state {
+    return;
+}
+";
+        assert_eq!(
+            messages(nested),
+            vec!["error: calls nest 2 deep but the return stack holds 1, raise `call_depth`"]
+        );
+        assert!(messages(&format!("call_depth => 2;{}", nested)).is_empty());
+    }
+
+    #[test]
+    fn wait_test() {
+        let found = messages(
+            "
+ack => input;
+.idle : state {
+    wait => 0;
+    then => poll;
+}
+.poll : state {
+    wait_until => ready timeout 4 => nowhere;
+    then => idle;
+}
+.pick : decision {
+    wait => 2;
+    check => ack;
+    yes => idle;
+    no => idle;
+}
+",
+        );
+        assert_eq!(
+            found,
+            vec![
+                "error: `wait` needs at least one cycle (in node `idle`)",
+                "error: `ready` is not declared (in node `poll`)",
+                "error: `nowhere` is not a node (in node `poll`)",
+                "error: only states can wait, once each and without a `call` or `return` (in node `pick`)",
+            ]
+        );
+    }
+
+    #[test]
+    fn enum_test() {
+        let found = messages(
+            "
+enum Opcode { ADD, SUB, JMP }
+enum Mode { IDLE, RUN, IDLE }
+enum Mode { HALT }
+op => input Opcode;
+mode => reg Mode;
+flag => reg Flag;
+.idle : state {
+    mode => op;
+    mode => 7;
+    ADD => 1;
+    then => pick;
+}
+.pick : decision {
+    check => op == RUN || op != SUB;
+    yes => idle;
+    no => idle;
+}
+",
+        );
+        assert_eq!(
+            found,
+            vec![
+                "error: `IDLE` is declared more than once",
+                "error: enum `Mode` is declared more than once",
+                "error: `Flag` is not an enum",
+                "error: `mode` holds `Mode`, not `Opcode` like `op` (in node `idle`)",
+                "warning: `mode` holds `Mode`, which has no member for 7 (in node `idle`)",
+                "error: `ADD` is a member of enum `Opcode` and cannot be written (in node `idle`)",
+                "error: `op` and `RUN` hold different enums, `Opcode` and `Mode` (in node `pick`)",
+            ]
+        );
+    }
+
+    #[test]
+    fn wire_test() {
+        let found = messages(
+            "
+a => input[3:0];
+sum => wire[3:0] = a + b;
+low => wire = sum[0] | c;
+b => wire[3:0] = {low, c};
+c => wire[1:0] = a;
+wide => wire[1:0] = missing;
+.idle : state {
+    sum => 1;
+    then => idle;
+}
+",
+        );
+        assert_eq!(
+            found,
+            vec![
+                "warning: in wire `low`: `(sum[0] | c)` is 2 bits wide but the wire only holds 1",
+                "warning: in wire `c`: `a` is 4 bits wide but the wire only holds 2",
+                "error: in wire `wide`: `missing` is not declared",
+                "error: combinational loop: `sum` reads `b` reads `low` reads `sum`",
+                "error: `sum` is a wire and cannot be written (in node `idle`)",
+            ]
+        );
+    }
+
+    #[test]
+    fn drive_test() {
+        let found = messages(
+            "
+bus => inout[7:0];
+r0 => reg;
+.idle : state {
+    drive => bus;
+    drive => r0;
+    release => bus;
+    release => pin;
+    then => idle;
+}
+",
+        );
+        assert_eq!(
+            found,
+            vec![
+                "error: `bus` is both driven and released (in node `idle`)",
+                "error: `r0` is not an inout and cannot be driven (in node `idle`)",
+                "error: `pin` is not declared (in node `idle`)",
+            ]
+        );
+        assert!(messages(
+            "
+bus => inout[7:0] registered;
+r0 => reg[7:0];
+.idle : state {
+    drive => bus;
+    bus => r0;
+    then => done;
+}
+.done : state {
+    release => bus;
+    r0 => bus;
+    then => idle;
+}
+"
+        )
+        .is_empty());
+    }
+
+    #[test]
+    fn memory_test() {
+        let found = messages(
+            "
+a => input[3:0];
+r0 => reg[7:0];
+mem => memory[7:0] depth 16;
+lut => memory[7:0] depth 16 ports 2 latency 0;
+empty => memory depth 0;
+w => wire[7:0] = lut[a];
+.load : state {
+    r0 => mem[a];
+    mem[a] => r0;
+    lut[a] => lut[a + 1] + lut[a + 2];
+    then => test;
+}
+.test : decision {
+    check => mem[0] == r0;
+    yes => load;
+    no => store;
+}
+.store : state {
+    mem => 0;
+    r0 => lut;
+    then => load;
+}
+",
+        );
+        assert_eq!(
+            found,
+            vec![
+                "error: memory `empty` needs a depth of at least 1",
+                "error: in wire `w`: memory `lut` can only be read by nodes",
+                "error: memory `mem` has a read latency of 1, so it can only be read into a register, such as `r0 => mem[address]` (in node `test`)",
+                "error: write whole words of memory `mem`, such as `mem[address] => value` (in node `store`)",
+                "error: memory `lut` is read one word at a time, such as `lut[address]` (in node `store`)",
+                "error: memory `mem` has 1 port but this state accesses 2 of its words: `a`, `0` (in node `load`)",
+                "error: memory `lut` has 2 ports but this state accesses 3 of its words: `a`, `(a + 1)`, `(a + 2)` (in node `load`)",
+            ]
+        );
+
+        let found = messages(
+            "
+a => input[3:0];
+r0 => reg[7:0];
+mem => memory[7:0] depth 16 ports 2;
+.load : state {
+    r0 => mem[a] + mem[a + 1];
+    then => load;
+}
+",
+        );
+        assert_eq!(found.len(), 1);
+        assert!(found[0].contains("memory `mem` has a read latency of 1"));
+
+        let found = messages(
+            "
+a => input[3:0];
+r0 => reg[7:0];
+mem => memory[7:0] depth 16 ports 2 latency 2;
+lut => memory[3:0] depth 4 latency 0;
+.load : state {
+    mem[a] => r0;
+    r0 => mem[a + 1];
+    then => test;
+}
+.test : decision {
+    check => lut[r0[1:0]] == 0;
+    yes => load;
+    no => store;
+}
+.store : conditional {
+    lut[r0[1:0]] => r0[3:0];
+    then => load;
+}
+",
+        );
+        assert!(found.is_empty());
+    }
+
+    #[test]
+    fn primitive_test() {
+        let found = messages(
+            "
+data => input[11:0];
+q => fifo[8] depth 16;
+t => counter[10];
+bad => fifo[8] depth 0;
+r => reg[7:0];
+.fill : state {
+    q.push(data);
+    q.push();
+    t.clear(1);
+    t.stop();
+    r.clear();
+    q.full => 1;
+    r => q;
+    then => test;
+}
+.test : decision {
+    check => q.empty | t.count;
+    yes => fill;
+    no => drain;
+}
+.drain : state {
+    q.pop();
+    r => q.data;
+    then => drain;
+}
+machine => side;
+.side : state {
+    q.pop();
+    then => side;
+}
+",
+        );
+        assert_eq!(
+            found,
+            vec![
+                "error: fifo `bad` needs a depth of at least 1",
+                "warning: `data` is 12 bits wide but fifo `q` only holds 8 (in node `fill`)",
+                "error: `q.push` needs a value (in node `fill`)",
+                "error: `t.clear` takes no value (in node `fill`)",
+                "error: counter `t` has no action `stop`, use `t.clear()` (in node `fill`)",
+                "error: `r` is not a fifo or counter and has no actions (in node `fill`)",
+                "error: `q.full` is a status of fifo `q` and cannot be written (in node `fill`)",
+                "error: `q` is a fifo, read `q.data`, `q.empty` or `q.full` (in node `fill`)",
+                "error: counter `t` has no `count`, read `t.value` (in node `test`)",
+                "error: `q` is used by the unnamed machine and machine `side`, but belongs to one machine (in node `side`)",
+            ]
+        );
+
+        let found = messages(
+            "
+data => input[7:0];
+r => reg[7:0];
+q => fifo[8] depth 16;
+t => counter[10];
+.idle : state {
+    then => test;
+}
+.test : decision {
+    check => q.full | t.value == 1000;
+    yes => drain;
+    no => fill;
+}
+.fill : conditional {
+    q.push(data);
+    then => idle;
+}
+.drain : state {
+    q.pop();
+    t.clear();
+    r => q.data;
+    then => idle;
+}
+",
+        );
+        assert!(found.is_empty());
+    }
+
+    #[test]
+    fn clock_test() {
+        let found = messages(
+            "
+req => input[1:0] async;
+bus => input[3:0][1:0] async;
+ce => input;
+r => reg[7:0];
+clock => clk enable ce;
+clock => clk_b;
+clock side => clk_s reset clk;
+clock ghost => clk;
+.idle : state {
+    r => req;
+    then => idle;
+}
+machine => side;
+.side : state {
+    r => 0;
+    then => side;
+}
+",
+        );
+        assert_eq!(
+            found,
+            vec![
+                "error: `r` is written by the unnamed machine and machine `side`, which run on different clocks",
+                "error: async input `bus` cannot be an array",
+                "error: the clock of the unnamed machine is declared more than once",
+                "error: `clock ghost => clk` is for machine `ghost`, which has no nodes",
+                "error: `clk` is used both as a clock and as a reset",
+                "error: `ce` is the clock enable input of the module and cannot also be declared",
+            ]
+        );
+
+        // Renaming the default clock frees `clk` and takes the new name.
+        let design =
+            parse("aclk => input;\nclk => input;\n.idle : state { then => idle; }").unwrap();
+        let options = crate::Options {
+            clock: "aclk".to_string(),
+            ..crate::Options::default()
+        };
+        let found: Vec<String> = check_with(&design, &options)
+            .iter()
+            .map(|diagnostic| diagnostic.to_string())
+            .collect();
+        assert_eq!(
+            found,
+            vec!["error: `aclk` is the clock input of the module and cannot also be declared"]
+        );
+        assert_eq!(
+            check(&design)[0].message,
+            "`clk` is the clock input of the module and cannot also be declared"
+        );
+
+        // A declaration without a reset takes the configured one.
+        let design =
+            parse("arst => input;\nclock => aclk;\n.idle : state { then => idle; }").unwrap();
+        let options = crate::Options {
+            reset: "arst".to_string(),
+            ..crate::Options::default()
+        };
+        assert_eq!(
+            check_with(&design, &options)[0].message,
+            "`arst` is the reset input of the module and cannot also be declared"
+        );
+        assert!(check(&design).is_empty());
+
+        assert_eq!(
+            messages("clock => always reset begin;\n.idle : state { then => idle; }"),
+            vec![
+                "error: `always` is a Verilog keyword and cannot name the clock input",
+                "error: `begin` is a Verilog keyword and cannot name the reset input",
+            ]
+        );
+        assert!(messages("clock => input;\n.idle : state { then => idle; }").is_empty());
+
+        let found = messages(
+            "
+go => input async;
+count => reg[3:0];
+clock => clk_a enable ce;
+clock bus => clk_b negedge reset rst_b;
+.idle : state {
+    count => count + go;
+    then => idle;
+}
+machine => bus;
+.wait : state {
+    then => wait;
+}
+",
+        );
+        assert!(found.is_empty());
+    }
+
+    #[test]
+    fn stream_test() {
+        let found = messages(
+            "
+rx_data => reg[7:0];
+rx => stream in[7:0];
+.idle : state {
+    rx_valid => 1;
+    rx_ready => rx_data;
+    then => idle;
+}
+",
+        );
+        assert_eq!(
+            found,
+            vec![
+                "error: `rx_data` clashes with the port generated for stream `rx`",
+                "error: `rx_valid` is an input and cannot be written (in node `idle`)",
+            ]
+        );
+        let found = messages(
+            "
+rx => stream in[7:0];
+tx => stream out[7:0];
+.idle : state {
+    rx_ready => 1;
+    tx_valid => rx_valid;
+    tx_data => rx_data;
+    then => idle;
+}
+",
+        );
+        assert!(found.is_empty());
+    }
+
+    #[test]
+    fn loop_and_reachability_test() {
+        let found = messages(
+            "
+r0 => reg;
+.main : state {
+    then => c;
+}
+.c : conditional {
+    then => c;
+}
+",
+        );
+        assert_eq!(found.len(), 1);
+        assert!(found[0].contains("never reaches a state"));
+
+        let found = messages(
+            "
+.main : state {
+    then => main;
+}
+.lost : state {
+    then => main;
+}
+",
+        );
+        assert_eq!(
+            found,
+            vec!["warning: state is unreachable from reset (in node `lost`)"]
+        );
+    }
+}
@@ -0,0 +1,182 @@
+use crate::command::{Command, Edge};
+use crate::Design;
+
+/// The clock, reset and clock enable a machine runs on.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Domain<'d> {
+    pub clock: &'d str,
+    pub edge: Edge,
+    pub reset: &'d str,
+    pub enable: Option<&'d str>,
+}
+
+/// Where machines without a `clock` declaration run.
+pub const DEFAULT: Domain<'static> = Domain {
+    clock: "clk",
+    edge: Edge::Posedge,
+    reset: "reset",
+    enable: None,
+};
+
+impl<'d> Domain<'d> {
+    /// `posedge clk`, what the domain's clocked blocks wait for.
+    pub fn event(&self) -> String {
+        format!("{} {}", self.edge, self.clock)
+    }
+
+    /// The clock as a submodule that updates on rising edges sees it.
+    pub fn rising(&self) -> String {
+        match self.edge {
+            Edge::Posedge => self.clock.to_string(),
+            Edge::Negedge => format!("~{}", self.clock),
+        }
+    }
+
+    /// Whether registers of both domains update on the same edges, and so
+    /// can share a clocked block.
+    pub fn same_clock(&self, other: &Domain) -> bool {
+        self.clock == other.clock && self.edge == other.edge
+    }
+}
+
+/// What an input the clocking adds to a module is for.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Role {
+    Clock,
+    Reset,
+    Enable,
+}
+
+impl Role {
+    pub fn name(self) -> &'static str {
+        match self {
+            Role::Clock => "clock",
+            Role::Reset => "reset",
+            Role::Enable => "clock enable",
+        }
+    }
+}
+
+/// The domain of `machine`, `""` being the unnamed one.
+pub fn domain<'d>(commands: &'d [Command], machine: &str) -> Domain<'d> {
+    commands
+        .iter()
+        .find_map(|cmd| match cmd {
+            Command::Clock {
+                machine: clocked,
+                clock_name,
+                edge,
+                reset,
+                enable,
+            } if clocked == machine => Some(Domain {
+                clock: clock_name,
+                edge: *edge,
+                reset: match reset.as_str() {
+                    "" => DEFAULT.reset,
+                    reset => reset,
+                },
+                enable: enable.as_deref(),
+            }),
+            _ => None,
+        })
+        .unwrap_or(DEFAULT)
+}
+
+/// Inputs the domains of `machines` add to the module, each once: the
+/// clocks, then the resets, then the clock enables.
+pub fn ports<'d>(commands: &'d [Command], machines: &[&str]) -> Vec<(&'d str, Role)> {
+    let domains: Vec<Domain> = match machines {
+        [] => vec![domain(commands, "")],
+        machines => machines
+            .iter()
+            .map(|machine| domain(commands, machine))
+            .collect(),
+    };
+    let mut out: Vec<(&str, Role)> = vec![];
+    for role in [Role::Clock, Role::Reset, Role::Enable] {
+        for domain in domains.iter() {
+            let name = match role {
+                Role::Clock => Some(domain.clock),
+                Role::Reset => Some(domain.reset),
+                Role::Enable => domain.enable,
+            };
+            match name {
+                Some(name) if !out.iter().any(|(known, _)| *known == name) => {
+                    out.push((name, role))
+                }
+                _ => {}
+            }
+        }
+    }
+    out
+}
+
+/// `design` with machines that have no `clock` declaration clocked by
+/// `clock` and reset by `reset`, as are those whose declaration names no
+/// reset.
+pub fn with_defaults(design: &Design, clock: &str, reset: &str) -> Design {
+    let mut out = design.clone();
+    for cmd in out.commands.iter_mut() {
+        if let Command::Clock { reset: named, .. } = cmd {
+            if named.is_empty() {
+                *named = reset.to_string();
+            }
+        }
+    }
+    if clock == DEFAULT.clock && reset == DEFAULT.reset {
+        return out;
+    }
+    let mut machines = design.machines();
+    if machines.is_empty() {
+        machines.push("");
+    }
+    for machine in machines {
+        let declared = design.commands.iter().any(
+            |cmd| matches!(cmd, Command::Clock { machine: clocked, .. } if clocked == machine),
+        );
+        if !declared {
+            out.commands.push(Command::Clock {
+                machine: machine.to_string(),
+                clock_name: clock.to_string(),
+                edge: Edge::Posedge,
+                reset: reset.to_string(),
+                enable: None,
+            });
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse;
+
+    #[test]
+    fn ports_test() {
+        let design = parse(
+            "
+clock bus => clk_b negedge enable ce;
+.idle : state { then => idle; }
+machine => bus;
+.wait : state { then => wait; }
+",
+        )
+        .unwrap();
+        let machines = design.machines();
+        assert_eq!(domain(&design.commands, ""), DEFAULT);
+        let bus = domain(&design.commands, "bus");
+        assert_eq!(bus.event(), "negedge clk_b");
+        assert_eq!(bus.rising(), "~clk_b");
+        assert!(!bus.same_clock(&DEFAULT));
+        assert_eq!(
+            ports(&design.commands, &machines),
+            vec![
+                ("clk", Role::Clock),
+                ("clk_b", Role::Clock),
+                ("reset", Role::Reset),
+                ("ce", Role::Enable),
+            ]
+        );
+    }
+}
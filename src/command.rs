@@ -1,50 +1,317 @@
+use crate::node::Node;
 use regex::Regex;
-use std::{fmt::Display, fs::read_to_string, ops::Range, path::Path, str::FromStr};
-#[derive(Debug, PartialEq)]
+use serde::{Deserialize, Serialize};
+use std::{ops::Range, str::FromStr};
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
 pub enum Command {
     Input {
+        #[serde(rename = "name")]
         pin_name: String,
+        #[serde(with = "crate::json::range")]
         bits: Range<u8>,
+        #[serde(with = "crate::json::range")]
         array: Range<u8>,
+        /// `op => input Opcode;`: holds the members of that enum, and
+        /// `bits` is sized to fit them.
+        #[serde(rename = "enum", default, skip_serializing_if = "Option::is_none")]
+        enum_name: Option<String>,
+        /// `req => input async;`: the pin changes independently of the
+        /// clock, so the chart reads it through a 2-flop synchronizer.
+        #[serde(rename = "async", default, skip_serializing_if = "std::ops::Not::not")]
+        asynchronous: bool,
     },
     Output {
+        #[serde(rename = "name")]
         pin_name: String,
+        #[serde(with = "crate::json::range")]
         bits: Range<u8>,
+        #[serde(with = "crate::json::range")]
         array: Range<u8>,
+        /// `op => output Opcode;`: holds the members of that enum, and
+        /// `bits` is sized to fit them.
+        #[serde(rename = "enum", default, skip_serializing_if = "Option::is_none")]
+        enum_name: Option<String>,
     },
     Inout {
+        #[serde(rename = "name")]
         pin_name: String,
+        #[serde(with = "crate::json::range")]
         bits: Range<u8>,
+        #[serde(with = "crate::json::range")]
         array: Range<u8>,
+        /// `data => inout[7:0] registered;`: the chart reads the pin as
+        /// sampled at the last clock edge instead of its current level.
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        registered: bool,
     },
     Register {
+        #[serde(rename = "name")]
         reg_name: String,
+        #[serde(with = "crate::json::range")]
         bits: Range<u8>,
+        #[serde(with = "crate::json::range")]
         array: Range<u8>,
+        /// `op => reg Opcode;`: holds the members of that enum, and
+        /// `bits` is sized to fit them.
+        #[serde(rename = "enum", default, skip_serializing_if = "Option::is_none")]
+        enum_name: Option<String>,
     },
+    /// `sum => wire[8:0] = r1 + r2;`: a name for an expression, recomputed
+    /// continuously rather than stored.
+    Wire {
+        #[serde(rename = "name")]
+        wire_name: String,
+        #[serde(with = "crate::json::range")]
+        bits: Range<u8>,
+        value: String,
+    },
+    /// `mem => memory[7:0] depth 256 ports 2 latency 1 init "mem.hex";`:
+    /// a memory built as block RAM, written and read one word at a time
+    /// through its ports. Reads take `latency` cycles longer than reads of
+    /// a register.
+    Memory {
+        #[serde(rename = "name")]
+        mem_name: String,
+        #[serde(with = "crate::json::range")]
+        bits: Range<u8>,
+        depth: u32,
+        ports: u8,
+        latency: u8,
+        /// `$readmemh` file holding the initial contents.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        init: Option<String>,
+    },
+    /// `q => fifo[8] depth 16;`: a first-in first-out queue of `depth`
+    /// words, changed by `q.push(data)` and `q.pop()` and read through
+    /// `q.data`, `q.empty` and `q.full`.
+    Fifo {
+        #[serde(rename = "name")]
+        fifo_name: String,
+        width: u8,
+        depth: u32,
+    },
+    /// `t => counter[10];`: counts up every cycle, read through `t.value`
+    /// and cleared by `t.clear()`.
+    Counter {
+        #[serde(rename = "name")]
+        counter_name: String,
+        width: u8,
+    },
+    /// `rx => stream in[7:0];`: a valid/ready/data bundle of ports named
+    /// `rx_valid`, `rx_ready` and `rx_data`. `valid` and `data` flow the
+    /// stream's way and `ready` flows back.
+    Stream {
+        #[serde(rename = "name")]
+        stream_name: String,
+        flow: Flow,
+        #[serde(with = "crate::json::range")]
+        bits: Range<u8>,
+    },
+    /// `q.push(data);`, `q.pop();` or `t.clear();` in a node: asks a fifo
+    /// or counter to act on the next clock edge.
+    Action {
+        name: String,
+        action: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        value: Option<String>,
+    },
+    #[serde(rename = "transfer")]
     RegisterTransfer {
+        #[serde(rename = "target")]
         reg_name: String,
+        #[serde(rename = "value")]
         reg_value: String,
     },
     Then {
+        #[serde(rename = "next")]
         next_node: String,
     },
     Check {
         check: String,
     },
     Yes {
+        #[serde(rename = "next")]
         next_node: String,
     },
     No {
+        #[serde(rename = "next")]
         next_node: String,
     },
+    /// `m => instance multiplier;`, a copy of another chart's module whose
+    /// ports are referenced as `m.start`, `m.ready`.
+    Instance {
+        #[serde(rename = "name")]
+        instance_name: String,
+        #[serde(rename = "module")]
+        module_name: String,
+    },
+    /// `module => name;`, naming the generated module.
+    Module {
+        #[serde(rename = "name")]
+        module_name: String,
+    },
+    /// `enum Opcode { ADD, SUB, JMP }`: names for the values 0, 1, 2 that
+    /// checks and transfers can use, and a type for declarations.
+    Enum {
+        name: String,
+        members: Vec<String>,
+    },
+    /// `machine => bus;` between nodes: the nodes that follow belong to the
+    /// machine `bus`, which runs alongside the others with its own state.
+    /// The parser records it on the nodes, so it never reaches a design.
+    #[serde(skip)]
+    Machine {
+        machine_name: String,
+    },
+    /// `clock bus => clk_b negedge reset rst_b enable ce;`: the inputs the
+    /// machine `bus` is clocked, reset and enabled by, and the clock edge
+    /// it runs on. A plain `clock => ...;` is for the unnamed machine.
+    /// Machines without one run on `posedge clk` and `reset`, or the
+    /// configured clock and reset.
+    Clock {
+        #[serde(default, skip_serializing_if = "String::is_empty")]
+        machine: String,
+        #[serde(rename = "name")]
+        clock_name: String,
+        #[serde(default)]
+        edge: Edge,
+        /// Empty for the configured reset.
+        #[serde(default, skip_serializing_if = "String::is_empty")]
+        reset: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        enable: Option<String>,
+    },
+    /// `priority => main, bus;`: when several machines write the same
+    /// register in one cycle, the one listed first wins.
+    Priority {
+        machines: Vec<String>,
+    },
+    /// `drive => data;`: the design drives the inout `data` from the next
+    /// cycle on, until a `release`.
+    Drive {
+        #[serde(rename = "name")]
+        pin_name: String,
+    },
+    /// `release => data;`: the design stops driving the inout `data`.
+    Release {
+        #[serde(rename = "name")]
+        pin_name: String,
+    },
+    /// `call => send_byte;` next to a `then`: runs the subroutine starting
+    /// at `send_byte` and resumes at the `then` state once it returns.
+    Call {
+        #[serde(rename = "node")]
+        node_name: String,
+    },
+    /// `return;` in place of a `then`, back to the state after the call.
+    Return,
+    /// `call_depth => 2;`: how many calls may be pending at once.
+    CallDepth {
+        depth: u8,
+    },
+    /// `wait => 100;` in a state: stay for that many cycles before the
+    /// `then`.
+    Wait {
+        cycles: u32,
+    },
+    /// `wait_until => ack timeout 50 => error;` in a state: stay until
+    /// `check` holds, then take the `then`, or give up after `timeout`.
+    WaitUntil {
+        check: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        timeout: Option<Timeout>,
+    },
+    /// `include "common.asmc";`: the declarations and nodes of that file
+    /// become part of this chart. Resolved by [`crate::load`].
+    Include {
+        path: String,
+    },
+    /// `import "uart_tx.asmc" as tx;`: that chart becomes the module `tx`
+    /// for `instance` declarations. Resolved by [`crate::load`].
+    Import {
+        path: String,
+        name: String,
+    },
+    /// `template handshake(req, ack, next) { .raise : state {...} ... }`:
+    /// nodes that `use` copies with the parameters replaced.
+    Template {
+        name: String,
+        params: Vec<String>,
+        nodes: Vec<Node>,
+    },
+    /// `use handshake(start, done, idle) as send;`: a copy of the template's
+    /// nodes named `send_raise` and so on. Expanded by [`crate::load`].
+    Use {
+        template: String,
+        args: Vec<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        prefix: Option<String>,
+    },
+    #[serde(skip)]
     Empty,
 }
-#[derive(Debug, PartialEq)]
+
+/// The edge of its clock a machine's registers update on.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Edge {
+    #[default]
+    Posedge,
+    Negedge,
+}
+
+impl std::fmt::Display for Edge {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Edge::Posedge => write!(f, "posedge"),
+            Edge::Negedge => write!(f, "negedge"),
+        }
+    }
+}
+
+/// Which way the data of a `stream` flows, seen from the module.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Flow {
+    In,
+    Out,
+}
+
+impl std::fmt::Display for Flow {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Flow::In => write!(f, "in"),
+            Flow::Out => write!(f, "out"),
+        }
+    }
+}
+
+/// The `timeout 50 => error` of a `wait_until`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Timeout {
+    pub cycles: u32,
+    #[serde(rename = "next")]
+    pub next_node: String,
+}
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum UnableToParseError {
     InvalidFormat,
     InvalidRange,
     CircularDependency,
+    UndefinedNode,
+    InvalidExpression,
+}
+impl std::fmt::Display for UnableToParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UnableToParseError::InvalidFormat => write!(f, "invalid format"),
+            UnableToParseError::InvalidRange => write!(f, "invalid range"),
+            UnableToParseError::CircularDependency => write!(f, "circular dependency"),
+            UnableToParseError::UndefinedNode => write!(f, "undefined node"),
+            UnableToParseError::InvalidExpression => write!(f, "invalid expression"),
+        }
+    }
 }
 impl FromStr for Command {
     type Err = UnableToParseError;
@@ -53,6 +320,77 @@ impl FromStr for Command {
         if s.trim().is_empty() {
             return Ok(Command::Empty);
         }
+        if s.trim() == "return" {
+            return Ok(Command::Return);
+        }
+        if let Some(capt) = USE.captures(s.trim()) {
+            return Ok(Command::Use {
+                template: capt[1].to_string(),
+                args: split_list(&capt[2]),
+                prefix: capt.get(3).map(|prefix| prefix.as_str().to_string()),
+            });
+        }
+        if let Some(capt) = ENUM.captures(s.trim()) {
+            return Ok(Command::Enum {
+                name: capt[1].to_string(),
+                members: split_list(&capt[2]),
+            });
+        }
+        if let Some(capt) = INCLUDE.captures(s.trim()) {
+            return Ok(Command::Include {
+                path: capt[1].to_string(),
+            });
+        }
+        if let Some(capt) = IMPORT.captures(s.trim()) {
+            return Ok(Command::Import {
+                path: capt[1].to_string(),
+                name: capt[2].to_string(),
+            });
+        }
+        if let Some(capt) = ACTION.captures(s.trim()) {
+            let value = capt[3].trim();
+            return Ok(Command::Action {
+                name: capt[1].to_string(),
+                action: capt[2].to_string(),
+                value: (!value.is_empty()).then(|| value.to_string()),
+            });
+        }
+        if let Some(("wait_until", rest)) = s.split_once("=>").map(|(lhs, rest)| (lhs.trim(), rest))
+        {
+            return Ok(match WAIT_TIMEOUT.captures(rest.trim()) {
+                Some(capt) => Command::WaitUntil {
+                    check: capt[1].trim().to_string(),
+                    timeout: Some(Timeout {
+                        cycles: capt[2]
+                            .parse()
+                            .map_err(|_| UnableToParseError::InvalidFormat)?,
+                        next_node: capt[3].to_string(),
+                    }),
+                },
+                None => Command::WaitUntil {
+                    check: rest.trim().to_string(),
+                    timeout: None,
+                },
+            });
+        }
+        if let Some((lhs, rhs)) = s.split_once("=>") {
+            // `clock => reg;` declares a register named `clock`.
+            let clock = CLOCK
+                .captures(rhs.trim())
+                .filter(|capt| !DECLARATION_KINDS.contains(&&capt[1]));
+            if let (Some(of), Some(capt)) = (CLOCK_OF.captures(lhs.trim()), clock) {
+                return Ok(Command::Clock {
+                    machine: of.get(1).map_or("", |machine| machine.as_str()).to_string(),
+                    clock_name: capt[1].to_string(),
+                    edge: match capt.get(2).map(|edge| edge.as_str()) {
+                        Some("negedge") => Edge::Negedge,
+                        _ => Edge::Posedge,
+                    },
+                    reset: capt.get(3).map_or("", |reset| reset.as_str()).to_string(),
+                    enable: capt.get(4).map(|enable| enable.as_str().to_string()),
+                });
+            }
+        }
         let mut parts = s.split("=>");
 
         if let (Some(lhs), Some(rhs)) = (parts.next(), parts.next()) {
@@ -69,30 +407,197 @@ impl FromStr for Command {
                 "check" => Ok(Self::Check {
                     check: rhs.trim().to_string(),
                 }),
+                "module" => Ok(Self::Module {
+                    module_name: rhs.trim().to_string(),
+                }),
+                "machine" => Ok(Self::Machine {
+                    machine_name: rhs.trim().to_string(),
+                }),
+                "call" => Ok(Self::Call {
+                    node_name: rhs.trim().to_string(),
+                }),
+                "drive" => Ok(Self::Drive {
+                    pin_name: rhs.trim().to_string(),
+                }),
+                "release" => Ok(Self::Release {
+                    pin_name: rhs.trim().to_string(),
+                }),
+                "wait" => Ok(Self::Wait {
+                    cycles: rhs
+                        .trim()
+                        .parse()
+                        .map_err(|_| UnableToParseError::InvalidFormat)?,
+                }),
+                "call_depth" => Ok(Self::CallDepth {
+                    depth: rhs
+                        .trim()
+                        .parse()
+                        .map_err(|_| UnableToParseError::InvalidFormat)?,
+                }),
+                "priority" => Ok(Self::Priority {
+                    machines: split_list(rhs),
+                }),
                 _ => {
-                    if SINGLE_BIT_INPUT.is_match(rhs.trim()) {
+                    if let Some(capt) = WIRE.captures(rhs.trim()) {
+                        let bits = match (capt.get(1), capt.get(2)) {
+                            (Some(l), Some(r)) => match (l.as_str().parse(), r.as_str().parse()) {
+                                (Ok(l), Ok(r)) => l..r,
+                                _ => return Err(UnableToParseError::InvalidRange),
+                            },
+                            _ => 0..0,
+                        };
+                        Ok(Self::Wire {
+                            wire_name: lhs.trim().to_string(),
+                            bits,
+                            value: capt[3].trim().to_string(),
+                        })
+                    } else if let Some(capt) = MEMORY.captures(rhs.trim()) {
+                        let number = |idx: usize, default: u32| match capt.get(idx) {
+                            Some(text) => text
+                                .as_str()
+                                .parse()
+                                .map_err(|_| UnableToParseError::InvalidFormat),
+                            None => Ok(default),
+                        };
+                        let bits = match (capt.get(1), capt.get(2)) {
+                            (Some(l), Some(r)) => match (l.as_str().parse(), r.as_str().parse()) {
+                                (Ok(l), Ok(r)) => l..r,
+                                _ => return Err(UnableToParseError::InvalidRange),
+                            },
+                            _ => 0..0,
+                        };
+                        Ok(Self::Memory {
+                            mem_name: lhs.trim().to_string(),
+                            bits,
+                            depth: number(3, 0)?,
+                            ports: u8::try_from(number(4, 1)?)
+                                .map_err(|_| UnableToParseError::InvalidFormat)?,
+                            latency: u8::try_from(number(5, 1)?)
+                                .map_err(|_| UnableToParseError::InvalidFormat)?,
+                            init: capt.get(6).map(|path| path.as_str().to_string()),
+                        })
+                    } else if let Some(capt) = FIFO.captures(rhs.trim()) {
+                        Ok(Self::Fifo {
+                            fifo_name: lhs.trim().to_string(),
+                            width: capt[1]
+                                .parse()
+                                .map_err(|_| UnableToParseError::InvalidRange)?,
+                            depth: capt[2]
+                                .parse()
+                                .map_err(|_| UnableToParseError::InvalidFormat)?,
+                        })
+                    } else if let Some(capt) = STREAM.captures(rhs.trim()) {
+                        let bits = match (capt.get(2), capt.get(3)) {
+                            (Some(l), Some(r)) => match (l.as_str().parse(), r.as_str().parse()) {
+                                (Ok(l), Ok(r)) => l..r,
+                                _ => return Err(UnableToParseError::InvalidRange),
+                            },
+                            _ => 0..0,
+                        };
+                        Ok(Self::Stream {
+                            stream_name: lhs.trim().to_string(),
+                            flow: match &capt[1] {
+                                "in" => Flow::In,
+                                _ => Flow::Out,
+                            },
+                            bits,
+                        })
+                    } else if let Some(capt) = COUNTER.captures(rhs.trim()) {
+                        Ok(Self::Counter {
+                            counter_name: lhs.trim().to_string(),
+                            width: capt[1]
+                                .parse()
+                                .map_err(|_| UnableToParseError::InvalidRange)?,
+                        })
+                    } else if let Some(capt) = ASYNC.captures(rhs.trim()) {
+                        match format!("{} => {}", lhs, &capt[1]).parse()? {
+                            Self::Input {
+                                pin_name,
+                                bits,
+                                array,
+                                enum_name,
+                                ..
+                            } => Ok(Self::Input {
+                                pin_name,
+                                bits,
+                                array,
+                                enum_name,
+                                asynchronous: true,
+                            }),
+                            _ => Err(UnableToParseError::InvalidFormat),
+                        }
+                    } else if let Some(capt) = REGISTERED.captures(rhs.trim()) {
+                        match format!("{} => {}", lhs, &capt[1]).parse()? {
+                            Self::Inout {
+                                pin_name,
+                                bits,
+                                array,
+                                ..
+                            } => Ok(Self::Inout {
+                                pin_name,
+                                bits,
+                                array,
+                                registered: true,
+                            }),
+                            _ => Err(UnableToParseError::InvalidFormat),
+                        }
+                    } else if let Some(capt) = INSTANCE.captures(rhs.trim()) {
+                        Ok(Self::Instance {
+                            instance_name: lhs.trim().to_string(),
+                            module_name: capt.get(1).unwrap().as_str().to_string(),
+                        })
+                    } else if let Some(capt) = TYPED.captures(rhs.trim()) {
+                        // Sized once the enum is known, see `crate::enums`.
+                        let (name, enum_name) = (lhs.trim().to_string(), Some(capt[2].to_string()));
+                        Ok(match &capt[1] {
+                            "input" => Self::Input {
+                                pin_name: name,
+                                bits: 0..0,
+                                array: 0..0,
+                                enum_name,
+                                asynchronous: false,
+                            },
+                            "output" => Self::Output {
+                                pin_name: name,
+                                bits: 0..0,
+                                array: 0..0,
+                                enum_name,
+                            },
+                            _ => Self::Register {
+                                reg_name: name,
+                                bits: 0..0,
+                                array: 0..0,
+                                enum_name,
+                            },
+                        })
+                    } else if SINGLE_BIT_INPUT.is_match(rhs.trim()) {
                         Ok(Self::Input {
                             pin_name: lhs.trim().to_string(),
                             bits: 0..0,
                             array: 0..0,
+                            enum_name: None,
+                            asynchronous: false,
                         })
                     } else if SINGLE_BIT_OUTPUT.is_match(rhs.trim()) {
                         Ok(Self::Output {
                             pin_name: lhs.trim().to_string(),
                             bits: 0..0,
                             array: 0..0,
+                            enum_name: None,
                         })
                     } else if SINGLE_BIT_INOUT.is_match(rhs.trim()) {
                         Ok(Self::Inout {
                             pin_name: lhs.trim().to_string(),
                             bits: 0..0,
                             array: 0..0,
+                            registered: false,
                         })
                     } else if SINGLE_BIT_REG.is_match(rhs.trim()) {
                         Ok(Self::Register {
                             reg_name: lhs.trim().to_string(),
                             bits: 0..0,
                             array: 0..0,
+                            enum_name: None,
                         })
                     } else if let Some(capt) = MULTI_BIT_INPUT.captures(rhs.trim()) {
                         if let (Ok(l), Ok(r)) = (
@@ -103,6 +608,8 @@ impl FromStr for Command {
                                 pin_name: lhs.trim().to_string(),
                                 bits: l..r,
                                 array: 0..0,
+                                enum_name: None,
+                                asynchronous: false,
                             })
                         } else {
                             Err(UnableToParseError::InvalidRange)
@@ -116,6 +623,7 @@ impl FromStr for Command {
                                 pin_name: lhs.trim().to_string(),
                                 bits: l..r,
                                 array: 0..0,
+                                enum_name: None,
                             })
                         } else {
                             Err(UnableToParseError::InvalidRange)
@@ -129,6 +637,7 @@ impl FromStr for Command {
                                 pin_name: lhs.trim().to_string(),
                                 bits: l..r,
                                 array: 0..0,
+                                registered: false,
                             })
                         } else {
                             Err(UnableToParseError::InvalidRange)
@@ -142,6 +651,7 @@ impl FromStr for Command {
                                 reg_name: lhs.trim().to_string(),
                                 bits: l..r,
                                 array: 0..0,
+                                enum_name: None,
                             })
                         } else {
                             Err(UnableToParseError::InvalidRange)
@@ -157,6 +667,8 @@ impl FromStr for Command {
                                 pin_name: lhs.trim().to_string(),
                                 bits: l2..r2,
                                 array: l1..r1,
+                                enum_name: None,
+                                asynchronous: false,
                             })
                         } else {
                             Err(UnableToParseError::InvalidRange)
@@ -172,6 +684,7 @@ impl FromStr for Command {
                                 pin_name: lhs.trim().to_string(),
                                 bits: l2..r2,
                                 array: l1..r1,
+                                enum_name: None,
                             })
                         } else {
                             Err(UnableToParseError::InvalidRange)
@@ -187,6 +700,7 @@ impl FromStr for Command {
                                 pin_name: lhs.trim().to_string(),
                                 bits: l2..r2,
                                 array: l1..r1,
+                                registered: false,
                             })
                         } else {
                             Err(UnableToParseError::InvalidRange)
@@ -202,6 +716,7 @@ impl FromStr for Command {
                                 reg_name: lhs.trim().to_string(),
                                 bits: l2..r2,
                                 array: l1..r1,
+                                enum_name: None,
                             })
                         } else {
                             Err(UnableToParseError::InvalidRange)
@@ -219,7 +734,262 @@ impl FromStr for Command {
         }
     }
 }
+fn fmt_declaration(
+    f: &mut std::fmt::Formatter<'_>,
+    name: &str,
+    kind: &str,
+    bits: &Range<u8>,
+    array: &Range<u8>,
+) -> std::fmt::Result {
+    write!(f, "{} => {}", name, kind)?;
+    if array.start != array.end || array.start != 0 {
+        write!(f, "[{}:{}]", array.start, array.end)?;
+    } else if bits.start == bits.end && bits.start == 0 {
+        return Ok(());
+    }
+    write!(f, "[{}:{}]", bits.start, bits.end)
+}
+/// Writes the command the way it is spelled in `.asmc` source, without the
+/// trailing `;`.
+impl std::fmt::Display for Command {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Command::Input {
+                pin_name,
+                bits,
+                array,
+                enum_name,
+                asynchronous,
+            } => {
+                match enum_name {
+                    Some(enum_name) => write!(f, "{} => input {}", pin_name, enum_name)?,
+                    None => fmt_declaration(f, pin_name, "input", bits, array)?,
+                }
+                match asynchronous {
+                    true => write!(f, " async"),
+                    false => Ok(()),
+                }
+            }
+            Command::Output {
+                pin_name,
+                enum_name: Some(enum_name),
+                ..
+            } => write!(f, "{} => output {}", pin_name, enum_name),
+            Command::Output {
+                pin_name,
+                bits,
+                array,
+                ..
+            } => fmt_declaration(f, pin_name, "output", bits, array),
+            Command::Inout {
+                pin_name,
+                bits,
+                array,
+                registered,
+            } => {
+                fmt_declaration(f, pin_name, "inout", bits, array)?;
+                match registered {
+                    true => write!(f, " registered"),
+                    false => Ok(()),
+                }
+            }
+            Command::Register {
+                reg_name,
+                enum_name: Some(enum_name),
+                ..
+            } => write!(f, "{} => reg {}", reg_name, enum_name),
+            Command::Register {
+                reg_name,
+                bits,
+                array,
+                ..
+            } => fmt_declaration(f, reg_name, "reg", bits, array),
+            Command::Wire {
+                wire_name,
+                bits,
+                value,
+            } => {
+                write!(f, "{} => wire", wire_name)?;
+                if bits.start != bits.end || bits.start != 0 {
+                    write!(f, "[{}:{}]", bits.start, bits.end)?;
+                }
+                write!(f, " = {}", value)
+            }
+            Command::Memory {
+                mem_name,
+                bits,
+                depth,
+                ports,
+                latency,
+                init,
+            } => {
+                write!(f, "{} => memory", mem_name)?;
+                if bits.start != bits.end || bits.start != 0 {
+                    write!(f, "[{}:{}]", bits.start, bits.end)?;
+                }
+                write!(f, " depth {}", depth)?;
+                if *ports != 1 {
+                    write!(f, " ports {}", ports)?;
+                }
+                if *latency != 1 {
+                    write!(f, " latency {}", latency)?;
+                }
+                if let Some(init) = init {
+                    write!(f, " init \"{}\"", init)?;
+                }
+                Ok(())
+            }
+            Command::Fifo {
+                fifo_name,
+                width,
+                depth,
+            } => write!(f, "{} => fifo[{}] depth {}", fifo_name, width, depth),
+            Command::Counter {
+                counter_name,
+                width,
+            } => write!(f, "{} => counter[{}]", counter_name, width),
+            Command::Stream {
+                stream_name,
+                flow,
+                bits,
+            } => fmt_declaration(f, stream_name, &format!("stream {}", flow), bits, &(0..0)),
+            Command::Action {
+                name,
+                action,
+                value,
+            } => write!(
+                f,
+                "{}.{}({})",
+                name,
+                action,
+                value.as_deref().unwrap_or_default()
+            ),
+            Command::RegisterTransfer {
+                reg_name,
+                reg_value,
+            } => write!(f, "{} => {}", reg_name, reg_value),
+            Command::Then { next_node } => write!(f, "then => {}", next_node),
+            Command::Check { check } => write!(f, "check => {}", check),
+            Command::Yes { next_node } => write!(f, "yes => {}", next_node),
+            Command::No { next_node } => write!(f, "no => {}", next_node),
+            Command::Instance {
+                instance_name,
+                module_name,
+            } => write!(f, "{} => instance {}", instance_name, module_name),
+            Command::Module { module_name } => write!(f, "module => {}", module_name),
+            Command::Enum { name, members } => {
+                write!(f, "enum {} {{ {} }}", name, members.join(", "))
+            }
+            Command::Machine { machine_name } => write!(f, "machine => {}", machine_name),
+            Command::Clock {
+                machine,
+                clock_name,
+                edge,
+                reset,
+                enable,
+            } => {
+                match machine.as_str() {
+                    "" => write!(f, "clock => {}", clock_name)?,
+                    machine => write!(f, "clock {} => {}", machine, clock_name)?,
+                }
+                if *edge != Edge::Posedge {
+                    write!(f, " {}", edge)?;
+                }
+                if !reset.is_empty() {
+                    write!(f, " reset {}", reset)?;
+                }
+                if let Some(enable) = enable {
+                    write!(f, " enable {}", enable)?;
+                }
+                Ok(())
+            }
+            Command::Priority { machines } => write!(f, "priority => {}", machines.join(", ")),
+            Command::Drive { pin_name } => write!(f, "drive => {}", pin_name),
+            Command::Release { pin_name } => write!(f, "release => {}", pin_name),
+            Command::Call { node_name } => write!(f, "call => {}", node_name),
+            Command::Return => write!(f, "return"),
+            Command::CallDepth { depth } => write!(f, "call_depth => {}", depth),
+            Command::Wait { cycles } => write!(f, "wait => {}", cycles),
+            Command::WaitUntil { check, timeout } => {
+                write!(f, "wait_until => {}", check)?;
+                if let Some(timeout) = timeout {
+                    write!(f, " timeout {} => {}", timeout.cycles, timeout.next_node)?;
+                }
+                Ok(())
+            }
+            Command::Include { path } => write!(f, "include \"{}\"", path),
+            Command::Import { path, name } => write!(f, "import \"{}\" as {}", path, name),
+            Command::Template {
+                name,
+                params,
+                nodes,
+            } => {
+                writeln!(f, "template {}({}) {{", name, params.join(", "))?;
+                for (idx, node) in nodes.iter().enumerate() {
+                    if idx > 0 {
+                        writeln!(f)?;
+                    }
+                    for line in node.to_string().lines() {
+                        writeln!(f, "    {}", line)?;
+                    }
+                }
+                write!(f, "}}")
+            }
+            Command::Use {
+                template,
+                args,
+                prefix,
+            } => {
+                write!(f, "use {}({})", template, args.join(", "))?;
+                if let Some(prefix) = prefix {
+                    write!(f, " as {}", prefix)?;
+                }
+                Ok(())
+            }
+            Command::Empty => Ok(()),
+        }
+    }
+}
+/// `a, b , c` as `["a", "b", "c"]`; empty text is an empty list.
+pub fn split_list(text: &str) -> Vec<String> {
+    if text.trim().is_empty() {
+        return vec![];
+    }
+    text.split(',')
+        .map(|item| item.trim().to_string())
+        .collect()
+}
+/// Right-hand sides that declare a signal rather than name a clock.
+const DECLARATION_KINDS: &[&str] = &[
+    "reg", "input", "output", "inout", "wire", "memory", "fifo", "counter", "stream", "instance",
+];
+
+/// Splits a transfer target such as `mem[address]` into the declared name
+/// and the select that follows it.
+pub fn split_target(reg_name: &str) -> (&str, &str) {
+    match reg_name.find('[') {
+        Some(idx) => (reg_name[..idx].trim(), &reg_name[idx..]),
+        None => (reg_name.trim(), ""),
+    }
+}
 lazy_static::lazy_static! {
+        static ref WAIT_TIMEOUT : Regex = Regex::new(r"^(.+)\s+timeout\s+([0-9]+)\s*=>\s*([a-zA-Z0-9_]+)$").unwrap();
+        static ref USE : Regex = Regex::new(r"^use\s+([a-zA-Z_][a-zA-Z0-9_]*)\s*\(([^()]*)\)(?:\s+as\s+([a-zA-Z_][a-zA-Z0-9_]*))?$").unwrap();
+        static ref INCLUDE : Regex = Regex::new(r#"^include\s+"([^"]+)"$"#).unwrap();
+        static ref IMPORT : Regex = Regex::new(r#"^import\s+"([^"]+)"\s+as\s+([a-zA-Z_][a-zA-Z0-9_]*)$"#).unwrap();
+        static ref WIRE : Regex = Regex::new(r"^wire *(?:\[ *(\d+) *: *(\d+) *\])? *=([^=].*)$").unwrap();
+        static ref TYPED : Regex = Regex::new(r"^(input|output|reg)\s+([a-zA-Z_][a-zA-Z0-9_]*)$").unwrap();
+        static ref ENUM : Regex = Regex::new(r"^enum\s+([a-zA-Z_][a-zA-Z0-9_]*)\s*\{([^{}]*)\}$").unwrap();
+        static ref MEMORY : Regex = Regex::new(r#"^memory *(?:\[ *(\d+) *: *(\d+) *\])?\s+depth\s+(\d+)(?:\s+ports\s+(\d+))?(?:\s+latency\s+(\d+))?(?:\s+init\s+"([^"]+)")?$"#).unwrap();
+        static ref FIFO : Regex = Regex::new(r"^fifo *\[ *(\d+) *\]\s+depth\s+(\d+)$").unwrap();
+        static ref STREAM : Regex = Regex::new(r"^stream\s+(in|out)\s*(?:\[\s*(\d+)\s*:\s*(\d+)\s*\])?$").unwrap();
+        static ref COUNTER : Regex = Regex::new(r"^counter *\[ *(\d+) *\]$").unwrap();
+        static ref ACTION : Regex = Regex::new(r"^([a-zA-Z_][a-zA-Z0-9_]*)\s*\.\s*([a-zA-Z_][a-zA-Z0-9_]*)\s*\((.*)\)$").unwrap();
+        static ref ASYNC : Regex = Regex::new(r"^(input\b.*?)\s+async$").unwrap();
+        static ref CLOCK : Regex = Regex::new(r"^([a-zA-Z_][a-zA-Z0-9_]*)(?:\s+(posedge|negedge))?(?:\s+reset\s+([a-zA-Z_][a-zA-Z0-9_]*))?(?:\s+enable\s+([a-zA-Z_][a-zA-Z0-9_]*))?$").unwrap();
+        static ref CLOCK_OF : Regex = Regex::new(r"^clock(?:\s+([a-zA-Z_][a-zA-Z0-9_]*))?$").unwrap();
+        static ref REGISTERED : Regex = Regex::new(r"^(inout\b.*?)\s+registered$").unwrap();
+        static ref INSTANCE : Regex = Regex::new(r"^instance +([a-zA-Z_][a-zA-Z0-9_]*)$").unwrap();
         static ref SINGLE_BIT_INPUT : Regex = Regex::new(r"^input$").unwrap();
         static ref SINGLE_BIT_OUTPUT : Regex = Regex::new(r"^output$").unwrap();
         static ref SINGLE_BIT_INOUT: Regex  = Regex::new(r"^inout$").unwrap();
@@ -239,6 +1009,7 @@ lazy_static::lazy_static! {
 }
 
 #[cfg(test)]
+#[allow(clippy::assertions_on_constants, clippy::reversed_empty_ranges)]
 mod tests {
     use super::*;
 
@@ -299,6 +1070,317 @@ mod tests {
         }
     }
     #[test]
+    fn module_test() {
+        let cmd = " module =>  multiplier ".parse::<Command>();
+        assert_eq!(
+            cmd,
+            Ok(Command::Module {
+                module_name: "multiplier".to_string()
+            })
+        );
+    }
+    #[test]
+    fn priority_test() {
+        let cmd = " priority =>  main ,bus ".parse::<Command>();
+        assert_eq!(
+            cmd,
+            Ok(Command::Priority {
+                machines: vec!["main".to_string(), "bus".to_string()]
+            })
+        );
+        assert_eq!(cmd.unwrap().to_string(), "priority => main, bus");
+    }
+    #[test]
+    fn call_test() {
+        assert_eq!(
+            " call =>  send_byte ".parse::<Command>(),
+            Ok(Command::Call {
+                node_name: "send_byte".to_string()
+            })
+        );
+        assert_eq!("\n  return ".parse::<Command>(), Ok(Command::Return));
+        assert_eq!(
+            "call_depth => 4".parse::<Command>(),
+            Ok(Command::CallDepth { depth: 4 })
+        );
+        assert!("call_depth => deep".parse::<Command>().is_err());
+    }
+    #[test]
+    fn wait_test() {
+        assert_eq!(
+            " wait =>  100 ".parse::<Command>(),
+            Ok(Command::Wait { cycles: 100 })
+        );
+        let cmd = " wait_until => ack & !busy  timeout 50 =>  error ".parse::<Command>();
+        assert_eq!(
+            cmd,
+            Ok(Command::WaitUntil {
+                check: "ack & !busy".to_string(),
+                timeout: Some(Timeout {
+                    cycles: 50,
+                    next_node: "error".to_string()
+                })
+            })
+        );
+        assert_eq!(
+            cmd.unwrap().to_string(),
+            "wait_until => ack & !busy timeout 50 => error"
+        );
+        assert_eq!(
+            "wait_until => ack".parse::<Command>(),
+            Ok(Command::WaitUntil {
+                check: "ack".to_string(),
+                timeout: None
+            })
+        );
+    }
+    #[test]
+    fn include_test() {
+        let cmd = "\ninclude   \"common ports.asmc\"".parse::<Command>();
+        assert_eq!(
+            cmd,
+            Ok(Command::Include {
+                path: "common ports.asmc".to_string()
+            })
+        );
+        assert_eq!(cmd.unwrap().to_string(), "include \"common ports.asmc\"");
+        let cmd = " import \"lib/uart_tx.asmc\"  as  tx ".parse::<Command>();
+        assert_eq!(
+            cmd,
+            Ok(Command::Import {
+                path: "lib/uart_tx.asmc".to_string(),
+                name: "tx".to_string()
+            })
+        );
+        assert_eq!(
+            cmd.unwrap().to_string(),
+            "import \"lib/uart_tx.asmc\" as tx"
+        );
+        assert!("import \"uart_tx.asmc\"".parse::<Command>().is_err());
+    }
+    #[test]
+    fn use_test() {
+        let cmd = " use  handshake( start,done , idle )  as send ".parse::<Command>();
+        assert_eq!(
+            cmd,
+            Ok(Command::Use {
+                template: "handshake".to_string(),
+                args: vec!["start".to_string(), "done".to_string(), "idle".to_string()],
+                prefix: Some("send".to_string())
+            })
+        );
+        assert_eq!(
+            cmd.unwrap().to_string(),
+            "use handshake(start, done, idle) as send"
+        );
+        assert_eq!(
+            "use blink()".parse::<Command>(),
+            Ok(Command::Use {
+                template: "blink".to_string(),
+                args: vec![],
+                prefix: None
+            })
+        );
+    }
+    #[test]
+    fn wire_test() {
+        let cmd = " sum  =>  wire[ 8:0 ]=r1 + r2 ".parse::<Command>();
+        assert_eq!(
+            cmd,
+            Ok(Command::Wire {
+                wire_name: "sum".to_string(),
+                bits: 8..0,
+                value: "r1 + r2".to_string(),
+            })
+        );
+        assert_eq!(cmd.unwrap().to_string(), "sum => wire[8:0] = r1 + r2");
+        let cmd = "same => wire = a == b".parse::<Command>().unwrap();
+        assert_eq!(cmd.to_string(), "same => wire = a == b");
+        assert_eq!(
+            "wired => wire == 1".parse::<Command>(),
+            Ok(Command::RegisterTransfer {
+                reg_name: "wired".to_string(),
+                reg_value: "wire == 1".to_string(),
+            })
+        );
+    }
+    #[test]
+    fn memory_test() {
+        let cmd = " mem =>  memory[ 7:0 ]  depth 256 ports 2  latency 0 init \"mem.hex\" "
+            .parse::<Command>();
+        assert_eq!(
+            cmd,
+            Ok(Command::Memory {
+                mem_name: "mem".to_string(),
+                bits: 7..0,
+                depth: 256,
+                ports: 2,
+                latency: 0,
+                init: Some("mem.hex".to_string()),
+            })
+        );
+        assert_eq!(
+            cmd.unwrap().to_string(),
+            "mem => memory[7:0] depth 256 ports 2 latency 0 init \"mem.hex\""
+        );
+        let cmd = "flags => memory depth 16".parse::<Command>().unwrap();
+        assert_eq!(
+            cmd,
+            Command::Memory {
+                mem_name: "flags".to_string(),
+                bits: 0..0,
+                depth: 16,
+                ports: 1,
+                latency: 1,
+                init: None,
+            }
+        );
+        assert_eq!(cmd.to_string(), "flags => memory depth 16");
+        assert!("mem => memory[7:0] depth 16 ports 300"
+            .parse::<Command>()
+            .is_err());
+    }
+    #[test]
+    fn clock_test() {
+        let cmd = "clock bus =>  clk_b negedge reset rst_b enable ce"
+            .parse::<Command>()
+            .unwrap();
+        assert_eq!(
+            cmd,
+            Command::Clock {
+                machine: "bus".to_string(),
+                clock_name: "clk_b".to_string(),
+                edge: Edge::Negedge,
+                reset: "rst_b".to_string(),
+                enable: Some("ce".to_string()),
+            }
+        );
+        assert_eq!(
+            cmd.to_string(),
+            "clock bus => clk_b negedge reset rst_b enable ce"
+        );
+        let cmd = "clock => sys_clk".parse::<Command>().unwrap();
+        assert!(matches!(&cmd, Command::Clock { reset, .. } if reset.is_empty()));
+        assert_eq!(cmd.to_string(), "clock => sys_clk");
+        // A signal may still be called `clock`.
+        assert_eq!(
+            "clock => reg".parse::<Command>().unwrap(),
+            Command::Register {
+                reg_name: "clock".to_string(),
+                bits: 0..0,
+                array: 0..0,
+                enum_name: None,
+            }
+        );
+        assert!(matches!(
+            "clock => input".parse::<Command>().unwrap(),
+            Command::Input { pin_name, .. } if pin_name == "clock"
+        ));
+        let cmd = "req => input[3:0] async".parse::<Command>().unwrap();
+        assert_eq!(
+            cmd,
+            Command::Input {
+                pin_name: "req".to_string(),
+                bits: 3..0,
+                array: 0..0,
+                enum_name: None,
+                asynchronous: true,
+            }
+        );
+        assert_eq!(cmd.to_string(), "req => input[3:0] async");
+        assert_eq!(
+            "op => input Opcode async"
+                .parse::<Command>()
+                .unwrap()
+                .to_string(),
+            "op => input Opcode async"
+        );
+    }
+    #[test]
+    fn stream_test() {
+        let cmd = "rx => stream in [ 7 : 0 ]".parse::<Command>().unwrap();
+        assert_eq!(
+            cmd,
+            Command::Stream {
+                stream_name: "rx".to_string(),
+                flow: Flow::In,
+                bits: 7..0,
+            }
+        );
+        assert_eq!(cmd.to_string(), "rx => stream in[7:0]");
+        let cmd = "tx => stream out".parse::<Command>().unwrap();
+        assert_eq!(cmd.to_string(), "tx => stream out");
+    }
+    #[test]
+    fn primitive_test() {
+        let cmd = "q => fifo [ 8 ]  depth 16".parse::<Command>().unwrap();
+        assert_eq!(
+            cmd,
+            Command::Fifo {
+                fifo_name: "q".to_string(),
+                width: 8,
+                depth: 16,
+            }
+        );
+        assert_eq!(cmd.to_string(), "q => fifo[8] depth 16");
+        let cmd = "t => counter[10]".parse::<Command>().unwrap();
+        assert_eq!(cmd.to_string(), "t => counter[10]");
+        let cmd = " q.push( data + 1 ) ".parse::<Command>().unwrap();
+        assert_eq!(
+            cmd,
+            Command::Action {
+                name: "q".to_string(),
+                action: "push".to_string(),
+                value: Some("data + 1".to_string()),
+            }
+        );
+        assert_eq!(cmd.to_string(), "q.push(data + 1)");
+        assert_eq!(
+            "t.clear()".parse::<Command>().unwrap().to_string(),
+            "t.clear()"
+        );
+        assert!("t => counter[300]".parse::<Command>().is_err());
+    }
+    #[test]
+    fn enum_test() {
+        let cmd = "enum  Opcode {ADD,SUB , JMP}".parse::<Command>();
+        assert_eq!(
+            cmd,
+            Ok(Command::Enum {
+                name: "Opcode".to_string(),
+                members: vec!["ADD".to_string(), "SUB".to_string(), "JMP".to_string()],
+            })
+        );
+        assert_eq!(cmd.unwrap().to_string(), "enum Opcode { ADD, SUB, JMP }");
+        let cmd = " op =>  reg   Opcode ".parse::<Command>();
+        assert_eq!(
+            cmd,
+            Ok(Command::Register {
+                reg_name: "op".to_string(),
+                bits: 0..0,
+                array: 0..0,
+                enum_name: Some("Opcode".to_string()),
+            })
+        );
+        assert_eq!(cmd.unwrap().to_string(), "op => reg Opcode");
+        assert_eq!(
+            "op => input Opcode".parse::<Command>().unwrap().to_string(),
+            "op => input Opcode"
+        );
+    }
+    #[test]
+    fn instance_test() {
+        let cmd = " m =>  instance   multiplier ".parse::<Command>();
+        assert_eq!(
+            cmd,
+            Ok(Command::Instance {
+                instance_name: "m".to_string(),
+                module_name: "multiplier".to_string()
+            })
+        );
+        assert_eq!(cmd.unwrap().to_string(), "m => instance multiplier");
+    }
+    #[test]
     fn regtrans_test() {
         let cmd = "  r0  =>   r0 + r1  ".parse::<Command>();
         match cmd {
@@ -332,6 +1414,7 @@ mod tests {
                 reg_name,
                 bits,
                 array,
+                enum_name: None,
             }) => {
                 assert_eq!(reg_name, "r0".to_string());
                 assert_eq!(bits, 0..0);
@@ -346,6 +1429,7 @@ mod tests {
                 reg_name,
                 bits,
                 array,
+                enum_name: None,
             }) => {
                 assert_eq!(reg_name, "r0".to_string());
                 assert_eq!(bits, 3..0);
@@ -360,6 +1444,7 @@ mod tests {
                 reg_name,
                 bits,
                 array,
+                enum_name: None,
             }) => {
                 assert_eq!(reg_name, "r0".to_string());
                 assert_eq!(bits, 1..0);
@@ -377,6 +1462,8 @@ mod tests {
                 pin_name,
                 bits,
                 array,
+                enum_name: None,
+                asynchronous: false,
             }) => {
                 assert_eq!(pin_name, "r0".to_string());
                 assert_eq!(bits, 0..0);
@@ -391,6 +1478,8 @@ mod tests {
                 pin_name,
                 bits,
                 array,
+                enum_name: None,
+                asynchronous: false,
             }) => {
                 assert_eq!(pin_name, "r0".to_string());
                 assert_eq!(bits, 3..0);
@@ -405,6 +1494,8 @@ mod tests {
                 pin_name,
                 bits,
                 array,
+                enum_name: None,
+                asynchronous: false,
             }) => {
                 assert_eq!(pin_name, "r0".to_string());
                 assert_eq!(bits, 1..0);
@@ -421,6 +1512,7 @@ mod tests {
                 pin_name,
                 bits,
                 array,
+                enum_name: None,
             }) => {
                 assert_eq!(pin_name, "r0".to_string());
                 assert_eq!(bits, 0..0);
@@ -435,6 +1527,7 @@ mod tests {
                 pin_name,
                 bits,
                 array,
+                enum_name: None,
             }) => {
                 assert_eq!(pin_name, "r0".to_string());
                 assert_eq!(bits, 3..0);
@@ -449,6 +1542,7 @@ mod tests {
                 pin_name,
                 bits,
                 array,
+                enum_name: None,
             }) => {
                 assert_eq!(pin_name, "r0".to_string());
                 assert_eq!(bits, 1..0);
@@ -465,6 +1559,7 @@ mod tests {
                 pin_name,
                 bits,
                 array,
+                registered: false,
             }) => {
                 assert_eq!(pin_name, "r0".to_string());
                 assert_eq!(bits, 0..0);
@@ -479,6 +1574,7 @@ mod tests {
                 pin_name,
                 bits,
                 array,
+                registered: false,
             }) => {
                 assert_eq!(pin_name, "r0".to_string());
                 assert_eq!(bits, 3..0);
@@ -493,6 +1589,7 @@ mod tests {
                 pin_name,
                 bits,
                 array,
+                registered: false,
             }) => {
                 assert_eq!(pin_name, "r0".to_string());
                 assert_eq!(bits, 1..0);
@@ -500,6 +1597,33 @@ mod tests {
             }
             _ => assert!(false),
         }
+
+        let cmd = " data => inout[7:0]  registered "
+            .parse::<Command>()
+            .unwrap();
+        assert_eq!(
+            cmd,
+            Command::Inout {
+                pin_name: "data".to_string(),
+                bits: 7..0,
+                array: 0..0,
+                registered: true,
+            }
+        );
+        assert_eq!(cmd.to_string(), "data => inout[7:0] registered");
+    }
+    #[test]
+    fn drive_test() {
+        let cmd = "  drive =>  data ".parse::<Command>().unwrap();
+        assert_eq!(
+            cmd,
+            Command::Drive {
+                pin_name: "data".to_string()
+            }
+        );
+        assert_eq!(cmd.to_string(), "drive => data");
+        let cmd = "release => data".parse::<Command>().unwrap();
+        assert_eq!(cmd.to_string(), "release => data");
     }
     #[test]
     fn empty_test() {
@@ -0,0 +1,535 @@
+use crate::clock;
+use crate::command::{Command, UnableToParseError};
+use crate::expr::{BinaryOp, Expr, UnaryOp};
+use crate::model::{self, Dialect, Kind, Lowering, Signal};
+use crate::naming::{self, Names, Naming, CPP_KEYWORDS};
+use crate::node::{Node, NodeType};
+use crate::Diagnostic;
+use std::collections::{HashMap, HashSet};
+
+fn cpp_type(width: u32) -> Result<&'static str, UnableToParseError> {
+    match width {
+        0..=8 => Ok("uint8_t"),
+        9..=16 => Ok("uint16_t"),
+        17..=32 => Ok("uint32_t"),
+        33..=64 => Ok("uint64_t"),
+        _ => Err(UnableToParseError::InvalidRange),
+    }
+}
+
+fn mask(width: u32) -> String {
+    format!("0x{:x}ULL", model::mask(width))
+}
+
+/// Members and methods every model has, besides the chart's names.
+const MODEL_MEMBERS: &[&str] = &["clk", "reset", "eval", "tick", "step"];
+
+/// Fails if the chart uses a name the model cannot declare.
+pub fn spellable(module: &str, commands: &[Command]) -> Result<(), Diagnostic> {
+    let words: Vec<&str> = CPP_KEYWORDS.iter().chain(MODEL_MEMBERS).copied().collect();
+    naming::spellable(module, commands, &words, "the C++ model", false)
+}
+
+/// The C++ spelling of the step: every member is loaded from a local of
+/// `step()` once the chart has run.
+struct Cpp {
+    /// The local of `step()` each member's next value is computed in.
+    next: HashMap<String, String>,
+}
+
+impl Cpp {
+    /// Reads a declared name; wires are computed by a method of their own
+    /// and registered inouts read from their sample.
+    fn read(&self, model: &Lowering, name: &str) -> Result<String, UnableToParseError> {
+        let signal = model.signal(name)?;
+        Ok(match (&signal.kind, &signal.sample) {
+            (Kind::Wire, _) => format!("{}()", name),
+            (_, Some(sample)) => sample.clone(),
+            _ => name.to_string(),
+        })
+    }
+
+    /// Array subscript for `index` into `signal`, wrapped into bounds.
+    fn subscript(
+        &self,
+        model: &Lowering,
+        signal: &Signal,
+        index: &Expr,
+    ) -> Result<String, UnableToParseError> {
+        let (low, count) = signal.extent();
+        let index = self.vector(model, index, model.width(index)?)?;
+        if low == 0 {
+            Ok(format!("({}) % {}", index, count))
+        } else {
+            Ok(format!("({} - {}) % {}", index, low, count))
+        }
+    }
+
+    /// Renders `expr` as an unsigned value wrapped to `width` bits.
+    fn vector(
+        &self,
+        model: &Lowering,
+        expr: &Expr,
+        width: u32,
+    ) -> Result<String, UnableToParseError> {
+        let width = width.min(64);
+        Ok(match expr {
+            Expr::Ident(name) => self.read(model, name)?,
+            Expr::Number { value, .. } => format!("{}ULL", value),
+            Expr::Index(base, index) => {
+                let Expr::Ident(name) = base.as_ref() else {
+                    return Err(UnableToParseError::InvalidExpression);
+                };
+                let signal = model.signal(name)?;
+                if signal.array.is_some() {
+                    format!(
+                        "{}[{}]",
+                        self.read(model, name)?,
+                        self.subscript(model, signal, index)?
+                    )
+                } else {
+                    format!(
+                        "(((uint64_t){} >> {}) & 1ULL)",
+                        self.read(model, name)?,
+                        self.vector(model, index, model.width(index)?)?
+                    )
+                }
+            }
+            Expr::Slice(base, high, low) => format!(
+                "(((uint64_t){} >> {}) & {})",
+                self.vector(model, base, model.width(base)?)?,
+                high.min(low),
+                mask(high.abs_diff(*low) + 1)
+            ),
+            Expr::Unary(UnaryOp::LogicalNot, inner) => {
+                format!("(uint64_t)(!{})", self.condition(model, inner)?)
+            }
+            Expr::Unary(UnaryOp::BitNot, inner) => {
+                format!(
+                    "(~(uint64_t){} & {})",
+                    self.vector(model, inner, width)?,
+                    mask(width)
+                )
+            }
+            Expr::Unary(UnaryOp::Negate, inner) => format!(
+                "((0ULL - (uint64_t){}) & {})",
+                self.vector(model, inner, width)?,
+                mask(width)
+            ),
+            Expr::Binary(op, _, _) if op.is_boolean() => {
+                format!("(uint64_t){}", self.condition(model, expr)?)
+            }
+            Expr::Binary(op @ (BinaryOp::Div | BinaryOp::Mod), lhs, rhs) => {
+                let rhs = self.vector(model, rhs, width)?;
+                format!(
+                    "({} ? (uint64_t){} {} {} : 0ULL)",
+                    rhs,
+                    self.vector(model, lhs, width)?,
+                    op.symbol(),
+                    rhs
+                )
+            }
+            Expr::Binary(BinaryOp::Shr, lhs, rhs) => format!(
+                "((uint64_t){} >> {})",
+                self.vector(model, lhs, width)?,
+                self.vector(model, rhs, model.width(rhs)?)?
+            ),
+            Expr::Binary(BinaryOp::Shl, lhs, rhs) => format!(
+                "(((uint64_t){} << {}) & {})",
+                self.vector(model, lhs, width)?,
+                self.vector(model, rhs, model.width(rhs)?)?,
+                mask(width)
+            ),
+            Expr::Binary(op @ (BinaryOp::Add | BinaryOp::Sub | BinaryOp::Mul), lhs, rhs) => {
+                format!(
+                    "(((uint64_t){} {} {}) & {})",
+                    self.vector(model, lhs, width)?,
+                    op.symbol(),
+                    self.vector(model, rhs, width)?,
+                    mask(width)
+                )
+            }
+            Expr::Binary(op, lhs, rhs) => format!(
+                "({} {} {})",
+                self.vector(model, lhs, width)?,
+                op.symbol(),
+                self.vector(model, rhs, width)?
+            ),
+            Expr::Ternary(cond, yes, no) => format!(
+                "({} ? (uint64_t){} : (uint64_t){})",
+                self.condition(model, cond)?,
+                self.vector(model, yes, width)?,
+                self.vector(model, no, width)?
+            ),
+            Expr::Concat(parts) => {
+                let mut shift = 0;
+                let mut texts = vec![];
+                for part in parts.iter().rev() {
+                    let part_width = model.width(part)?;
+                    texts.push(format!(
+                        "((uint64_t){} << {})",
+                        self.vector(model, part, part_width)?,
+                        shift
+                    ));
+                    shift += part_width;
+                }
+                texts.reverse();
+                format!("({})", texts.join(" | "))
+            }
+        })
+    }
+}
+
+impl Dialect for Cpp {
+    /// Renders `expr` as a C++ `bool`.
+    fn condition(&self, model: &Lowering, expr: &Expr) -> Result<String, UnableToParseError> {
+        Ok(match expr {
+            Expr::Unary(UnaryOp::LogicalNot, inner) => {
+                format!("!{}", self.condition(model, inner)?)
+            }
+            Expr::Binary(op @ (BinaryOp::LogicalAnd | BinaryOp::LogicalOr), lhs, rhs) => format!(
+                "({} {} {})",
+                self.condition(model, lhs)?,
+                op.symbol(),
+                self.condition(model, rhs)?
+            ),
+            Expr::Binary(op, lhs, rhs) if op.is_boolean() => {
+                let width = model.width(lhs)?.max(model.width(rhs)?);
+                format!(
+                    "({} {} {})",
+                    self.vector(model, lhs, width)?,
+                    op.symbol(),
+                    self.vector(model, rhs, width)?
+                )
+            }
+            _ => format!("({} != 0)", self.vector(model, expr, model.width(expr)?)?),
+        })
+    }
+
+    fn branch(&self, condition: &str) -> String {
+        // A negation is the one condition not already in parentheses.
+        if condition.starts_with('!') {
+            format!("if ({}) {{", condition)
+        } else {
+            format!("if {} {{", condition)
+        }
+    }
+
+    fn store(
+        &self,
+        model: &Lowering,
+        name: &str,
+        target: &Expr,
+        value: &Expr,
+    ) -> Result<String, UnableToParseError> {
+        let signal = model.signal(name)?;
+        let next = match &signal.driven {
+            Some((driven, _)) => &self.next[driven],
+            None => &self.next[name],
+        };
+        Ok(match target {
+            Expr::Index(_, index) if signal.array.is_some() => format!(
+                "{}[{}] = {} & {};",
+                next,
+                self.subscript(model, signal, index)?,
+                self.vector(model, value, signal.width)?,
+                mask(signal.width)
+            ),
+            Expr::Index(_, index) => {
+                let bit = self.vector(model, index, model.width(index)?)?;
+                format!(
+                    "{next} = ({next} & ~(1ULL << {bit})) | (({} & 1ULL) << {bit});",
+                    self.vector(model, value, 1)?
+                )
+            }
+            Expr::Slice(_, high, low) => {
+                let field = mask(high.abs_diff(*low) + 1);
+                let low = high.min(low);
+                format!(
+                    "{next} = ({next} & ~({field} << {low})) | (({} & {field}) << {low});",
+                    self.vector(model, value, high.abs_diff(*low) + 1)?
+                )
+            }
+            _ => format!(
+                "{} = {} & {};",
+                next,
+                self.vector(model, value, signal.width)?,
+                mask(signal.width)
+            ),
+        })
+    }
+
+    fn next_state(&self, model: &Lowering, id: u32) -> String {
+        format!("{} = {};", self.next[&model.state_reg], id)
+    }
+
+    fn next_enable(&self, enable: &str, on: bool) -> String {
+        format!("{} = {};", self.next[enable], on as u8)
+    }
+}
+
+/// Emits a header-only C++ class modelling the chart cycle by cycle.
+/// Drive the public inputs, then call `eval()` after changing `clk` or
+/// `reset` the way a Verilator model is driven, or `tick()` for a whole
+/// clock period.
+pub fn emit_cpp(
+    module: &str,
+    commands: &[Command],
+    nodes: &[Node],
+    naming: &Naming,
+) -> Result<String, UnableToParseError> {
+    let mut names = Names::new(naming, commands, &clock::DEFAULT, CPP_KEYWORDS, false);
+    for member in MODEL_MEMBERS.iter() {
+        names.reserve(member);
+    }
+    let mut lowering = Lowering::new(nodes, names);
+    let declared = lowering.declare(commands);
+
+    let mut public = vec![];
+    let mut private = vec![];
+    // Pins under `drive` and `release` hold their enable until a reset.
+    let mut resets = vec![];
+    // Storage updated by `step()`: member name and the value it is loaded
+    // from before the chart runs.
+    let mut storage = vec![];
+    for name in declared.iter() {
+        let signal = &lowering.signals[name];
+        let element = cpp_type(signal.width)?;
+        let ty = match signal.extent() {
+            (_, count) if signal.array.is_some() => format!("std::array<{}, {}>", element, count),
+            _ => element.to_string(),
+        };
+        let declaration = |member: &str| format!("{} {}{{}};", ty, member);
+        match signal.kind {
+            Kind::Input => public.push(declaration(name)),
+            Kind::Output => {
+                public.push(declaration(name));
+                storage.push((name.clone(), name.clone()));
+            }
+            Kind::Register => {
+                private.push(declaration(name));
+                storage.push((name.clone(), name.clone()));
+            }
+            Kind::Wire => {}
+            Kind::Inout => {
+                let (driven, enable) = signal.driven.clone().unwrap();
+                public.push(format!("// `{}` is the value on the pin, `{}` and `{}` what the design drives onto it.", name, driven, enable));
+                public.push(declaration(name));
+                public.push(declaration(&driven));
+                public.push(format!("uint8_t {}{{}};", enable));
+                storage.push((driven.clone(), driven));
+                if lowering.controlled.contains(name.as_str()) {
+                    resets.push(format!("{} = 0;", enable));
+                    storage.push((enable.clone(), enable));
+                } else {
+                    storage.push((enable, "0".to_string()));
+                }
+                if let Some(sample) = &signal.sample {
+                    private.push(declaration(sample));
+                    storage.push((sample.clone(), name.clone()));
+                }
+            }
+        }
+    }
+
+    // Wires read the current values, so they are methods rather than
+    // storage; a wire may read any signal, including a later wire.
+    let mut cpp = Cpp {
+        next: HashMap::new(),
+    };
+    let mut wires = vec![];
+    for cmd in commands.iter() {
+        if let Command::Wire {
+            wire_name,
+            bits,
+            value,
+        } = cmd
+        {
+            let width = model::width_of(bits);
+            wires.push(format!(
+                "{} {}() const {{\n        return {} & {};\n    }}",
+                cpp_type(width)?,
+                wire_name,
+                cpp.vector(&lowering, &value.parse()?, width)?,
+                mask(width)
+            ));
+        }
+    }
+
+    let state_count = lowering.state_ids.len() as u32;
+    let reset_state = nodes
+        .iter()
+        .find(|node| node.node_type == NodeType::State)
+        .map(|node| node.id)
+        .ok_or(UnableToParseError::UndefinedNode)?;
+    lowering.state_reg = lowering.code.fresh_name("currentState");
+    let clk_last = lowering.code.fresh_name("clk_last");
+    private.insert(
+        0,
+        format!(
+            "{} {}{{{}}};",
+            cpp_type(Expr::bits_for(state_count.max(1) as u64 - 1))?,
+            lowering.state_reg,
+            reset_state
+        ),
+    );
+    private.insert(0, format!("uint8_t {}{{}};", clk_last));
+    storage.insert(0, (lowering.state_reg.clone(), lowering.state_reg.clone()));
+    for (member, _) in storage.iter() {
+        let next = lowering.code.fresh_name(&format!("{}_next", member));
+        cpp.next.insert(member.clone(), next);
+    }
+
+    let mut code = format!(
+        "// Cycle model of `{module}` generated from its ASM chart.
+#pragma once
+
+#include <array>
+#include <cstdint>
+
+class {module} {{
+public:
+    uint8_t clk{{}};
+    uint8_t reset{{}};"
+    );
+    for member in public.iter() {
+        code.push_str(&format!("\n    {}", member));
+    }
+    code.push_str(&format!(
+        "
+
+    void eval() {{
+        if (reset) {{
+            {} = {};{}
+        }} else if (clk && !{}) {{
+            step();
+        }}
+        {} = clk;
+    }}
+
+    void tick() {{
+        clk = 0;
+        eval();
+        clk = 1;
+        eval();
+    }}",
+        lowering.state_reg,
+        reset_state,
+        resets
+            .iter()
+            .map(|reset| format!("\n            {}", reset))
+            .collect::<String>(),
+        clk_last,
+        clk_last
+    ));
+    for wire in wires.iter() {
+        code.push_str(&format!("\n\n    {}", wire));
+    }
+    code.push_str("\n\nprivate:");
+    for member in private.iter() {
+        code.push_str(&format!("\n    {}", member));
+    }
+    code.push_str("\n\n    void step() {");
+    for (member, source) in storage.iter() {
+        code.push_str(&format!(
+            "\n        auto {} = {};",
+            cpp.next[member], source
+        ));
+    }
+    code.push_str(&format!("\n        switch ({}) {{", lowering.state_reg));
+    lowering.code.code = code;
+    for node in nodes.iter() {
+        if node.node_type == NodeType::State {
+            lowering.line(2, format!("case {}: {{", node.id));
+            lowering.compile_node(&cpp, node, &mut HashSet::new(), 3, true)?;
+            lowering.line(3, "break;".to_string());
+            lowering.line(2, "}".to_string());
+        }
+    }
+    lowering.line(2, "default:".to_string());
+    let state_next = cpp.next_state(&lowering, reset_state);
+    lowering.line(3, state_next);
+    lowering.line(3, "break;".to_string());
+    lowering.line(2, "}".to_string());
+    for (member, _) in storage.iter() {
+        lowering.line(2, format!("{} = {};", member, cpp.next[member]));
+    }
+    lowering.code.update("\n    }\n};\n".to_string());
+    Ok(lowering.code.code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counter_test() {
+        let commands: Vec<Command> = [
+            "go => input",
+            "count => output[3:0]",
+            "mem => reg[7:0][3:0]",
+        ]
+        .iter()
+        .map(|cmd| cmd.parse().unwrap())
+        .collect();
+        let mut nodes = vec![
+            Node::try_parse("idle", "state", "then => check;").unwrap(),
+            Node::try_parse("check", "decision", "check => go; yes => bump; no => idle;").unwrap(),
+            Node::try_parse(
+                "bump",
+                "conditional",
+                "count => count + 1; mem[count] => count; count[0] => 1; then => idle;",
+            )
+            .unwrap(),
+        ];
+        crate::node::number_states(&mut nodes);
+        let cpp = emit_cpp("Counter", &commands, &nodes, &Naming::default()).unwrap();
+        assert!(cpp.contains("class Counter {\npublic:\n    uint8_t clk{};\n    uint8_t reset{};\n    uint8_t go{};\n    uint8_t count{};"));
+        assert!(cpp.contains("std::array<uint8_t, 8> mem{};"));
+        assert!(cpp.contains("if (go != 0) {"));
+        assert!(cpp.contains("count_next = (((uint64_t)count + 1ULL) & 0xfULL) & 0xfULL;"));
+        assert!(cpp.contains("mem_next[(count) % 8] = count & 0xfULL;"));
+        assert!(
+            cpp.contains("count_next = (count_next & ~(1ULL << 0ULL)) | ((1ULL & 1ULL) << 0ULL);")
+        );
+        assert!(cpp.contains("void tick() {"));
+    }
+
+    #[test]
+    fn names_test() {
+        let commands: Vec<Command> = [
+            "bus => inout[3:0]",
+            "bus_out => reg[3:0]",
+            "count => reg[3:0]",
+            "count_next => reg[3:0]",
+        ]
+        .iter()
+        .map(|cmd| cmd.parse().unwrap())
+        .collect();
+        let mut nodes = vec![Node::try_parse(
+            "idle",
+            "state",
+            "count => count + 1; count_next => count; bus => count_next; then => idle;",
+        )
+        .unwrap()];
+        crate::node::number_states(&mut nodes);
+        let cpp = emit_cpp("Clash", &commands, &nodes, &Naming::default()).unwrap();
+        // The generated names step aside for the chart's own.
+        assert!(cpp.contains("\n    uint8_t bus_out_2{};\n    uint8_t bus_oe{};"));
+        assert!(cpp.contains("\n    uint8_t bus_out{};"));
+        assert!(cpp.contains(
+            "\n        auto count_next_2 = count;\n        auto count_next_next = count_next;"
+        ));
+        assert!(cpp.contains("\n            count_next_next = count & 0xfULL;"));
+        assert!(
+            cpp.contains("\n        count = count_next_2;\n        count_next = count_next_next;")
+        );
+
+        let commands: Vec<Command> = vec!["class => reg".parse().unwrap()];
+        assert_eq!(
+            spellable("Top", &commands).unwrap_err().message,
+            "`class` is reserved in the C++ model, rename it"
+        );
+        assert!(spellable("tick", &[]).is_err());
+    }
+}
@@ -0,0 +1,114 @@
+use crate::command::Command;
+use crate::node::{Node, NodeType};
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+fn transfers(node: &Node) -> Vec<String> {
+    node.commands
+        .iter()
+        .filter_map(|cmd| match cmd {
+            Command::RegisterTransfer {
+                reg_name,
+                reg_value,
+            } => Some(format!("{} <= {}", reg_name, reg_value)),
+            Command::Action { .. } => Some(cmd.to_string()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Renders the node graph as a Graphviz digraph drawn like an ASM chart:
+/// states are rectangles, decisions are diamonds and conditional outputs
+/// are rounded boxes.
+pub fn emit_dot(module: &str, nodes: &[Node]) -> String {
+    let mut out = format!(
+        "digraph \"{}\" {{\n    node [fontname=\"monospace\"];\n",
+        escape(module)
+    );
+
+    for node in nodes.iter() {
+        let name = escape(&node.node_name);
+        match node.node_type {
+            NodeType::State => {
+                let mut lines = vec![node.node_name.clone()];
+                lines.extend(transfers(node));
+                out.push_str(&format!(
+                    "    \"{}\" [shape=box, label=\"{}\"];\n",
+                    name,
+                    escape(&lines.join("\n")).replace('\n', "\\n")
+                ));
+            }
+            NodeType::Decision => {
+                let check = node
+                    .commands
+                    .iter()
+                    .find_map(|cmd| match cmd {
+                        Command::Check { check } => Some(check.as_str()),
+                        _ => None,
+                    })
+                    .unwrap_or("0");
+                out.push_str(&format!(
+                    "    \"{}\" [shape=diamond, label=\"{}\"];\n",
+                    name,
+                    escape(check)
+                ));
+            }
+            NodeType::Conditional => {
+                out.push_str(&format!(
+                    "    \"{}\" [shape=box, style=rounded, label=\"{}\"];\n",
+                    name,
+                    escape(&transfers(node).join("\n")).replace('\n', "\\n")
+                ));
+            }
+        }
+    }
+
+    for node in nodes.iter() {
+        for cmd in node.commands.iter() {
+            let (next_node, label) = match cmd {
+                Command::Then { next_node } => (next_node, "then"),
+                Command::Yes { next_node } => (next_node, "yes"),
+                Command::No { next_node } => (next_node, "no"),
+                _ => continue,
+            };
+            out.push_str(&format!(
+                "    \"{}\" -> \"{}\" [label=\"{}\"];\n",
+                escape(&node.node_name),
+                escape(next_node),
+                label
+            ));
+        }
+    }
+
+    out.push_str("}\n");
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shapes_and_edges_test() {
+        let nodes = vec![
+            Node::try_parse("main", "state", "r0 => r0 + 1; then => check;").unwrap(),
+            Node::try_parse(
+                "check",
+                "decision",
+                "check => r0 == 3; yes => done; no => main;",
+            )
+            .unwrap(),
+            Node::try_parse("done", "conditional", "ready => 1; then => main;").unwrap(),
+        ];
+        let dot = emit_dot("Top", &nodes);
+        assert!(dot.starts_with("digraph \"Top\" {"));
+        assert!(dot.contains("\"main\" [shape=box, label=\"main\\nr0 <= r0 + 1\"];"));
+        assert!(dot.contains("\"check\" [shape=diamond, label=\"r0 == 3\"];"));
+        assert!(dot.contains("\"done\" [shape=box, style=rounded, label=\"ready <= 1\"];"));
+        assert!(dot.contains("\"check\" -> \"done\" [label=\"yes\"];"));
+        assert!(dot.contains("\"check\" -> \"main\" [label=\"no\"];"));
+        assert!(dot.contains("\"main\" -> \"check\" [label=\"then\"];"));
+    }
+}
@@ -0,0 +1,136 @@
+use crate::command::Command;
+use crate::expr::Expr;
+use crate::template::substitute;
+use crate::Design;
+use std::collections::HashMap;
+
+/// The `enum` declarations of `design` as `(name, members)`.
+pub fn enums(design: &Design) -> Vec<(&str, &[String])> {
+    design
+        .commands
+        .iter()
+        .filter_map(|cmd| match cmd {
+            Command::Enum { name, members } => Some((name.as_str(), members.as_slice())),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Bits needed for every member of an enum with `count` members.
+pub fn width(count: usize) -> u32 {
+    Expr::bits_for(count.saturating_sub(1) as u64)
+}
+
+/// Sizes the declarations typed with an enum of `design`. Declarations of
+/// unknown enums are left for `check` to report.
+pub fn size(design: &mut Design) {
+    let widths: HashMap<String, u32> = enums(design)
+        .into_iter()
+        .map(|(name, members)| (name.to_string(), width(members.len())))
+        .collect();
+    for cmd in design.commands.iter_mut() {
+        if let Command::Input {
+            bits,
+            enum_name: Some(enum_name),
+            ..
+        }
+        | Command::Output {
+            bits,
+            enum_name: Some(enum_name),
+            ..
+        }
+        | Command::Register {
+            bits,
+            enum_name: Some(enum_name),
+            ..
+        } = cmd
+        {
+            if let Some(width) = widths.get(enum_name) {
+                *bits = (*width - 1) as u8..0;
+            }
+        }
+    }
+}
+
+/// Members by name, with their value and the width of their enum.
+pub fn members(design: &Design) -> HashMap<&str, (u64, u32)> {
+    let mut out = HashMap::new();
+    for (_, members) in enums(design) {
+        for (value, member) in members.iter().enumerate() {
+            out.insert(member.as_str(), (value as u64, width(members.len())));
+        }
+    }
+    out
+}
+
+/// Replaces every enum member in checks, transfers and wires with its sized
+/// constant, for backends without named constants.
+pub fn inline(design: &Design) -> Design {
+    let constants: HashMap<&str, String> = members(design)
+        .into_iter()
+        .map(|(member, (value, width))| (member, format!("{}'d{}", width, value)))
+        .collect();
+    let mut inlined = design.clone();
+    if constants.is_empty() {
+        return inlined;
+    }
+    for cmd in inlined.commands.iter_mut() {
+        if let Command::Wire { value, .. } = cmd {
+            *value = substitute(value, &constants);
+        }
+    }
+    for node in inlined.nodes.iter_mut() {
+        for cmd in node.commands.iter_mut() {
+            match cmd {
+                Command::RegisterTransfer {
+                    reg_name,
+                    reg_value,
+                } => {
+                    *reg_name = substitute(reg_name, &constants);
+                    *reg_value = substitute(reg_value, &constants);
+                }
+                Command::Check { check } | Command::WaitUntil { check, .. } => {
+                    *check = substitute(check, &constants)
+                }
+                Command::Action {
+                    value: Some(value), ..
+                } => *value = substitute(value, &constants),
+                _ => {}
+            }
+        }
+    }
+    inlined
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse;
+
+    #[test]
+    fn size_and_inline_test() {
+        let design = parse(
+            "
+enum Opcode { ADD, SUB, JMP }
+enum Flag { OFF, ON }
+op => input Opcode;
+last => reg Opcode;
+.idle : state {
+    last => op == JMP ? ADD : op;
+    then => idle;
+}
+",
+        )
+        .unwrap();
+        let Command::Input { bits, .. } = &design.commands[2] else {
+            panic!("`op` is not an input");
+        };
+        assert_eq!((bits.start, bits.end), (1, 0));
+        assert_eq!(width(2), 1);
+        assert_eq!(width(5), 3);
+        assert_eq!(
+            inline(&design).nodes[0].commands[0].to_string(),
+            "last => op == 2'd2 ? 2'd0 : op"
+        );
+    }
+}
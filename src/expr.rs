@@ -0,0 +1,494 @@
+use crate::command::UnableToParseError;
+use std::{fmt::Display, str::FromStr};
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum UnaryOp {
+    LogicalNot,
+    BitNot,
+    Negate,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum BinaryOp {
+    Mul,
+    Div,
+    Mod,
+    Add,
+    Sub,
+    Shl,
+    Shr,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+    BitAnd,
+    BitXor,
+    BitOr,
+    LogicalAnd,
+    LogicalOr,
+}
+
+/// Expressions as written in checks and transfers, using the Verilog
+/// operator set and precedence.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Expr {
+    Ident(String),
+    Number { value: u64, width: Option<u32> },
+    Unary(UnaryOp, Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+    Ternary(Box<Expr>, Box<Expr>, Box<Expr>),
+    Index(Box<Expr>, Box<Expr>),
+    Slice(Box<Expr>, u32, u32),
+    Concat(Vec<Expr>),
+}
+
+impl BinaryOp {
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            BinaryOp::Mul => "*",
+            BinaryOp::Div => "/",
+            BinaryOp::Mod => "%",
+            BinaryOp::Add => "+",
+            BinaryOp::Sub => "-",
+            BinaryOp::Shl => "<<",
+            BinaryOp::Shr => ">>",
+            BinaryOp::Lt => "<",
+            BinaryOp::Le => "<=",
+            BinaryOp::Gt => ">",
+            BinaryOp::Ge => ">=",
+            BinaryOp::Eq => "==",
+            BinaryOp::Ne => "!=",
+            BinaryOp::BitAnd => "&",
+            BinaryOp::BitXor => "^",
+            BinaryOp::BitOr => "|",
+            BinaryOp::LogicalAnd => "&&",
+            BinaryOp::LogicalOr => "||",
+        }
+    }
+    fn precedence(&self) -> u8 {
+        match self {
+            BinaryOp::LogicalOr => 1,
+            BinaryOp::LogicalAnd => 2,
+            BinaryOp::BitOr => 3,
+            BinaryOp::BitXor => 4,
+            BinaryOp::BitAnd => 5,
+            BinaryOp::Eq | BinaryOp::Ne => 6,
+            BinaryOp::Lt | BinaryOp::Le | BinaryOp::Gt | BinaryOp::Ge => 7,
+            BinaryOp::Shl | BinaryOp::Shr => 8,
+            BinaryOp::Add | BinaryOp::Sub => 9,
+            BinaryOp::Mul | BinaryOp::Div | BinaryOp::Mod => 10,
+        }
+    }
+    /// Whether the result is a truth value rather than a vector.
+    pub fn is_boolean(&self) -> bool {
+        matches!(
+            self,
+            BinaryOp::Lt
+                | BinaryOp::Le
+                | BinaryOp::Gt
+                | BinaryOp::Ge
+                | BinaryOp::Eq
+                | BinaryOp::Ne
+                | BinaryOp::LogicalAnd
+                | BinaryOp::LogicalOr
+        )
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+enum Token {
+    Ident(String),
+    Number(u64, Option<u32>),
+    Op(&'static str),
+}
+
+const OPERATORS: [&str; 29] = [
+    "&&", "||", "==", "!=", "<=", ">=", "<<", ">>", "*", "/", "%", "+", "-", "<", ">", "&", "^",
+    "|", "!", "~", "?", ":", "(", ")", "[", "]", "{", "}", ",",
+];
+
+fn parse_number(text: &str) -> Result<Token, UnableToParseError> {
+    let text = text.replace('_', "");
+    let Some((width, rest)) = text.split_once('\'') else {
+        return text
+            .parse()
+            .map(|value| Token::Number(value, None))
+            .map_err(|_| UnableToParseError::InvalidExpression);
+    };
+    let width = if width.is_empty() {
+        None
+    } else {
+        Some(
+            width
+                .parse()
+                .map_err(|_| UnableToParseError::InvalidExpression)?,
+        )
+    };
+    let radix = match rest.chars().next().map(|c| c.to_ascii_lowercase()) {
+        Some('b') => 2,
+        Some('o') => 8,
+        Some('d') => 10,
+        Some('h') => 16,
+        _ => return Err(UnableToParseError::InvalidExpression),
+    };
+    u64::from_str_radix(&rest[1..], radix)
+        .map(|value| Token::Number(value, width))
+        .map_err(|_| UnableToParseError::InvalidExpression)
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, UnableToParseError> {
+    let mut tokens = vec![];
+    let mut rest = text.trim_start();
+    while !rest.is_empty() {
+        let first = rest.chars().next().unwrap();
+        if first.is_ascii_alphabetic() || first == '_' {
+            let end = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '$' || c == '.'))
+                .unwrap_or(rest.len());
+            tokens.push(Token::Ident(rest[..end].to_string()));
+            rest = &rest[end..];
+        } else if first.is_ascii_digit() || first == '\'' {
+            let end = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '\''))
+                .unwrap_or(rest.len());
+            tokens.push(parse_number(&rest[..end])?);
+            rest = &rest[end..];
+        } else if let Some(op) = OPERATORS.iter().find(|op| rest.starts_with(*op)) {
+            tokens.push(Token::Op(op));
+            rest = &rest[op.len()..];
+        } else {
+            return Err(UnableToParseError::InvalidExpression);
+        }
+        rest = rest.trim_start();
+    }
+    Ok(tokens)
+}
+
+fn binary_op(token: &Token) -> Option<BinaryOp> {
+    let Token::Op(op) = token else {
+        return None;
+    };
+    Some(match *op {
+        "*" => BinaryOp::Mul,
+        "/" => BinaryOp::Div,
+        "%" => BinaryOp::Mod,
+        "+" => BinaryOp::Add,
+        "-" => BinaryOp::Sub,
+        "<<" => BinaryOp::Shl,
+        ">>" => BinaryOp::Shr,
+        "<" => BinaryOp::Lt,
+        "<=" => BinaryOp::Le,
+        ">" => BinaryOp::Gt,
+        ">=" => BinaryOp::Ge,
+        "==" => BinaryOp::Eq,
+        "!=" => BinaryOp::Ne,
+        "&" => BinaryOp::BitAnd,
+        "^" => BinaryOp::BitXor,
+        "|" => BinaryOp::BitOr,
+        "&&" => BinaryOp::LogicalAnd,
+        "||" => BinaryOp::LogicalOr,
+        _ => return None,
+    })
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+    fn expect(&mut self, op: &str) -> Result<(), UnableToParseError> {
+        match self.next() {
+            Some(Token::Op(found)) if found == op => Ok(()),
+            _ => Err(UnableToParseError::InvalidExpression),
+        }
+    }
+    fn eat(&mut self, op: &str) -> bool {
+        if matches!(self.peek(), Some(Token::Op(found)) if *found == op) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expression(&mut self) -> Result<Expr, UnableToParseError> {
+        let cond = self.binary(1)?;
+        if self.eat("?") {
+            let yes = self.expression()?;
+            self.expect(":")?;
+            let no = self.expression()?;
+            return Ok(Expr::Ternary(Box::new(cond), Box::new(yes), Box::new(no)));
+        }
+        Ok(cond)
+    }
+
+    fn binary(&mut self, min_precedence: u8) -> Result<Expr, UnableToParseError> {
+        let mut lhs = self.unary()?;
+        while let Some(op) = self.peek().and_then(binary_op) {
+            if op.precedence() < min_precedence {
+                break;
+            }
+            self.pos += 1;
+            let rhs = self.binary(op.precedence() + 1)?;
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> Result<Expr, UnableToParseError> {
+        let op = if self.eat("!") {
+            UnaryOp::LogicalNot
+        } else if self.eat("~") {
+            UnaryOp::BitNot
+        } else if self.eat("-") {
+            UnaryOp::Negate
+        } else {
+            return self.postfix();
+        };
+        Ok(Expr::Unary(op, Box::new(self.unary()?)))
+    }
+
+    fn postfix(&mut self) -> Result<Expr, UnableToParseError> {
+        let mut expr = self.primary()?;
+        while self.eat("[") {
+            let index = self.expression()?;
+            if self.eat(":") {
+                let high = constant(&index)?;
+                let low = constant(&self.expression()?)?;
+                expr = Expr::Slice(Box::new(expr), high as u32, low as u32);
+            } else {
+                expr = Expr::Index(Box::new(expr), Box::new(index));
+            }
+            self.expect("]")?;
+        }
+        Ok(expr)
+    }
+
+    fn primary(&mut self) -> Result<Expr, UnableToParseError> {
+        match self.next() {
+            Some(Token::Ident(name)) => Ok(Expr::Ident(name)),
+            Some(Token::Number(value, width)) => Ok(Expr::Number { value, width }),
+            Some(Token::Op("(")) => {
+                let expr = self.expression()?;
+                self.expect(")")?;
+                Ok(expr)
+            }
+            Some(Token::Op("{")) => {
+                let mut parts = vec![self.expression()?];
+                while !self.eat("}") {
+                    self.expect(",")?;
+                    parts.push(self.expression()?);
+                }
+                Ok(Expr::Concat(parts))
+            }
+            _ => Err(UnableToParseError::InvalidExpression),
+        }
+    }
+}
+
+fn constant(expr: &Expr) -> Result<u64, UnableToParseError> {
+    match expr {
+        Expr::Number { value, .. } => Ok(*value),
+        _ => Err(UnableToParseError::InvalidExpression),
+    }
+}
+
+impl FromStr for Expr {
+    type Err = UnableToParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser {
+            tokens: tokenize(s)?,
+            pos: 0,
+        };
+        let expr = parser.expression()?;
+        if parser.peek().is_some() {
+            return Err(UnableToParseError::InvalidExpression);
+        }
+        Ok(expr)
+    }
+}
+
+impl Expr {
+    /// Number of bits needed to hold `value`, at least one.
+    pub fn bits_for(value: u64) -> u32 {
+        (64 - value.leading_zeros()).max(1)
+    }
+
+    /// Self-determined width following the Verilog sizing rules, under
+    /// which an unsized number is 32 bits. `lookup` returns the element
+    /// width of a declared name and whether it is an array.
+    pub fn width(
+        &self,
+        lookup: &dyn Fn(&str) -> Option<(u32, bool)>,
+    ) -> Result<u32, UnableToParseError> {
+        self.sized(lookup, &|value| Expr::bits_for(value).max(32))
+    }
+
+    /// Like `width`, but with unsized numbers only as wide as their value,
+    /// the bits the result can actually need.
+    pub fn value_width(
+        &self,
+        lookup: &dyn Fn(&str) -> Option<(u32, bool)>,
+    ) -> Result<u32, UnableToParseError> {
+        self.sized(lookup, &Expr::bits_for)
+    }
+
+    fn sized(
+        &self,
+        lookup: &dyn Fn(&str) -> Option<(u32, bool)>,
+        literal: &dyn Fn(u64) -> u32,
+    ) -> Result<u32, UnableToParseError> {
+        let declared = |name: &str| lookup(name).ok_or(UnableToParseError::InvalidExpression);
+        Ok(match self {
+            Expr::Ident(name) => declared(name)?.0,
+            Expr::Number { value, width } => width.unwrap_or_else(|| literal(*value)),
+            Expr::Unary(UnaryOp::LogicalNot, _) => 1,
+            Expr::Unary(_, inner) => inner.sized(lookup, literal)?,
+            Expr::Binary(op, _, _) if op.is_boolean() => 1,
+            Expr::Binary(BinaryOp::Shl | BinaryOp::Shr, lhs, _) => lhs.sized(lookup, literal)?,
+            Expr::Binary(_, lhs, rhs) => {
+                lhs.sized(lookup, literal)?.max(rhs.sized(lookup, literal)?)
+            }
+            Expr::Ternary(_, yes, no) => {
+                yes.sized(lookup, literal)?.max(no.sized(lookup, literal)?)
+            }
+            Expr::Index(base, _) => match base.as_ref() {
+                Expr::Ident(name) if declared(name)?.1 => declared(name)?.0,
+                _ => 1,
+            },
+            Expr::Slice(_, high, low) => high.abs_diff(*low) + 1,
+            Expr::Concat(parts) => {
+                let mut total = 0;
+                for part in parts.iter() {
+                    total += part.sized(lookup, literal)?;
+                }
+                total
+            }
+        })
+    }
+
+    /// Every identifier the expression reads, in order of appearance.
+    pub fn idents(&self) -> Vec<&str> {
+        let mut out = vec![];
+        self.collect_idents(&mut out);
+        out
+    }
+    fn collect_idents<'l>(&'l self, out: &mut Vec<&'l str>) {
+        match self {
+            Expr::Ident(name) => out.push(name),
+            Expr::Number { .. } => {}
+            Expr::Unary(_, inner) | Expr::Slice(inner, _, _) => inner.collect_idents(out),
+            Expr::Binary(_, lhs, rhs) | Expr::Index(lhs, rhs) => {
+                lhs.collect_idents(out);
+                rhs.collect_idents(out);
+            }
+            Expr::Ternary(cond, yes, no) => {
+                cond.collect_idents(out);
+                yes.collect_idents(out);
+                no.collect_idents(out);
+            }
+            Expr::Concat(parts) => parts.iter().for_each(|part| part.collect_idents(out)),
+        }
+    }
+}
+
+impl Display for Expr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Expr::Ident(name) => write!(f, "{}", name),
+            Expr::Number { value, width: None } => write!(f, "{}", value),
+            Expr::Number {
+                value,
+                width: Some(width),
+            } => write!(f, "{}'d{}", width, value),
+            Expr::Unary(op, inner) => {
+                let symbol = match op {
+                    UnaryOp::LogicalNot => "!",
+                    UnaryOp::BitNot => "~",
+                    UnaryOp::Negate => "-",
+                };
+                write!(f, "{}({})", symbol, inner)
+            }
+            Expr::Binary(op, lhs, rhs) => write!(f, "({} {} {})", lhs, op.symbol(), rhs),
+            Expr::Ternary(cond, yes, no) => write!(f, "({} ? {} : {})", cond, yes, no),
+            Expr::Index(inner, index) => write!(f, "{}[{}]", inner, index),
+            Expr::Slice(inner, high, low) => write!(f, "{}[{}:{}]", inner, high, low),
+            Expr::Concat(parts) => write!(
+                f,
+                "{{{}}}",
+                parts
+                    .iter()
+                    .map(|part| part.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn precedence_test() {
+        let expr: Expr = "a + b * 2 == c && !d".parse().unwrap();
+        assert_eq!(expr.to_string(), "(((a + (b * 2)) == c) && !(d))");
+        let expr: Expr = "r0 >> 1 | r1 << 2".parse().unwrap();
+        assert_eq!(expr.to_string(), "((r0 >> 1) | (r1 << 2))");
+    }
+
+    #[test]
+    fn select_and_concat_test() {
+        let expr: Expr = "{mem[address], r0[3:1]}".parse().unwrap();
+        assert_eq!(
+            expr,
+            Expr::Concat(vec![
+                Expr::Index(
+                    Box::new(Expr::Ident("mem".to_string())),
+                    Box::new(Expr::Ident("address".to_string()))
+                ),
+                Expr::Slice(Box::new(Expr::Ident("r0".to_string())), 3, 1),
+            ])
+        );
+        assert_eq!(expr.idents(), vec!["mem", "address", "r0"]);
+        let expr: Expr = "m.ready && !m.busy".parse().unwrap();
+        assert_eq!(expr.idents(), vec!["m.ready", "m.busy"]);
+    }
+
+    #[test]
+    fn number_test() {
+        assert_eq!(
+            "4'b1010".parse::<Expr>(),
+            Ok(Expr::Number {
+                value: 10,
+                width: Some(4)
+            })
+        );
+        assert_eq!(
+            "'hff".parse::<Expr>(),
+            Ok(Expr::Number {
+                value: 255,
+                width: None
+            })
+        );
+        assert_eq!(
+            "c ? 1_000 : 2".parse::<Expr>().unwrap().to_string(),
+            "(c ? 1000 : 2)"
+        );
+        assert!("a +".parse::<Expr>().is_err());
+        assert!("a b".parse::<Expr>().is_err());
+    }
+}
@@ -0,0 +1,454 @@
+use crate::command::Command;
+use crate::{enums, template};
+use crate::{from_json, parse, Design, Diagnostic, Library};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+/// A chart imported with `import "path" as name;`, with its own includes
+/// merged in.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Import {
+    pub name: String,
+    /// Where the chart was read from, relative to the importing file's
+    /// directory the way the `import` spelled it.
+    pub path: String,
+    pub design: Design,
+}
+
+/// A chart with every `include` merged in, plus every chart it imports,
+/// directly or through the charts it imports.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Loaded {
+    pub design: Design,
+    pub imports: Vec<Import>,
+}
+
+impl Loaded {
+    /// The imports by name, for [`crate::check_with`] and
+    /// [`crate::Options::library`].
+    pub fn library(&self) -> Library {
+        let mut library = Library::new();
+        for import in self.imports.iter() {
+            library.insert(&import.name, import.design.clone());
+        }
+        library
+    }
+}
+
+/// One file on the way from the file given on the command line to the one
+/// being read.
+struct Step {
+    canonical: PathBuf,
+    shown: String,
+    /// `included` or `imported`, how the previous step reached this one.
+    reached: &'static str,
+}
+
+struct Loader {
+    chain: Vec<Step>,
+    /// Every import so far, with the file it came from and where it was
+    /// imported, to tell repeats from clashes.
+    imports: Vec<(Import, PathBuf, String)>,
+}
+
+/// `c.asmc (included from b.asmc, imported from top.asmc)`.
+fn describe(chain: &[Step]) -> String {
+    let Some((last, rest)) = chain.split_last() else {
+        return String::new();
+    };
+    let mut text = last.shown.clone();
+    let mut reached = last.reached;
+    let mut origins = vec![];
+    for step in rest.iter().rev() {
+        origins.push(format!("{} from {}", reached, step.shown));
+        reached = step.reached;
+    }
+    if !origins.is_empty() {
+        text.push_str(&format!(" ({})", origins.join(", ")));
+    }
+    text
+}
+
+fn defined_name(cmd: &Command) -> Option<&str> {
+    match cmd {
+        Command::Input { pin_name, .. }
+        | Command::Output { pin_name, .. }
+        | Command::Inout { pin_name, .. } => Some(pin_name),
+        Command::Register { reg_name, .. } => Some(reg_name),
+        Command::Instance { instance_name, .. } => Some(instance_name),
+        Command::Wire { wire_name, .. } => Some(wire_name),
+        Command::Memory { mem_name, .. } => Some(mem_name),
+        Command::Fifo { fifo_name, .. } => Some(fifo_name),
+        Command::Counter { counter_name, .. } => Some(counter_name),
+        Command::Stream { stream_name, .. } => Some(stream_name),
+        Command::Enum { name, .. } => Some(name),
+        Command::Template { name, .. } => Some(name),
+        _ => None,
+    }
+}
+
+impl Loader {
+    /// Finds `path`, which the file at the end of the chain spells relative
+    /// to its own directory.
+    fn locate(&self, path: &str, reached: &str) -> Result<(String, PathBuf), Diagnostic> {
+        let base = self
+            .chain
+            .last()
+            .and_then(|step| Path::new(&step.shown).parent())
+            .unwrap_or(Path::new(""));
+        let shown = base.join(path).display().to_string();
+        let canonical = std::fs::canonicalize(&shown).map_err(|err| {
+            Diagnostic::error(format!(
+                "cannot read `{}` {} from {}: {}",
+                shown,
+                reached,
+                describe(&self.chain),
+                err
+            ))
+        })?;
+        Ok((shown, canonical))
+    }
+
+    /// Reads, parses and resolves a file found by [`Loader::locate`], or
+    /// returns `None` if it is already part of the chart being built.
+    fn read(
+        &mut self,
+        (shown, canonical): (String, PathBuf),
+        reached: &'static str,
+        included: &mut HashSet<PathBuf>,
+        origins: &mut HashMap<String, String>,
+    ) -> Result<Option<Design>, Diagnostic> {
+        if let Some(start) = self
+            .chain
+            .iter()
+            .position(|step| step.canonical == canonical)
+        {
+            let verb = |reached: &str| match reached {
+                "included" => "includes",
+                _ => "imports",
+            };
+            let mut cycle = self.chain[start].shown.clone();
+            for step in self.chain[start + 1..].iter() {
+                cycle.push_str(&format!(" {} {}", verb(step.reached), step.shown));
+            }
+            cycle.push_str(&format!(" {} {}", verb(reached), shown));
+            // A cycle that does not start at the root is shown with the
+            // files that lead to it.
+            let message = match start {
+                0 => format!("include cycle: {}", cycle),
+                _ => format!(
+                    "{}: include cycle: {}",
+                    describe(&self.chain[..start]),
+                    cycle
+                ),
+            };
+            return Err(Diagnostic::error(message));
+        }
+        if included.contains(&canonical) {
+            return Ok(None);
+        }
+        let contents = std::fs::read_to_string(&canonical).map_err(|err| {
+            Diagnostic::error(format!(
+                "cannot read `{}` {} from {}: {}",
+                shown,
+                reached,
+                describe(&self.chain),
+                err
+            ))
+        })?;
+        self.chain.push(Step {
+            canonical,
+            shown,
+            reached,
+        });
+        let result = match parse(&contents) {
+            Ok(design) => self.resolve(design, included, origins),
+            Err(mut diagnostic) => {
+                diagnostic.message =
+                    format!("in {}: {}", describe(&self.chain), diagnostic.message);
+                Err(diagnostic)
+            }
+        };
+        self.chain.pop();
+        result.map(Some)
+    }
+
+    /// Merges the includes of `design`, the file at the end of the chain,
+    /// and collects its imports. `included` holds the files already merged
+    /// into the chart being built, which are skipped when included again,
+    /// and `origins` where each of its names was defined.
+    fn resolve(
+        &mut self,
+        design: Design,
+        included: &mut HashSet<PathBuf>,
+        origins: &mut HashMap<String, String>,
+    ) -> Result<Design, Diagnostic> {
+        let here = describe(&self.chain);
+        if let Some(step) = self.chain.last() {
+            included.insert(step.canonical.clone());
+        }
+        let names = design
+            .commands
+            .iter()
+            .filter_map(defined_name)
+            .chain(design.nodes.iter().map(|node| node.node_name.as_str()));
+        for name in names {
+            match origins.get(name) {
+                // Repeats within one file are left to `check`.
+                Some(first) if *first != here => {
+                    return Err(Diagnostic::error(format!(
+                        "`{}` is defined in {} and again in {}",
+                        name, first, here
+                    )))
+                }
+                _ => origins.insert(name.to_string(), here.clone()),
+            };
+        }
+
+        let mut commands = vec![];
+        let mut nodes = design.nodes;
+        for cmd in design.commands {
+            match cmd {
+                Command::Include { path } => {
+                    let found = self.locate(&path, "included")?;
+                    if let Some(mut sub) = self.read(found, "included", included, origins)? {
+                        commands.append(&mut sub.commands);
+                        nodes.append(&mut sub.nodes);
+                    }
+                }
+                Command::Import { path, name } => {
+                    let (shown, canonical) = self.locate(&path, "imported")?;
+                    let origin = format!("{} (imported from {})", shown, here);
+                    if let Some((_, first, first_origin)) = self
+                        .imports
+                        .iter()
+                        .find(|(import, _, _)| import.name == name)
+                    {
+                        if *first == canonical {
+                            continue;
+                        }
+                        return Err(Diagnostic::error(format!(
+                            "`{}` is imported from {} and again from {}",
+                            name, first_origin, origin
+                        )));
+                    }
+                    let design = self
+                        .read(
+                            (shown.clone(), canonical.clone()),
+                            "imported",
+                            &mut HashSet::new(),
+                            &mut HashMap::new(),
+                        )?
+                        .unwrap_or_else(|| unreachable!("imports start a new chart"));
+                    self.imports.push((
+                        Import {
+                            name,
+                            path: shown,
+                            design,
+                        },
+                        canonical,
+                        origin,
+                    ));
+                }
+                cmd => commands.push(cmd),
+            }
+        }
+        let design = Design { commands, nodes };
+        // Templates and enums may come from any file the chart includes, so
+        // `use` is expanded and typed declarations sized once it is whole.
+        if let Some("included") = self.chain.last().map(|step| step.reached) {
+            return Ok(design);
+        }
+        let mut design = template::expand(design)?;
+        enums::size(&mut design);
+        Ok(design)
+    }
+}
+
+/// Resolves the `include` and `import` directives of a chart read from
+/// `path`; their paths are relative to its directory. Includes cycles and
+/// names defined in two files are errors naming the chain of files. Then
+/// expands every `use` of a template.
+pub fn resolve(design: Design, path: &Path) -> Result<Loaded, Diagnostic> {
+    let mut loader = Loader {
+        chain: vec![Step {
+            canonical: std::fs::canonicalize(path).unwrap_or(path.to_path_buf()),
+            shown: path.display().to_string(),
+            reached: "",
+        }],
+        imports: vec![],
+    };
+    let design = loader.resolve(design, &mut HashSet::new(), &mut HashMap::new())?;
+    Ok(Loaded {
+        design,
+        imports: loader
+            .imports
+            .into_iter()
+            .map(|(import, _, _)| import)
+            .collect(),
+    })
+}
+
+/// Reads the chart at `path`, `.json` or `.asmc`, and resolves it.
+pub fn load(path: &Path) -> Result<Loaded, Diagnostic> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|err| Diagnostic::error(format!("cannot read `{}`: {}", path.display(), err)))?;
+    let design = if path
+        .extension()
+        .is_some_and(|extension| extension == "json")
+    {
+        from_json(&contents)?
+    } else {
+        parse(&contents)?
+    };
+    resolve(design, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes `files` into a fresh directory under the system temp dir.
+    fn fixture(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("asmc_include_{}_{}", name, std::process::id()));
+        for (path, contents) in files {
+            let path = dir.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, contents).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn include_and_import_test() {
+        let dir = fixture(
+            "merge",
+            &[
+                ("ports.asmc", "go => input;\n"),
+                (
+                    "lib/common.asmc",
+                    "include \"../ports.asmc\";\nx => input[3:0];\n",
+                ),
+                (
+                    "lib/mult.asmc",
+                    &std::fs::read_to_string("multiplier.asmc").unwrap(),
+                ),
+                (
+                    "top.asmc",
+                    "include \"lib/common.asmc\";
+include \"ports.asmc\";
+import \"lib/mult.asmc\" as mult;
+m => instance mult;
+.idle : state {
+    m.a => x;
+    m.b => x;
+    m.start => go;
+    then => idle;
+}
+",
+                ),
+            ],
+        );
+        let loaded = load(&dir.join("top.asmc")).unwrap();
+        let names: Vec<String> = loaded
+            .design
+            .commands
+            .iter()
+            .map(|cmd| cmd.to_string())
+            .collect();
+        assert_eq!(
+            names,
+            vec!["go => input", "x => input[3:0]", "m => instance mult"]
+        );
+        assert_eq!(loaded.imports.len(), 1);
+        assert_eq!(loaded.imports[0].name, "mult");
+        let options = crate::Options {
+            library: loaded.library(),
+            ..crate::Options::default()
+        };
+        assert!(crate::check_with(&loaded.design, &options).is_empty());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn include_errors_test() {
+        let dir = fixture(
+            "errors",
+            &[
+                ("a.asmc", "include \"b.asmc\";\n"),
+                ("b.asmc", "include \"lib/c.asmc\";\n"),
+                ("lib/c.asmc", "import \"../a.asmc\" as a;\n"),
+                ("d.asmc", "go => input;\ninclude \"lib/e.asmc\";\n"),
+                ("lib/e.asmc", "go => reg;\n"),
+                ("top.asmc", "include \"lib/f.asmc\";\n"),
+                ("lib/f.asmc", "include \"g.asmc\";\n"),
+                ("lib/g.asmc", "include \"f.asmc\";\n"),
+                ("h.asmc", "enum Op { ADD }\ninclude \"lib/i.asmc\";\n"),
+                ("lib/i.asmc", "enum Op { SUB }\n"),
+                (
+                    "j.asmc",
+                    "include \"lib/k.asmc\";\ninclude \"lib/k2.asmc\";\n",
+                ),
+                (
+                    "lib/k.asmc",
+                    "template t(n) {\n.s : state {\n    then => n;\n}\n}\n",
+                ),
+                (
+                    "lib/k2.asmc",
+                    "template t(n) {\n.u : state {\n    then => n;\n}\n}\n",
+                ),
+            ],
+        );
+        let shown = |path: &str| dir.join(path).display().to_string();
+        assert_eq!(
+            load(&dir.join("a.asmc")).unwrap_err().message,
+            format!(
+                "include cycle: {} includes {} includes {} imports {}",
+                shown("a.asmc"),
+                shown("b.asmc"),
+                shown("lib/c.asmc"),
+                shown("lib/../a.asmc")
+            )
+        );
+        assert_eq!(
+            load(&dir.join("d.asmc")).unwrap_err().message,
+            format!(
+                "`go` is defined in {} and again in {} (included from {})",
+                shown("d.asmc"),
+                shown("lib/e.asmc"),
+                shown("d.asmc")
+            )
+        );
+        assert_eq!(
+            load(&dir.join("top.asmc")).unwrap_err().message,
+            format!(
+                "{}: include cycle: {} includes {} includes {}",
+                shown("top.asmc"),
+                shown("lib/f.asmc"),
+                shown("lib/g.asmc"),
+                shown("lib/f.asmc")
+            )
+        );
+        assert_eq!(
+            load(&dir.join("h.asmc")).unwrap_err().message,
+            format!(
+                "`Op` is defined in {} and again in {} (included from {})",
+                shown("h.asmc"),
+                shown("lib/i.asmc"),
+                shown("h.asmc")
+            )
+        );
+        assert_eq!(
+            load(&dir.join("j.asmc")).unwrap_err().message,
+            format!(
+                "`t` is defined in {} (included from {}) and again in {} (included from {})",
+                shown("lib/k.asmc"),
+                shown("j.asmc"),
+                shown("lib/k2.asmc"),
+                shown("j.asmc")
+            )
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
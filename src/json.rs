@@ -0,0 +1,191 @@
+use crate::command::Command;
+use crate::node::{self, Node};
+use crate::{Design, Diagnostic};
+use serde::{Deserialize, Serialize};
+
+/// Bumped whenever the layout below changes in a way older readers would
+/// misinterpret. Readers reject versions newer than theirs, and read older
+/// ones as if every field added since held its default.
+///
+/// 2. Nodes carry the `machine` they belong to.
+/// 3. Inouts can be `registered`.
+/// 4. Inputs can be `async`.
+pub const JSON_VERSION: u32 = 4;
+
+#[derive(Serialize)]
+struct DesignOut<'a> {
+    version: u32,
+    declarations: &'a [Command],
+    nodes: &'a [Node],
+}
+
+#[derive(Deserialize)]
+struct DesignIn {
+    version: u32,
+    declarations: Vec<Command>,
+    nodes: Vec<Node>,
+}
+
+/// Ranges are written the way they appear in `.asmc` source, `[msb, lsb]`.
+pub(crate) mod range {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use std::ops::Range;
+
+    pub fn serialize<S: Serializer>(range: &Range<u8>, serializer: S) -> Result<S::Ok, S::Error> {
+        [range.start, range.end].serialize(serializer)
+    }
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Range<u8>, D::Error> {
+        let [start, end] = <[u8; 2]>::deserialize(deserializer)?;
+        Ok(start..end)
+    }
+}
+
+pub fn to_json(design: &Design) -> String {
+    let out = DesignOut {
+        version: JSON_VERSION,
+        declarations: &design.commands,
+        nodes: &design.nodes,
+    };
+    let mut json = serde_json::to_string_pretty(&out).unwrap();
+    json.push('\n');
+    json
+}
+
+pub fn from_json(source: &str) -> Result<Design, Diagnostic> {
+    let version = serde_json::from_str::<serde_json::Value>(source)
+        .map_err(|err| Diagnostic::error(format!("invalid JSON: {}", err)))?
+        .get("version")
+        .and_then(|version| version.as_u64());
+    if !version.is_some_and(|version| (1..=JSON_VERSION as u64).contains(&version)) {
+        return Err(Diagnostic::error(format!(
+            "unsupported JSON design version {}, expected 1 to {}",
+            version.map_or("(missing)".to_string(), |version| version.to_string()),
+            JSON_VERSION
+        )));
+    }
+    let design: DesignIn = serde_json::from_str(source)
+        .map_err(|err| Diagnostic::error(format!("invalid JSON design: {}", err)))?;
+    debug_assert!(design.version <= JSON_VERSION);
+    let mut nodes = design.nodes;
+    node::number_states(&mut nodes);
+    Ok(Design {
+        commands: design.declarations,
+        nodes,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse;
+
+    #[test]
+    fn round_trip_test() {
+        for file in ["multiplier.asmc", "ram.asmc", "sampleformat.asmc"] {
+            let design = parse(&std::fs::read_to_string(file).unwrap()).unwrap();
+            assert_eq!(from_json(&to_json(&design)).unwrap(), design);
+        }
+        let sources = [
+            "bus => inout[7:0] registered;
+.idle : state {
+    drive => bus;
+    then => idle;
+}
+",
+            "r0 => reg[7:0];
+mem => memory[7:0] depth 16 ports 2 latency 2 init \"mem.hex\";
+.idle : state {
+    r0 => mem[0];
+    then => idle;
+}
+",
+            "q => fifo[8] depth 16;
+t => counter[10];
+.idle : state {
+    q.push(t.value[7:0]);
+    t.clear();
+    then => idle;
+}
+",
+            "go => input async;
+clock => clk_a enable ce;
+clock bus => clk_b negedge reset rst_b;
+.idle : state {
+    then => idle;
+}
+machine => bus;
+.wait : state {
+    then => wait;
+}
+",
+            "rx => stream in[7:0];
+tx => stream out;
+.idle : state {
+    tx_valid => rx_valid;
+    then => idle;
+}
+",
+        ];
+        for source in sources {
+            let design = parse(source).unwrap();
+            assert_eq!(from_json(&to_json(&design)).unwrap(), design);
+        }
+    }
+
+    #[test]
+    fn layout_test() {
+        let design =
+            parse("mem => reg[15:0][3:0];\n.main : state { mem[0] => 1; then => main; }").unwrap();
+        let json: serde_json::Value = serde_json::from_str(&to_json(&design)).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "version": 4,
+                "declarations": [
+                    {"kind": "register", "name": "mem", "bits": [3, 0], "array": [15, 0]}
+                ],
+                "nodes": [{
+                    "name": "main",
+                    "type": "state",
+                    "commands": [
+                        {"kind": "transfer", "target": "mem[0]", "value": "1"},
+                        {"kind": "then", "next": "main"}
+                    ]
+                }]
+            })
+        );
+    }
+
+    #[test]
+    fn version_test() {
+        let err = from_json(r#"{"version": 99, "declarations": [], "nodes": []}"#).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "error: unsupported JSON design version 99, expected 1 to 4"
+        );
+        assert!(from_json(r#"{"version": 0, "declarations": [], "nodes": []}"#).is_err());
+
+        // Version 1 had no machines, so its nodes all belong to the unnamed one.
+        let design = from_json(
+            r#"{"version": 1, "declarations": [], "nodes": [
+                {"name": "main", "type": "state", "commands": [{"kind": "then", "next": "main"}]}
+            ]}"#,
+        )
+        .unwrap();
+        assert_eq!(design.machines(), vec![""]);
+
+        // Before version 3 inouts were read at their current level.
+        let design = from_json(
+            r#"{"version": 2, "declarations": [
+                {"kind": "inout", "name": "data", "bits": [7, 0], "array": [0, 0]}
+            ], "nodes": []}"#,
+        )
+        .unwrap();
+        assert_eq!(design.commands[0].to_string(), "data => inout[7:0]");
+
+        let design = parse("go => input async;\n.main : state { then => main; }").unwrap();
+        let json = to_json(&design);
+        assert!(json.contains("\"async\": true"));
+        assert_eq!(from_json(&json).unwrap(), design);
+    }
+}
@@ -0,0 +1,819 @@
+//! Compiler from `.asmc` ASM charts to Verilog and the other supported
+//! targets. `parse` turns source text into a [`Design`], `check` reports
+//! what is wrong with it and the `emit_*` functions lower it.
+mod builder;
+mod call;
+mod check;
+mod clock;
+pub mod command;
+mod cpp_code_gen;
+mod dot_code_gen;
+mod enums;
+pub mod expr;
+mod include;
+mod json;
+pub mod library;
+mod memory;
+mod model;
+mod naming;
+pub mod node;
+mod primitive;
+mod rust_code_gen;
+mod sim;
+mod state_diagram_code_gen;
+mod stream;
+mod sv_code_gen;
+mod template;
+mod transition;
+mod vectors;
+mod verilog_code_gen;
+mod vhdl_code_gen;
+mod wait;
+
+pub use builder::DesignBuilder;
+pub use command::{Command, UnableToParseError};
+pub use include::{load, resolve, Import, Loaded};
+pub use json::{from_json, to_json, JSON_VERSION};
+pub use library::Library;
+pub use naming::Naming;
+pub use node::{Node, NodeType};
+pub use sim::Simulator;
+pub use vectors::{parse_assignment, run_vectors, VectorReport};
+
+use naming::{Names, CPP_KEYWORDS, RUST_KEYWORDS, VERILOG_KEYWORDS, VHDL_KEYWORDS};
+use primitive::Primitive;
+use regex::Regex;
+use std::fmt::Display;
+
+/// A parsed chart: its top-level declarations and its nodes, both in
+/// source order. The first state node is the one entered on reset.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Design {
+    pub commands: Vec<Command>,
+    pub nodes: Vec<Node>,
+}
+
+impl Design {
+    /// The name given by a `module => name;` directive, if any.
+    pub fn module_name(&self) -> Option<&str> {
+        self.commands.iter().find_map(|cmd| match cmd {
+            Command::Module { module_name } => Some(module_name.as_str()),
+            _ => None,
+        })
+    }
+    /// See [`node::machines`].
+    pub fn machines(&self) -> Vec<&str> {
+        node::machines(&self.nodes)
+    }
+}
+
+/// Canonical `.asmc` source: declarations first, then the nodes, each
+/// separated by a blank line. This is what `fmt` writes.
+impl Display for Design {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (idx, cmd) in self.commands.iter().enumerate() {
+            let block = matches!(cmd, Command::Template { .. });
+            // Blocks stand apart like nodes; they and enums take no `;`.
+            if block && idx > 0 {
+                writeln!(f)?;
+            }
+            match cmd {
+                Command::Template { .. } | Command::Enum { .. } => writeln!(f, "{}", cmd)?,
+                cmd => writeln!(f, "{};", cmd)?,
+            }
+            if block && idx + 1 < self.commands.len() {
+                writeln!(f)?;
+            }
+        }
+        let mut machine = "";
+        for node in self.nodes.iter() {
+            if node.machine != machine {
+                machine = &node.machine;
+                writeln!(f)?;
+                writeln!(f, "machine => {};", machine)?;
+            }
+            writeln!(f)?;
+            write!(f, "{}", node)?;
+        }
+        Ok(())
+    }
+}
+
+/// Settings shared by every backend.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Options {
+    pub module_name: String,
+    /// Modules that `instance` declarations refer to.
+    pub library: Library,
+    /// Verilog: the clock and reset inputs of machines without a `clock`
+    /// declaration.
+    pub clock: String,
+    pub reset: String,
+    /// Verilog: how the module header declares its ports.
+    pub header: Header,
+    /// Verilog: compile the chart as `{module_name}_core` and wrap it in a
+    /// `module_name` whose outputs are plain wires rather than `reg`.
+    pub wrapper: bool,
+    /// How the registers and wires the backends add are named.
+    pub naming: Naming,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            module_name: "Top".to_string(),
+            library: Library::new(),
+            clock: "clk".to_string(),
+            reset: "reset".to_string(),
+            header: Header::Ansi,
+            wrapper: false,
+            naming: Naming::default(),
+        }
+    }
+}
+
+/// Where a Verilog module declares its ports.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Header {
+    /// `module Top(input clk , output reg [7:0]y);`
+    #[default]
+    Ansi,
+    /// `module Top(clk , y);` followed by `input clk;` and so on, for tools
+    /// and top-levels that predate Verilog-2001.
+    NonAnsi,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    /// Node the diagnostic is about, if it is about one.
+    pub node: Option<String>,
+}
+
+impl Diagnostic {
+    pub fn error(message: String) -> Self {
+        Diagnostic {
+            severity: Severity::Error,
+            message,
+            node: None,
+        }
+    }
+    pub fn warning(message: String) -> Self {
+        Diagnostic {
+            severity: Severity::Warning,
+            message,
+            node: None,
+        }
+    }
+    pub fn in_node(mut self, node: &str) -> Self {
+        self.node = Some(node.to_string());
+        self
+    }
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+}
+
+impl From<UnableToParseError> for Diagnostic {
+    fn from(err: UnableToParseError) -> Self {
+        Diagnostic::error(err.to_string())
+    }
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.severity {
+            Severity::Error => write!(f, "error: ")?,
+            Severity::Warning => write!(f, "warning: ")?,
+        }
+        write!(f, "{}", self.message)?;
+        if let Some(node) = &self.node {
+            write!(f, " (in node `{}`)", node)?;
+        }
+        Ok(())
+    }
+}
+
+/// Parses the declarations between two nodes; a `machine =>` directive
+/// among them changes `machine` instead of becoming a command.
+fn parse_commands(
+    text: &str,
+    commands: &mut Vec<Command>,
+    machine: &mut String,
+) -> Result<(), Diagnostic> {
+    for cmd_text in text.split(';') {
+        let cmd: Command = cmd_text.parse().map_err(|err: UnableToParseError| {
+            Diagnostic::error(format!("{} in `{}`", err, cmd_text.trim()))
+        })?;
+        match cmd {
+            Command::Empty => {}
+            Command::Machine { machine_name } => *machine = machine_name,
+            cmd => commands.push(cmd),
+        }
+    }
+    Ok(())
+}
+
+/// Parses `.asmc` source text into a design. Only the syntax is validated
+/// here; run [`check`] before emitting.
+pub fn parse(source: &str) -> Result<Design, Diagnostic> {
+    let header = Regex::new(
+        r"(?:\.([a-zA-Z0-9_]+) *: *(state|decision|conditional)|\btemplate\s+([a-zA-Z_][a-zA-Z0-9_]*)\s*\(([^()]*)\)|\benum\s+([a-zA-Z_][a-zA-Z0-9_]*)) *\{",
+    )
+    .unwrap();
+
+    let mut nodes: Vec<Node> = vec![];
+    let mut commands: Vec<Command> = vec![];
+    let mut machine = String::new();
+
+    let mut rest = source;
+    while let Some(capt) = header.captures(rest) {
+        let whole = capt.get(0).unwrap();
+        parse_commands(&rest[..whole.start()], &mut commands, &mut machine)?;
+
+        // Bodies may hold `{a, b}` concatenations, so find the brace that
+        // closes the block rather than the first one.
+        let mut depth = 1;
+        let body_end = rest[whole.end()..]
+            .find(|c| {
+                match c {
+                    '{' => depth += 1,
+                    '}' => depth -= 1,
+                    _ => {}
+                }
+                depth == 0
+            })
+            .map(|idx| whole.end() + idx);
+        let body = body_end.map(|body_end| &rest[whole.end()..body_end]);
+        if let (Some(name), Some(node_type)) = (capt.get(1), capt.get(2)) {
+            let node_name = name.as_str();
+            let body = body
+                .ok_or_else(|| Diagnostic::error("missing `}`".to_string()).in_node(node_name))?;
+            let mut node = Node::try_parse(node_name, node_type.as_str(), body)
+                .map_err(|err| Diagnostic::from(err).in_node(node_name))?;
+            node.machine = machine.clone();
+            nodes.push(node);
+        } else if let Some(name) = capt.get(5) {
+            let body = body.ok_or_else(|| {
+                Diagnostic::error(format!("missing `}}` in enum `{}`", name.as_str()))
+            })?;
+            commands.push(Command::Enum {
+                name: name.as_str().to_string(),
+                members: command::split_list(body),
+            });
+        } else {
+            let name = &capt[3];
+            let in_template =
+                |message: String| Diagnostic::error(format!("in template `{}`: {}", name, message));
+            let body = body.ok_or_else(|| in_template("missing `}`".to_string()))?;
+            let template = parse(body).map_err(|mut diagnostic| {
+                diagnostic.message = format!("in template `{}`: {}", name, diagnostic.message);
+                diagnostic
+            })?;
+            if let Some(cmd) = template.commands.first() {
+                return Err(in_template(format!(
+                    "`{}` is not a node, templates hold only nodes",
+                    cmd
+                )));
+            }
+            commands.push(Command::Template {
+                name: name.to_string(),
+                params: command::split_list(&capt[4]),
+                nodes: template.nodes,
+            });
+        }
+        rest = &rest[body_end.unwrap() + 1..];
+    }
+    parse_commands(rest, &mut commands, &mut machine)?;
+    node::number_states(&mut nodes);
+    let mut design = Design { commands, nodes };
+    enums::size(&mut design);
+    Ok(design)
+}
+
+/// Reports everything that would make the design fail to compile or
+/// behave unexpectedly. Backends assume there are no errors.
+pub fn check(design: &Design) -> Vec<Diagnostic> {
+    check::check(design, &Options::default())
+}
+
+/// [`check`] for a design compiled with `options`: its `instance`
+/// declarations refer to modules in `options.library`, and machines without
+/// a `clock` declaration run on `options.clock` and `options.reset`.
+pub fn check_with(design: &Design, options: &Options) -> Vec<Diagnostic> {
+    check::check(design, options)
+}
+
+/// Rewrites `call`, `return` and the waits into plain registers, transfers
+/// and decisions, and streams into their ports, which is all the backends
+/// handle. The registers it adds are named by `names`.
+pub(crate) fn lower(design: &Design, names: &mut Names) -> Design {
+    stream::lower(&wait::lower(&call::lower(design, names), names))
+}
+
+/// [`lower`] for the outputs that only draw the chart.
+fn drawn(design: &Design, options: &Options) -> Design {
+    let mut names = Names::new(
+        &options.naming,
+        &design.commands,
+        &clock::DEFAULT,
+        &[],
+        false,
+    );
+    lower(design, &mut names)
+}
+
+/// Backends that cannot lower `instance` or `memory` declarations, clocking
+/// or more than one machine yet.
+fn flat_only(design: &Design, backend: &str) -> Result<(), Diagnostic> {
+    if let Some((instance, _)) = library::instances(design).next() {
+        return Err(Diagnostic::error(format!(
+            "the {} backend cannot emit instance `{}`, use Verilog",
+            backend, instance
+        )));
+    }
+    let block = design.commands.iter().find_map(|cmd| match cmd {
+        Command::Memory { mem_name, .. } => Some(("memory", mem_name.as_str())),
+        cmd => Primitive::of(cmd).map(|primitive| (primitive.kind.name(), primitive.name)),
+    });
+    if let Some((kind, name)) = block {
+        return Err(Diagnostic::error(format!(
+            "the {} backend cannot emit {} `{}`, use Verilog",
+            backend, kind, name
+        )));
+    }
+    for cmd in design.commands.iter() {
+        match cmd {
+            Command::Clock { .. } => {
+                return Err(Diagnostic::error(format!(
+                    "the {} backend cannot emit `{}`, use Verilog",
+                    backend, cmd
+                )))
+            }
+            Command::Input {
+                pin_name,
+                asynchronous: true,
+                ..
+            } => {
+                return Err(Diagnostic::error(format!(
+                    "the {} backend cannot emit async input `{}`, use Verilog",
+                    backend, pin_name
+                )))
+            }
+            _ => {}
+        }
+    }
+    if design.machines().len() > 1 {
+        return Err(Diagnostic::error(format!(
+            "the {} backend cannot emit more than one machine, use Verilog",
+            backend
+        )));
+    }
+    Ok(())
+}
+
+pub fn emit_verilog(design: &Design, options: &Options) -> Result<String, Diagnostic> {
+    options.naming.validate()?;
+    let clocking = clock::Domain {
+        clock: &options.clock,
+        reset: &options.reset,
+        ..clock::DEFAULT
+    };
+    let mut names = Names::new(
+        &options.naming,
+        &design.commands,
+        &clocking,
+        VERILOG_KEYWORDS,
+        false,
+    );
+    let design = &lower(design, &mut names);
+    let design = &clock::with_defaults(design, &options.clock, &options.reset);
+    let mut subs = vec![];
+    for (instance, module) in library::instances(design) {
+        let sub = options.library.get(module).ok_or_else(|| {
+            Diagnostic::error(format!("module `{}` is not in the library", module))
+        })?;
+        // The modules of a library are compiled with the same options.
+        let sub = clock::with_defaults(sub, &options.clock, &options.reset);
+        subs.push((instance, module, sub));
+    }
+    let instances: Vec<_> = subs
+        .iter()
+        .map(|(instance, module, sub)| {
+            let clocking = clock::ports(&sub.commands, &sub.machines());
+            (*instance, *module, library::ports(sub), clocking)
+        })
+        .collect();
+    if !options.wrapper {
+        return Ok(verilog_code_gen::emit_verilog(
+            &options.module_name,
+            &design.commands,
+            &design.nodes,
+            &instances,
+            (options.header, &options.naming, &clocking),
+        )?);
+    }
+    for port in library::ports(design) {
+        if port.is_array() {
+            return Err(Diagnostic::error(format!(
+                "the wrapper cannot pass array port `{}`",
+                port.name
+            )));
+        }
+        if port.name == "core" {
+            return Err(Diagnostic::error(
+                "port `core` clashes with the instance the wrapper makes of the chart".to_string(),
+            ));
+        }
+    }
+    let core = format!("{}_core", options.module_name);
+    let mut code = verilog_code_gen::emit_verilog(
+        &core,
+        &design.commands,
+        &design.nodes,
+        &instances,
+        (options.header, &options.naming, &clocking),
+    )?;
+    code.push_str(&verilog_code_gen::wrap(
+        &options.module_name,
+        &core,
+        &design.commands,
+        &design.nodes,
+        options.header,
+    ));
+    Ok(code)
+}
+
+pub fn emit_sv(design: &Design, options: &Options) -> Result<String, Diagnostic> {
+    let mut names = Names::new(
+        &options.naming,
+        &design.commands,
+        &clock::DEFAULT,
+        VERILOG_KEYWORDS,
+        false,
+    );
+    let design = &lower(design, &mut names);
+    flat_only(design, "SystemVerilog")?;
+    options.naming.validate()?;
+    sv_code_gen::spellable(&options.module_name, &design.commands)?;
+    Ok(sv_code_gen::emit_sv(
+        &options.module_name,
+        &design.commands,
+        &design.nodes,
+        &options.naming,
+    )?)
+}
+
+pub fn emit_vhdl(design: &Design, options: &Options) -> Result<String, Diagnostic> {
+    let mut names = Names::new(
+        &options.naming,
+        &design.commands,
+        &clock::DEFAULT,
+        VHDL_KEYWORDS,
+        true,
+    );
+    let design = &enums::inline(&lower(design, &mut names));
+    flat_only(design, "VHDL")?;
+    options.naming.validate()?;
+    vhdl_code_gen::spellable(&options.module_name, &design.commands)?;
+    Ok(vhdl_code_gen::emit_vhdl(
+        &options.module_name,
+        &design.commands,
+        &design.nodes,
+        &options.naming,
+    )?)
+}
+
+pub fn emit_cpp(design: &Design, options: &Options) -> Result<String, Diagnostic> {
+    let mut names = Names::new(
+        &options.naming,
+        &design.commands,
+        &clock::DEFAULT,
+        CPP_KEYWORDS,
+        false,
+    );
+    let design = &enums::inline(&lower(design, &mut names));
+    flat_only(design, "C++")?;
+    options.naming.validate()?;
+    cpp_code_gen::spellable(&options.module_name, &design.commands)?;
+    Ok(cpp_code_gen::emit_cpp(
+        &options.module_name,
+        &design.commands,
+        &design.nodes,
+        &options.naming,
+    )?)
+}
+
+/// Emits a Rust cycle model; callable from a `build.rs` so that tests can
+/// `include!` the generated struct.
+pub fn emit_rust(design: &Design, options: &Options) -> Result<String, Diagnostic> {
+    let mut names = Names::new(
+        &options.naming,
+        &design.commands,
+        &clock::DEFAULT,
+        RUST_KEYWORDS,
+        false,
+    );
+    let design = &enums::inline(&lower(design, &mut names));
+    flat_only(design, "Rust")?;
+    options.naming.validate()?;
+    rust_code_gen::spellable(&options.module_name, &design.commands)?;
+    Ok(rust_code_gen::emit_rust(
+        &options.module_name,
+        &design.commands,
+        &design.nodes,
+        &options.naming,
+    )?)
+}
+
+pub fn emit_dot(design: &Design, options: &Options) -> String {
+    let design = &drawn(design, options);
+    dot_code_gen::emit_dot(&options.module_name, &design.nodes)
+}
+
+pub fn emit_mermaid(design: &Design, options: &Options) -> Result<String, Diagnostic> {
+    let design = &drawn(design, options);
+    Ok(state_diagram_code_gen::emit_mermaid(
+        &options.module_name,
+        &design.nodes,
+    )?)
+}
+
+pub fn emit_plantuml(design: &Design, options: &Options) -> Result<String, Diagnostic> {
+    let design = &drawn(design, options);
+    Ok(state_diagram_code_gen::emit_plantuml(
+        &options.module_name,
+        &design.nodes,
+    )?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_test() {
+        let design = parse(
+            "
+go => input;
+r0 => reg[3:0];
+
+.idle : state {
+    then => check;
+}
+.check : decision {
+    check => go;
+    yes => busy;
+    no => idle;
+}
+.busy : state {
+    r0 => r0 + 1;
+    then => idle;
+}
+",
+        )
+        .unwrap();
+        assert_eq!(design.commands.len(), 2);
+        assert_eq!(design.nodes.len(), 3);
+        assert_eq!(design.nodes[2].id, 1);
+        assert!(check(&design).is_empty());
+        let verilog = emit_verilog(&design, &Options::default()).unwrap();
+        assert!(verilog.contains("module Top(input clk , input reset , input [0:0]go);"));
+    }
+
+    #[test]
+    fn format_test() {
+        let source = "a=>input[ 3:0 ];mem => reg[15:0][3:0];go => output;\n\n.main:state{mem[a]=>a+1;then=>main;}";
+        let formatted = parse(source).unwrap().to_string();
+        assert_eq!(
+            formatted,
+            "a => input[3:0];
+mem => reg[15:0][3:0];
+go => output;
+
+.main : state {
+    mem[a] => a+1;
+    then => main;
+}
+"
+        );
+        for file in ["multiplier.asmc", "ram.asmc", "sampleformat.asmc"] {
+            let design = parse(&std::fs::read_to_string(file).unwrap()).unwrap();
+            assert_eq!(parse(&design.to_string()).unwrap(), design);
+        }
+    }
+
+    #[test]
+    fn instance_test() {
+        let mut options = Options::default();
+        options.library.insert(
+            "multiplier",
+            parse(&std::fs::read_to_string("multiplier.asmc").unwrap()).unwrap(),
+        );
+        let design = parse(
+            "
+go => input;
+m => instance multiplier;
+.idle : state {
+    m.a => 3;
+    m.b => 5;
+    m.start => go;
+    then => wait;
+}
+.wait : decision {
+    check => m.ready;
+    yes => idle;
+    no => idle;
+}
+",
+        )
+        .unwrap();
+        assert!(check_with(&design, &options).is_empty());
+        let verilog = emit_verilog(&design, &options).unwrap();
+        assert!(verilog.contains("\nreg [0:0]m_start;\nwire [7:0]m_res;\nwire [0:0]m_ready;"));
+        assert!(verilog.contains(
+            "\nmultiplier m(.clk(clk) , .reset(reset) , .a(m_a) , .b(m_b) , .start(m_start) , .res(m_res) , .ready(m_ready));"
+        ));
+        assert!(verilog.contains("\nm_start <= go;"));
+        assert!(verilog.contains("\nif (m_ready) begin"));
+        assert!(emit_vhdl(&design, &options).is_err());
+    }
+
+    #[test]
+    fn wrapper_test() {
+        let options = Options {
+            wrapper: true,
+            ..Options::default()
+        };
+        let design = parse("core => input;\n.idle : state {\n    then => idle;\n}\n").unwrap();
+        assert_eq!(
+            emit_verilog(&design, &options).unwrap_err().message,
+            "port `core` clashes with the instance the wrapper makes of the chart"
+        );
+        let design =
+            parse("pins => input[1:0][3:0];\n.idle : state {\n    then => idle;\n}\n").unwrap();
+        assert_eq!(
+            emit_verilog(&design, &options).unwrap_err().message,
+            "the wrapper cannot pass array port `pins`"
+        );
+    }
+
+    #[test]
+    fn machines_test() {
+        let source = "count => reg[3:0];
+priority => bus, main;
+
+machine => main;
+
+.idle : state {
+    count => count + 1;
+    then => idle;
+}
+
+machine => bus;
+
+.wait : state {
+    count => 0;
+    then => wait;
+}
+";
+        let design = parse(source).unwrap();
+        assert_eq!(design.machines(), vec!["main", "bus"]);
+        assert_eq!(design.nodes[1].id, 0);
+        assert_eq!(design.to_string(), source);
+        assert_eq!(from_json(&to_json(&design)).unwrap(), design);
+        assert!(check(&design).is_empty());
+
+        let verilog = emit_verilog(&design, &Options::default()).unwrap();
+        let main = verilog.find("\ncount <= count + 1;").unwrap();
+        let bus = verilog.find("\ncount <= 0;").unwrap();
+        assert!(main < bus, "the machine listed first must assign last");
+        assert_eq!(verilog.matches("always @(posedge reset)").count(), 2);
+        assert!(emit_cpp(&design, &Options::default()).is_err());
+    }
+
+    #[test]
+    fn literal_width_test() {
+        // An unsized number is 32 bits, so `a + a` keeps its carry.
+        let design = parse(
+            "a => reg[3:0];
+hit => output;
+
+.init : state {
+    a => 15;
+    then => test;
+}
+
+.test : decision {
+    check => a + a == 14;
+    yes => found;
+    no => init;
+}
+
+.found : conditional {
+    hit => 1;
+    then => init;
+}
+",
+        )
+        .unwrap();
+        let mut sim = Simulator::new(&design).unwrap();
+        for _ in 0..4 {
+            sim.step().unwrap();
+            assert_eq!(sim.get("hit", None), Some(0));
+        }
+        let options = Options::default();
+        assert!(emit_verilog(&design, &options)
+            .unwrap()
+            .contains("if (a + a == 14) begin"));
+        assert!(emit_cpp(&design, &options)
+            .unwrap()
+            .contains("if ((((uint64_t)a + a) & 0xffffffffULL) == 14ULL) {"));
+        assert!(emit_rust(&design, &options)
+            .unwrap()
+            .contains("(self.a as u64).wrapping_add((self.a as u64)) & 0xffffffff) == 14u64"));
+    }
+
+    #[test]
+    fn naming_test() {
+        let source = "currentState => reg[1:0];
+data => inout[3:0];
+
+.idle : state {
+    data => currentState;
+    then => idle;
+}
+";
+        let design = parse(source).unwrap();
+        let verilog = emit_verilog(&design, &Options::default()).unwrap();
+        // The state register steps aside for the chart's own `currentState`.
+        assert!(verilog.contains("\nreg [0:0]currentState_2;\nreg [1 : 0]currentState;"));
+        assert!(verilog.contains("\nassign data = data_write_reg ? data_out : {4{1'bz}};"));
+
+        let options = Options {
+            naming: Naming {
+                prefix: "asm_".to_string(),
+                suffix: "_r".to_string(),
+            },
+            ..Options::default()
+        };
+        let verilog = emit_verilog(&design, &options).unwrap();
+        assert!(verilog.contains("\nreg [0:0]asm_currentState_r;"));
+        assert!(verilog.contains("\nassign data = asm_data_write_reg_r ? asm_data_out_r"));
+        let vhdl = emit_vhdl(&design, &options).unwrap();
+        assert!(vhdl.contains("asm_data_write_reg_r"));
+
+        let options = Options {
+            naming: Naming {
+                prefix: "1x".to_string(),
+                suffix: String::new(),
+            },
+            ..Options::default()
+        };
+        assert!(emit_verilog(&design, &options).is_err());
+    }
+
+    #[test]
+    fn flat_only_test() {
+        let design = parse(
+            "mem => memory[7:0] depth 16;\nr0 => reg[7:0];\n.idle : state {\n    r0 => mem[0];\n    then => idle;\n}\n",
+        )
+        .unwrap();
+        assert_eq!(
+            emit_cpp(&design, &Options::default()).unwrap_err().message,
+            "the C++ backend cannot emit memory `mem`, use Verilog"
+        );
+
+        let design =
+            parse("q => fifo[8] depth 16;\n.idle : state {\n    q.pop();\n    then => idle;\n}\n")
+                .unwrap();
+        assert_eq!(
+            emit_rust(&design, &Options::default()).unwrap_err().message,
+            "the Rust backend cannot emit fifo `q`, use Verilog"
+        );
+
+        let design =
+            parse("go => input async;\nr0 => reg;\n.idle : state {\n    r0 => go;\n    then => idle;\n}\n")
+                .unwrap();
+        assert_eq!(
+            emit_sv(&design, &Options::default()).unwrap_err().message,
+            "the SystemVerilog backend cannot emit async input `go`, use Verilog"
+        );
+    }
+
+    #[test]
+    fn parse_error_test() {
+        let err = parse("r0 reg;\n.idle : state { then => idle; }").unwrap_err();
+        assert!(err.is_error());
+        assert_eq!(err.node, None);
+        let err = parse(".idle : state { then idle; }").unwrap_err();
+        assert_eq!(err.node, Some("idle".to_string()));
+    }
+}
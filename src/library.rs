@@ -0,0 +1,106 @@
+use crate::command::Command;
+use crate::stream;
+use crate::Design;
+use std::{collections::BTreeMap, ops::Range};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Direction {
+    Input,
+    Output,
+    Inout,
+}
+
+/// A port of a module as seen by a chart instantiating it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Port {
+    pub name: String,
+    pub direction: Direction,
+    pub bits: Range<u8>,
+    pub array: Range<u8>,
+}
+
+impl Port {
+    pub fn width(&self) -> u32 {
+        self.bits.start.abs_diff(self.bits.end) as u32 + 1
+    }
+    pub fn is_array(&self) -> bool {
+        self.array.start != self.array.end || self.array.start != 0
+    }
+}
+
+/// The ports of `design` in declaration order, without its clocks, resets
+/// and clock enables.
+pub fn ports(design: &Design) -> Vec<Port> {
+    command_ports(&design.commands)
+}
+
+/// The ports `commands` declare, those of streams included.
+pub fn command_ports(commands: &[Command]) -> Vec<Port> {
+    stream::expand(commands)
+        .iter()
+        .filter_map(|cmd| {
+            let (name, direction, bits, array) = match cmd {
+                Command::Input {
+                    pin_name,
+                    bits,
+                    array,
+                    ..
+                } => (pin_name, Direction::Input, bits, array),
+                Command::Output {
+                    pin_name,
+                    bits,
+                    array,
+                    ..
+                } => (pin_name, Direction::Output, bits, array),
+                Command::Inout {
+                    pin_name,
+                    bits,
+                    array,
+                    ..
+                } => (pin_name, Direction::Inout, bits, array),
+                _ => return None,
+            };
+            Some(Port {
+                name: name.clone(),
+                direction,
+                bits: bits.clone(),
+                array: array.clone(),
+            })
+        })
+        .collect()
+}
+
+/// Wire in the parent module that carries `port` of `instance`; also what
+/// `instance.port` is rewritten to in checks and transfers.
+pub fn port_wire(instance: &str, port: &str) -> String {
+    format!("{}_{}", instance, port)
+}
+
+/// The modules `instance` declarations can refer to, by module name.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Library {
+    modules: BTreeMap<String, Design>,
+}
+
+impl Library {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn insert(&mut self, module_name: &str, design: Design) {
+        self.modules.insert(module_name.to_string(), design);
+    }
+    pub fn get(&self, module_name: &str) -> Option<&Design> {
+        self.modules.get(module_name)
+    }
+}
+
+/// `(instance, module)` for every `instance` declaration in `design`.
+pub fn instances(design: &Design) -> impl Iterator<Item = (&str, &str)> {
+    design.commands.iter().filter_map(|cmd| match cmd {
+        Command::Instance {
+            instance_name,
+            module_name,
+        } => Some((instance_name.as_str(), module_name.as_str())),
+        _ => None,
+    })
+}
@@ -1,452 +1,651 @@
-mod command;
-mod node;
-mod verilog_code_gen;
-use command::{Command, UnableToParseError};
-use node::Node;
-use regex::Regex;
+use asm_to_verilog_compiler::{
+    check_with, from_json, parse, parse_assignment, resolve, run_vectors, Design, Diagnostic,
+    Header, Import, Library, Loaded, Naming, Options, Simulator,
+};
 use std::{
-    collections::{HashMap, HashSet},
-    fs::read_to_string,
-    ops::Range,
+    io::{Read, Write},
     path::Path,
-    str::FromStr,
+    process::ExitCode,
 };
-use verilog_code_gen::*;
-
-use crate::node::NodeType;
-
-fn main() -> Result<(), UnableToParseError> {
-    let mut all_args = std::env::args();
-    let file_path = all_args.nth(1).expect("no file given");
-    let mut contents = read_to_string(Path::new(&file_path))
-        .expect("unable to read file")
-        .to_string();
-    contents.push_str(
-        "
-.random_default_node : state{}
-",
-    );
 
-    let mut outpath = "output.v".to_string();
-    let mut module = "Top".to_string();
-    while let Some(flag_name) = all_args.next() {
-        match flag_name.as_ref() {
-            "-o" | "--output" => {
-                if let Some(path_dir) = all_args.next() {
-                    outpath = path_dir;
-                }
-            }
-            "-n" | "--name" => {
-                if let Some(mod_name) = all_args.next() {
-                    module = mod_name;
-                }
-            }
-            _ => {}
-        }
+const USAGE: &str = "\
+Usage: asm_to_verilog_compiler <COMMAND> [OPTIONS] <INPUT>...
+
+Commands:
+  build   Compile charts to HDL or cycle models, one module per input
+  check   Report problems in charts without generating anything
+  sim     Run a chart cycle by cycle and print every signal
+  fmt     Rewrite a chart in canonical layout
+  graph   Export the state diagram of a chart
+  test    Run a chart against a test vector file: test <INPUT> <VECTORS>
+
+Options:
+  -o, --output <FILE>   Where to write the result, `-` for stdout
+  -n, --name <NAME>     Module name [default: the `module` directive, else
+                        the input file stem]
+  -d, --out-dir <DIR>   build: write one `<module>.<ext>` per input into DIR
+      --filelist <FILE> build: list every written file, one per line
+  -t, --target <KIND>   build: verilog, sv, vhdl, cpp, rust or json [default: verilog]
+      --clock <NAME>    build, check: clock input of machines without a
+                        `clock` declaration [default: clk]
+      --reset <NAME>    build, check: their reset input [default: reset]
+      --header <KIND>   build: ansi or non-ansi port declarations [default: ansi]
+      --wrapper         build: wrap each module in one whose outputs are
+                        plain wires, the chart becoming `<module>_core`
+      --name-prefix <TEXT>
+                        build: start every generated signal name with TEXT
+      --name-suffix <TEXT>
+                        build: end every generated signal name with TEXT
+  -f, --format <KIND>   graph: dot, mermaid or plantuml [default: dot]
+  -c, --cycles <N>      sim: clock edges to run [default: 20]
+  -s, --set <NAME=VAL>  sim: drive an input for the whole run, repeatable
+      --check           fmt: only report whether the file is formatted
+  -h, --help            Print this help
+  -V, --version         Print the version
+
+Without `-o` or `-d`, `build` writes `<module>.<ext>` into the working
+directory; `-o` puts every module into one file. INPUT may be `-` to read
+standard input. Inputs ending in `.json` are read
+as the JSON form written by `build -t json`.
+
+Exit status: 0 on success, 1 when the chart has errors, a test fails or
+`fmt --check` finds changes, 2 on invalid usage or unreadable files.";
+
+/// Why the command failed, which decides the exit status.
+enum Failure {
+    /// Errors in the chart or its tests; already reported.
+    Design,
+    /// An error in the chart, reported on exit.
+    Diagnostic(Diagnostic),
+    Usage(String),
+    Io(String),
+}
+
+impl From<Diagnostic> for Failure {
+    fn from(diagnostic: Diagnostic) -> Self {
+        Failure::Diagnostic(diagnostic)
     }
+}
 
-    let node_regex =
-        Regex::new(r"([^}]*)\.([a-zA-Z0-9_]+) *: *(state|decision|conditional) *\{([^.]*)}([^.]*)")
-            .unwrap();
-
-    let mut all_nodes: Vec<node::Node> = vec![];
-    let mut top_level_commands: Vec<Command> = vec![];
-
-    for capt in node_regex.captures_iter(&contents) {
-        let pre_node = capt.get(1).unwrap();
-        let node_name = capt.get(2).unwrap();
-        let node_type = capt.get(3).unwrap();
-        let node_content = capt.get(4).unwrap();
-        let post_node = capt.get(5).unwrap();
-        all_nodes.push(Node::try_parse(
-            node_name.as_str(),
-            node_type.as_str(),
-            node_content.as_str(),
-        )?);
-
-        for cmd in pre_node.as_str().split(';') {
-            let cmd: Command = cmd.parse()?;
-            if cmd != Command::Empty {
-                top_level_commands.push(cmd);
+#[derive(Default)]
+struct Args {
+    command: String,
+    inputs: Vec<String>,
+    output: Option<String>,
+    name: Option<String>,
+    out_dir: Option<String>,
+    filelist: Option<String>,
+    target: Option<String>,
+    clock: Option<String>,
+    reset: Option<String>,
+    header: Option<Header>,
+    wrapper: bool,
+    name_prefix: Option<String>,
+    name_suffix: Option<String>,
+    format: Option<String>,
+    cycles: Option<u64>,
+    sets: Vec<String>,
+    check: bool,
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Option<Args>, Failure> {
+    let mut parsed = Args::default();
+    while let Some(arg) = args.next() {
+        let mut value = |flag: &str| {
+            args.next()
+                .ok_or_else(|| Failure::Usage(format!("`{}` needs a value", flag)))
+        };
+        match arg.as_ref() {
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return Ok(None);
             }
-        }
-        for cmd in post_node.as_str().split(';') {
-            let cmd: Command = cmd.parse()?;
-            if cmd != Command::Empty {
-                top_level_commands.push(cmd);
+            "-V" | "--version" => {
+                println!("asm_to_verilog_compiler {}", env!("CARGO_PKG_VERSION"));
+                return Ok(None);
             }
-        }
-    }
-    all_nodes.pop();
-
-    let mut code = Code {
-        code: String::new(),
-        hsh: 1231332,
-    };
-    let mut params = vec![];
-    for cmd in top_level_commands.iter() {
-        match cmd {
-            Command::Input {
-                pin_name,
-                bits,
-                array,
-            } => {
-                if array.start != array.end || array.start != 0 {
-                    params.push(format!(
-                        "input [{}:{}]{}[{}:{}]",
-                        bits.start, bits.end, pin_name, array.start, array.end
-                    ));
-                } else {
-                    params.push(format!("input [{}:{}]{}", bits.start, bits.end, pin_name));
-                }
+            "-o" | "--output" => parsed.output = Some(value(&arg)?),
+            "-n" | "--name" => parsed.name = Some(value(&arg)?),
+            "-d" | "--out-dir" => parsed.out_dir = Some(value(&arg)?),
+            "--filelist" => parsed.filelist = Some(value(&arg)?),
+            "-t" | "--target" => parsed.target = Some(value(&arg)?),
+            "--clock" => parsed.clock = Some(value(&arg)?),
+            "--reset" => parsed.reset = Some(value(&arg)?),
+            "--header" => {
+                parsed.header = Some(match value(&arg)?.as_str() {
+                    "ansi" => Header::Ansi,
+                    "non-ansi" => Header::NonAnsi,
+                    other => {
+                        return Err(Failure::Usage(format!(
+                            "unknown header `{}`, use ansi or non-ansi",
+                            other
+                        )))
+                    }
+                })
             }
-            Command::Output {
-                pin_name,
-                bits,
-                array,
-            } => {
-                if array.start != array.end || array.start != 0 {
-                    params.push(format!(
-                        "output reg [{}:{}]{}[{}:{}]",
-                        bits.start, bits.end, pin_name, array.start, array.end
-                    ));
-                } else {
-                    params.push(format!(
-                        "output reg [{}:{}]{}",
-                        bits.start, bits.end, pin_name
-                    ));
-                }
+            "--wrapper" => parsed.wrapper = true,
+            "--name-prefix" => parsed.name_prefix = Some(value(&arg)?),
+            "--name-suffix" => parsed.name_suffix = Some(value(&arg)?),
+            "-f" | "--format" => parsed.format = Some(value(&arg)?),
+            "-c" | "--cycles" => {
+                let cycles = value(&arg)?;
+                parsed.cycles =
+                    Some(cycles.parse().map_err(|_| {
+                        Failure::Usage(format!("`{}` is not a cycle count", cycles))
+                    })?);
             }
-            Command::Inout {
-                pin_name,
-                bits,
-                array,
-            } => {
-                if array.start != array.end || array.start != 0 {
-                    params.push(format!(
-                        "inout [{}:{}]{}[{}:{}]",
-                        bits.start, bits.end, pin_name, array.start, array.end
-                    ));
-                } else {
-                    params.push(format!("inout [{}:{}]{}", bits.start, bits.end, pin_name));
-                }
+            "-s" | "--set" => parsed.sets.push(value(&arg)?),
+            "--check" => parsed.check = true,
+            "-" => parsed.inputs.push(arg),
+            flag if flag.starts_with('-') => {
+                return Err(Failure::Usage(format!("unknown option `{}`", flag)))
             }
-            _ => {}
+            _ if parsed.command.is_empty() => parsed.command = arg,
+            _ => parsed.inputs.push(arg),
         }
     }
+    Ok(Some(parsed))
+}
 
-    code.update(format!(
-        "
-module {}(input clk , input reset , {});",
-        module,
-        params.join(" , ")
-    ));
-
-    for cmd in top_level_commands.iter() {
-        match cmd {
-            Command::Register {
-                reg_name,
-                bits,
-                array,
-            } => {
-                if array.start != array.end || array.start != 0 {
-                    params.push(format!(
-                        "
-reg [{}:{}]{}[{}:{}];",
-                        bits.start, bits.end, reg_name, array.start, array.end
-                    ));
-                } else {
-                    params.push(format!(
-                        "
-reg [{}:{}]{};",
-                        bits.start, bits.end, reg_name
-                    ));
-                }
+impl Args {
+    /// Rejects options that the chosen command would silently ignore.
+    fn allow(&self, allowed: &[&str]) -> Result<(), Failure> {
+        let given = [
+            ("--output", self.output.is_some()),
+            ("--name", self.name.is_some()),
+            ("--out-dir", self.out_dir.is_some()),
+            ("--filelist", self.filelist.is_some()),
+            ("--target", self.target.is_some()),
+            ("--clock", self.clock.is_some()),
+            ("--reset", self.reset.is_some()),
+            ("--header", self.header.is_some()),
+            ("--wrapper", self.wrapper),
+            ("--name-prefix", self.name_prefix.is_some()),
+            ("--name-suffix", self.name_suffix.is_some()),
+            ("--format", self.format.is_some()),
+            ("--cycles", self.cycles.is_some()),
+            ("--set", !self.sets.is_empty()),
+            ("--check", self.check),
+        ];
+        for (flag, present) in given {
+            if present && !allowed.contains(&flag) {
+                return Err(Failure::Usage(format!(
+                    "`{}` does not take `{}`",
+                    self.command, flag
+                )));
             }
-            _ => {}
         }
+        Ok(())
     }
 
-    let mut node_map = HashMap::new();
-    let mut state_count = 0;
-    for node in all_nodes.iter_mut() {
-        if node.node_type == NodeType::State {
-            node.id = state_count;
-            state_count += 1;
+    fn inputs(&self, count: usize) -> Result<&[String], Failure> {
+        if self.inputs.len() != count {
+            return Err(Failure::Usage(format!(
+                "`{}` takes {} input file{}",
+                self.command,
+                count,
+                if count == 1 { "" } else { "s" }
+            )));
         }
+        Ok(&self.inputs)
+    }
+}
+
+fn read_input(path: &str) -> Result<String, Failure> {
+    let mut contents = String::new();
+    let result = if path == "-" {
+        std::io::stdin().read_to_string(&mut contents).map(|_| ())
+    } else {
+        std::fs::read_to_string(path).map(|text| contents = text)
+    };
+    result.map_err(|err| Failure::Io(format!("cannot read `{}`: {}", path, err)))?;
+    Ok(contents)
+}
+
+fn write_output(path: &str, contents: &str) -> Result<(), Failure> {
+    let result = if path == "-" {
+        std::io::stdout().write_all(contents.as_bytes())
+    } else {
+        std::fs::write(path, contents)
+    };
+    result.map_err(|err| Failure::Io(format!("cannot write `{}`: {}", path, err)))
+}
+
+/// The input file name without directory or extension, used for the
+/// default module name and output file.
+fn stem(path: &str) -> Option<&str> {
+    if path == "-" {
+        return None;
+    }
+    Path::new(path).file_stem().and_then(|stem| stem.to_str())
+}
+
+/// Reads a chart and what it includes and imports; paths in stdin input
+/// are relative to the working directory.
+fn read_design(path: &str) -> Result<Loaded, Failure> {
+    let contents = read_input(path)?;
+    if path.ends_with(".json") {
+        from_json(&contents)
+    } else {
+        parse(&contents)
     }
-    for node in all_nodes.iter() {
-        node_map.insert(node.get_name(), node);
+    .and_then(|design| resolve(design, Path::new(path)))
+    .map_err(|diagnostic| {
+        eprintln!("{}: {}", path, diagnostic);
+        Failure::Design
+    })
+}
+
+/// Prints every diagnostic and fails if any of them is an error.
+fn report(path: &str, diagnostics: &[Diagnostic]) -> Result<(), Failure> {
+    for diagnostic in diagnostics.iter() {
+        eprintln!("{}: {}", path, diagnostic);
     }
+    if diagnostics.iter().any(|diagnostic| diagnostic.is_error()) {
+        return Err(Failure::Design);
+    }
+    Ok(())
+}
 
-    let bit_count = state_count.ilog2() as u8;
-    let current_state_reg = code.get_varname(&"currentState".to_string());
-    code.update(format!(
-        "
-reg [{bit_count}:0]{current_state_reg};",
-    ));
+/// Parses and checks a single chart.
+fn load(path: &str) -> Result<Design, Failure> {
+    let loaded = read_design(path)?;
+    let options = Options {
+        library: loaded.library(),
+        ..Options::default()
+    };
+    report(path, &check_with(&loaded.design, &options))?;
+    Ok(loaded.design)
+}
 
-    let mut inout_write_regs = HashMap::new();
-    for command in top_level_commands.iter() {
-        if let Command::Register {
-            reg_name,
-            bits,
-            array,
-        } = command
+/// Parses every input and checks each against the others, so that charts
+/// can instantiate one another. Charts the inputs import come after them,
+/// named as imported. Reports all failing inputs before failing.
+fn load_all(args: &Args) -> Result<Vec<(String, Design, Options)>, Failure> {
+    let mut modules: Vec<(String, Design, Options)> = vec![];
+    let mut imported: Vec<(String, Import)> = vec![];
+    let mut failed = false;
+    for input in args.inputs.iter() {
+        let loaded = match read_design(input) {
+            Ok(loaded) => loaded,
+            Err(Failure::Design) => {
+                failed = true;
+                continue;
+            }
+            Err(failure) => return Err(failure),
+        };
+        let options = options(args, input, &loaded.design);
+        if let Some((first, _, _)) = modules
+            .iter()
+            .find(|(_, _, other)| other.module_name == options.module_name)
         {
-            if array.start == array.end && array.start == 0 {
-                code.update(format!(
-                    "
-reg [{} : {}]{};",
-                    bits.start, bits.end, reg_name
-                ));
-            } else {
-                code.update(format!(
-                    "
-reg [{} : {}]{}[{} : {}];",
-                    bits.start, bits.end, reg_name, array.start, array.end
-                ));
+            eprintln!(
+                "{}: error: module `{}` is already defined by `{}`",
+                input, options.module_name, first
+            );
+            failed = true;
+            continue;
+        }
+        modules.push((input.clone(), loaded.design, options));
+        for import in loaded.imports {
+            match imported.iter().find(|(_, other)| other.name == import.name) {
+                Some((_, other)) if other.design == import.design => {}
+                Some((first, other)) => {
+                    eprintln!(
+                        "{}: error: `{}` is imported from `{}` here but from `{}` by `{}`",
+                        input, import.name, import.path, other.path, first
+                    );
+                    failed = true;
+                }
+                None => imported.push((input.clone(), import)),
             }
         }
-        if let Command::Inout {
-            pin_name,
-            bits,
-            array,
-        } = command
+    }
+    for (input, import) in imported {
+        if let Some((first, _, _)) = modules
+            .iter()
+            .find(|(_, _, options)| options.module_name == import.name)
         {
-            let main_reg = code.get_varname(pin_name);
-            let write_reg = code.get_varname(&format!("{}_write_reg", pin_name));
-            if array.start == array.end && array.start == 0 {
-                code.update(format!(
-                    "
-reg [{} : {}]{};",
-                    bits.start, bits.end, main_reg
-                ));
-            } else {
-                code.update(format!(
-                    "
-reg [{} : {}]{}[{} : {}];",
-                    bits.start, bits.end, main_reg, array.start, array.end
-                ));
-            }
-            code.update(format!(
-                "
-reg {write_reg};
-assign {pin_name} = {write_reg} ? {main_reg} : 'bZ;"
-            ));
-            inout_write_regs.insert(pin_name.clone(), (main_reg, write_reg));
+            eprintln!(
+                "{}: error: `{}` imports `{}`, which is already defined by `{}`",
+                input, import.path, import.name, first
+            );
+            failed = true;
+            continue;
         }
+        let options = Options {
+            module_name: import.name,
+            ..style(args)
+        };
+        modules.push((import.path, import.design, options));
     }
+    let mut library = Library::new();
+    for (_, design, options) in modules.iter() {
+        library.insert(&options.module_name, design.clone());
+    }
+    for (input, design, options) in modules.iter_mut() {
+        options.library = library.clone();
+        if report(input, &check_with(design, options)).is_err() {
+            failed = true;
+        }
+    }
+    if failed {
+        return Err(Failure::Design);
+    }
+    Ok(modules)
+}
 
-    code.update(format!(
-        "
-always @(posedge reset)
-{current_state_reg} = 0;
-"
-    ));
+fn options(args: &Args, input: &str, design: &Design) -> Options {
+    Options {
+        module_name: args
+            .name
+            .as_deref()
+            .or(design.module_name())
+            .or(stem(input))
+            .map(|name| name.to_string())
+            .unwrap_or(Options::default().module_name),
+        ..style(args)
+    }
+}
 
-    code.update(
-        "
-always @(posedge clk) begin"
-            .to_string(),
-    );
+/// The options every module is built with alike.
+fn style(args: &Args) -> Options {
+    let default = Options::default();
+    Options {
+        clock: args.clock.clone().unwrap_or(default.clock),
+        reset: args.reset.clone().unwrap_or(default.reset),
+        header: args.header.unwrap_or(default.header),
+        wrapper: args.wrapper,
+        naming: Naming {
+            prefix: args.name_prefix.clone().unwrap_or_default(),
+            suffix: args.name_suffix.clone().unwrap_or_default(),
+        },
+        ..Options::default()
+    }
+}
 
-    //     for command in top_level_commands.iter() {
-    //         if let Command::Output {
-    //             pin_name,
-    //             bits,
-    //             array,
-    //         } = command
-    //         {
-    //             code.update(format!(
-    //                 "
-    // {pin_name} = 0;"
-    //             ))
-    //         }
-    //     }
-    for inout in inout_write_regs.iter() {
-        code.update(format!(
-            "
-{} <= 0;
-{} <= {};",
-            inout.1 .1, inout.1 .0, inout.0
-        ));
+/// `-o` if given, otherwise `<module>.<extension>` in the working
+/// directory, or stdout when reading stdin.
+fn output_path(args: &Args, input: &str, module: &str, extension: &str) -> Result<String, Failure> {
+    let output = match (&args.output, input) {
+        (Some(output), _) => output.clone(),
+        (None, "-") => "-".to_string(),
+        (None, _) => format!("{}.{}", module, extension),
+    };
+    not_an_input(args, &output)?;
+    Ok(output)
+}
+
+fn not_an_input(args: &Args, output: &str) -> Result<(), Failure> {
+    if output != "-"
+        && args
+            .inputs
+            .iter()
+            .any(|input| Path::new(output) == Path::new(input))
+    {
+        return Err(Failure::Usage(format!(
+            "refusing to overwrite the input `{}`",
+            output
+        )));
     }
+    Ok(())
+}
 
-    for node in all_nodes.iter() {
-        if node.node_type == NodeType::State {
-            code.update(format!(
-                "
-if ({} == {}) begin",
-                current_state_reg, node.id
-            ));
-
-            let mut seen = HashSet::new();
-            if !compile_node(
-                &mut code,
-                node,
-                &node_map,
-                &mut seen,
-                &current_state_reg,
-                true,
-                &inout_write_regs,
-            ) {
-                return Err(UnableToParseError::CircularDependency);
-            }
+/// `file` as a filelist line: relative to the filelist's directory and
+/// starting with `./` like the lists `find . -name "*.v"` produces.
+fn filelist_entry(filelist: &Path, file: &Path) -> String {
+    let base = filelist.parent().unwrap_or(Path::new(""));
+    let relative = if base.as_os_str().is_empty() {
+        Some(file)
+    } else {
+        file.strip_prefix(base).ok()
+    };
+    match relative {
+        Some(relative) if relative.is_relative() => {
+            let relative = relative.strip_prefix(".").unwrap_or(relative);
+            format!("./{}", relative.display())
+        }
+        _ => file.display().to_string(),
+    }
+}
 
-            code.update(
-                "
-end else"
-                    .to_string(),
-            );
+fn build(args: &Args) -> Result<(), Failure> {
+    args.allow(&[
+        "--output",
+        "--name",
+        "--out-dir",
+        "--filelist",
+        "--target",
+        "--clock",
+        "--reset",
+        "--header",
+        "--wrapper",
+        "--name-prefix",
+        "--name-suffix",
+    ])?;
+    if args.inputs.is_empty() {
+        return Err(Failure::Usage("`build` needs an input file".to_string()));
+    }
+    if args.name.is_some() && args.inputs.len() > 1 {
+        return Err(Failure::Usage(
+            "`--name` needs a single input, name the others with `module => name;`".to_string(),
+        ));
+    }
+    if args.output.is_some() && args.out_dir.is_some() {
+        return Err(Failure::Usage(
+            "`--output` and `--out-dir` cannot be combined".to_string(),
+        ));
+    }
+    let target = args.target.as_deref().unwrap_or("verilog");
+    let (extension, joinable) = match target {
+        "verilog" => ("v", true),
+        "sv" => ("sv", true),
+        "vhdl" => ("vhd", true),
+        "cpp" => ("h", false),
+        "rust" => ("rs", false),
+        "json" => ("json", false),
+        other => return Err(Failure::Usage(format!("unknown target `{}`", other))),
+    };
+    if target != "verilog" {
+        let verilog_only = [
+            ("--clock", args.clock.is_some()),
+            ("--reset", args.reset.is_some()),
+            ("--header", args.header.is_some()),
+            ("--wrapper", args.wrapper),
+        ];
+        if let Some((flag, _)) = verilog_only.iter().find(|(_, given)| *given) {
+            return Err(Failure::Usage(format!(
+                "`{}` only applies to `--target verilog`",
+                flag
+            )));
         }
     }
-    code.update(" begin".to_string());
-    code.update(format!(
-        "
-{} = 0;",
-        current_state_reg
-    ));
-    code.update(
-        "
-end"
-        .to_string(),
-    );
+    let loaded = load_all(args)?;
+    if args.output.is_some() && loaded.len() > 1 && !joinable {
+        return Err(Failure::Usage(format!(
+            "`{}` output cannot hold several modules in one file, use `--out-dir`",
+            target
+        )));
+    }
 
-    code.update(
-        "
-end"
-        .to_string(),
-    );
+    let mut modules: Vec<(String, String, String)> = vec![];
+    for (input, design, options) in loaded {
+        let code = match target {
+            "verilog" => asm_to_verilog_compiler::emit_verilog(&design, &options)?,
+            "sv" => asm_to_verilog_compiler::emit_sv(&design, &options)?,
+            "vhdl" => asm_to_verilog_compiler::emit_vhdl(&design, &options)?,
+            "cpp" => asm_to_verilog_compiler::emit_cpp(&design, &options)?,
+            "rust" => asm_to_verilog_compiler::emit_rust(&design, &options)?,
+            _ => asm_to_verilog_compiler::to_json(&design),
+        };
+        modules.push((options.module_name, input, code));
+    }
 
-    code.update(
-        "
-endmodule"
-            .to_string(),
-    );
+    let mut written = vec![];
+    if args.out_dir.is_none() && (args.output.is_some() || modules.len() == 1) {
+        let (module, input, _) = &modules[0];
+        let output = output_path(args, input, module, extension)?;
+        let codes: Vec<&str> = modules.iter().map(|(_, _, code)| code.as_str()).collect();
+        write_output(&output, &codes.join("\n"))?;
+        written.push(output);
+    } else {
+        let dir = Path::new(args.out_dir.as_deref().unwrap_or(""));
+        if !dir.as_os_str().is_empty() {
+            std::fs::create_dir_all(dir).map_err(|err| {
+                Failure::Io(format!("cannot create `{}`: {}", dir.display(), err))
+            })?;
+        }
+        for (module, _, code) in modules.iter() {
+            let output = dir
+                .join(format!("{}.{}", module, extension))
+                .display()
+                .to_string();
+            not_an_input(args, &output)?;
+            write_output(&output, code)?;
+            written.push(output);
+        }
+    }
 
-    let _ = std::fs::write(Path::new(&outpath), code.code);
+    if let Some(filelist) = &args.filelist {
+        not_an_input(args, filelist)?;
+        let mut lines: Vec<String> = written
+            .iter()
+            .filter(|output| *output != "-")
+            .map(|output| filelist_entry(Path::new(filelist), Path::new(output)))
+            .collect();
+        lines.sort();
+        lines.dedup();
+        let mut contents = lines.join("\n");
+        contents.push('\n');
+        write_output(filelist, &contents)?;
+    }
     Ok(())
 }
 
-fn compile_node<'l>(
-    code: &mut Code,
-    node: &'l Node,
-    node_map: &'l HashMap<String, &'l Node>,
-    seen: &mut HashSet<&'l String>,
-    current_state_reg: &String,
-    full_compile: bool,
-    inout_regs: &HashMap<String, (String, String)>,
-) -> bool {
-    if !full_compile && node.node_type == NodeType::State {
-        code.update(format!(
-            "
-{} <= {};",
-            current_state_reg, node.id
-        ));
-        return true;
-    }
+fn graph(args: &Args) -> Result<(), Failure> {
+    args.allow(&["--output", "--name", "--format"])?;
+    let input = &args.inputs(1)?[0];
+    let design = load(input)?;
+    let options = options(args, input, &design);
+    let (extension, code) = match args.format.as_deref().unwrap_or("dot") {
+        "dot" => ("dot", asm_to_verilog_compiler::emit_dot(&design, &options)),
+        "mermaid" => (
+            "mmd",
+            asm_to_verilog_compiler::emit_mermaid(&design, &options)?,
+        ),
+        "plantuml" => (
+            "puml",
+            asm_to_verilog_compiler::emit_plantuml(&design, &options)?,
+        ),
+        other => return Err(Failure::Usage(format!("unknown graph format `{}`", other))),
+    };
+    write_output(
+        &output_path(args, input, &options.module_name, extension)?,
+        &code,
+    )
+}
 
-    if seen.contains(&node.node_name) {
-        return false;
+fn check_command(args: &Args) -> Result<(), Failure> {
+    args.allow(&["--clock", "--reset"])?;
+    if args.inputs.is_empty() {
+        return Err(Failure::Usage("`check` needs an input file".to_string()));
     }
-    seen.insert(&node.node_name);
-    if node.node_type == NodeType::Decision {
-        let mut check_cond = "0".to_string();
-        let mut yes_node = "".to_string();
-        let mut no_node = "".to_string();
-        for command in node.commands.iter() {
-            match command {
-                Command::Check { check } => {
-                    check_cond = check.to_string();
-                }
-                Command::Yes { next_node } => {
-                    yes_node = next_node.to_string();
-                }
-                Command::No { next_node } => {
-                    no_node = next_node.to_string();
-                }
-                _ => {}
-            }
+    load_all(args).map(|_| ())
+}
+
+fn fmt(args: &Args) -> Result<(), Failure> {
+    args.allow(&["--output", "--check"])?;
+    let input = &args.inputs(1)?[0];
+    let contents = read_input(input)?;
+    let design = parse(&contents).map_err(|diagnostic| {
+        eprintln!("{}: {}", input, diagnostic);
+        Failure::Design
+    })?;
+    let formatted = design.to_string();
+    if args.check {
+        if formatted != contents {
+            eprintln!("{}: not formatted", input);
+            return Err(Failure::Design);
         }
+        return Ok(());
+    }
+    let output = args.output.clone().unwrap_or(input.clone());
+    write_output(&output, &formatted)
+}
 
-        code.update(format!(
-            "
-if ({}) begin",
-            check_cond
-        ));
+fn sim(args: &Args) -> Result<(), Failure> {
+    args.allow(&["--output", "--cycles", "--set"])?;
+    let input = &args.inputs(1)?[0];
+    let design = load(input)?;
+    let mut simulator = Simulator::new(&design)?;
+    for set in args.sets.iter() {
+        let (name, index, value) = parse_assignment(set).map_err(Failure::Usage)?;
+        simulator.set(name, index, value)?;
+    }
+    let mut trace = simulator.dump();
+    trace.push('\n');
+    for _ in 0..args.cycles.unwrap_or(20) {
+        simulator.step()?;
+        trace.push_str(&simulator.dump());
+        trace.push('\n');
+    }
+    write_output(args.output.as_deref().unwrap_or("-"), &trace)
+}
 
-        if !compile_node(
-            code,
-            node_map.get(&yes_node).unwrap(),
-            node_map,
-            seen,
-            current_state_reg,
-            false,
-            inout_regs,
-        ) {
-            return false;
-        }
+fn test(args: &Args) -> Result<(), Failure> {
+    args.allow(&[])?;
+    let inputs = args.inputs(2)?;
+    let design = load(&inputs[0])?;
+    let report = run_vectors(&design, &read_input(&inputs[1])?).map_err(|diagnostic| {
+        eprintln!("{}: {}", inputs[1], diagnostic);
+        Failure::Design
+    })?;
+    for failure in report.failures.iter() {
+        eprintln!("{}: {}", inputs[1], failure);
+    }
+    println!(
+        "{} of {} expectations passed",
+        report.checked - report.failures.len(),
+        report.checked
+    );
+    if report.passed() {
+        Ok(())
+    } else {
+        Err(Failure::Design)
+    }
+}
 
-        code.update(format!(
-            "
-end else begin"
-        ));
+fn run() -> Result<(), Failure> {
+    let Some(args) = parse_args(std::env::args().skip(1))? else {
+        return Ok(());
+    };
+    match args.command.as_ref() {
+        "build" => build(&args),
+        "check" => check_command(&args),
+        "sim" => sim(&args),
+        "fmt" => fmt(&args),
+        "graph" => graph(&args),
+        "test" => test(&args),
+        "" => Err(Failure::Usage("no command given".to_string())),
+        other => Err(Failure::Usage(format!("unknown command `{}`", other))),
+    }
+}
 
-        if !compile_node(
-            code,
-            node_map.get(&no_node).unwrap(),
-            node_map,
-            seen,
-            current_state_reg,
-            false,
-            inout_regs,
-        ) {
-            return false;
+fn main() -> ExitCode {
+    match run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(Failure::Design) => ExitCode::from(1),
+        Err(Failure::Diagnostic(diagnostic)) => {
+            eprintln!("{}", diagnostic);
+            ExitCode::from(1)
         }
-
-        code.update(format!(
-            "
-end"
-        ));
-        return true;
-    }
-    let mut then_node = "".to_string();
-    for command in node.commands.iter() {
-        match command {
-            Command::RegisterTransfer {
-                reg_name,
-                reg_value,
-            } => {
-                if let Some(inout) = inout_regs.get(reg_name) {
-                    code.update(format!(
-                        "
-{} <= {};
-{} <= 1;",
-                        inout.0, reg_value, inout.1
-                    ))
-                } else {
-                    code.update(format!(
-                        "
-{} <= {};",
-                        reg_name, reg_value
-                    ));
-                }
-            }
-            Command::Then { next_node } => {
-                then_node = next_node.to_string();
-            }
-            _ => {}
+        Err(Failure::Usage(message)) => {
+            eprintln!("error: {}\n\n{}", message, USAGE);
+            ExitCode::from(2)
+        }
+        Err(Failure::Io(message)) => {
+            eprintln!("error: {}", message);
+            ExitCode::from(2)
         }
     }
-
-    compile_node(
-        code,
-        node_map.get(&then_node).unwrap(),
-        node_map,
-        seen,
-        current_state_reg,
-        false,
-        inout_regs,
-    )
 }
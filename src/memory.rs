@@ -0,0 +1,294 @@
+use crate::command::Command;
+use crate::expr::Expr;
+use crate::node::{Node, NodeType};
+use std::collections::{HashMap, HashSet};
+
+/// The `memory` declarations of `design` by name, with their read latency.
+pub fn latencies(commands: &[Command]) -> HashMap<&str, u8> {
+    commands
+        .iter()
+        .filter_map(|cmd| match cmd {
+            Command::Memory {
+                mem_name, latency, ..
+            } => Some((mem_name.as_str(), *latency)),
+            _ => None,
+        })
+        .collect()
+}
+
+/// One word of a memory as `(memory, address)`, the address spelled the
+/// way [`Expr`] prints it so that `mem[a+1]` and `mem[a + 1]` are the same.
+pub type Access = (String, String);
+
+/// The word `expr` selects, if it is `mem[address]` for one of `memories`.
+pub fn word(expr: &Expr, memories: &HashMap<&str, u8>) -> Option<Access> {
+    match expr {
+        Expr::Index(base, address) => match base.as_ref() {
+            Expr::Ident(name) if memories.contains_key(name.as_str()) => {
+                Some((name.clone(), address.to_string()))
+            }
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Every word of `memories` that `expr` reads, in order of appearance.
+pub fn reads(expr: &Expr, memories: &HashMap<&str, u8>, out: &mut Vec<Access>) {
+    if let Some(access) = word(expr, memories) {
+        if let Expr::Index(_, address) = expr {
+            reads(address, memories, out);
+        }
+        out.push(access);
+        return;
+    }
+    match expr {
+        Expr::Ident(_) | Expr::Number { .. } => {}
+        Expr::Unary(_, inner) | Expr::Slice(inner, _, _) => reads(inner, memories, out),
+        Expr::Binary(_, lhs, rhs) | Expr::Index(lhs, rhs) => {
+            reads(lhs, memories, out);
+            reads(rhs, memories, out);
+        }
+        Expr::Ternary(cond, yes, no) => {
+            reads(cond, memories, out);
+            reads(yes, memories, out);
+            reads(no, memories, out);
+        }
+        Expr::Concat(parts) => parts.iter().for_each(|part| reads(part, memories, out)),
+    }
+}
+
+/// Words of `memories` that `cmd` writes or reads, the written one first.
+/// Text that does not parse has none; `check` reports it.
+pub fn accesses(cmd: &Command, memories: &HashMap<&str, u8>) -> Vec<Access> {
+    let mut out = vec![];
+    match cmd {
+        Command::RegisterTransfer {
+            reg_name,
+            reg_value,
+        } => {
+            if let Ok(target) = reg_name.parse::<Expr>() {
+                match (word(&target, memories), &target) {
+                    (Some(access), Expr::Index(_, address)) => {
+                        reads(address, memories, &mut out);
+                        out.insert(0, access);
+                    }
+                    _ => reads(&target, memories, &mut out),
+                }
+            }
+            if let Ok(value) = reg_value.parse::<Expr>() {
+                reads(&value, memories, &mut out);
+            }
+        }
+        Command::Check { check } | Command::WaitUntil { check, .. } => {
+            if let Ok(check) = check.parse::<Expr>() {
+                reads(&check, memories, &mut out);
+            }
+        }
+        _ => {}
+    }
+    out
+}
+
+/// The distinct words a state accesses, counting the decisions and
+/// conditional outputs behind it, in the order they are met. The `n`th
+/// word of a memory goes through its port `n`.
+pub fn state_accesses(
+    state: &Node,
+    node_map: &HashMap<&str, &Node>,
+    memories: &HashMap<&str, u8>,
+) -> Vec<Access> {
+    let mut out: Vec<Access> = vec![];
+    let mut seen = HashSet::from([state.node_name.as_str()]);
+    let mut pending = vec![state];
+    while let Some(node) = pending.pop() {
+        for cmd in node.commands.iter() {
+            for access in accesses(cmd, memories) {
+                if !out.contains(&access) {
+                    out.push(access);
+                }
+            }
+        }
+        // Reversed so that the `yes` branch is walked before the `no`.
+        for cmd in node.commands.iter().rev() {
+            if let Command::Then { next_node }
+            | Command::Yes { next_node }
+            | Command::No { next_node } = cmd
+            {
+                match node_map.get(next_node.as_str()) {
+                    Some(next) if next.node_type != NodeType::State && seen.insert(next_node) => {
+                        pending.push(next)
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+    out
+}
+
+/// The port of every word a state accesses, by memory and address.
+pub fn ports(accesses: &[Access]) -> HashMap<Access, usize> {
+    let mut used: HashMap<&str, usize> = HashMap::new();
+    accesses
+        .iter()
+        .map(|access| {
+            let port = used.entry(&access.0).or_default();
+            *port += 1;
+            (access.clone(), *port - 1)
+        })
+        .collect()
+}
+
+/// Registers that a read with latency loads, per memory, in the order they
+/// first appear. A load is tagged with the position of its target plus
+/// one, so that a tag of 0 loads nothing.
+pub fn load_targets(commands: &[Command], nodes: &[Node]) -> HashMap<String, Vec<String>> {
+    let memories = latencies(commands);
+    let mut out: HashMap<String, Vec<String>> = HashMap::new();
+    for cmd in nodes.iter().flat_map(|node| node.commands.iter()) {
+        if let Some((mem, target)) = load(cmd, &memories) {
+            let targets = out.entry(mem.0).or_default();
+            if !targets.contains(&target) {
+                targets.push(target);
+            }
+        }
+    }
+    out
+}
+
+/// `r0 => mem[address]` for a memory with a read latency: the word and the
+/// register the data lands in `latency` cycles late.
+pub fn load(cmd: &Command, memories: &HashMap<&str, u8>) -> Option<(Access, String)> {
+    let Command::RegisterTransfer {
+        reg_name,
+        reg_value,
+    } = cmd
+    else {
+        return None;
+    };
+    let access = word(&reg_value.parse().ok()?, memories)?;
+    (memories[access.0.as_str()] > 0).then(|| (access, reg_name.trim().to_string()))
+}
+
+/// `expr` with every word of `memories` that has a port in `ports`
+/// replaced by the name `port_name` gives that port's data.
+pub fn replace_reads(
+    expr: &Expr,
+    memories: &HashMap<&str, u8>,
+    ports: &HashMap<Access, usize>,
+    port_name: &dyn Fn(&str, usize) -> String,
+) -> Expr {
+    let replace = |inner: &Expr| Box::new(replace_reads(inner, memories, ports, port_name));
+    if let Some(access) = word(expr, memories) {
+        if let Some(port) = ports.get(&access) {
+            return Expr::Ident(port_name(&access.0, *port));
+        }
+    }
+    match expr {
+        Expr::Ident(_) | Expr::Number { .. } => expr.clone(),
+        Expr::Unary(op, inner) => Expr::Unary(*op, replace(inner)),
+        Expr::Slice(inner, high, low) => Expr::Slice(replace(inner), *high, *low),
+        Expr::Binary(op, lhs, rhs) => Expr::Binary(*op, replace(lhs), replace(rhs)),
+        Expr::Index(lhs, rhs) => Expr::Index(replace(lhs), replace(rhs)),
+        Expr::Ternary(cond, yes, no) => Expr::Ternary(replace(cond), replace(yes), replace(no)),
+        Expr::Concat(parts) => Expr::Concat(
+            parts
+                .iter()
+                .map(|part| replace_reads(part, memories, ports, port_name))
+                .collect(),
+        ),
+    }
+}
+
+/// Words from a `$readmemh` file as `(address, value)`: hex words separated
+/// by whitespace, `@address` to move on and `//` or `/* */` comments.
+pub fn read_hex(text: &str) -> Result<Vec<(u64, u64)>, String> {
+    let mut out = vec![];
+    let mut address = 0;
+    let mut rest = text.trim_start();
+    let mut words = vec![];
+    while !rest.is_empty() {
+        if let Some(after) = rest.strip_prefix("//") {
+            rest = after.split_once('\n').map_or("", |(_, rest)| rest);
+        } else if let Some(after) = rest.strip_prefix("/*") {
+            rest = after.split_once("*/").map_or("", |(_, rest)| rest);
+        } else {
+            let end = rest
+                .find(|c: char| c.is_whitespace() || c == '/')
+                .map_or(rest.len(), |end| end.max(1));
+            words.push(&rest[..end]);
+            rest = rest[end..].trim_start();
+        }
+        rest = rest.trim_start();
+    }
+    for word in words {
+        let (text, is_address) = match word.strip_prefix('@') {
+            Some(text) => (text, true),
+            None => (word, false),
+        };
+        let value = u64::from_str_radix(&text.replace('_', ""), 16)
+            .map_err(|_| format!("`{}` is not a hex number", word))?;
+        if is_address {
+            address = value;
+        } else {
+            out.push((address, value));
+            address += 1;
+        }
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse;
+
+    #[test]
+    fn ports_test() {
+        let design = parse(
+            "
+mem => memory[7:0] depth 16 ports 2;
+address => input[3:0];
+r0 => reg[7:0];
+.idle : state { mem[address] => r0; r0 => mem[address + 1]; then => test; }
+.test : decision { check => mem[address + 1] == 0; yes => clear; no => idle; }
+.clear : conditional { mem[0] => 0; then => idle; }
+",
+        )
+        .unwrap();
+        let memories = latencies(&design.commands);
+        let node_map = design
+            .nodes
+            .iter()
+            .map(|node| (node.node_name.as_str(), node))
+            .collect();
+        let accesses = state_accesses(&design.nodes[0], &node_map, &memories);
+        let address = |text: &str| ("mem".to_string(), text.to_string());
+        assert_eq!(
+            accesses,
+            vec![address("address"), address("(address + 1)"), address("0")]
+        );
+        let ports = ports(&accesses);
+        assert_eq!(ports[&address("0")], 2);
+        let expr = "mem[address + 1] & r0".parse().unwrap();
+        let name = |mem: &str, port: usize| format!("{}_q{}", mem, port);
+        assert_eq!(
+            replace_reads(&expr, &memories, &ports, &name).to_string(),
+            "(mem_q1 & r0)"
+        );
+        assert_eq!(
+            load_targets(&design.commands, &design.nodes),
+            HashMap::from([("mem".to_string(), vec!["r0".to_string()])])
+        );
+    }
+
+    #[test]
+    fn read_hex_test() {
+        assert_eq!(
+            read_hex("// header\n0a ff\n@10 /* skip */ 1_2\n"),
+            Ok(vec![(0, 10), (1, 255), (16, 18)])
+        );
+        assert!(read_hex("0g").is_err());
+    }
+}
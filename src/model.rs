@@ -0,0 +1,298 @@
+use crate::command::{Command, UnableToParseError};
+use crate::expr::Expr;
+use crate::naming::Names;
+use crate::node::{self, Node, NodeType};
+use crate::verilog_code_gen::Code;
+use std::{
+    collections::{HashMap, HashSet},
+    ops::Range,
+};
+
+#[derive(PartialEq)]
+pub enum Kind {
+    Input,
+    Output,
+    Inout,
+    Register,
+    Wire,
+}
+
+/// A name of the chart as a cycle model holds it.
+pub struct Signal {
+    pub kind: Kind,
+    pub width: u32,
+    pub array: Option<Range<u8>>,
+    /// The members an inout drives its pin through: the value and the
+    /// enable.
+    pub driven: Option<(String, String)>,
+    /// The member a `registered` inout is read through, its sample at the
+    /// last clock edge.
+    pub sample: Option<String>,
+}
+
+impl Signal {
+    /// Lowest array index and number of elements.
+    pub fn extent(&self) -> (u32, u32) {
+        match &self.array {
+            Some(array) => (
+                array.start.min(array.end) as u32,
+                array.start.abs_diff(array.end) as u32 + 1,
+            ),
+            None => (0, 1),
+        }
+    }
+}
+
+pub fn is_array(array: &Range<u8>) -> bool {
+    array.start != array.end || array.start != 0
+}
+
+pub fn width_of(bits: &Range<u8>) -> u32 {
+    bits.start.abs_diff(bits.end) as u32 + 1
+}
+
+/// The low `width` bits set.
+pub fn mask(width: u32) -> u64 {
+    if width >= 64 {
+        u64::MAX
+    } else {
+        (1u64 << width) - 1
+    }
+}
+
+/// How a cycle-model backend spells what the node walk produces.
+pub trait Dialect {
+    /// `expr` as a boolean.
+    fn condition(&self, model: &Lowering, expr: &Expr) -> Result<String, UnableToParseError>;
+    /// Opens the block taken while `condition` holds.
+    fn branch(&self, condition: &str) -> String;
+    /// Loads `value` into `target`, which is the signal `name` or part of
+    /// it, on the next clock edge.
+    fn store(
+        &self,
+        model: &Lowering,
+        name: &str,
+        target: &Expr,
+        value: &Expr,
+    ) -> Result<String, UnableToParseError>;
+    /// Moves to the state numbered `id` on the next clock edge.
+    fn next_state(&self, model: &Lowering, id: u32) -> String;
+    /// Sets the inout enable `enable` on the next clock edge.
+    fn next_enable(&self, enable: &str, on: bool) -> String;
+}
+
+/// The chart as a cycle model sees it, and the body of its step so far.
+pub struct Lowering<'l> {
+    pub code: Code,
+    pub signals: HashMap<String, Signal>,
+    node_map: HashMap<String, &'l Node>,
+    pub state_reg: String,
+    pub state_ids: HashMap<String, u32>,
+    /// Inouts whose enable only `drive` and `release` change.
+    pub controlled: HashSet<&'l str>,
+}
+
+impl<'l> Lowering<'l> {
+    pub fn new(nodes: &'l [Node], names: Names) -> Self {
+        let mut lowering = Lowering {
+            code: Code::new(names),
+            signals: HashMap::new(),
+            node_map: HashMap::new(),
+            state_reg: String::new(),
+            state_ids: HashMap::new(),
+            controlled: node::controlled_pins(nodes),
+        };
+        for node in nodes.iter() {
+            lowering.node_map.insert(node.get_name(), node);
+            if node.node_type == NodeType::State {
+                lowering.state_ids.insert(node.get_name(), node.id);
+            }
+        }
+        lowering
+    }
+
+    /// Records the signals of `commands`, naming the members the model
+    /// adds for inouts, and returns every name but the wires in
+    /// declaration order.
+    pub fn declare(&mut self, commands: &[Command]) -> Vec<String> {
+        let mut declared = vec![];
+        for cmd in commands.iter() {
+            let (name, bits, array, kind) = match cmd {
+                Command::Input {
+                    pin_name,
+                    bits,
+                    array,
+                    ..
+                } => (pin_name, bits, array, Kind::Input),
+                Command::Output {
+                    pin_name,
+                    bits,
+                    array,
+                    ..
+                } => (pin_name, bits, array, Kind::Output),
+                Command::Inout {
+                    pin_name,
+                    bits,
+                    array,
+                    ..
+                } => (pin_name, bits, array, Kind::Inout),
+                Command::Register {
+                    reg_name,
+                    bits,
+                    array,
+                    ..
+                } => (reg_name, bits, array, Kind::Register),
+                Command::Wire {
+                    wire_name, bits, ..
+                } => (wire_name, bits, &(0..0), Kind::Wire),
+                _ => continue,
+            };
+            let code = &mut self.code;
+            let signal = Signal {
+                width: width_of(bits),
+                array: is_array(array).then(|| array.clone()),
+                driven: (kind == Kind::Inout).then(|| {
+                    (
+                        code.fresh_name(&format!("{}_out", name)),
+                        code.fresh_name(&format!("{}_oe", name)),
+                    )
+                }),
+                sample: matches!(
+                    cmd,
+                    Command::Inout {
+                        registered: true,
+                        ..
+                    }
+                )
+                .then(|| code.fresh_name(&format!("{}_q", name))),
+                kind,
+            };
+            if signal.kind != Kind::Wire {
+                declared.push(name.clone());
+            }
+            self.signals.insert(name.clone(), signal);
+        }
+        declared
+    }
+
+    pub fn line(&mut self, depth: usize, text: String) {
+        self.code
+            .update(format!("\n{}{}", "    ".repeat(depth), text));
+    }
+
+    pub fn signal(&self, name: &str) -> Result<&Signal, UnableToParseError> {
+        self.signals
+            .get(name)
+            .ok_or(UnableToParseError::InvalidExpression)
+    }
+
+    pub fn width(&self, expr: &Expr) -> Result<u32, UnableToParseError> {
+        expr.width(&|name| {
+            self.signals
+                .get(name)
+                .map(|signal| (signal.width, signal.array.is_some()))
+        })
+    }
+
+    fn transfer<D: Dialect>(
+        &mut self,
+        dialect: &D,
+        depth: usize,
+        reg_name: &str,
+        reg_value: &str,
+    ) -> Result<(), UnableToParseError> {
+        let target: Expr = reg_name.parse()?;
+        let value: Expr = reg_value.parse()?;
+        let name = match &target {
+            Expr::Ident(name) => name,
+            Expr::Index(base, _) | Expr::Slice(base, _, _) => match base.as_ref() {
+                Expr::Ident(name) => name,
+                _ => return Err(UnableToParseError::InvalidExpression),
+            },
+            _ => return Err(UnableToParseError::InvalidExpression),
+        };
+        let text = dialect.store(self, name, &target, &value)?;
+        let drives = match &self.signal(name)?.driven {
+            Some((_, enable)) if !self.controlled.contains(name.as_str()) => {
+                Some(dialect.next_enable(enable, true))
+            }
+            _ => None,
+        };
+        self.line(depth, text);
+        if let Some(drives) = drives {
+            self.line(depth, drives);
+        }
+        Ok(())
+    }
+
+    fn lookup(&self, name: &str) -> Result<&'l Node, UnableToParseError> {
+        self.node_map
+            .get(name)
+            .copied()
+            .ok_or(UnableToParseError::UndefinedNode)
+    }
+
+    /// Emits what `node` does, following decisions and conditional outputs
+    /// until the next state.
+    pub fn compile_node<D: Dialect>(
+        &mut self,
+        dialect: &D,
+        node: &'l Node,
+        seen: &mut HashSet<&'l String>,
+        depth: usize,
+        full_compile: bool,
+    ) -> Result<(), UnableToParseError> {
+        if !full_compile && node.node_type == NodeType::State {
+            let id = self.state_ids[&node.node_name];
+            let text = dialect.next_state(self, id);
+            self.line(depth, text);
+            return Ok(());
+        }
+        if !seen.insert(&node.node_name) {
+            return Err(UnableToParseError::CircularDependency);
+        }
+
+        if node.node_type == NodeType::Decision {
+            let mut check_cond = "0";
+            let mut yes_node = "";
+            let mut no_node = "";
+            for command in node.commands.iter() {
+                match command {
+                    Command::Check { check } => check_cond = check,
+                    Command::Yes { next_node } => yes_node = next_node,
+                    Command::No { next_node } => no_node = next_node,
+                    _ => {}
+                }
+            }
+            let cond = dialect.condition(self, &check_cond.parse()?)?;
+            self.line(depth, dialect.branch(&cond));
+            self.compile_node(dialect, self.lookup(yes_node)?, seen, depth + 1, false)?;
+            self.line(depth, "} else {".to_string());
+            self.compile_node(dialect, self.lookup(no_node)?, seen, depth + 1, false)?;
+            self.line(depth, "}".to_string());
+        } else {
+            let mut then_node = "";
+            for command in node.commands.iter() {
+                match command {
+                    Command::RegisterTransfer {
+                        reg_name,
+                        reg_value,
+                    } => self.transfer(dialect, depth, reg_name, reg_value)?,
+                    Command::Drive { pin_name } | Command::Release { pin_name } => {
+                        let Some((_, enable)) = &self.signal(pin_name)?.driven else {
+                            return Err(UnableToParseError::InvalidExpression);
+                        };
+                        let on = matches!(command, Command::Drive { .. });
+                        let text = dialect.next_enable(enable, on);
+                        self.line(depth, text);
+                    }
+                    Command::Then { next_node } => then_node = next_node,
+                    _ => {}
+                }
+            }
+            self.compile_node(dialect, self.lookup(then_node)?, seen, depth, false)?;
+        }
+        seen.remove(&node.node_name);
+        Ok(())
+    }
+}
@@ -0,0 +1,754 @@
+use crate::clock::Domain;
+use crate::command::Command;
+use crate::library::port_wire;
+use crate::primitive::Primitive;
+use crate::stream;
+use crate::Diagnostic;
+use std::collections::{HashMap, HashSet};
+
+/// Reserved words of Verilog-2005 and SystemVerilog-2017, which the Verilog
+/// and SystemVerilog backends both avoid.
+pub const VERILOG_KEYWORDS: &[&str] = &[
+    "accept_on",
+    "alias",
+    "always",
+    "always_comb",
+    "always_ff",
+    "always_latch",
+    "and",
+    "assert",
+    "assign",
+    "assume",
+    "automatic",
+    "before",
+    "begin",
+    "bind",
+    "bins",
+    "binsof",
+    "bit",
+    "break",
+    "buf",
+    "bufif0",
+    "bufif1",
+    "byte",
+    "case",
+    "casex",
+    "casez",
+    "cell",
+    "chandle",
+    "checker",
+    "class",
+    "clocking",
+    "cmos",
+    "config",
+    "const",
+    "constraint",
+    "context",
+    "continue",
+    "cover",
+    "covergroup",
+    "coverpoint",
+    "cross",
+    "deassign",
+    "default",
+    "defparam",
+    "design",
+    "disable",
+    "dist",
+    "do",
+    "edge",
+    "else",
+    "end",
+    "endcase",
+    "endchecker",
+    "endclass",
+    "endclocking",
+    "endconfig",
+    "endfunction",
+    "endgenerate",
+    "endgroup",
+    "endinterface",
+    "endmodule",
+    "endpackage",
+    "endprimitive",
+    "endprogram",
+    "endproperty",
+    "endsequence",
+    "endspecify",
+    "endtable",
+    "endtask",
+    "enum",
+    "event",
+    "eventually",
+    "expect",
+    "export",
+    "extends",
+    "extern",
+    "final",
+    "first_match",
+    "for",
+    "force",
+    "foreach",
+    "forever",
+    "fork",
+    "forkjoin",
+    "function",
+    "generate",
+    "genvar",
+    "global",
+    "highz0",
+    "highz1",
+    "if",
+    "iff",
+    "ifnone",
+    "ignore_bins",
+    "illegal_bins",
+    "implements",
+    "implies",
+    "import",
+    "incdir",
+    "include",
+    "initial",
+    "inout",
+    "input",
+    "inside",
+    "instance",
+    "int",
+    "integer",
+    "interconnect",
+    "interface",
+    "intersect",
+    "join",
+    "join_any",
+    "join_none",
+    "large",
+    "let",
+    "liblist",
+    "library",
+    "local",
+    "localparam",
+    "logic",
+    "longint",
+    "macromodule",
+    "matches",
+    "medium",
+    "modport",
+    "module",
+    "nand",
+    "negedge",
+    "nettype",
+    "new",
+    "nexttime",
+    "nmos",
+    "nor",
+    "noshowcancelled",
+    "not",
+    "notif0",
+    "notif1",
+    "null",
+    "or",
+    "output",
+    "package",
+    "packed",
+    "parameter",
+    "pmos",
+    "posedge",
+    "primitive",
+    "priority",
+    "program",
+    "property",
+    "protected",
+    "pull0",
+    "pull1",
+    "pulldown",
+    "pullup",
+    "pulsestyle_ondetect",
+    "pulsestyle_onevent",
+    "pure",
+    "rand",
+    "randc",
+    "randcase",
+    "randsequence",
+    "rcmos",
+    "real",
+    "realtime",
+    "ref",
+    "reg",
+    "reject_on",
+    "release",
+    "repeat",
+    "restrict",
+    "return",
+    "rnmos",
+    "rpmos",
+    "rtran",
+    "rtranif0",
+    "rtranif1",
+    "s_always",
+    "s_eventually",
+    "s_nexttime",
+    "s_until",
+    "s_until_with",
+    "scalared",
+    "sequence",
+    "shortint",
+    "shortreal",
+    "showcancelled",
+    "signed",
+    "small",
+    "soft",
+    "solve",
+    "specify",
+    "specparam",
+    "static",
+    "string",
+    "strong",
+    "strong0",
+    "strong1",
+    "struct",
+    "super",
+    "supply0",
+    "supply1",
+    "sync_accept_on",
+    "sync_reject_on",
+    "table",
+    "tagged",
+    "task",
+    "this",
+    "throughout",
+    "time",
+    "timeprecision",
+    "timeunit",
+    "tran",
+    "tranif0",
+    "tranif1",
+    "tri",
+    "tri0",
+    "tri1",
+    "triand",
+    "trior",
+    "trireg",
+    "type",
+    "typedef",
+    "union",
+    "unique",
+    "unique0",
+    "unsigned",
+    "until",
+    "until_with",
+    "untyped",
+    "use",
+    "uwire",
+    "var",
+    "vectored",
+    "virtual",
+    "void",
+    "wait",
+    "wait_order",
+    "wand",
+    "weak",
+    "weak0",
+    "weak1",
+    "while",
+    "wildcard",
+    "wire",
+    "with",
+    "within",
+    "wor",
+    "xnor",
+    "xor",
+];
+
+/// Reserved words of VHDL-2008, compared ignoring case like VHDL does.
+pub const VHDL_KEYWORDS: &[&str] = &[
+    "abs",
+    "access",
+    "after",
+    "alias",
+    "all",
+    "and",
+    "architecture",
+    "array",
+    "assert",
+    "assume",
+    "assume_guarantee",
+    "attribute",
+    "begin",
+    "block",
+    "body",
+    "buffer",
+    "bus",
+    "case",
+    "component",
+    "configuration",
+    "constant",
+    "context",
+    "cover",
+    "default",
+    "disconnect",
+    "downto",
+    "else",
+    "elsif",
+    "end",
+    "entity",
+    "exit",
+    "fairness",
+    "file",
+    "for",
+    "force",
+    "function",
+    "generate",
+    "generic",
+    "group",
+    "guarded",
+    "if",
+    "impure",
+    "in",
+    "inertial",
+    "inout",
+    "is",
+    "label",
+    "library",
+    "linkage",
+    "literal",
+    "loop",
+    "map",
+    "mod",
+    "nand",
+    "new",
+    "next",
+    "nor",
+    "not",
+    "null",
+    "of",
+    "on",
+    "open",
+    "or",
+    "others",
+    "out",
+    "package",
+    "parameter",
+    "port",
+    "postponed",
+    "procedure",
+    "process",
+    "property",
+    "protected",
+    "pure",
+    "range",
+    "record",
+    "register",
+    "reject",
+    "release",
+    "rem",
+    "report",
+    "restrict",
+    "restrict_guarantee",
+    "return",
+    "rol",
+    "ror",
+    "select",
+    "sequence",
+    "severity",
+    "shared",
+    "signal",
+    "sla",
+    "sll",
+    "sra",
+    "srl",
+    "strong",
+    "subtype",
+    "then",
+    "to",
+    "transport",
+    "type",
+    "unaffected",
+    "units",
+    "until",
+    "use",
+    "variable",
+    "vmode",
+    "vprop",
+    "vunit",
+    "wait",
+    "when",
+    "while",
+    "with",
+    "xnor",
+    "xor",
+];
+
+/// Reserved words of C++20, and the types the C++ backend spells, which a
+/// member of the same name would hide.
+pub const CPP_KEYWORDS: &[&str] = &[
+    "alignas",
+    "alignof",
+    "and",
+    "and_eq",
+    "asm",
+    "auto",
+    "bitand",
+    "bitor",
+    "bool",
+    "break",
+    "case",
+    "catch",
+    "char",
+    "char8_t",
+    "char16_t",
+    "char32_t",
+    "class",
+    "co_await",
+    "co_return",
+    "co_yield",
+    "compl",
+    "concept",
+    "const",
+    "const_cast",
+    "consteval",
+    "constexpr",
+    "constinit",
+    "continue",
+    "decltype",
+    "default",
+    "delete",
+    "do",
+    "double",
+    "dynamic_cast",
+    "else",
+    "enum",
+    "explicit",
+    "export",
+    "extern",
+    "false",
+    "float",
+    "for",
+    "friend",
+    "goto",
+    "if",
+    "inline",
+    "int",
+    "long",
+    "mutable",
+    "namespace",
+    "new",
+    "noexcept",
+    "not",
+    "not_eq",
+    "nullptr",
+    "operator",
+    "or",
+    "or_eq",
+    "private",
+    "protected",
+    "public",
+    "register",
+    "reinterpret_cast",
+    "requires",
+    "return",
+    "short",
+    "signed",
+    "sizeof",
+    "static",
+    "static_assert",
+    "static_cast",
+    "struct",
+    "switch",
+    "template",
+    "this",
+    "thread_local",
+    "throw",
+    "true",
+    "try",
+    "typedef",
+    "typeid",
+    "typename",
+    "union",
+    "unsigned",
+    "using",
+    "virtual",
+    "void",
+    "volatile",
+    "wchar_t",
+    "while",
+    "xor",
+    "xor_eq",
+    "std",
+    "uint8_t",
+    "uint16_t",
+    "uint32_t",
+    "uint64_t",
+];
+
+/// Reserved words of Rust, strict and reserved for later editions. Names
+/// of the chart's among them are written as raw identifiers.
+pub const RUST_KEYWORDS: &[&str] = &[
+    "abstract", "as", "async", "await", "become", "box", "break", "const", "continue", "crate",
+    "do", "dyn", "else", "enum", "extern", "false", "final", "fn", "for", "gen", "if", "impl",
+    "in", "let", "loop", "macro", "match", "mod", "move", "mut", "override", "priv", "pub", "ref",
+    "return", "self", "Self", "static", "struct", "super", "trait", "true", "try", "type",
+    "typeof", "unsafe", "unsized", "use", "virtual", "where", "while", "yield",
+];
+
+/// How the signals a backend adds to a module, such as state registers
+/// and the enables of inouts, are named: after the design element they
+/// belong to, as in `bus_write_reg` for the inout `bus`, between `prefix`
+/// and `suffix`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Naming {
+    pub prefix: String,
+    pub suffix: String,
+}
+
+impl Naming {
+    /// Rejects a prefix or suffix that would not make identifiers.
+    pub fn validate(&self) -> Result<(), Diagnostic> {
+        for (what, text) in [("prefix", &self.prefix), ("suffix", &self.suffix)] {
+            if !text.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+                return Err(Diagnostic::error(format!(
+                    "the name {} `{}` may only hold letters, digits and `_`",
+                    what, text
+                )));
+            }
+        }
+        if self.prefix.starts_with(|c: char| c.is_ascii_digit()) {
+            return Err(Diagnostic::error(format!(
+                "the name prefix `{}` cannot start with a digit",
+                self.prefix
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Hands out the names of the signals a backend adds to one module. A name
+/// depends only on the element it is for, unless it would clash with a
+/// name of the chart, a keyword or an earlier generated name; then it is
+/// numbered, as `currentState_2`.
+pub struct Names {
+    naming: Naming,
+    taken: HashSet<String>,
+    /// Whether names differing only in case clash, as in VHDL.
+    fold_case: bool,
+}
+
+impl Names {
+    /// `clocking` is where the machines of `commands` without a `clock`
+    /// declaration run.
+    pub fn new(
+        naming: &Naming,
+        commands: &[Command],
+        clocking: &Domain,
+        keywords: &[&str],
+        fold_case: bool,
+    ) -> Self {
+        let mut names = Names {
+            naming: naming.clone(),
+            taken: HashSet::new(),
+            fold_case,
+        };
+        for keyword in keywords.iter() {
+            names.reserve(keyword);
+        }
+        for name in declared(commands, clocking) {
+            names.reserve(&name);
+        }
+        names
+    }
+
+    /// Keeps `name` from being handed out.
+    pub fn reserve(&mut self, name: &str) {
+        let key = self.key(name);
+        self.taken.insert(key);
+    }
+
+    /// A new name for the signal `base`.
+    pub fn fresh(&mut self, base: &str) -> String {
+        let spelled = format!("{}{}{}", self.naming.prefix, base, self.naming.suffix);
+        let mut name = spelled.clone();
+        let mut count = 1;
+        while self.taken.contains(&self.key(&name)) {
+            count += 1;
+            name = format!("{}_{}", spelled, count);
+        }
+        self.reserve(&name);
+        name
+    }
+
+    fn key(&self, name: &str) -> String {
+        match self.fold_case {
+            true => name.to_ascii_lowercase(),
+            false => name.to_string(),
+        }
+    }
+}
+
+/// Fails if the module or a signal of `commands` is named one of `words`,
+/// which `backend`, as in "the C++ model", cannot use for a name of the
+/// chart's. With
+/// `fold_case`, as in VHDL, names differing only in case are the same.
+pub fn spellable(
+    module: &str,
+    commands: &[Command],
+    words: &[&str],
+    backend: &str,
+    fold_case: bool,
+) -> Result<(), Diagnostic> {
+    let key = |name: &str| match fold_case {
+        true => name.to_ascii_lowercase(),
+        false => name.to_string(),
+    };
+    let words: HashSet<String> = words.iter().map(|word| key(word)).collect();
+    let mut seen: HashMap<String, &str> = HashMap::new();
+    for name in std::iter::once(module).chain(signals(commands)) {
+        if words.contains(&key(name)) {
+            return Err(Diagnostic::error(format!(
+                "`{}` is reserved in {}, rename it",
+                name, backend
+            )));
+        }
+        match seen.insert(key(name), name) {
+            Some(other) if other != name => {
+                return Err(Diagnostic::error(format!(
+                    "`{}` and `{}` are the same name in {}, rename one",
+                    other, name, backend
+                )))
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// The names of the signals `commands` declare.
+fn signals(commands: &[Command]) -> impl Iterator<Item = &str> {
+    commands.iter().filter_map(|cmd| match cmd {
+        Command::Input { pin_name, .. }
+        | Command::Output { pin_name, .. }
+        | Command::Inout { pin_name, .. } => Some(pin_name.as_str()),
+        Command::Register { reg_name, .. } => Some(reg_name),
+        Command::Wire { wire_name, .. } => Some(wire_name),
+        _ => None,
+    })
+}
+
+/// Every name `commands` declare, the ports of their streams and the
+/// inputs of `clocking`.
+fn declared(commands: &[Command], clocking: &Domain) -> Vec<String> {
+    let mut out = vec![];
+    for cmd in commands.iter() {
+        match cmd {
+            Command::Input { pin_name, .. }
+            | Command::Output { pin_name, .. }
+            | Command::Inout { pin_name, .. } => out.push(pin_name.clone()),
+            Command::Register { reg_name, .. } => out.push(reg_name.clone()),
+            Command::Wire { wire_name, .. } => out.push(wire_name.clone()),
+            Command::Memory { mem_name, .. } => out.push(mem_name.clone()),
+            Command::Instance { instance_name, .. } => out.push(instance_name.clone()),
+            Command::Enum { name, members } => {
+                out.push(name.clone());
+                out.extend(members.iter().cloned());
+            }
+            Command::Clock {
+                clock_name,
+                reset,
+                enable,
+                ..
+            } => {
+                out.push(clock_name.clone());
+                out.push(reset.clone());
+                out.extend(enable.iter().cloned());
+            }
+            Command::Stream { stream_name, .. } => {
+                for (signal, _) in stream::SIGNALS.iter() {
+                    out.push(port_wire(stream_name, signal));
+                }
+            }
+            cmd => {
+                if let Some(primitive) = Primitive::of(cmd) {
+                    out.push(primitive.name.to_string());
+                }
+            }
+        }
+    }
+    // Charts without `clock` declarations still have these inputs.
+    out.extend([clocking.clock, clocking.reset].map(str::to_string));
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::DEFAULT;
+    use crate::{emit_cpp, emit_rust, parse, Options};
+
+    #[test]
+    fn names_test() {
+        let design = parse("currentState => reg;\nbus => inout;").unwrap();
+        let mut names = Names::new(
+            &Naming::default(),
+            &design.commands,
+            &DEFAULT,
+            VERILOG_KEYWORDS,
+            false,
+        );
+        assert_eq!(names.fresh("currentState"), "currentState_2");
+        assert_eq!(names.fresh("bus_write_reg"), "bus_write_reg");
+        assert_eq!(names.fresh("bus_write_reg"), "bus_write_reg_2");
+        assert_eq!(names.fresh("wire"), "wire_2");
+
+        let naming = Naming {
+            prefix: "asm_".to_string(),
+            suffix: "_q".to_string(),
+        };
+        let mut names = Names::new(&naming, &design.commands, &DEFAULT, VHDL_KEYWORDS, true);
+        assert_eq!(names.fresh("currentState"), "asm_currentState_q");
+        names.reserve("ASM_X_Q");
+        assert_eq!(names.fresh("x"), "asm_x_q_2");
+        assert!(Naming {
+            prefix: "2x".to_string(),
+            suffix: String::new(),
+        }
+        .validate()
+        .is_err());
+
+        // Once renamed, `clk` and `reset` are free and the configured
+        // names are taken.
+        let clocking = Domain {
+            clock: "aclk",
+            reset: "arst",
+            ..DEFAULT
+        };
+        let mut names = Names::new(
+            &Naming::default(),
+            &design.commands,
+            &clocking,
+            VERILOG_KEYWORDS,
+            false,
+        );
+        assert_eq!(names.fresh("clk"), "clk");
+        assert_eq!(names.fresh("aclk"), "aclk_2");
+        assert_eq!(names.fresh("arst"), "arst_2");
+
+        // The cycle models number what they add around the chart's names.
+        let design = parse(
+            "bus => inout[3:0];
+bus_out => reg[3:0];
+bus_oe => reg;
+
+.idle : state {
+    bus => bus_out;
+    bus_oe => 1;
+    then => idle;
+}
+",
+        )
+        .unwrap();
+        let cpp = emit_cpp(&design, &Options::default()).unwrap();
+        assert!(cpp.contains("\n    uint8_t bus_out_2{};\n    uint8_t bus_oe_2{};"));
+        assert!(cpp.contains("\n            bus_out_2_next = bus_out & 0xfULL;"));
+        let rust = emit_rust(&design, &Options::default()).unwrap();
+        assert!(rust.contains("\n    pub bus_out_2: u8,\n    pub bus_oe_2: bool,"));
+        assert!(rust.contains("\n                next.bus_oe_2 = true;"));
+    }
+}
@@ -1,19 +1,40 @@
 use crate::command::{Command, UnableToParseError};
-use std::str::FromStr;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum NodeType {
     State,
     Decision,
     Conditional,
 }
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Node {
+    #[serde(rename = "name")]
     pub node_name: String,
+    #[serde(rename = "type")]
     pub node_type: NodeType,
     pub commands: Vec<Command>,
+    /// The machine the node belongs to, named by the last `machine =>`
+    /// directive before it; empty for the unnamed machine.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub machine: String,
+    /// The `use` that copied the node from a template, if one did.
+    #[serde(skip)]
+    pub origin: Option<Origin>,
+    /// Derived from declaration order, see [`number_states`].
+    #[serde(skip)]
     pub id: u32,
 }
+/// The template a node was copied from and the `use` that copied it, as
+/// written, for diagnostics to point back at the source.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Origin {
+    pub template: String,
+    pub site: String,
+}
+
 impl Node {
     pub fn get_name(&self) -> String {
         self.node_name.clone()
@@ -23,7 +44,7 @@ impl Node {
         node_type: &str,
         contents: &str,
     ) -> Result<Self, UnableToParseError> {
-        let mut command_strs = contents.split(';');
+        let command_strs = contents.split(';');
         let node_type = match node_type.trim() {
             "state" => NodeType::State,
             "conditional" => NodeType::Conditional,
@@ -35,8 +56,10 @@ impl Node {
             node_name: name.to_string(),
             node_type,
             commands: vec![],
+            machine: String::new(),
+            origin: None,
         };
-        while let Some(str) = command_strs.next() {
+        for str in command_strs {
             let cmd: Command = str.parse()?;
             if cmd != Command::Empty {
                 result.commands.push(cmd);
@@ -46,7 +69,66 @@ impl Node {
     }
 }
 
+impl std::fmt::Display for NodeType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NodeType::State => write!(f, "state"),
+            NodeType::Decision => write!(f, "decision"),
+            NodeType::Conditional => write!(f, "conditional"),
+        }
+    }
+}
+impl std::fmt::Display for Node {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, ".{} : {} {{", self.node_name, self.node_type)?;
+        for cmd in self.commands.iter() {
+            writeln!(f, "    {};", cmd)?;
+        }
+        writeln!(f, "}}")
+    }
+}
+
+/// Gives every state node its encoding within its machine, in declaration
+/// order, so the first state of each machine is the one entered on reset.
+pub fn number_states(nodes: &mut [Node]) {
+    let mut state_counts = HashMap::new();
+    for node in nodes.iter_mut() {
+        if node.node_type == NodeType::State {
+            let state_count = state_counts.entry(node.machine.clone()).or_insert(0);
+            node.id = *state_count;
+            *state_count += 1;
+        }
+    }
+}
+
+/// The machines of the chart in the order their first node appears. A
+/// chart without `machine =>` directives has the single machine `""`.
+pub fn machines(nodes: &[Node]) -> Vec<&str> {
+    let mut machines: Vec<&str> = vec![];
+    for node in nodes.iter() {
+        if !machines.contains(&node.machine.as_str()) {
+            machines.push(&node.machine);
+        }
+    }
+    machines
+}
+
+/// Inouts that some node names in a `drive` or `release`. Their output
+/// enable is a register only those actions change; any other inout is
+/// driven for the cycle after each transfer to it.
+pub fn controlled_pins(nodes: &[Node]) -> HashSet<&str> {
+    nodes
+        .iter()
+        .flat_map(|node| node.commands.iter())
+        .filter_map(|cmd| match cmd {
+            Command::Drive { pin_name } | Command::Release { pin_name } => Some(pin_name.as_str()),
+            _ => None,
+        })
+        .collect()
+}
+
 #[cfg(test)]
+#[allow(clippy::assertions_on_constants)]
 mod tests {
     use super::*;
     #[test]
@@ -65,10 +147,11 @@ then    =>    .state;
                 node_name,
                 node_type,
                 commands,
+                ..
             }) => {
                 assert_eq!(node_name, ".123salam".to_string());
                 assert_eq!(node_type, NodeType::State);
-                assert_eq!(commands.len(), 3);
+                assert_eq!(commands.len(), 2);
                 assert_eq!(
                     commands[0],
                     Command::RegisterTransfer {
@@ -82,7 +165,6 @@ then    =>    .state;
                         next_node: ".state".to_string()
                     }
                 );
-                assert_eq!(commands[2], Command::Empty);
             }
             _ => assert!(false),
         }
@@ -0,0 +1,116 @@
+use crate::command::Command;
+
+/// The blocks a chart can declare next to its machines.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kind {
+    Fifo,
+    Counter,
+}
+
+impl Kind {
+    pub fn name(self) -> &'static str {
+        match self {
+            Kind::Fifo => "fifo",
+            Kind::Counter => "counter",
+        }
+    }
+
+    /// The actions nodes can ask for, and whether each takes a value.
+    pub fn actions(self) -> &'static [(&'static str, bool)] {
+        match self {
+            Kind::Fifo => &[("push", true), ("pop", false)],
+            Kind::Counter => &[("clear", false)],
+        }
+    }
+
+    /// What nodes can read of it, as `q.empty` for the status `empty`.
+    pub fn statuses(self) -> &'static [&'static str] {
+        match self {
+            Kind::Fifo => &["data", "empty", "full"],
+            Kind::Counter => &["value"],
+        }
+    }
+}
+
+/// A `fifo` or `counter` declaration.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Primitive<'d> {
+    pub name: &'d str,
+    pub kind: Kind,
+    pub width: u8,
+    /// Words a fifo holds; 0 for a counter.
+    pub depth: u32,
+}
+
+impl<'d> Primitive<'d> {
+    pub fn of(cmd: &'d Command) -> Option<Self> {
+        match cmd {
+            Command::Fifo {
+                fifo_name,
+                width,
+                depth,
+            } => Some(Primitive {
+                name: fifo_name,
+                kind: Kind::Fifo,
+                width: *width,
+                depth: *depth,
+            }),
+            Command::Counter {
+                counter_name,
+                width,
+            } => Some(Primitive {
+                name: counter_name,
+                kind: Kind::Counter,
+                width: *width,
+                depth: 0,
+            }),
+            _ => None,
+        }
+    }
+
+    /// Signals the block is driven through, as `(port, width)`. An action
+    /// raises the port of its name and puts its value on `{action}_data`.
+    pub fn inputs(&self) -> Vec<(&'static str, u32)> {
+        let width = self.width as u32;
+        match self.kind {
+            Kind::Fifo => vec![("push", 1), ("push_data", width), ("pop", 1)],
+            Kind::Counter => vec![("clear", 1)],
+        }
+    }
+
+    /// Signals nodes read as `q.empty`, as `(port, width)`.
+    pub fn statuses(&self) -> Vec<(&'static str, u32)> {
+        self.kind
+            .statuses()
+            .iter()
+            .map(|status| match *status {
+                "data" | "value" => (*status, self.width as u32),
+                _ => (*status, 1),
+            })
+            .collect()
+    }
+}
+
+/// Every `fifo` and `counter` of `commands`, in declaration order.
+pub fn primitives(commands: &[Command]) -> Vec<Primitive<'_>> {
+    commands.iter().filter_map(Primitive::of).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse;
+
+    #[test]
+    fn primitives_test() {
+        let design = parse("q => fifo[8] depth 16;\nt => counter[10];\nr => reg;").unwrap();
+        let found = primitives(&design.commands);
+        assert_eq!(found.len(), 2);
+        assert_eq!(
+            found[0].statuses(),
+            vec![("data", 8), ("empty", 1), ("full", 1)]
+        );
+        assert_eq!(found[1].kind.name(), "counter");
+        assert_eq!(found[1].inputs(), vec![("clear", 1)]);
+    }
+}
@@ -0,0 +1,555 @@
+use crate::clock;
+use crate::command::{Command, UnableToParseError};
+use crate::expr::{BinaryOp, Expr, UnaryOp};
+use crate::model::{self, Dialect, Kind, Lowering, Signal};
+use crate::naming::{self, Names, Naming, RUST_KEYWORDS};
+use crate::node::{Node, NodeType};
+use crate::Diagnostic;
+use std::collections::HashSet;
+
+fn rust_type(width: u32) -> Result<&'static str, UnableToParseError> {
+    match width {
+        0..=8 => Ok("u8"),
+        9..=16 => Ok("u16"),
+        17..=32 => Ok("u32"),
+        33..=64 => Ok("u64"),
+        _ => Err(UnableToParseError::InvalidRange),
+    }
+}
+
+fn mask(width: u32) -> String {
+    if width >= 64 {
+        "u64::MAX".to_string()
+    } else {
+        format!("0x{:x}", model::mask(width))
+    }
+}
+
+/// A name of the chart as Rust source; keywords become raw identifiers.
+fn ident(name: &str) -> String {
+    if RUST_KEYWORDS.contains(&name) {
+        format!("r#{}", name)
+    } else {
+        name.to_string()
+    }
+}
+
+/// Keywords that cannot be raw identifiers.
+const UNSPELLABLE: &[&str] = &["self", "Self", "super", "crate"];
+
+/// Types the model spells, which a struct of the same name would hide.
+const TYPES: &[&str] = &["u8", "u16", "u32", "u64", "bool", "usize"];
+
+/// Methods of the model, besides the wires.
+const METHODS: &[&str] = &["new", "reset", "step", "clone"];
+
+/// Fails if the chart uses a name the model cannot declare.
+pub fn spellable(module: &str, commands: &[Command]) -> Result<(), Diagnostic> {
+    naming::spellable(module, commands, UNSPELLABLE, "the Rust model", false)?;
+    naming::spellable(module, &[], TYPES, "the Rust model", false)?;
+    let wires: Vec<Command> = commands
+        .iter()
+        .filter(|cmd| matches!(cmd, Command::Wire { .. }))
+        .cloned()
+        .collect();
+    naming::spellable("", &wires, METHODS, "the Rust model", false)
+}
+
+/// The Rust spelling of the step: the chart runs on a copy of the model,
+/// `next`, that replaces it at the end.
+struct Rust;
+
+impl Rust {
+    /// Array subscript for `index` into `signal`, wrapped into bounds.
+    fn subscript(
+        &self,
+        model: &Lowering,
+        signal: &Signal,
+        index: &Expr,
+    ) -> Result<String, UnableToParseError> {
+        let (low, count) = signal.extent();
+        let index = self.vector(model, index, model.width(index)?)?;
+        if low == 0 {
+            Ok(format!("({} % {}) as usize", index, count))
+        } else {
+            Ok(format!(
+                "({}.wrapping_sub({}) % {}) as usize",
+                index, low, count
+            ))
+        }
+    }
+
+    /// Reads a declared name; wires are computed by a method of their own
+    /// and registered inouts read from their sample.
+    fn read(&self, model: &Lowering, name: &str) -> Result<String, UnableToParseError> {
+        let signal = model.signal(name)?;
+        Ok(match (&signal.kind, &signal.sample) {
+            (Kind::Wire, _) => format!("self.{}()", ident(name)),
+            (_, Some(sample)) => format!("self.{}", sample),
+            _ => format!("self.{}", ident(name)),
+        })
+    }
+
+    /// Renders `expr` as a `u64` wrapped to `width` bits.
+    fn vector(
+        &self,
+        model: &Lowering,
+        expr: &Expr,
+        width: u32,
+    ) -> Result<String, UnableToParseError> {
+        let width = width.min(64);
+        Ok(match expr {
+            Expr::Ident(name) => format!("({} as u64)", self.read(model, name)?),
+            Expr::Number { value, .. } => format!("{}u64", value),
+            Expr::Index(base, index) => {
+                let Expr::Ident(name) = base.as_ref() else {
+                    return Err(UnableToParseError::InvalidExpression);
+                };
+                let signal = model.signal(name)?;
+                if signal.array.is_some() {
+                    format!(
+                        "({}[{}] as u64)",
+                        self.read(model, name)?,
+                        self.subscript(model, signal, index)?
+                    )
+                } else {
+                    format!(
+                        "(({} as u64).checked_shr({} as u32).unwrap_or(0) & 1)",
+                        self.read(model, name)?,
+                        self.vector(model, index, model.width(index)?)?
+                    )
+                }
+            }
+            Expr::Slice(base, high, low) => format!(
+                "(({} >> {}) & {})",
+                self.vector(model, base, model.width(base)?)?,
+                high.min(low),
+                mask(high.abs_diff(*low) + 1)
+            ),
+            Expr::Unary(UnaryOp::LogicalNot, inner) => {
+                format!("(!{} as u64)", self.condition(model, inner)?)
+            }
+            Expr::Unary(UnaryOp::BitNot, inner) => {
+                format!("(!{} & {})", self.vector(model, inner, width)?, mask(width))
+            }
+            Expr::Unary(UnaryOp::Negate, inner) => format!(
+                "({}.wrapping_neg() & {})",
+                self.vector(model, inner, width)?,
+                mask(width)
+            ),
+            Expr::Binary(op, _, _) if op.is_boolean() => {
+                format!("({} as u64)", self.condition(model, expr)?)
+            }
+            Expr::Binary(op, lhs, rhs) => {
+                let method = match op {
+                    BinaryOp::Add => "wrapping_add",
+                    BinaryOp::Sub => "wrapping_sub",
+                    BinaryOp::Mul => "wrapping_mul",
+                    BinaryOp::Div => "checked_div",
+                    BinaryOp::Mod => "checked_rem",
+                    BinaryOp::Shl => "checked_shl",
+                    BinaryOp::Shr => "checked_shr",
+                    BinaryOp::BitAnd => {
+                        return Ok(format!(
+                            "({} & {})",
+                            self.vector(model, lhs, width)?,
+                            self.vector(model, rhs, width)?
+                        ))
+                    }
+                    BinaryOp::BitXor => {
+                        return Ok(format!(
+                            "({} ^ {})",
+                            self.vector(model, lhs, width)?,
+                            self.vector(model, rhs, width)?
+                        ))
+                    }
+                    _ => {
+                        return Ok(format!(
+                            "({} | {})",
+                            self.vector(model, lhs, width)?,
+                            self.vector(model, rhs, width)?
+                        ))
+                    }
+                };
+                match op {
+                    BinaryOp::Add | BinaryOp::Sub | BinaryOp::Mul => format!(
+                        "({}.{}({}) & {})",
+                        self.vector(model, lhs, width)?,
+                        method,
+                        self.vector(model, rhs, width)?,
+                        mask(width)
+                    ),
+                    BinaryOp::Div | BinaryOp::Mod => format!(
+                        "{}.{}({}).unwrap_or(0)",
+                        self.vector(model, lhs, width)?,
+                        method,
+                        self.vector(model, rhs, width)?
+                    ),
+                    _ => format!(
+                        "({}.{}({} as u32).unwrap_or(0) & {})",
+                        self.vector(model, lhs, width)?,
+                        method,
+                        self.vector(model, rhs, model.width(rhs)?)?,
+                        mask(width)
+                    ),
+                }
+            }
+            Expr::Ternary(cond, yes, no) => format!(
+                "(if {} {{ {} }} else {{ {} }})",
+                self.condition(model, cond)?,
+                self.vector(model, yes, width)?,
+                self.vector(model, no, width)?
+            ),
+            Expr::Concat(parts) => {
+                let mut shift = 0;
+                let mut texts = vec![];
+                for part in parts.iter().rev() {
+                    let part_width = model.width(part)?;
+                    texts.push(format!(
+                        "({} << {})",
+                        self.vector(model, part, part_width)?,
+                        shift
+                    ));
+                    shift += part_width;
+                }
+                texts.reverse();
+                format!("({})", texts.join(" | "))
+            }
+        })
+    }
+}
+
+impl Dialect for Rust {
+    /// Renders `expr` as a Rust `bool`.
+    fn condition(&self, model: &Lowering, expr: &Expr) -> Result<String, UnableToParseError> {
+        Ok(match expr {
+            Expr::Unary(UnaryOp::LogicalNot, inner) => {
+                format!("!{}", self.condition(model, inner)?)
+            }
+            Expr::Binary(op @ (BinaryOp::LogicalAnd | BinaryOp::LogicalOr), lhs, rhs) => format!(
+                "({} {} {})",
+                self.condition(model, lhs)?,
+                op.symbol(),
+                self.condition(model, rhs)?
+            ),
+            Expr::Binary(op, lhs, rhs) if op.is_boolean() => {
+                let width = model.width(lhs)?.max(model.width(rhs)?);
+                format!(
+                    "({} {} {})",
+                    self.vector(model, lhs, width)?,
+                    op.symbol(),
+                    self.vector(model, rhs, width)?
+                )
+            }
+            _ => format!("({} != 0)", self.vector(model, expr, model.width(expr)?)?),
+        })
+    }
+
+    fn branch(&self, condition: &str) -> String {
+        format!("if {} {{", condition)
+    }
+
+    fn store(
+        &self,
+        model: &Lowering,
+        name: &str,
+        target: &Expr,
+        value: &Expr,
+    ) -> Result<String, UnableToParseError> {
+        let signal = model.signal(name)?;
+        let field = match &signal.driven {
+            Some((driven, _)) => format!("next.{}", driven),
+            None => format!("next.{}", ident(name)),
+        };
+        let ty = rust_type(signal.width)?;
+        Ok(match target {
+            Expr::Index(_, index) if signal.array.is_some() => format!(
+                "{}[{}] = ({} & {}) as {};",
+                field,
+                self.subscript(model, signal, index)?,
+                self.vector(model, value, signal.width)?,
+                mask(signal.width),
+                ty
+            ),
+            Expr::Index(_, index) => {
+                let bit = self.vector(model, index, model.width(index)?)?;
+                format!(
+                    "{field} = (({field} as u64 & !(1u64 << {bit})) | (({} & 1) << {bit})) as {ty};",
+                    self.vector(model, value, 1)?
+                )
+            }
+            Expr::Slice(_, high, low) => {
+                let bits = mask(high.abs_diff(*low) + 1);
+                let low = high.min(low);
+                format!(
+                    "{field} = (({field} as u64 & !({bits} << {low})) | (({} & {bits}) << {low})) as {ty};",
+                    self.vector(model, value, high.abs_diff(*low) + 1)?
+                )
+            }
+            _ => format!(
+                "{} = ({} & {}) as {};",
+                field,
+                self.vector(model, value, signal.width)?,
+                mask(signal.width),
+                ty
+            ),
+        })
+    }
+
+    fn next_state(&self, model: &Lowering, id: u32) -> String {
+        format!("next.{} = {};", model.state_reg, id)
+    }
+
+    fn next_enable(&self, enable: &str, on: bool) -> String {
+        format!("next.{} = {};", enable, on)
+    }
+}
+
+/// Emits a Rust struct modelling the chart cycle by cycle, meant to be
+/// `include!`d into a test harness. Set the public inputs, then call
+/// `step()` once per rising clock edge.
+pub fn emit_rust(
+    module: &str,
+    commands: &[Command],
+    nodes: &[Node],
+    naming: &Naming,
+) -> Result<String, UnableToParseError> {
+    let mut lowering = Lowering::new(
+        nodes,
+        Names::new(naming, commands, &clock::DEFAULT, RUST_KEYWORDS, false),
+    );
+    let state_count = lowering.state_ids.len() as u32;
+    let reset_state = nodes
+        .iter()
+        .find(|node| node.node_type == NodeType::State)
+        .map(|node| node.id)
+        .ok_or(UnableToParseError::UndefinedNode)?;
+    lowering.state_reg = lowering.code.fresh_name("current_state");
+    let declared = lowering.declare(commands);
+
+    // Field declarations and their initial values.
+    let mut fields = vec![(
+        format!(
+            "{}: {}",
+            lowering.state_reg,
+            rust_type(Expr::bits_for(state_count.max(1) as u64 - 1))?
+        ),
+        format!("{}: {}", lowering.state_reg, reset_state),
+    )];
+    // Per inout, what `step()` does before the chart runs and what
+    // `reset()` does.
+    let mut samples = vec![];
+    let mut resets = vec![];
+    for name in declared.iter() {
+        let signal = &lowering.signals[name];
+        let element = rust_type(signal.width)?;
+        let (ty, zero) = match signal.extent() {
+            (_, count) if signal.array.is_some() => (
+                format!("[{}; {}]", element, count),
+                format!("[0; {}]", count),
+            ),
+            _ => (element.to_string(), "0".to_string()),
+        };
+        let visibility = if signal.kind == Kind::Register {
+            ""
+        } else {
+            "pub "
+        };
+        fields.push((
+            format!("{}{}: {}", visibility, ident(name), ty),
+            format!("{}: {}", ident(name), zero),
+        ));
+        if let Some((driven, enable)) = &signal.driven {
+            fields.push((
+                format!(
+                    "/// Driven onto `{}` while `{}` is set.\n    pub {}: {}",
+                    name, enable, driven, ty
+                ),
+                format!("{}: {}", driven, zero),
+            ));
+            fields.push((
+                format!("pub {}: bool", enable),
+                format!("{}: false", enable),
+            ));
+            if lowering.controlled.contains(name.as_str()) {
+                resets.push(format!("self.{} = false;", enable));
+            } else {
+                samples.push(format!("next.{} = false;", enable));
+            }
+            if let Some(sample) = &signal.sample {
+                fields.push((
+                    format!("{}: {}", sample, ty),
+                    format!("{}: {}", sample, zero),
+                ));
+                samples.push(format!("next.{} = self.{};", sample, ident(name)));
+            }
+        }
+    }
+
+    // Wires read the current values, so they are methods rather than
+    // fields; a wire may read any signal, including a later wire.
+    let mut wires = vec![];
+    for cmd in commands.iter() {
+        if let Command::Wire {
+            wire_name,
+            bits,
+            value,
+        } = cmd
+        {
+            let width = model::width_of(bits);
+            rust_type(width)?;
+            wires.push(format!(
+                "pub fn {}(&self) -> u64 {{\n        {} & {}\n    }}",
+                ident(wire_name),
+                Rust.vector(&lowering, &value.parse()?, width)?,
+                mask(width)
+            ));
+        }
+    }
+
+    let name = ident(module);
+    let mut code = format!(
+        "/// Cycle model of `{module}` generated from its ASM chart.
+#[derive(Debug, Clone, PartialEq)]
+#[allow(non_snake_case, non_camel_case_types, dead_code, clippy::all)]
+pub struct {name} {{"
+    );
+    for (declaration, _) in fields.iter() {
+        code.push_str(&format!("\n    {},", declaration));
+    }
+    code.push_str(&format!(
+        "
+}}
+
+impl Default for {name} {{
+    fn default() -> Self {{
+        Self::new()
+    }}
+}}
+
+#[allow(non_snake_case, non_camel_case_types, unused_parens, clippy::all)]
+impl {name} {{
+    pub fn new() -> Self {{
+        Self {{"
+    ));
+    for (_, init) in fields.iter() {
+        code.push_str(&format!("\n            {},", init));
+    }
+    code.push_str(&format!(
+        "
+        }}
+    }}{}
+
+    /// Returns to the reset state, leaving registers untouched.
+    pub fn reset(&mut self) {{
+        self.{} = {};{}
+    }}
+
+    /// Advances the model by one rising clock edge.
+    pub fn step(&mut self) {{
+        let mut next = self.clone();",
+        wires
+            .iter()
+            .map(|wire| format!("\n\n    {}", wire))
+            .collect::<String>(),
+        lowering.state_reg,
+        reset_state,
+        resets
+            .iter()
+            .map(|reset| format!("\n        {}", reset))
+            .collect::<String>()
+    ));
+    for sample in samples.iter() {
+        code.push_str(&format!("\n        {}", sample));
+    }
+    code.push_str(&format!("\n        match self.{} {{", lowering.state_reg));
+    lowering.code.code = code;
+    for node in nodes.iter() {
+        if node.node_type == NodeType::State {
+            lowering.line(3, format!("{} => {{", node.id));
+            lowering.compile_node(&Rust, node, &mut HashSet::new(), 4, true)?;
+            lowering.line(3, "}".to_string());
+        }
+    }
+    let state_reg = lowering.state_reg.clone();
+    lowering.line(3, format!("_ => next.{} = {},", state_reg, reset_state));
+    lowering
+        .code
+        .update("\n        }\n        *self = next;\n    }\n}\n".to_string());
+    Ok(lowering.code.code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counter_test() {
+        let commands: Vec<Command> = [
+            "go => input",
+            "count => output[3:0]",
+            "mem => reg[7:0][3:0]",
+        ]
+        .iter()
+        .map(|cmd| cmd.parse().unwrap())
+        .collect();
+        let mut nodes = vec![
+            Node::try_parse("idle", "state", "then => check;").unwrap(),
+            Node::try_parse("check", "decision", "check => go; yes => bump; no => idle;").unwrap(),
+            Node::try_parse(
+                "bump",
+                "conditional",
+                "count => count + 1; mem[count] => count; then => idle;",
+            )
+            .unwrap(),
+        ];
+        crate::node::number_states(&mut nodes);
+        let rust = emit_rust("Counter", &commands, &nodes, &Naming::default()).unwrap();
+        assert!(rust.contains("pub struct Counter {"));
+        assert!(rust.contains("    pub go: u8,\n    pub count: u8,\n    mem: [u8; 8],"));
+        assert!(rust.contains("if ((self.go as u64) != 0) {"));
+        assert!(rust.contains(
+            "next.count = (((self.count as u64).wrapping_add(1u64) & 0xf) & 0xf) as u8;"
+        ));
+        assert!(rust.contains("next.mem[((self.count as u64) % 8) as usize] ="));
+        assert!(rust.contains("pub fn step(&mut self) {"));
+    }
+
+    #[test]
+    fn names_test() {
+        let commands: Vec<Command> = [
+            "bus => inout[3:0]",
+            "bus_out => reg[3:0]",
+            "type => reg[3:0]",
+            "match => wire[3:0] = type + 1",
+        ]
+        .iter()
+        .map(|cmd| cmd.parse().unwrap())
+        .collect();
+        let mut nodes = vec![Node::try_parse(
+            "idle",
+            "state",
+            "type => match; bus_out => type; bus => bus_out; then => idle;",
+        )
+        .unwrap()];
+        crate::node::number_states(&mut nodes);
+        let rust = emit_rust("loop", &commands, &nodes, &Naming::default()).unwrap();
+        // The generated names step aside for the chart's own, and keywords
+        // are written as raw identifiers.
+        assert!(rust.contains("pub struct r#loop {"));
+        assert!(rust.contains("\n    pub bus_out_2: u8,\n    pub bus_oe: bool,\n    bus_out: u8,"));
+        assert!(rust.contains("\n    r#type: u8,"));
+        assert!(rust.contains("pub fn r#match(&self) -> u64 {"));
+        assert!(rust.contains("next.r#type = ((self.r#match() as u64) & 0xf) as u8;"));
+        assert!(rust.contains("next.bus_out_2 = ((self.bus_out as u64) & 0xf) as u8;"));
+
+        let commands: Vec<Command> = vec!["self => reg".parse().unwrap()];
+        assert_eq!(
+            spellable("Top", &commands).unwrap_err().message,
+            "`self` is reserved in the Rust model, rename it"
+        );
+        let commands: Vec<Command> = vec!["step => wire = 1".parse().unwrap()];
+        assert!(spellable("Top", &commands).is_err());
+        assert!(spellable("u8", &[]).is_err());
+    }
+}
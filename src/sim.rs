@@ -0,0 +1,1305 @@
+use crate::command::{split_target, Command, UnableToParseError};
+use crate::expr::{BinaryOp, Expr, UnaryOp};
+use crate::node::{self, Node, NodeType};
+use crate::primitive::{self, Primitive};
+use crate::{call, clock, enums, memory, stream};
+use crate::{Design, Diagnostic};
+use std::collections::{HashMap, HashSet, VecDeque};
+
+#[derive(PartialEq)]
+enum Kind {
+    Input,
+    Output,
+    Inout,
+    Register,
+    /// An enum member, read like a signal that never changes.
+    Member,
+    /// A `wire`, computed from its expression whenever it is read.
+    Wire(Expr),
+    Memory,
+    /// `q.empty`, kept up to date by the simulator.
+    Status,
+}
+
+struct Signal {
+    kind: Kind,
+    width: u32,
+    is_array: bool,
+    /// Lowest array index; 0 for scalars.
+    low: u32,
+    /// Members of the enum the signal holds, to show values by name.
+    members: Vec<String>,
+}
+
+/// A read from a memory with latency, landing in `target` at the clock
+/// edge that ends cycle `due`.
+#[derive(Clone)]
+struct Load {
+    due: u64,
+    target: String,
+    value: u64,
+}
+
+/// The words of a `fifo`, oldest first.
+struct Queue {
+    depth: usize,
+    width: u32,
+    words: VecDeque<u64>,
+}
+
+fn mask(width: u32) -> u64 {
+    if width >= 64 {
+        u64::MAX
+    } else {
+        (1u64 << width) - 1
+    }
+}
+
+/// `width`, or an error naming `name` if a 64-bit word cannot hold it.
+fn fits(name: &str, width: u32) -> Result<u32, Diagnostic> {
+    if width > 64 {
+        return Err(Diagnostic::error(format!(
+            "`{}` is {} bits wide, the simulator holds at most 64",
+            name, width
+        )));
+    }
+    Ok(width)
+}
+
+/// Interprets a checked design cycle by cycle, with the same semantics as
+/// the generated hardware: every transfer on the path taken through the
+/// chart reads the values from before the clock edge.
+pub struct Simulator<'d> {
+    node_map: HashMap<&'d str, &'d Node>,
+    reset_state: &'d str,
+    state: &'d str,
+    signals: HashMap<String, Signal>,
+    /// Every declared name but the memories in declaration order, for
+    /// [`Simulator::dump`].
+    order: Vec<String>,
+    values: HashMap<String, Vec<u64>>,
+    /// What the design drives onto each inout, and whether it does. The
+    /// pin shows the driven value while it is enabled.
+    driven: HashMap<String, Vec<u64>>,
+    enabled: HashMap<String, bool>,
+    /// Inouts whose enable only `drive` and `release` change.
+    controlled: HashSet<String>,
+    /// Registered inouts as they were before the last clock edge.
+    sampled: HashMap<String, Vec<u64>>,
+    /// Async inputs as the two flops of their synchronizer hold them, the
+    /// design reading the second.
+    synced: HashMap<String, [Vec<u64>; 2]>,
+    /// The clock enable input, if the machine has one. Nothing but the
+    /// synchronizers and counters moves on an edge while it is low.
+    enable: Option<&'d str>,
+    /// Read latency of every memory.
+    memories: HashMap<&'d str, u8>,
+    loads: Vec<Load>,
+    queues: HashMap<String, Queue>,
+    /// Counters, whose count is the value of their `value` status.
+    counters: Vec<String>,
+    /// States to resume at when the pending calls return, innermost first.
+    /// Like the return stack in hardware it drops the oldest entry when
+    /// `call_depth` is exceeded.
+    calls: Vec<&'d str>,
+    call_depth: usize,
+    /// Cycles spent so far in the current `wait` or `wait_until`. Like the
+    /// counter in hardware it survives a reset.
+    waited: u32,
+    cycle: u64,
+}
+
+impl<'d> Simulator<'d> {
+    /// Starts in the reset state with every signal cleared.
+    pub fn new(design: &'d Design) -> Result<Self, Diagnostic> {
+        if let [_, machine, ..] = design.machines()[..] {
+            return Err(Diagnostic::error(format!(
+                "machine `{}` cannot be simulated, only charts with one machine can",
+                machine
+            )));
+        }
+        let reset_state = design
+            .nodes
+            .iter()
+            .find(|node| node.node_type == NodeType::State)
+            .ok_or_else(|| Diagnostic::error("the chart has no state node".to_string()))?;
+        let mut sim = Simulator {
+            node_map: design
+                .nodes
+                .iter()
+                .map(|node| (node.node_name.as_str(), node))
+                .collect(),
+            reset_state: &reset_state.node_name,
+            state: &reset_state.node_name,
+            signals: HashMap::new(),
+            order: vec![],
+            values: HashMap::new(),
+            driven: HashMap::new(),
+            enabled: HashMap::new(),
+            controlled: node::controlled_pins(&design.nodes)
+                .into_iter()
+                .map(str::to_string)
+                .collect(),
+            sampled: HashMap::new(),
+            synced: HashMap::new(),
+            enable: None,
+            memories: memory::latencies(&design.commands),
+            loads: vec![],
+            queues: HashMap::new(),
+            counters: vec![],
+            calls: vec![],
+            call_depth: call::call_depth(design) as usize,
+            waited: 0,
+            cycle: 0,
+        };
+        let enums = enums::enums(design);
+        for (member, (value, width)) in enums::members(design) {
+            sim.signals.insert(
+                member.to_string(),
+                Signal {
+                    kind: Kind::Member,
+                    width,
+                    is_array: false,
+                    low: 0,
+                    members: vec![],
+                },
+            );
+            sim.values.insert(member.to_string(), vec![value]);
+        }
+        for cmd in stream::expand(&design.commands).iter() {
+            let (name, bits, array, kind, enum_name) = match cmd {
+                Command::Input {
+                    pin_name,
+                    bits,
+                    array,
+                    enum_name,
+                    ..
+                } => (pin_name, bits, array, Kind::Input, enum_name),
+                Command::Output {
+                    pin_name,
+                    bits,
+                    array,
+                    enum_name,
+                } => (pin_name, bits, array, Kind::Output, enum_name),
+                Command::Inout {
+                    pin_name,
+                    bits,
+                    array,
+                    ..
+                } => (pin_name, bits, array, Kind::Inout, &None),
+                Command::Register {
+                    reg_name,
+                    bits,
+                    array,
+                    enum_name,
+                } => (reg_name, bits, array, Kind::Register, enum_name),
+                Command::Wire {
+                    wire_name,
+                    bits,
+                    value,
+                } => {
+                    let value = value
+                        .parse()
+                        .map_err(|err| Diagnostic::from(err).in_node(wire_name))?;
+                    (wire_name, bits, &(0..0), Kind::Wire(value), &None)
+                }
+                Command::Instance { instance_name, .. } => {
+                    return Err(Diagnostic::error(format!(
+                        "instance `{}` cannot be simulated, only flat charts can",
+                        instance_name
+                    )))
+                }
+                Command::Fifo { .. } | Command::Counter { .. } => {
+                    let block = Primitive::of(cmd).unwrap();
+                    for (status, width) in block.statuses() {
+                        let name = format!("{}.{}", block.name, status);
+                        sim.signals.insert(
+                            name.clone(),
+                            Signal {
+                                kind: Kind::Status,
+                                width: fits(&name, width)?,
+                                is_array: false,
+                                low: 0,
+                                members: vec![],
+                            },
+                        );
+                        sim.values.insert(name.clone(), vec![0]);
+                        sim.order.push(name);
+                    }
+                    match block.kind {
+                        primitive::Kind::Fifo => {
+                            let queue = Queue {
+                                depth: block.depth as usize,
+                                width: fits(block.name, block.width as u32)?,
+                                words: VecDeque::new(),
+                            };
+                            sim.queues.insert(block.name.to_string(), queue);
+                        }
+                        primitive::Kind::Counter => sim.counters.push(block.name.to_string()),
+                    }
+                    continue;
+                }
+                Command::Memory {
+                    mem_name,
+                    bits,
+                    depth,
+                    init,
+                    ..
+                } => {
+                    let width = fits(mem_name, bits.start.abs_diff(bits.end) as u32 + 1)?;
+                    let mut words = vec![0; *depth as usize];
+                    if let Some(init) = init {
+                        let text = std::fs::read_to_string(init).map_err(|err| {
+                            Diagnostic::error(format!(
+                                "cannot read `{}` for memory `{}`: {}",
+                                init, mem_name, err
+                            ))
+                        })?;
+                        let contents = memory::read_hex(&text)
+                            .map_err(|err| Diagnostic::error(format!("in `{}`: {}", init, err)))?;
+                        for (address, value) in contents {
+                            let Some(word) = words.get_mut(address as usize) else {
+                                return Err(Diagnostic::error(format!(
+                                    "`{}` fills past the end of memory `{}`",
+                                    init, mem_name
+                                )));
+                            };
+                            *word = value & mask(width);
+                        }
+                    }
+                    sim.signals.insert(
+                        mem_name.clone(),
+                        Signal {
+                            kind: Kind::Memory,
+                            width,
+                            is_array: true,
+                            low: 0,
+                            members: vec![],
+                        },
+                    );
+                    sim.values.insert(mem_name.clone(), words);
+                    continue;
+                }
+                _ => continue,
+            };
+            let count = array.start.abs_diff(array.end) as usize + 1;
+            sim.signals.insert(
+                name.clone(),
+                Signal {
+                    kind,
+                    width: fits(name, bits.start.abs_diff(bits.end) as u32 + 1)?,
+                    is_array: array.start != array.end || array.start != 0,
+                    low: array.start.min(array.end) as u32,
+                    members: enums
+                        .iter()
+                        .find(|(name, _)| Some(*name) == enum_name.as_deref())
+                        .map(|(_, members)| members.to_vec())
+                        .unwrap_or_default(),
+                },
+            );
+            if let Command::Inout { registered, .. } = cmd {
+                sim.driven.insert(name.clone(), vec![0; count]);
+                sim.enabled.insert(name.clone(), false);
+                if *registered {
+                    sim.sampled.insert(name.clone(), vec![0; count]);
+                }
+            }
+            if let Command::Input {
+                asynchronous: true, ..
+            } = cmd
+            {
+                let stages = [vec![0; count], vec![0; count]];
+                sim.synced.insert(name.clone(), stages);
+            }
+            sim.order.push(name.clone());
+            if !matches!(sim.signals[name].kind, Kind::Wire(_)) {
+                sim.values.insert(name.clone(), vec![0; count]);
+            }
+        }
+        let machine = design.machines().first().copied().unwrap_or_default();
+        sim.enable = clock::domain(&design.commands, machine).enable;
+        if let Some(enable) = sim.enable {
+            // Not declared, so not dumped either; it starts out enabled.
+            sim.signals.insert(
+                enable.to_string(),
+                Signal {
+                    kind: Kind::Input,
+                    width: 1,
+                    is_array: false,
+                    low: 0,
+                    members: vec![],
+                },
+            );
+            sim.values.insert(enable.to_string(), vec![1]);
+        }
+        sim.show_queues();
+        Ok(sim)
+    }
+
+    pub fn state(&self) -> &str {
+        self.state
+    }
+
+    /// Number of clock edges since the simulator was created.
+    pub fn cycle(&self) -> u64 {
+        self.cycle
+    }
+
+    /// Returns to the reset state, leaving registers untouched but
+    /// dropping the memory reads in flight, emptying the fifos and
+    /// clearing the counters.
+    pub fn reset(&mut self) {
+        self.state = self.reset_state;
+        self.loads.clear();
+        for queue in self.queues.values_mut() {
+            queue.words.clear();
+        }
+        for counter in self.counters.iter() {
+            self.values.insert(format!("{}.value", counter), vec![0]);
+        }
+        self.show_queues();
+    }
+
+    /// Updates the statuses of every fifo from its words. `data` reads 0
+    /// while the fifo is empty.
+    fn show_queues(&mut self) {
+        for (name, queue) in self.queues.iter() {
+            let len = queue.words.len();
+            let statuses = [
+                ("data", queue.words.front().copied().unwrap_or_default()),
+                ("empty", (len == 0) as u64),
+                ("full", (len >= queue.depth) as u64),
+            ];
+            for (status, value) in statuses {
+                self.values
+                    .insert(format!("{}.{}", name, status), vec![value]);
+            }
+        }
+    }
+
+    /// Reads `name`, or element `index` of it when it is an array.
+    pub fn get(&self, name: &str, index: Option<u64>) -> Option<u64> {
+        let signal = self.signals.get(name)?;
+        if let Kind::Wire(_) = signal.kind {
+            return match index {
+                Some(_) => None,
+                None => self.read(name).ok(),
+            };
+        }
+        let values = &self.values[name];
+        match index {
+            Some(index) => values
+                .get(index.checked_sub(signal.low as u64)? as usize)
+                .copied(),
+            None => values.first().copied(),
+        }
+    }
+
+    /// Drives an input or inout from outside the design.
+    pub fn set(&mut self, name: &str, index: Option<u64>, value: u64) -> Result<(), Diagnostic> {
+        let signal = self
+            .signals
+            .get(name)
+            .ok_or_else(|| Diagnostic::error(format!("`{}` is not declared", name)))?;
+        if !matches!(signal.kind, Kind::Input | Kind::Inout) {
+            return Err(Diagnostic::error(format!(
+                "`{}` is not an input and cannot be set",
+                name
+            )));
+        }
+        let slot = match index {
+            Some(index) => index.wrapping_sub(signal.low as u64) as usize,
+            None => 0,
+        };
+        let value = value & mask(signal.width);
+        match self.values.get_mut(name).unwrap().get_mut(slot) {
+            Some(element) => *element = value,
+            None => {
+                return Err(Diagnostic::error(format!(
+                    "`{}` has no element {}",
+                    name,
+                    index.unwrap_or(0)
+                )))
+            }
+        }
+        Ok(())
+    }
+
+    /// One line per cycle: the state followed by every declared signal.
+    /// Values of typed signals show as their member's name.
+    pub fn dump(&self) -> String {
+        let mut line = format!("{:>6} {}", self.cycle, self.state);
+        for name in self.order.iter() {
+            let signal = &self.signals[name];
+            let show = |value: &u64| match signal.members.get(*value as usize) {
+                Some(member) => member.clone(),
+                None => value.to_string(),
+            };
+            if let Kind::Wire(_) = signal.kind {
+                let value = self.read(name).unwrap_or_default();
+                line.push_str(&format!(" {}={}", name, show(&value)));
+                continue;
+            }
+            let values = &self.values[name];
+            if signal.is_array {
+                let values: Vec<String> = values.iter().map(show).collect();
+                line.push_str(&format!(" {}=[{}]", name, values.join(",")));
+            } else {
+                line.push_str(&format!(" {}={}", name, show(&values[0])));
+            }
+        }
+        line
+    }
+
+    /// Advances by one active clock edge.
+    pub fn step(&mut self) -> Result<(), Diagnostic> {
+        if let Some(enable) = self.enable {
+            if self.values[enable][0] == 0 {
+                return self.hold();
+            }
+        }
+        // Transfers to an inout change the value driven onto it, and only
+        // drive it for the next cycle unless `drive` and `release` do.
+        let mut next = self.values.clone();
+        next.extend(self.driven.clone());
+        let mut enabled = self.enabled.clone();
+        for (pin, enable) in enabled.iter_mut() {
+            if !self.controlled.contains(pin) {
+                *enable = false;
+            }
+        }
+        // Reads with latency land first, so a transfer in this cycle wins.
+        let mut loads = self.loads.clone();
+        loads.retain(|load| {
+            if load.due > self.cycle {
+                return true;
+            }
+            let width = self.signals[&load.target].width;
+            next.get_mut(&load.target).unwrap()[0] = load.value & mask(width);
+            false
+        });
+        let mut calls = self.calls.clone();
+        let mut waited = self.waited;
+        let mut node = self.node_map[self.state];
+        let mut seen = HashSet::new();
+        let mut actions: Vec<(&str, &str, u64)> = vec![];
+        loop {
+            if !seen.insert(node.node_name.as_str()) {
+                return Err(Diagnostic::from(UnableToParseError::CircularDependency)
+                    .in_node(&node.node_name));
+            }
+            for cmd in node.commands.iter() {
+                if let Command::Action {
+                    name,
+                    action,
+                    value,
+                } = cmd
+                {
+                    let value = match value {
+                        Some(value) => value
+                            .parse()
+                            .and_then(|value| self.vector(&value, 64))
+                            .map_err(|err| Diagnostic::from(err).in_node(&node.node_name))?,
+                        None => 0,
+                    };
+                    actions.push((name, action, value));
+                }
+            }
+            let next_name = self
+                .walk(
+                    node,
+                    &mut next,
+                    &mut enabled,
+                    &mut loads,
+                    &mut calls,
+                    &mut waited,
+                )
+                .map_err(|err| Diagnostic::from(err).in_node(&node.node_name))?;
+            node = self.node_map.get(next_name).copied().ok_or_else(|| {
+                Diagnostic::from(UnableToParseError::UndefinedNode).in_node(&node.node_name)
+            })?;
+            if node.node_type == NodeType::State {
+                break;
+            }
+        }
+        for (pin, driven) in self.driven.iter_mut() {
+            *driven = next[pin].clone();
+            if !enabled[pin] {
+                next.insert(pin.clone(), self.values[pin].clone());
+            }
+        }
+        for (pin, sampled) in self.sampled.iter_mut() {
+            sampled.clone_from(&self.values[pin]);
+        }
+        // Fifos and counters act on what they held before the edge, like
+        // their submodules: a push to a full fifo and a pop from an empty
+        // one are ignored.
+        let acts = |name: &str, action: &str| {
+            actions
+                .iter()
+                .find(|(acted, act, _)| *acted == name && *act == action)
+                .map(|(_, _, value)| *value)
+        };
+        for (name, queue) in self.queues.iter_mut() {
+            let full = queue.words.len() >= queue.depth;
+            if acts(name, "pop").is_some() {
+                queue.words.pop_front();
+            }
+            if let Some(value) = acts(name, "push").filter(|_| !full) {
+                queue.words.push_back(value & mask(queue.width));
+            }
+        }
+        for counter in self.counters.iter() {
+            let status = format!("{}.value", counter);
+            let width = self.signals[&status].width;
+            let value = match acts(counter, "clear") {
+                Some(_) => 0,
+                None => self.values[&status][0].wrapping_add(1) & mask(width),
+            };
+            next.insert(status, vec![value]);
+        }
+        self.state = &node.node_name;
+        self.values = next;
+        self.enabled = enabled;
+        self.loads = loads;
+        self.calls = calls;
+        self.waited = waited;
+        self.synchronize();
+        self.cycle += 1;
+        self.show_queues();
+        Ok(())
+    }
+
+    /// Shifts every async input into its synchronizer.
+    fn synchronize(&mut self) {
+        for (pin, [first, second]) in self.synced.iter_mut() {
+            *second = std::mem::replace(first, self.values[pin].clone());
+        }
+    }
+
+    /// An edge with the clock enable low: the counters still count, and
+    /// the memory reads in flight wait for the machine.
+    fn hold(&mut self) -> Result<(), Diagnostic> {
+        for counter in self.counters.iter() {
+            let status = format!("{}.value", counter);
+            let width = self.signals[&status].width;
+            let value = self.values[&status][0].wrapping_add(1) & mask(width);
+            self.values.insert(status, vec![value]);
+        }
+        for (pin, sampled) in self.sampled.iter_mut() {
+            sampled.clone_from(&self.values[pin]);
+        }
+        for load in self.loads.iter_mut() {
+            load.due += 1;
+        }
+        self.synchronize();
+        self.cycle += 1;
+        Ok(())
+    }
+
+    /// Performs the transfers of one node and returns the name of the node
+    /// control moves to.
+    fn walk(
+        &self,
+        node: &'d Node,
+        next: &mut HashMap<String, Vec<u64>>,
+        enabled: &mut HashMap<String, bool>,
+        loads: &mut Vec<Load>,
+        calls: &mut Vec<&'d str>,
+        waited: &mut u32,
+    ) -> Result<&'d str, UnableToParseError> {
+        let mut check = None;
+        let (mut yes, mut no, mut then) = ("", "", "");
+        let (mut callee, mut returns) = (None, false);
+        for cmd in node.commands.iter() {
+            match cmd {
+                Command::RegisterTransfer {
+                    reg_name,
+                    reg_value,
+                } => {
+                    if let Some(((mem, _), target)) = memory::load(cmd, &self.memories) {
+                        loads.push(Load {
+                            due: self.cycle + self.memories[mem.as_str()] as u64,
+                            target,
+                            value: self.vector(&reg_value.parse()?, self.signal(&mem)?.width)?,
+                        });
+                        continue;
+                    }
+                    self.transfer(reg_name, reg_value, next)?;
+                    let (base, _) = split_target(reg_name);
+                    if let Some(enable) = enabled.get_mut(base) {
+                        *enable |= !self.controlled.contains(base);
+                    }
+                }
+                Command::Drive { pin_name } => {
+                    enabled.insert(pin_name.clone(), true);
+                }
+                Command::Release { pin_name } => {
+                    enabled.insert(pin_name.clone(), false);
+                }
+                Command::Check { check: text } => check = Some(text),
+                Command::Yes { next_node } => yes = next_node,
+                Command::No { next_node } => no = next_node,
+                Command::Then { next_node } => then = next_node,
+                Command::Call { node_name } => callee = Some(node_name.as_str()),
+                Command::Return => returns = true,
+                _ => {}
+            }
+        }
+        for cmd in node.commands.iter() {
+            // Leaving is decided against the count from before this cycle,
+            // as the generated counter compares with `>=`.
+            let done = |cycles: u32| *waited + 1 >= cycles;
+            let leave = match cmd {
+                Command::Wait { cycles } => done(*cycles).then_some(then),
+                Command::WaitUntil { check, timeout } => {
+                    if self.condition(&check.parse()?)? {
+                        Some(then)
+                    } else {
+                        timeout
+                            .as_ref()
+                            .filter(|timeout| done(timeout.cycles))
+                            .map(|timeout| timeout.next_node.as_str())
+                    }
+                }
+                _ => continue,
+            };
+            return Ok(match leave {
+                Some(next) => {
+                    *waited = 0;
+                    next
+                }
+                None => {
+                    *waited += 1;
+                    &node.node_name
+                }
+            });
+        }
+        if let Some(callee) = callee {
+            calls.insert(0, then);
+            calls.truncate(self.call_depth);
+            return Ok(callee);
+        }
+        if returns {
+            if calls.is_empty() {
+                return Err(UnableToParseError::UndefinedNode);
+            }
+            return Ok(calls.remove(0));
+        }
+        Ok(match (node.node_type, check) {
+            (NodeType::Decision, Some(check)) => {
+                if self.condition(&check.parse()?)? {
+                    yes
+                } else {
+                    no
+                }
+            }
+            (NodeType::Decision, None) => return Err(UnableToParseError::InvalidFormat),
+            _ => then,
+        })
+    }
+
+    fn signal(&self, name: &str) -> Result<&Signal, UnableToParseError> {
+        self.signals
+            .get(name)
+            .ok_or(UnableToParseError::InvalidExpression)
+    }
+
+    fn width(&self, expr: &Expr) -> Result<u32, UnableToParseError> {
+        Ok(expr
+            .width(&|name| {
+                self.signals
+                    .get(name)
+                    .map(|signal| (signal.width, signal.is_array))
+            })?
+            .min(64))
+    }
+
+    /// Array slot selected by `index`, wrapped into bounds.
+    fn slot(&self, name: &str, index: &Expr) -> Result<usize, UnableToParseError> {
+        let signal = self.signal(name)?;
+        let index = self.vector(index, self.width(index)?)?;
+        let count = self.values[name].len() as u64;
+        Ok((index.wrapping_sub(signal.low as u64) % count) as usize)
+    }
+
+    /// The values the design reads for `name`: registered inouts read
+    /// their sample and async inputs their synchronizer.
+    fn stored(&self, name: &str) -> &[u64] {
+        if let Some([_, synced]) = self.synced.get(name) {
+            return synced;
+        }
+        match self.sampled.get(name) {
+            Some(sampled) => sampled,
+            None => &self.values[name],
+        }
+    }
+
+    /// Current value of a scalar; wires are evaluated on the spot.
+    fn read(&self, name: &str) -> Result<u64, UnableToParseError> {
+        match &self.signal(name)?.kind {
+            Kind::Wire(value) => self.vector(value, self.signal(name)?.width),
+            _ => Ok(self.stored(name)[0]),
+        }
+    }
+
+    /// Evaluates `expr` wrapped to `width` bits.
+    fn vector(&self, expr: &Expr, width: u32) -> Result<u64, UnableToParseError> {
+        let width = width.min(64);
+        let value = match expr {
+            Expr::Ident(name) => self.read(name)?,
+            Expr::Number { value, .. } => *value,
+            Expr::Index(base, index) => {
+                let Expr::Ident(name) = base.as_ref() else {
+                    return Err(UnableToParseError::InvalidExpression);
+                };
+                if self.signal(name)?.is_array {
+                    self.stored(name)[self.slot(name, index)?]
+                } else {
+                    let bit = self.vector(index, self.width(index)?)?;
+                    self.read(name)?.checked_shr(bit as u32).unwrap_or(0) & 1
+                }
+            }
+            Expr::Slice(base, high, low) => {
+                (self.vector(base, self.width(base)?)? >> high.min(low))
+                    & mask(high.abs_diff(*low) + 1)
+            }
+            Expr::Unary(UnaryOp::LogicalNot, inner) => !self.condition(inner)? as u64,
+            Expr::Unary(UnaryOp::BitNot, inner) => !self.vector(inner, width)?,
+            Expr::Unary(UnaryOp::Negate, inner) => self.vector(inner, width)?.wrapping_neg(),
+            Expr::Binary(op, _, _) if op.is_boolean() => self.condition(expr)? as u64,
+            Expr::Binary(op, lhs, rhs) => {
+                let lhs_value = self.vector(lhs, width)?;
+                let rhs_value = match op {
+                    BinaryOp::Shl | BinaryOp::Shr => self.vector(rhs, self.width(rhs)?)?,
+                    _ => self.vector(rhs, width)?,
+                };
+                match op {
+                    BinaryOp::Add => lhs_value.wrapping_add(rhs_value),
+                    BinaryOp::Sub => lhs_value.wrapping_sub(rhs_value),
+                    BinaryOp::Mul => lhs_value.wrapping_mul(rhs_value),
+                    BinaryOp::Div => lhs_value.checked_div(rhs_value).unwrap_or(0),
+                    BinaryOp::Mod => lhs_value.checked_rem(rhs_value).unwrap_or(0),
+                    BinaryOp::Shl => lhs_value.checked_shl(rhs_value as u32).unwrap_or(0),
+                    BinaryOp::Shr => lhs_value.checked_shr(rhs_value as u32).unwrap_or(0),
+                    BinaryOp::BitAnd => lhs_value & rhs_value,
+                    BinaryOp::BitXor => lhs_value ^ rhs_value,
+                    _ => lhs_value | rhs_value,
+                }
+            }
+            Expr::Ternary(cond, yes, no) => {
+                if self.condition(cond)? {
+                    self.vector(yes, width)?
+                } else {
+                    self.vector(no, width)?
+                }
+            }
+            Expr::Concat(parts) => {
+                let mut value = 0u64;
+                for part in parts.iter() {
+                    let part_width = self.width(part)?;
+                    value = value.checked_shl(part_width).unwrap_or(0)
+                        | self.vector(part, part_width)?;
+                }
+                value
+            }
+        };
+        Ok(value & mask(width))
+    }
+
+    fn condition(&self, expr: &Expr) -> Result<bool, UnableToParseError> {
+        Ok(match expr {
+            Expr::Unary(UnaryOp::LogicalNot, inner) => !self.condition(inner)?,
+            Expr::Binary(BinaryOp::LogicalAnd, lhs, rhs) => {
+                self.condition(lhs)? && self.condition(rhs)?
+            }
+            Expr::Binary(BinaryOp::LogicalOr, lhs, rhs) => {
+                self.condition(lhs)? || self.condition(rhs)?
+            }
+            Expr::Binary(op, lhs, rhs) if op.is_boolean() => {
+                let width = self.width(lhs)?.max(self.width(rhs)?);
+                let (lhs, rhs) = (self.vector(lhs, width)?, self.vector(rhs, width)?);
+                match op {
+                    BinaryOp::Lt => lhs < rhs,
+                    BinaryOp::Le => lhs <= rhs,
+                    BinaryOp::Gt => lhs > rhs,
+                    BinaryOp::Ge => lhs >= rhs,
+                    BinaryOp::Eq => lhs == rhs,
+                    _ => lhs != rhs,
+                }
+            }
+            _ => self.vector(expr, self.width(expr)?)? != 0,
+        })
+    }
+
+    fn transfer(
+        &self,
+        reg_name: &str,
+        reg_value: &str,
+        next: &mut HashMap<String, Vec<u64>>,
+    ) -> Result<(), UnableToParseError> {
+        let target: Expr = reg_name.parse()?;
+        let value: Expr = reg_value.parse()?;
+        let name = match &target {
+            Expr::Ident(name) => name,
+            Expr::Index(base, _) | Expr::Slice(base, _, _) => match base.as_ref() {
+                Expr::Ident(name) => name,
+                _ => return Err(UnableToParseError::InvalidExpression),
+            },
+            _ => return Err(UnableToParseError::InvalidExpression),
+        };
+        let signal = self.signal(name)?;
+        let (slot, field_mask, shift) = match &target {
+            Expr::Index(_, index) if signal.is_array => {
+                (self.slot(name, index)?, mask(signal.width), 0)
+            }
+            Expr::Index(_, index) => (0, 1, self.vector(index, self.width(index)?)? as u32),
+            Expr::Slice(_, high, low) => (0, mask(high.abs_diff(*low) + 1), *high.min(low)),
+            _ => (0, mask(signal.width), 0),
+        };
+        let value = self.vector(&value, 64 - field_mask.leading_zeros())?;
+        let element = &mut next.get_mut(name).unwrap()[slot];
+        let field_mask = field_mask.checked_shl(shift).unwrap_or(0);
+        *element = ((*element & !field_mask)
+            | (value.checked_shl(shift).unwrap_or(0) & field_mask))
+            & mask(signal.width);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse;
+
+    #[test]
+    fn multiplier_test() {
+        let design = parse(&std::fs::read_to_string("multiplier.asmc").unwrap()).unwrap();
+        let mut sim = Simulator::new(&design).unwrap();
+        for (a, b) in [(3, 5), (15, 15), (0, 7)] {
+            sim.set("a", None, a).unwrap();
+            sim.set("b", None, b).unwrap();
+            sim.set("start", None, 1).unwrap();
+            sim.step().unwrap();
+            sim.set("start", None, 0).unwrap();
+            for _ in 0..64 {
+                sim.step().unwrap();
+                if sim.get("ready", None) == Some(1) {
+                    break;
+                }
+            }
+            assert_eq!(sim.get("res", None), Some(a * b));
+        }
+    }
+
+    #[test]
+    fn transfer_test() {
+        let design = parse(
+            "
+r0 => reg[7:0];
+mem => reg[3:0][3:0];
+.main : state {
+    r0[7:4] => 4'b1010;
+    r0[0] => 1;
+    mem[r0[1:0]] => r0 + 1;
+    then => main;
+}
+",
+        )
+        .unwrap();
+        let mut sim = Simulator::new(&design).unwrap();
+        sim.step().unwrap();
+        assert_eq!(sim.get("r0", None), Some(0xa1));
+        assert_eq!(sim.get("mem", Some(0)), Some(1));
+        sim.step().unwrap();
+        assert_eq!(sim.get("mem", Some(1)), Some(2));
+        assert_eq!(sim.state(), "main");
+        assert!(sim.set("r0", None, 1).is_err());
+    }
+
+    #[test]
+    fn enum_test() {
+        let design = parse(
+            "
+enum Opcode { ADD, SUB, JMP }
+op => input Opcode;
+last => reg Opcode;
+.idle : state {
+    last => op == JMP ? SUB : op;
+    then => idle;
+}
+",
+        )
+        .unwrap();
+        let mut sim = Simulator::new(&design).unwrap();
+        sim.set("op", None, 2).unwrap();
+        sim.step().unwrap();
+        assert_eq!(sim.get("last", None), Some(1));
+        assert_eq!(sim.dump(), "     1 idle op=JMP last=SUB");
+        assert!(sim.set("JMP", None, 0).is_err());
+    }
+
+    #[test]
+    fn memory_test() {
+        let init = std::env::temp_dir().join("asm_sim_memory_test.hex");
+        std::fs::write(&init, "// two words\n@2 1ff 07\n").unwrap();
+        let source = format!(
+            "
+address => input[3:0];
+r0 => reg[7:0];
+r1 => reg[7:0];
+mem => memory[7:0] depth 16 init \"{}\";
+lut => memory[7:0] depth 4 latency 0;
+.fill : state {{
+    lut[1] => 5;
+    mem[address] => 9;
+    then => read;
+}}
+.read : state {{
+    r0 => mem[address + 1];
+    r1 => lut[1] + 1;
+    then => fill;
+}}
+",
+            init.display()
+        );
+        let design = parse(&source).unwrap();
+        let mut sim = Simulator::new(&design).unwrap();
+        assert_eq!(sim.get("mem", Some(2)), Some(0xff));
+        sim.set("address", None, 2).unwrap();
+        sim.step().unwrap();
+        assert_eq!(sim.get("mem", Some(2)), Some(9));
+        assert_eq!(sim.get("lut", Some(1)), Some(5));
+        sim.step().unwrap();
+        assert_eq!(sim.get("r1", None), Some(6));
+        assert_eq!(sim.get("r0", None), Some(0));
+        sim.step().unwrap();
+        assert_eq!(sim.get("r0", None), Some(7));
+        assert_eq!(sim.dump(), "     3 read address=2 r0=7 r1=6");
+    }
+
+    #[test]
+    fn primitive_test() {
+        let design = parse(
+            "
+data => input[7:0];
+r0 => reg[7:0];
+q => fifo[8] depth 2;
+t => counter[2];
+.fill : state {
+    q.push(data);
+    then => full;
+}
+.full : decision {
+    check => q.full;
+    yes => drain;
+    no => fill;
+}
+.drain : state {
+    r0 => q.data;
+    q.pop();
+    t.clear();
+    then => fill;
+}
+",
+        )
+        .unwrap();
+        let mut sim = Simulator::new(&design).unwrap();
+        assert_eq!(sim.get("q.empty", None), Some(1));
+        sim.set("data", None, 3).unwrap();
+        sim.step().unwrap();
+        sim.set("data", None, 4).unwrap();
+        sim.step().unwrap();
+        // Decided on `q.full` from before the edge; this push is ignored.
+        sim.step().unwrap();
+        assert_eq!(sim.state(), "drain");
+        assert_eq!(sim.get("q.full", None), Some(1));
+        assert_eq!(sim.get("t.value", None), Some(3));
+        sim.step().unwrap();
+        assert_eq!(sim.get("r0", None), Some(3));
+        assert_eq!(sim.get("t.value", None), Some(0));
+        assert_eq!(
+            sim.dump(),
+            "     4 fill data=4 r0=3 q.data=4 q.empty=0 q.full=0 t.value=0"
+        );
+        assert!(sim.set("q.full", None, 0).is_err());
+        sim.reset();
+        assert_eq!(sim.get("q.empty", None), Some(1));
+    }
+
+    #[test]
+    fn fifo_test() {
+        let design = parse(
+            "
+r0 => reg[7:0];
+q => fifo[8] depth 2;
+.push : state {
+    q.push(9);
+    then => pop;
+}
+.pop : state {
+    q.pop();
+    r0 => q.data;
+    then => stale;
+}
+.stale : state {
+    r0 => q.data;
+    then => stale;
+}
+",
+        )
+        .unwrap();
+        let mut sim = Simulator::new(&design).unwrap();
+        sim.step().unwrap();
+        sim.step().unwrap();
+        assert_eq!(sim.get("r0", None), Some(9));
+        assert_eq!(sim.get("q.empty", None), Some(1));
+        assert_eq!(sim.get("q.data", None), Some(0));
+        sim.step().unwrap();
+        assert_eq!(sim.get("r0", None), Some(0));
+    }
+
+    #[test]
+    fn wire_test() {
+        let design = parse(
+            "
+a => input[3:0];
+r0 => reg[3:0];
+sum => wire[4:0] = a + r0;
+carry => wire = sum[4];
+.idle : state {
+    r0 => carry ? 0 : sum;
+    then => idle;
+}
+",
+        )
+        .unwrap();
+        let mut sim = Simulator::new(&design).unwrap();
+        sim.set("a", None, 9).unwrap();
+        sim.step().unwrap();
+        assert_eq!(sim.get("sum", None), Some(18));
+        assert_eq!(sim.get("carry", None), Some(1));
+        assert_eq!(sim.dump(), "     1 idle a=9 r0=9 sum=18 carry=1");
+        sim.step().unwrap();
+        assert_eq!(sim.get("r0", None), Some(0));
+    }
+
+    #[test]
+    fn inout_test() {
+        let design = parse(
+            "
+bus => inout[7:0] registered;
+r0 => reg[7:0];
+.idle : state {
+    drive => bus;
+    bus => r0 + 1;
+    then => done;
+}
+.done : state {
+    release => bus;
+    r0 => bus;
+    then => idle;
+}
+",
+        )
+        .unwrap();
+        let mut sim = Simulator::new(&design).unwrap();
+        sim.set("bus", None, 5).unwrap();
+        sim.step().unwrap();
+        assert_eq!(sim.get("bus", None), Some(1));
+        // `bus` is read as it was before the last edge.
+        sim.step().unwrap();
+        assert_eq!(sim.get("r0", None), Some(5));
+        sim.set("bus", None, 7).unwrap();
+        sim.step().unwrap();
+        assert_eq!(sim.get("bus", None), Some(6));
+        sim.step().unwrap();
+        assert_eq!(sim.get("r0", None), Some(7));
+    }
+
+    #[test]
+    fn pulse_test() {
+        let design = parse(
+            "
+pins => inout[1:0][3:0];
+.put : state {
+    pins[1] => 5;
+    then => idle;
+}
+.idle : state {
+    then => idle;
+}
+",
+        )
+        .unwrap();
+        let mut sim = Simulator::new(&design).unwrap();
+        sim.step().unwrap();
+        assert_eq!(sim.get("pins", Some(1)), Some(5));
+        assert_eq!(sim.get("pins", Some(0)), Some(0));
+        // Without `drive` the pin is let go a cycle after the transfer, so
+        // what drives it from outside shows from then on.
+        sim.set("pins", Some(1), 2).unwrap();
+        sim.step().unwrap();
+        assert_eq!(sim.get("pins", Some(1)), Some(2));
+    }
+
+    #[test]
+    fn stream_test() {
+        let design = parse(
+            "
+rx => stream in[7:0];
+tx => stream out[7:0];
+.idle : state {
+    rx_ready => 1;
+    tx_valid => rx_valid;
+    tx_data => rx_data;
+    then => idle;
+}
+",
+        )
+        .unwrap();
+        let mut sim = Simulator::new(&design).unwrap();
+        sim.set("rx_valid", None, 1).unwrap();
+        sim.set("rx_data", None, 0x5a).unwrap();
+        sim.step().unwrap();
+        assert_eq!(sim.get("rx_ready", None), Some(1));
+        assert_eq!(sim.get("tx_valid", None), Some(1));
+        assert_eq!(sim.get("tx_data", None), Some(0x5a));
+        assert!(sim.set("tx_valid", None, 0).is_err());
+        sim.set("tx_ready", None, 1).unwrap();
+    }
+
+    #[test]
+    fn clock_test() {
+        let design = parse(
+            "
+go => input async;
+r0 => reg[7:0];
+clock => clk reset reset enable ce;
+.idle : state {
+    r0 => go;
+    then => idle;
+}
+",
+        )
+        .unwrap();
+        let mut sim = Simulator::new(&design).unwrap();
+        sim.set("go", None, 1).unwrap();
+        sim.step().unwrap();
+        assert_eq!(sim.get("r0", None), Some(0));
+        // Two flops later the machine sees it.
+        sim.step().unwrap();
+        sim.step().unwrap();
+        assert_eq!(sim.get("r0", None), Some(1));
+        sim.set("ce", None, 0).unwrap();
+        sim.set("go", None, 0).unwrap();
+        sim.step().unwrap();
+        sim.step().unwrap();
+        sim.step().unwrap();
+        assert_eq!(sim.get("r0", None), Some(1));
+        assert_eq!(sim.cycle(), 6);
+        assert_eq!(sim.dump(), "     6 idle go=0 r0=1");
+        sim.set("ce", None, 1).unwrap();
+        sim.step().unwrap();
+        assert_eq!(sim.get("r0", None), Some(0));
+    }
+
+    #[test]
+    fn wait_test() {
+        let design = parse(
+            "
+ack => input;
+r0 => reg[3:0];
+.idle : state {
+    r0 => r0 + 1;
+    wait => 3;
+    then => poll;
+}
+.poll : state {
+    wait_until => ack timeout 2 => idle;
+    then => done;
+}
+.done : state {
+    then => done;
+}
+",
+        )
+        .unwrap();
+        let mut sim = Simulator::new(&design).unwrap();
+        let mut states = vec![];
+        for cycle in 0..9 {
+            if cycle == 7 {
+                sim.set("ack", None, 1).unwrap();
+            }
+            sim.step().unwrap();
+            states.push(sim.state().to_string());
+        }
+        assert_eq!(
+            states,
+            vec!["idle", "idle", "poll", "poll", "idle", "idle", "idle", "poll", "done"]
+        );
+        assert_eq!(sim.get("r0", None), Some(6));
+    }
+
+    #[test]
+    fn call_test() {
+        let design = parse(
+            "
+call_depth => 2;
+r0 => reg[3:0];
+.main : state {
+    call => outer;
+    then => done;
+}
+.done : state {
+    then => done;
+}
+.outer : state {
+    r0 => r0 + 1;
+    call => inner;
+    then => back;
+}
+.back : state {
+    return;
+}
+.inner : state {
+    r0 => r0 + 4;
+    return;
+}
+",
+        )
+        .unwrap();
+        let mut sim = Simulator::new(&design).unwrap();
+        let mut states = vec![];
+        for _ in 0..5 {
+            sim.step().unwrap();
+            states.push(sim.state().to_string());
+        }
+        assert_eq!(states, vec!["outer", "inner", "back", "done", "done"]);
+        assert_eq!(sim.get("r0", None), Some(5));
+    }
+
+    #[test]
+    fn width_test() {
+        for (chart, message) in [
+            (
+                "wide => reg[79:0];\n.main : state {\n    then => main;\n}\n",
+                "`wide` is 80 bits wide, the simulator holds at most 64",
+            ),
+            (
+                "rom => memory[64:0] depth 4;\n.main : state {\n    then => main;\n}\n",
+                "`rom` is 65 bits wide, the simulator holds at most 64",
+            ),
+        ] {
+            let design = parse(chart).unwrap();
+            let err = Simulator::new(&design).err().unwrap();
+            assert_eq!(err.message, message);
+        }
+        let design =
+            parse("r0 => reg[63:0];\n.main : state {\n    r0 => r0 - 1;\n    then => main;\n}\n")
+                .unwrap();
+        let mut sim = Simulator::new(&design).unwrap();
+        sim.step().unwrap();
+        assert_eq!(sim.get("r0", None), Some(u64::MAX));
+    }
+}
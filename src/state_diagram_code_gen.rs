@@ -0,0 +1,107 @@
+use crate::command::{Command, UnableToParseError};
+use crate::node::{self, Node, NodeType};
+use crate::transition::collect_transitions;
+
+fn state_actions(node: &Node) -> Vec<String> {
+    node.commands
+        .iter()
+        .filter_map(|cmd| match cmd {
+            Command::RegisterTransfer {
+                reg_name,
+                reg_value,
+            } => Some(format!("{} <= {}", reg_name, reg_value)),
+            Command::Action { .. } => Some(cmd.to_string()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Writes the body shared by both formats: a reset arrow per machine, the
+/// actions performed inside each state and one labelled arrow per
+/// transition.
+fn emit_body(nodes: &[Node], out: &mut String) -> Result<(), UnableToParseError> {
+    let states: Vec<&Node> = nodes
+        .iter()
+        .filter(|node| node.node_type == NodeType::State)
+        .collect();
+    for machine in node::machines(nodes) {
+        if let Some(reset_state) = states.iter().find(|state| state.machine == machine) {
+            out.push_str(&format!("    [*] --> {}\n", reset_state.node_name));
+        }
+    }
+    for state in states.iter() {
+        for action in state_actions(state) {
+            out.push_str(&format!("    {} : {}\n", state.node_name, action));
+        }
+    }
+    for transition in collect_transitions(nodes)? {
+        let label = transition.label();
+        if label.is_empty() {
+            out.push_str(&format!("    {} --> {}\n", transition.from, transition.to));
+        } else {
+            out.push_str(&format!(
+                "    {} --> {} : {}\n",
+                transition.from, transition.to, label
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Renders the collapsed state-transition view as a Mermaid
+/// `stateDiagram-v2`.
+pub fn emit_mermaid(module: &str, nodes: &[Node]) -> Result<String, UnableToParseError> {
+    let mut out = format!("---\ntitle: {}\n---\nstateDiagram-v2\n", module);
+    emit_body(nodes, &mut out)?;
+    Ok(out)
+}
+
+/// Renders the collapsed state-transition view as a PlantUML state diagram.
+pub fn emit_plantuml(module: &str, nodes: &[Node]) -> Result<String, UnableToParseError> {
+    let mut out = format!("@startuml {}\n", module);
+    emit_body(nodes, &mut out)?;
+    out.push_str("@enduml\n");
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> Vec<Node> {
+        vec![
+            Node::try_parse("idle", "state", "then => go;").unwrap(),
+            Node::try_parse("go", "decision", "check => start; yes => busy; no => idle;").unwrap(),
+            Node::try_parse("busy", "state", "r0 => r0 + 1; then => idle;").unwrap(),
+        ]
+    }
+
+    #[test]
+    fn mermaid_test() {
+        let text = emit_mermaid("Top", &sample()).unwrap();
+        assert!(text.contains("stateDiagram-v2\n    [*] --> idle\n"));
+        assert!(text.contains("    busy : r0 <= r0 + 1\n"));
+        assert!(text.contains("    idle --> busy : start\n"));
+        assert!(text.contains("    idle --> idle : !(start)\n"));
+        assert!(text.contains("    busy --> idle\n"));
+    }
+
+    #[test]
+    fn plantuml_test() {
+        let text = emit_plantuml("Top", &sample()).unwrap();
+        assert!(text.starts_with("@startuml Top\n    [*] --> idle\n"));
+        assert!(text.ends_with("@enduml\n"));
+        assert!(text.contains("    idle --> busy : start\n"));
+    }
+
+    #[test]
+    fn machines_test() {
+        let mut nodes = sample();
+        let mut side = Node::try_parse("poll", "state", "then => poll;").unwrap();
+        side.machine = "bus".to_string();
+        nodes.push(side);
+        let text = emit_mermaid("Top", &nodes).unwrap();
+        assert!(text.contains("stateDiagram-v2\n    [*] --> idle\n    [*] --> poll\n"));
+        assert!(text.contains("    poll --> poll\n"));
+    }
+}
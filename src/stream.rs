@@ -0,0 +1,90 @@
+use crate::command::{Command, Flow};
+use crate::library::port_wire;
+use crate::Design;
+use std::ops::Range;
+
+/// The signals of a stream, with whether each flows the stream's way.
+pub const SIGNALS: [(&str, bool); 3] = [("valid", true), ("ready", false), ("data", true)];
+
+/// The inputs and outputs the `stream` declaration `name` stands for, in
+/// the order of [`SIGNALS`].
+pub fn ports(name: &str, flow: Flow, bits: &Range<u8>) -> Vec<Command> {
+    SIGNALS
+        .iter()
+        .map(|(signal, forward)| {
+            let pin_name = port_wire(name, signal);
+            let bits = match *signal {
+                "data" => bits.clone(),
+                _ => 0..0,
+            };
+            match (flow == Flow::In) == *forward {
+                true => Command::Input {
+                    pin_name,
+                    bits,
+                    array: 0..0,
+                    enum_name: None,
+                    asynchronous: false,
+                },
+                false => Command::Output {
+                    pin_name,
+                    bits,
+                    array: 0..0,
+                    enum_name: None,
+                },
+            }
+        })
+        .collect()
+}
+
+/// `commands` with every `stream` replaced by its ports, in place so that
+/// the ports of a stream stay together.
+pub fn expand(commands: &[Command]) -> Vec<Command> {
+    commands
+        .iter()
+        .flat_map(|cmd| match cmd {
+            Command::Stream {
+                stream_name,
+                flow,
+                bits,
+            } => ports(stream_name, *flow, bits),
+            cmd => vec![cmd.clone()],
+        })
+        .collect()
+}
+
+/// Rewrites `stream` declarations into plain inputs and outputs, which is
+/// all the backends handle.
+pub fn lower(design: &Design) -> Design {
+    Design {
+        commands: expand(&design.commands),
+        nodes: design.nodes.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse;
+
+    #[test]
+    fn lower_test() {
+        let design = parse("rx => stream in[7:0];\ntx => stream out;\nr => reg;").unwrap();
+        let ports: Vec<String> = lower(&design)
+            .commands
+            .iter()
+            .map(|cmd| cmd.to_string())
+            .collect();
+        assert_eq!(
+            ports,
+            vec![
+                "rx_valid => input",
+                "rx_ready => output",
+                "rx_data => input[7:0]",
+                "tx_valid => output",
+                "tx_ready => input",
+                "tx_data => output",
+                "r => reg",
+            ]
+        );
+    }
+}
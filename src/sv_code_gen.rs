@@ -0,0 +1,572 @@
+use crate::clock;
+use crate::command::{split_target, Command, UnableToParseError};
+use crate::enums;
+use crate::naming::{self, Names, Naming, VERILOG_KEYWORDS};
+use crate::node::{self, Node, NodeType};
+use crate::template::substitute;
+use crate::verilog_code_gen::Code;
+use crate::Diagnostic;
+use std::{
+    collections::{HashMap, HashSet},
+    ops::Range,
+};
+
+fn is_array(array: &Range<u8>) -> bool {
+    array.start != array.end || array.start != 0
+}
+
+fn declare(kind: &str, bits: &Range<u8>, name: &str, array: &Range<u8>) -> String {
+    if is_array(array) {
+        format!(
+            "{} [{}:{}] {} [{}:{}]",
+            kind, bits.start, bits.end, name, array.start, array.end
+        )
+    } else {
+        format!("{} [{}:{}] {}", kind, bits.start, bits.end, name)
+    }
+}
+
+/// [`declare`] for a `logic` port or variable, or one of an enum type.
+/// `direction` is empty for variables.
+fn declare_typed(
+    direction: &str,
+    bits: &Range<u8>,
+    name: &str,
+    array: &Range<u8>,
+    enum_name: &Option<String>,
+) -> String {
+    let kind = enum_name.as_deref().unwrap_or("logic");
+    let kind = format!("{} {}", direction, kind);
+    match enum_name {
+        Some(_) => format!("{} {}", kind.trim_start(), name),
+        None => declare(kind.trim_start(), bits, name, array),
+    }
+}
+
+/// Next-value names that a transfer to one declaration writes to.
+struct NextNames {
+    next: String,
+    /// Output-enable register and its next value, for inouts only.
+    enable: Option<(String, String)>,
+    /// Whether only `drive` and `release` set the enable, rather than
+    /// every transfer for one cycle.
+    controlled: bool,
+    /// The enum the declaration holds, which other values are cast to.
+    enum_name: Option<String>,
+}
+
+struct Walker<'l> {
+    code: &'l mut Code,
+    node_map: &'l HashMap<String, &'l Node>,
+    next_names: &'l HashMap<String, NextNames>,
+    /// The enum of every member and typed declaration.
+    typed: &'l HashMap<&'l str, &'l str>,
+    /// Registered inouts and the register they are read through.
+    reads: &'l HashMap<&'l str, String>,
+    /// The enum literal of every state.
+    literals: &'l HashMap<&'l str, String>,
+    state_next: &'l str,
+}
+
+impl<'l> Walker<'l> {
+    fn line(&mut self, depth: usize, text: String) {
+        self.code
+            .update(format!("\n{}{}", "    ".repeat(depth), text));
+    }
+
+    fn lookup(&self, name: &str) -> Result<&'l Node, UnableToParseError> {
+        self.node_map
+            .get(name)
+            .copied()
+            .ok_or(UnableToParseError::UndefinedNode)
+    }
+
+    fn compile_node(
+        &mut self,
+        node: &'l Node,
+        seen: &mut HashSet<&'l String>,
+        depth: usize,
+        full_compile: bool,
+    ) -> Result<(), UnableToParseError> {
+        if !full_compile && node.node_type == NodeType::State {
+            let literal = &self.literals[node.node_name.as_str()];
+            self.line(depth, format!("{} = {};", self.state_next, literal));
+            return Ok(());
+        }
+        if !seen.insert(&node.node_name) {
+            return Err(UnableToParseError::CircularDependency);
+        }
+
+        if node.node_type == NodeType::Decision {
+            let mut check_cond = "0".to_string();
+            let mut yes_node = "";
+            let mut no_node = "";
+            for command in node.commands.iter() {
+                match command {
+                    Command::Check { check } => check_cond = check.clone(),
+                    Command::Yes { next_node } => yes_node = next_node,
+                    Command::No { next_node } => no_node = next_node,
+                    _ => {}
+                }
+            }
+            let check_cond = substitute(&check_cond, self.reads);
+            self.line(depth, format!("if ({}) begin", check_cond));
+            self.compile_node(self.lookup(yes_node)?, seen, depth + 1, false)?;
+            self.line(depth, "end else begin".to_string());
+            self.compile_node(self.lookup(no_node)?, seen, depth + 1, false)?;
+            self.line(depth, "end".to_string());
+        } else {
+            let mut then_node = "";
+            for command in node.commands.iter() {
+                match command {
+                    Command::RegisterTransfer {
+                        reg_name,
+                        reg_value,
+                    } => {
+                        let (base, select) = split_target(reg_name);
+                        let select = substitute(select, self.reads);
+                        let reg_value = &substitute(reg_value, self.reads);
+                        match self.next_names.get(base) {
+                            Some(names) => {
+                                let next = names.next.clone();
+                                // Enum variables only take their own members
+                                // without a cast.
+                                let value = match &names.enum_name {
+                                    Some(enum_name)
+                                        if select.is_empty()
+                                            && self.typed.get(reg_value.trim())
+                                                != Some(&enum_name.as_str()) =>
+                                    {
+                                        format!("{}'({})", enum_name, reg_value)
+                                    }
+                                    _ => reg_value.clone(),
+                                };
+                                self.line(depth, format!("{}{} = {};", next, select, value));
+                                if let (Some((_, enable_next)), false) =
+                                    (&names.enable, names.controlled)
+                                {
+                                    let enable_next = enable_next.clone();
+                                    self.line(depth, format!("{} = 1'b1;", enable_next));
+                                }
+                            }
+                            None => self.line(depth, format!("{} = {};", reg_name, reg_value)),
+                        }
+                    }
+                    Command::Drive { pin_name } | Command::Release { pin_name } => {
+                        let level = match command {
+                            Command::Drive { .. } => "1'b1",
+                            _ => "1'b0",
+                        };
+                        if let Some((_, enable_next)) = self
+                            .next_names
+                            .get(pin_name)
+                            .and_then(|names| names.enable.as_ref())
+                        {
+                            let enable_next = enable_next.clone();
+                            self.line(depth, format!("{} = {};", enable_next, level));
+                        }
+                    }
+                    Command::Then { next_node } => then_node = next_node,
+                    _ => {}
+                }
+            }
+            self.compile_node(self.lookup(then_node)?, seen, depth, false)?;
+        }
+        seen.remove(&node.node_name);
+        Ok(())
+    }
+}
+
+/// Lowers the chart to SystemVerilog: the states become an enum, the
+/// combinational block computes every next value and a single `always_ff`
+/// block registers them.
+/// Fails if the chart uses a SystemVerilog keyword for a name.
+pub fn spellable(module: &str, commands: &[Command]) -> Result<(), Diagnostic> {
+    naming::spellable(module, commands, VERILOG_KEYWORDS, "SystemVerilog", false)
+}
+
+pub fn emit_sv(
+    module: &str,
+    commands: &[Command],
+    nodes: &[Node],
+    naming: &Naming,
+) -> Result<String, UnableToParseError> {
+    let mut code = Code::new(Names::new(
+        naming,
+        commands,
+        &clock::DEFAULT,
+        VERILOG_KEYWORDS,
+        false,
+    ));
+
+    let mut typed = HashMap::new();
+    for cmd in commands.iter() {
+        match cmd {
+            Command::Enum { name, members } => {
+                code.update(format!(
+                    "typedef enum logic [{}:0] {{\n    {}\n}} {};\n\n",
+                    enums::width(members.len()) - 1,
+                    members.join(",\n    "),
+                    name
+                ));
+                for member in members.iter() {
+                    typed.insert(member.as_str(), name.as_str());
+                }
+            }
+            Command::Input {
+                pin_name: name,
+                enum_name: Some(enum_name),
+                ..
+            }
+            | Command::Output {
+                pin_name: name,
+                enum_name: Some(enum_name),
+                ..
+            }
+            | Command::Register {
+                reg_name: name,
+                enum_name: Some(enum_name),
+                ..
+            } => {
+                typed.insert(name.as_str(), enum_name.as_str());
+            }
+            _ => {}
+        }
+    }
+
+    let mut ports = vec![
+        "input logic clk".to_string(),
+        "input logic reset".to_string(),
+    ];
+    for cmd in commands.iter() {
+        match cmd {
+            Command::Input {
+                pin_name,
+                bits,
+                array,
+                enum_name,
+                ..
+            } => ports.push(declare_typed("input", bits, pin_name, array, enum_name)),
+            Command::Output {
+                pin_name,
+                bits,
+                array,
+                enum_name,
+            } => ports.push(declare_typed("output", bits, pin_name, array, enum_name)),
+            Command::Inout {
+                pin_name,
+                bits,
+                array,
+                ..
+            } => ports.push(declare("inout wire", bits, pin_name, array)),
+            _ => {}
+        }
+    }
+    code.update(format!(
+        "module {} (\n    {}\n);",
+        module,
+        ports.join(",\n    ")
+    ));
+
+    let states: Vec<&Node> = nodes
+        .iter()
+        .filter(|node| node.node_type == NodeType::State)
+        .collect();
+    let bit_count = (states.len() as u32).max(1).ilog2();
+    let literals: Vec<String> = states
+        .iter()
+        .map(|node| code.fresh_name(&format!("ST_{}", node.node_name)))
+        .collect();
+    let literal_of: HashMap<&str, String> = states
+        .iter()
+        .map(|node| node.node_name.as_str())
+        .zip(literals.iter().cloned())
+        .collect();
+    let state_type = code.fresh_name("state_t");
+    let current_state_reg = code.fresh_name("currentState");
+    let next_state_reg = code.fresh_name("nextState");
+    code.update(format!(
+        "\n\ntypedef enum logic [{}:0] {{\n    {}\n}} {};\n{} {}, {};\n",
+        bit_count,
+        literals.join(",\n    "),
+        state_type,
+        state_type,
+        current_state_reg,
+        next_state_reg
+    ));
+
+    // Storage in declaration order together with the register it is
+    // loaded from.
+    let controlled = node::controlled_pins(nodes);
+    let mut next_names = HashMap::new();
+    let mut reads = HashMap::new();
+    let mut resets = vec![];
+    let mut storage = vec![];
+    for cmd in commands.iter() {
+        match cmd {
+            Command::Register {
+                reg_name: name,
+                bits,
+                array,
+                enum_name,
+            }
+            | Command::Output {
+                pin_name: name,
+                bits,
+                array,
+                enum_name,
+            } => {
+                let next = code.fresh_name(&format!("{}_next", name));
+                if let Command::Register { .. } = cmd {
+                    code.update(format!(
+                        "\n{};",
+                        declare_typed("", bits, name, array, enum_name)
+                    ));
+                }
+                code.update(format!(
+                    "\n{};",
+                    declare_typed("", bits, &next, array, enum_name)
+                ));
+                storage.push((name.clone(), next.clone(), name.clone()));
+                next_names.insert(
+                    name.clone(),
+                    NextNames {
+                        next,
+                        enable: None,
+                        controlled: false,
+                        enum_name: enum_name.clone(),
+                    },
+                );
+            }
+            Command::Inout {
+                pin_name,
+                bits,
+                array,
+                registered,
+            } => {
+                let main_reg = code.fresh_name(&format!("{}_out", pin_name));
+                let main_next = code.fresh_name(&format!("{}_out_next", pin_name));
+                let write_reg = code.fresh_name(&format!("{}_write_reg", pin_name));
+                let write_next = code.fresh_name(&format!("{}_write_next", pin_name));
+                code.update(format!(
+                    "\n{};\n{};\nlogic {}, {};",
+                    declare("logic", bits, &main_reg, array),
+                    declare("logic", bits, &main_next, array),
+                    write_reg,
+                    write_next
+                ));
+                if is_array(array) {
+                    let (low, high) = (array.start.min(array.end), array.start.max(array.end));
+                    let block = code.fresh_name(&format!("{}_drive", pin_name));
+                    code.update(format!(
+                        "\nfor (genvar i = {low}; i <= {high}; i++) begin : {block}\n    assign {pin_name}[i] = {write_reg} ? {main_reg}[i] : 'z;\nend"
+                    ));
+                } else {
+                    code.update(format!(
+                        "\nassign {pin_name} = {write_reg} ? {main_reg} : 'z;"
+                    ));
+                }
+                let is_controlled = controlled.contains(pin_name.as_str());
+                storage.push((main_reg.clone(), main_next.clone(), main_reg.clone()));
+                if is_controlled {
+                    storage.push((write_reg.clone(), write_next.clone(), write_reg.clone()));
+                    resets.push(write_reg.clone());
+                } else {
+                    storage.push((write_reg.clone(), write_next.clone(), "1'b0".to_string()));
+                }
+                if *registered {
+                    let read_reg = code.fresh_name(&format!("{}_read_reg", pin_name));
+                    let read_next = code.fresh_name(&format!("{}_read_next", pin_name));
+                    code.update(format!(
+                        "\n{};\n{};",
+                        declare("logic", bits, &read_reg, array),
+                        declare("logic", bits, &read_next, array)
+                    ));
+                    storage.push((read_reg.clone(), read_next, pin_name.clone()));
+                    reads.insert(pin_name.as_str(), read_reg);
+                }
+                next_names.insert(
+                    pin_name.clone(),
+                    NextNames {
+                        next: main_next,
+                        enable: Some((write_reg, write_next)),
+                        controlled: is_controlled,
+                        enum_name: None,
+                    },
+                );
+            }
+            _ => {}
+        }
+    }
+
+    for cmd in commands.iter() {
+        if let Command::Wire {
+            wire_name, bits, ..
+        } = cmd
+        {
+            code.update(format!("\n{};", declare("logic", bits, wire_name, &(0..0))));
+        }
+    }
+    for cmd in commands.iter() {
+        if let Command::Wire {
+            wire_name, value, ..
+        } = cmd
+        {
+            code.update(format!(
+                "\nassign {} = {};",
+                wire_name,
+                substitute(value, &reads)
+            ));
+        }
+    }
+
+    let mut node_map = HashMap::new();
+    for node in nodes.iter() {
+        node_map.insert(node.get_name(), node);
+    }
+
+    code.update(format!(
+        "\n\nalways_comb begin\n    {} = {};",
+        next_state_reg, current_state_reg
+    ));
+    for (_, next, default) in storage.iter() {
+        code.update(format!("\n    {} = {};", next, default));
+    }
+    code.update(format!("\n    unique case ({})", current_state_reg));
+    let mut walker = Walker {
+        code: &mut code,
+        node_map: &node_map,
+        next_names: &next_names,
+        typed: &typed,
+        reads: &reads,
+        literals: &literal_of,
+        state_next: &next_state_reg,
+    };
+    for state in states.iter() {
+        walker.line(
+            2,
+            format!("{}: begin", literal_of[state.node_name.as_str()]),
+        );
+        walker.compile_node(state, &mut HashSet::new(), 3, true)?;
+        walker.line(2, "end".to_string());
+    }
+    if let Some(reset_literal) = literals.first() {
+        walker.line(
+            2,
+            format!("default: {} = {};", next_state_reg, reset_literal),
+        );
+    }
+    code.update("\n    endcase\nend\n".to_string());
+
+    code.update(format!(
+        "\nalways_ff @(posedge clk or posedge reset) begin\n    if (reset) begin\n        {} <= {};",
+        current_state_reg,
+        literals.first().cloned().unwrap_or("'0".to_string()),
+    ));
+    // Pins under `drive` and `release` hold their enable, so a reset
+    // releases them.
+    for write_reg in resets.iter() {
+        code.update(format!("\n        {} <= 1'b0;", write_reg));
+    }
+    code.update(format!(
+        "\n    end else begin\n        {} <= {};",
+        current_state_reg, next_state_reg
+    ));
+    for (target, next, _) in storage.iter() {
+        code.update(format!("\n        {} <= {};", target, next));
+    }
+    code.update("\n    end\nend\n\nendmodule\n".to_string());
+
+    Ok(code.code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn multiplier_shape_test() {
+        let commands: Vec<Command> = ["start => input", "ready => output", "r0 => reg[3:0]"]
+            .iter()
+            .map(|cmd| cmd.parse().unwrap())
+            .collect();
+        let mut nodes = vec![
+            Node::try_parse("idle", "state", "then => go;").unwrap(),
+            Node::try_parse("go", "decision", "check => start; yes => busy; no => idle;").unwrap(),
+            Node::try_parse("busy", "state", "r0 => r0 + 1; ready => 1; then => idle;").unwrap(),
+        ];
+        crate::node::number_states(&mut nodes);
+        let sv = emit_sv("Top", &commands, &nodes, &Naming::default()).unwrap();
+        assert!(sv.starts_with("module Top (\n    input logic clk,\n    input logic reset,"));
+        assert!(sv.contains("output logic [0:0] ready"));
+        assert!(sv.contains("    ST_idle,\n    ST_busy\n}"));
+        assert!(sv.contains("always_comb begin"));
+        assert!(sv.contains("unique case ("));
+        assert!(sv.contains("always_ff @(posedge clk or posedge reset) begin"));
+        assert!(sv.contains("        ST_busy: begin\n            r0_next"));
+        assert!(sv.contains("            if (start) begin\n"));
+    }
+
+    #[test]
+    fn names_test() {
+        let commands: Vec<Command> = ["ST_idle => input", "r0 => reg"]
+            .iter()
+            .map(|cmd| cmd.parse().unwrap())
+            .collect();
+        let mut nodes =
+            vec![Node::try_parse("idle", "state", "r0 => ST_idle; then => idle;").unwrap()];
+        crate::node::number_states(&mut nodes);
+        let sv = emit_sv("Top", &commands, &nodes, &Naming::default()).unwrap();
+        // The state literal steps aside for the chart's own `ST_idle`.
+        assert!(sv.contains("    ST_idle_2\n}"));
+        assert!(sv.contains("        ST_idle_2: begin\n            r0_next = ST_idle;"));
+
+        let commands: Vec<Command> = vec!["logic => reg".parse().unwrap()];
+        assert_eq!(
+            spellable("Top", &commands).unwrap_err().message,
+            "`logic` is reserved in SystemVerilog, rename it"
+        );
+        assert!(spellable("unique", &[]).is_err());
+    }
+
+    #[test]
+    fn enum_test() {
+        let design = crate::parse(
+            "
+enum Opcode { ADD, SUB, JMP }
+op => input Opcode;
+last => reg Opcode;
+.idle : state {
+    last => op;
+    then => busy;
+}
+.busy : state {
+    last => 2;
+    then => idle;
+}
+",
+        )
+        .unwrap();
+        let sv = emit_sv("Top", &design.commands, &design.nodes, &Naming::default()).unwrap();
+        assert!(sv.starts_with(
+            "typedef enum logic [1:0] {\n    ADD,\n    SUB,\n    JMP\n} Opcode;\n\nmodule Top ("
+        ));
+        assert!(sv.contains("    input Opcode op\n"));
+        assert!(sv.contains("\nOpcode last;\nOpcode last_next"));
+        assert!(sv.contains(" = op;"));
+        assert!(sv.contains(" = Opcode'(2);"));
+    }
+
+    #[test]
+    fn array_inout_test() {
+        let commands: Vec<Command> = ["c => inout[4:0][3:0]"]
+            .iter()
+            .map(|cmd| cmd.parse().unwrap())
+            .collect();
+        let nodes = vec![Node::try_parse("idle", "state", "c[1] => 2; then => idle;").unwrap()];
+        let sv = emit_sv("Top", &commands, &nodes, &Naming::default()).unwrap();
+        assert!(sv.contains("inout wire [3:0] c [4:0]"));
+        assert!(sv.contains("for (genvar i = 0; i <= 4; i++) begin : c_drive"));
+        assert!(sv.contains("[1] = 2;"));
+    }
+}
@@ -0,0 +1,420 @@
+use crate::command::{Command, Timeout};
+use crate::node::{self, Node, Origin};
+use crate::{Design, Diagnostic};
+use regex::Regex;
+use std::collections::HashMap;
+
+lazy_static::lazy_static! {
+    static ref IDENT: Regex = Regex::new(r"[a-zA-Z_][a-zA-Z0-9_]*").unwrap();
+}
+
+/// Replaces the identifiers of `text` that are keys of `args`. Port names
+/// after a `.` and the digits of constants such as `8'hff` are left alone.
+pub fn substitute(text: &str, args: &HashMap<&str, String>) -> String {
+    let mut out = String::new();
+    let mut last = 0;
+    for found in IDENT.find_iter(text) {
+        let before = text[..found.start()].chars().next_back();
+        if before.is_some_and(|c| c == '.' || c == '\'' || c.is_ascii_digit()) {
+            continue;
+        }
+        if let Some(arg) = args.get(found.as_str()) {
+            out.push_str(&text[last..found.start()]);
+            out.push_str(arg);
+            last = found.end();
+        }
+    }
+    out.push_str(&text[last..]);
+    out
+}
+
+/// Arguments that can stand in an expression without parentheses.
+fn is_simple(arg: &str) -> bool {
+    arg.chars()
+        .all(|c| c.is_ascii_alphanumeric() || "_.'[]:".contains(c))
+}
+
+struct Template<'d> {
+    name: &'d str,
+    params: &'d [String],
+    nodes: &'d [Node],
+}
+
+impl std::fmt::Display for Template<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}({})", self.name, self.params.join(", "))
+    }
+}
+
+fn templates(design: &Design) -> Result<HashMap<&str, Template<'_>>, Diagnostic> {
+    let mut out: HashMap<&str, Template> = HashMap::new();
+    for cmd in design.commands.iter() {
+        let Command::Template {
+            name,
+            params,
+            nodes,
+        } = cmd
+        else {
+            continue;
+        };
+        let template = Template {
+            name,
+            params,
+            nodes,
+        };
+        if let Some(first) = out.get(name.as_str()) {
+            return Err(Diagnostic::error(format!(
+                "template `{}` is defined again as `{}`",
+                first, template
+            )));
+        }
+        for (idx, param) in params.iter().enumerate() {
+            let error = |message: String| {
+                Err(Diagnostic::error(format!(
+                    "in template `{}`: {}",
+                    template, message
+                )))
+            };
+            if IDENT
+                .find(param)
+                .is_none_or(|found| found.len() != param.len())
+            {
+                return error(format!("`{}` is not a valid parameter name", param));
+            }
+            if params[..idx].contains(param) {
+                return error(format!("parameter `{}` is listed twice", param));
+            }
+            if nodes.iter().any(|node| node.node_name == *param) {
+                return error(format!("parameter `{}` is also the name of a node", param));
+            }
+        }
+        out.insert(name, template);
+    }
+    Ok(out)
+}
+
+/// Copies the nodes of `template` for one `use`, naming them
+/// `{prefix}_{node}` and replacing its parameters with `args`.
+fn instantiate(template: &Template, args: &[String], prefix: &str) -> Vec<Node> {
+    let raw: HashMap<&str, String> = template
+        .params
+        .iter()
+        .map(String::as_str)
+        .zip(args.iter().cloned())
+        .collect();
+    let wrapped: HashMap<&str, String> = raw
+        .iter()
+        .map(|(param, arg)| match is_simple(arg) {
+            true => (*param, arg.clone()),
+            false => (*param, format!("({})", arg)),
+        })
+        .collect();
+    let target = |next_node: &str| {
+        if template
+            .nodes
+            .iter()
+            .any(|node| node.node_name == next_node)
+        {
+            format!("{}_{}", prefix, next_node)
+        } else {
+            raw.get(next_node)
+                .cloned()
+                .unwrap_or_else(|| next_node.to_string())
+        }
+    };
+    template
+        .nodes
+        .iter()
+        .map(|node| Node {
+            node_name: format!("{}_{}", prefix, node.node_name),
+            node_type: node.node_type,
+            commands: node
+                .commands
+                .iter()
+                .map(|cmd| match cmd {
+                    Command::RegisterTransfer {
+                        reg_name,
+                        reg_value,
+                    } => Command::RegisterTransfer {
+                        reg_name: substitute(reg_name, &raw),
+                        reg_value: substitute(reg_value, &wrapped),
+                    },
+                    Command::Check { check } => Command::Check {
+                        check: substitute(check, &wrapped),
+                    },
+                    Command::Then { next_node } => Command::Then {
+                        next_node: target(next_node),
+                    },
+                    Command::Yes { next_node } => Command::Yes {
+                        next_node: target(next_node),
+                    },
+                    Command::No { next_node } => Command::No {
+                        next_node: target(next_node),
+                    },
+                    Command::Call { node_name } => Command::Call {
+                        node_name: target(node_name),
+                    },
+                    Command::Action {
+                        name,
+                        action,
+                        value,
+                    } => Command::Action {
+                        name: substitute(name, &raw),
+                        action: action.clone(),
+                        value: value.as_ref().map(|value| substitute(value, &wrapped)),
+                    },
+                    Command::Drive { pin_name } => Command::Drive {
+                        pin_name: substitute(pin_name, &raw),
+                    },
+                    Command::Release { pin_name } => Command::Release {
+                        pin_name: substitute(pin_name, &raw),
+                    },
+                    Command::WaitUntil { check, timeout } => Command::WaitUntil {
+                        check: substitute(check, &wrapped),
+                        timeout: timeout.as_ref().map(|timeout| Timeout {
+                            cycles: timeout.cycles,
+                            next_node: target(&timeout.next_node),
+                        }),
+                    },
+                    cmd => cmd.clone(),
+                })
+                .collect(),
+            machine: String::new(),
+            origin: None,
+            id: 0,
+        })
+        .collect()
+}
+
+/// Replaces every `use` with a copy of its template's nodes and drops the
+/// templates. A copy takes the `as` name as its prefix, or the template
+/// name and a count, and joins the machine of the nodes it leads to.
+pub fn expand(design: Design) -> Result<Design, Diagnostic> {
+    let templates = templates(&design)?;
+    let mut machines: HashMap<String, String> = design
+        .nodes
+        .iter()
+        .map(|node| (node.node_name.clone(), node.machine.clone()))
+        .collect();
+    let mut uses: HashMap<&str, usize> = HashMap::new();
+    let mut added = vec![];
+    for cmd in design.commands.iter() {
+        let Command::Use {
+            template,
+            args,
+            prefix,
+        } = cmd
+        else {
+            continue;
+        };
+        let Some(template) = templates.get(template.as_str()) else {
+            return Err(Diagnostic::error(format!("`{}` names no template", cmd)));
+        };
+        let error = |message: String| {
+            Err(Diagnostic::error(format!(
+                "in `{}` of template `{}`: {}",
+                cmd, template, message
+            )))
+        };
+        if args.len() != template.params.len() {
+            return error(format!(
+                "{} arguments given, {} expected",
+                args.len(),
+                template.params.len()
+            ));
+        }
+        let count = uses.entry(template.name).or_default();
+        *count += 1;
+        let prefix = prefix
+            .clone()
+            .unwrap_or_else(|| format!("{}{}", template.name, count));
+        let mut nodes = instantiate(template, args, &prefix);
+
+        let mut joined: Option<&String> = None;
+        for (node, original) in nodes.iter().zip(template.nodes.iter()) {
+            if machines.contains_key(&node.node_name) {
+                return error(format!(
+                    "node `{}` becomes `{}`, which is already a node",
+                    original.node_name, node.node_name
+                ));
+            }
+            let targets = node.commands.iter().filter_map(|cmd| match cmd {
+                Command::Then { next_node }
+                | Command::Yes { next_node }
+                | Command::No { next_node }
+                | Command::WaitUntil {
+                    timeout: Some(Timeout { next_node, .. }),
+                    ..
+                } => Some(next_node),
+                Command::Call { node_name } => Some(node_name),
+                _ => None,
+            });
+            for machine in targets.filter_map(|target| machines.get(target)) {
+                match joined {
+                    Some(first) if first != machine => {
+                        return error(format!(
+                            "its nodes lead to both machine `{}` and machine `{}`",
+                            first, machine
+                        ))
+                    }
+                    _ => joined = Some(machine),
+                }
+            }
+        }
+        let machine = joined.cloned().unwrap_or_default();
+        for node in nodes.iter_mut() {
+            node.machine = machine.clone();
+            node.origin = Some(Origin {
+                template: template.name.to_string(),
+                site: cmd.to_string(),
+            });
+            machines.insert(node.node_name.clone(), machine.clone());
+        }
+        added.append(&mut nodes);
+    }
+
+    let commands = design
+        .commands
+        .into_iter()
+        .filter(|cmd| !matches!(cmd, Command::Template { .. } | Command::Use { .. }))
+        .collect();
+    let mut nodes = design.nodes;
+    nodes.append(&mut added);
+    node::number_states(&mut nodes);
+    Ok(Design { commands, nodes })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse;
+
+    const HANDSHAKE: &str = "
+template handshake(req, ack, next) {
+    .raise : state {
+        req => 1;
+        then => hold;
+    }
+    .hold : decision {
+        check => ack;
+        yes => drop;
+        no => raise;
+    }
+    .drop : state {
+        req => 0;
+        then => next;
+    }
+}
+";
+
+    #[test]
+    fn expand_test() {
+        let design = parse(&format!(
+            "start => reg;
+done => input[1:0];
+{}
+.idle : state {{
+    then => send_raise;
+}}
+use handshake(start, done == 2'b11, handshake2_raise) as send;
+use handshake(start, done[0], idle);
+",
+            HANDSHAKE
+        ))
+        .unwrap();
+        let expanded = expand(design).unwrap();
+        assert_eq!(
+            expanded.to_string(),
+            "start => reg;
+done => input[1:0];
+
+.idle : state {
+    then => send_raise;
+}
+
+.send_raise : state {
+    start => 1;
+    then => send_hold;
+}
+
+.send_hold : decision {
+    check => (done == 2'b11);
+    yes => send_drop;
+    no => send_raise;
+}
+
+.send_drop : state {
+    start => 0;
+    then => handshake2_raise;
+}
+
+.handshake2_raise : state {
+    start => 1;
+    then => handshake2_hold;
+}
+
+.handshake2_hold : decision {
+    check => done[0];
+    yes => handshake2_drop;
+    no => handshake2_raise;
+}
+
+.handshake2_drop : state {
+    start => 0;
+    then => idle;
+}
+"
+        );
+        assert!(crate::check(&expanded).is_empty());
+
+        let design = parse(&format!(
+            "start => reg;\n{}\n.idle : state {{ then => idle; }}\nuse handshake(start, done, nowhere);",
+            HANDSHAKE
+        ))
+        .unwrap();
+        let found: Vec<String> = crate::check(&expand(design).unwrap())
+            .iter()
+            .map(|diagnostic| diagnostic.to_string())
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                "error: in template `handshake` used at `use handshake(start, done, nowhere)`: \
+                 `done` is not declared (in node `handshake1_hold`)",
+                "error: in template `handshake` used at `use handshake(start, done, nowhere)`: \
+                 `nowhere` is not a node (in node `handshake1_drop`)",
+            ]
+        );
+    }
+
+    #[test]
+    fn expand_errors_test() {
+        let error = |uses: &str| {
+            expand(parse(&format!("{}{}", HANDSHAKE, uses)).unwrap())
+                .unwrap_err()
+                .message
+        };
+        assert_eq!(
+            error("use handshake(start, done);"),
+            "in `use handshake(start, done)` of template `handshake(req, ack, next)`: \
+             2 arguments given, 3 expected"
+        );
+        assert_eq!(
+            error("use shake(start);"),
+            "`use shake(start)` names no template"
+        );
+        assert_eq!(
+            error(".h_raise : state { then => h_raise; }\nuse handshake(a, b, c) as h;"),
+            "in `use handshake(a, b, c) as h` of template `handshake(req, ack, next)`: \
+             node `raise` becomes `h_raise`, which is already a node"
+        );
+        assert_eq!(
+            error("template handshake(a) {}"),
+            "template `handshake(req, ack, next)` is defined again as `handshake(a)`"
+        );
+        assert_eq!(
+            error("template pulse(x, x) {}"),
+            "in template `pulse(x, x)`: parameter `x` is listed twice"
+        );
+    }
+}
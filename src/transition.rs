@@ -0,0 +1,193 @@
+use crate::command::{Command, UnableToParseError};
+use crate::node::{Node, NodeType};
+use std::collections::{HashMap, HashSet};
+
+/// One edge of the collapsed state graph: every decision taken and every
+/// conditional action performed while walking from `from` to `to`.
+#[derive(Debug, PartialEq)]
+pub struct Transition {
+    pub from: String,
+    pub to: String,
+    pub conditions: Vec<(String, bool)>,
+    /// Transfers and `fifo`/`counter` actions, in the order performed.
+    pub actions: Vec<Command>,
+}
+
+impl Transition {
+    pub fn condition_label(&self) -> String {
+        self.conditions
+            .iter()
+            .map(|(check, taken)| {
+                if *taken {
+                    check.clone()
+                } else {
+                    format!("!({})", check)
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(" && ")
+    }
+    pub fn action_label(&self) -> String {
+        self.actions
+            .iter()
+            .map(|cmd| match cmd {
+                Command::RegisterTransfer {
+                    reg_name,
+                    reg_value,
+                } => format!("{} <= {}", reg_name, reg_value),
+                cmd => cmd.to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+    /// `condition / actions`, leaving out whichever half is empty.
+    pub fn label(&self) -> String {
+        match (self.condition_label(), self.action_label()) {
+            (cond, act) if act.is_empty() => cond,
+            (cond, act) if cond.is_empty() => format!("/ {}", act),
+            (cond, act) => format!("{} / {}", cond, act),
+        }
+    }
+}
+
+fn next_of(node: &Node) -> Option<&String> {
+    node.commands.iter().find_map(|cmd| match cmd {
+        Command::Then { next_node } => Some(next_node),
+        _ => None,
+    })
+}
+
+fn walk(
+    from: &Node,
+    node: &Node,
+    node_map: &HashMap<&str, &Node>,
+    seen: &mut HashSet<String>,
+    conditions: &mut Vec<(String, bool)>,
+    actions: &mut Vec<Command>,
+    out: &mut Vec<Transition>,
+) -> Result<(), UnableToParseError> {
+    if node.node_type == NodeType::State {
+        out.push(Transition {
+            from: from.node_name.clone(),
+            to: node.node_name.clone(),
+            conditions: conditions.clone(),
+            actions: actions.clone(),
+        });
+        return Ok(());
+    }
+    if !seen.insert(node.node_name.clone()) {
+        return Err(UnableToParseError::CircularDependency);
+    }
+    let lookup = |name: &String| {
+        node_map
+            .get(name.as_str())
+            .copied()
+            .ok_or(UnableToParseError::UndefinedNode)
+    };
+    if node.node_type == NodeType::Decision {
+        let mut check_cond = "0".to_string();
+        let mut yes_node = None;
+        let mut no_node = None;
+        for command in node.commands.iter() {
+            match command {
+                Command::Check { check } => check_cond = check.clone(),
+                Command::Yes { next_node } => yes_node = Some(next_node),
+                Command::No { next_node } => no_node = Some(next_node),
+                _ => {}
+            }
+        }
+        for (next, taken) in [(yes_node, true), (no_node, false)] {
+            let next = lookup(next.ok_or(UnableToParseError::UndefinedNode)?)?;
+            conditions.push((check_cond.clone(), taken));
+            walk(from, next, node_map, seen, conditions, actions, out)?;
+            conditions.pop();
+        }
+    } else {
+        let before = actions.len();
+        actions.extend(
+            node.commands
+                .iter()
+                .filter(|cmd| {
+                    matches!(
+                        cmd,
+                        Command::RegisterTransfer { .. } | Command::Action { .. }
+                    )
+                })
+                .cloned(),
+        );
+        let next = lookup(next_of(node).ok_or(UnableToParseError::UndefinedNode)?)?;
+        walk(from, next, node_map, seen, conditions, actions, out)?;
+        actions.truncate(before);
+    }
+    seen.remove(&node.node_name);
+    Ok(())
+}
+
+/// Collapses the chart to its state-to-state transitions, following each
+/// state's `then` through the decision and conditional nodes behind it.
+pub fn collect_transitions(nodes: &[Node]) -> Result<Vec<Transition>, UnableToParseError> {
+    let node_map: HashMap<&str, &Node> = nodes
+        .iter()
+        .map(|node| (node.node_name.as_str(), node))
+        .collect();
+    let mut out = vec![];
+    for node in nodes.iter() {
+        if node.node_type != NodeType::State {
+            continue;
+        }
+        let next = next_of(node)
+            .and_then(|name| node_map.get(name.as_str()))
+            .ok_or(UnableToParseError::UndefinedNode)?;
+        walk(
+            node,
+            next,
+            &node_map,
+            &mut HashSet::new(),
+            &mut vec![],
+            &mut vec![],
+            &mut out,
+        )?;
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn collapse_test() {
+        let nodes = vec![
+            Node::try_parse("main", "state", "then => starting;").unwrap(),
+            Node::try_parse(
+                "starting",
+                "decision",
+                "check => start; yes => init; no => main;",
+            )
+            .unwrap(),
+            Node::try_parse("init", "conditional", "r0 => a; q.pop(); then => mult;").unwrap(),
+            Node::try_parse("mult", "state", "then => main;").unwrap(),
+        ];
+        let transitions = collect_transitions(&nodes).unwrap();
+        assert_eq!(transitions.len(), 3);
+        assert_eq!(transitions[0].from, "main");
+        assert_eq!(transitions[0].to, "mult");
+        assert_eq!(transitions[0].label(), "start / r0 <= a, q.pop()");
+        assert_eq!(transitions[1].to, "main");
+        assert_eq!(transitions[1].label(), "!(start)");
+        assert_eq!(transitions[2].label(), "");
+    }
+
+    #[test]
+    fn loop_without_state_test() {
+        let nodes = vec![
+            Node::try_parse("main", "state", "then => a;").unwrap(),
+            Node::try_parse("a", "conditional", "then => b;").unwrap(),
+            Node::try_parse("b", "conditional", "then => a;").unwrap(),
+        ];
+        assert_eq!(
+            collect_transitions(&nodes),
+            Err(UnableToParseError::CircularDependency)
+        );
+    }
+}
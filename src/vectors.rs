@@ -0,0 +1,173 @@
+use crate::expr::Expr;
+use crate::{Design, Diagnostic, Simulator};
+
+/// Outcome of [`run_vectors`]: how many values were compared and a
+/// message for every mismatch.
+#[derive(Debug, Default, PartialEq)]
+pub struct VectorReport {
+    pub checked: usize,
+    pub failures: Vec<String>,
+}
+
+impl VectorReport {
+    pub fn passed(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+/// `name` or `name[index]` on the left of a `name=value` pair.
+fn parse_signal(text: &str) -> Result<(&str, Option<u64>), String> {
+    match text.split_once('[') {
+        Some((name, index)) => {
+            let index = index
+                .strip_suffix(']')
+                .ok_or_else(|| format!("missing `]` in `{}`", text))?;
+            Ok((name, Some(parse_value(index)?)))
+        }
+        None => Ok((text, None)),
+    }
+}
+
+/// Decimal or Verilog-style (`4'b1010`, `'hff`) constants.
+fn parse_value(text: &str) -> Result<u64, String> {
+    match text.parse::<Expr>() {
+        Ok(Expr::Number { value, .. }) => Ok(value),
+        _ => Err(format!("`{}` is not a number", text)),
+    }
+}
+
+/// `name=value` or `name[index]=value`, a value to drive a signal with.
+pub fn parse_assignment(text: &str) -> Result<(&str, Option<u64>, u64), String> {
+    let (signal, value) = text
+        .split_once('=')
+        .ok_or_else(|| format!("expected `name=value`, found `{}`", text))?;
+    let (name, index) = parse_signal(signal)?;
+    Ok((name, index, parse_value(value)?))
+}
+
+fn pairs(words: &[&str]) -> Result<Vec<(String, Option<u64>, String)>, String> {
+    let mut out = vec![];
+    for word in words {
+        let (signal, value) = word
+            .split_once('=')
+            .ok_or_else(|| format!("expected `name=value`, found `{}`", word))?;
+        let (name, index) = parse_signal(signal)?;
+        out.push((name.to_string(), index, value.to_string()));
+    }
+    Ok(out)
+}
+
+/// Runs a test vector file against the design. Each line is one of
+///
+/// ```text
+/// # comment
+/// set a=3 b=5 start=1     drive inputs until they are set again
+/// step 4                  advance four clock edges (one if no count)
+/// expect res=15 mem[2]=1  compare signals, `state=<node>` checks the state
+/// reset                   return to the reset state
+/// ```
+pub fn run_vectors(design: &Design, vectors: &str) -> Result<VectorReport, Diagnostic> {
+    let mut sim = Simulator::new(design)?;
+    let mut report = VectorReport::default();
+    for (number, line) in vectors.lines().enumerate() {
+        let line = line.split('#').next().unwrap().trim();
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let Some((&command, args)) = words.split_first() else {
+            continue;
+        };
+        let at = |message: String| Diagnostic::error(format!("line {}: {}", number + 1, message));
+        match command {
+            "set" => {
+                for word in args {
+                    let (name, index, value) = parse_assignment(word).map_err(at)?;
+                    sim.set(name, index, value).map_err(|err| at(err.message))?;
+                }
+            }
+            "step" => {
+                let count = match args {
+                    [] => 1,
+                    [count] => count
+                        .parse()
+                        .map_err(|_| at(format!("`{}` is not a cycle count", count)))?,
+                    _ => return Err(at("`step` takes at most one count".to_string())),
+                };
+                for _ in 0..count {
+                    sim.step()?;
+                }
+            }
+            "expect" => {
+                for (name, index, expected) in pairs(args).map_err(at)? {
+                    report.checked += 1;
+                    let found = if name == "state" && index.is_none() {
+                        sim.state().to_string()
+                    } else {
+                        let value = parse_value(&expected).map_err(at)?;
+                        let found = sim
+                            .get(&name, index)
+                            .ok_or_else(|| at(format!("`{}` is not declared", name)))?;
+                        if found == value {
+                            continue;
+                        }
+                        found.to_string()
+                    };
+                    if found != expected {
+                        let signal = match index {
+                            Some(index) => format!("{}[{}]", name, index),
+                            None => name,
+                        };
+                        report.failures.push(format!(
+                            "line {}: cycle {}: expected {}={}, found {}",
+                            number + 1,
+                            sim.cycle(),
+                            signal,
+                            expected,
+                            found
+                        ));
+                    }
+                }
+            }
+            "reset" => sim.reset(),
+            _ => return Err(at(format!("unknown vector command `{}`", command))),
+        }
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse;
+
+    #[test]
+    fn multiplier_vectors_test() {
+        let design = parse(&std::fs::read_to_string("multiplier.asmc").unwrap()).unwrap();
+        let report = run_vectors(
+            &design,
+            "
+# 3 * 5
+set a=3 b=5 start=1
+step
+expect state=mult
+set start=0
+step 12
+expect ready=1 res=15 state=main
+expect res=16
+",
+        )
+        .unwrap();
+        assert_eq!(report.checked, 5);
+        assert_eq!(
+            report.failures,
+            vec!["line 9: cycle 13: expected res=16, found 15"]
+        );
+
+        let err = run_vectors(&design, "set a=3\nstep x").unwrap_err();
+        assert_eq!(err.to_string(), "error: line 2: `x` is not a cycle count");
+
+        assert_eq!(parse_assignment("mem[2]='hf"), Ok(("mem", Some(2), 15)));
+        assert_eq!(
+            parse_assignment("a"),
+            Err("expected `name=value`, found `a`".to_string())
+        );
+    }
+}
@@ -1,22 +1,41 @@
-const HSH_BASE: u64 = 57;
-const HSH_MOD: u64 = 1e9 as u64 + 7;
-use std::{fmt::Display, ops::Range, path::Path};
+use crate::clock::{self, Domain, Role};
+use crate::command::{split_target, Command, UnableToParseError};
+use crate::enums;
+use crate::expr::Expr;
+use crate::library::{self, port_wire, Direction, Port};
+use crate::memory::{self, Access};
+use crate::naming::{Names, Naming, VERILOG_KEYWORDS};
+use crate::node::{self, Node, NodeType};
+use crate::primitive::{self, Kind, Primitive};
+use crate::template::substitute;
+use crate::transition::collect_transitions;
+use crate::Header;
+use regex::Regex;
+use std::collections::{HashMap, HashSet};
+
 pub struct Code {
     pub code: String,
-    pub hsh: u32,
+    /// Names of the signals the backend adds to the module.
+    pub names: Names,
 }
 // pub struct Object {
 //     name: String,
 //     code_name: String,
 // }
 impl Code {
+    pub fn new(names: Names) -> Self {
+        Code {
+            code: String::new(),
+            names,
+        }
+    }
     pub fn update(&mut self, text: String) {
         self.code.push_str(text.as_ref());
     }
-    pub fn get_varname(&mut self, name: &String) -> String {
-        self.hsh = ((self.hsh + 1) as u64 * HSH_BASE % HSH_MOD) as u32;
-        let var_name = format!("{}__{:x}", name, self.hsh);
-        var_name
+    /// A name for the signal `base` that clashes with nothing else in the
+    /// module.
+    pub fn fresh_name(&mut self, base: &str) -> String {
+        self.names.fresh(base)
     }
 }
 // impl Object {
@@ -59,6 +78,7 @@ impl Code {
 //         self.as_ref().fmt(f)
 //     }
 // }
+#[allow(dead_code)]
 pub fn impl_buf(code: &mut Code, i: String, o: String) {
     code.update(format!(
         "
@@ -66,6 +86,7 @@ buf({} , {});",
         o, i
     ));
 }
+#[allow(dead_code)]
 pub fn impl_bufif1(code: &mut Code, c: String, i: String, o: String) {
     code.update(format!(
         "
@@ -73,3 +94,1800 @@ bufif1({} , {} , {});",
         o, i, c
     ));
 }
+
+lazy_static::lazy_static! {
+    static ref PORT_REF: Regex =
+        Regex::new(r"\b([a-zA-Z_][a-zA-Z0-9_$]*)\.([a-zA-Z_][a-zA-Z0-9_$]*)").unwrap();
+}
+
+/// Registers behind one inout pin.
+struct Pin {
+    /// The value the design drives onto the pin.
+    main_reg: String,
+    /// Output enable.
+    write_reg: String,
+    /// Whether `drive` and `release` set the enable, rather than every
+    /// transfer to the pin for one cycle.
+    controlled: bool,
+}
+
+/// Signals behind one port of a memory, driven from the state.
+struct MemoryPort {
+    address: String,
+    write_enable: String,
+    write_data: String,
+    /// The word read, one register per cycle of latency, or a wire when
+    /// there is none.
+    data: Vec<String>,
+    /// Which register each read in flight loads, in step with `data`.
+    tags: Vec<String>,
+}
+
+struct Memory {
+    ports: Vec<MemoryPort>,
+    /// Registers that reads with latency load; a tag is a position here
+    /// plus one.
+    targets: Vec<String>,
+}
+
+struct Lowering<'c> {
+    pins: HashMap<String, Pin>,
+    /// Registered pins and the register they are read through.
+    reads: HashMap<&'c str, String>,
+    memories: HashMap<String, Memory>,
+    latencies: HashMap<&'c str, u8>,
+    /// The port of every word the state being lowered accesses.
+    ports: HashMap<Access, usize>,
+    /// Async inputs and their synchronized copy, per domain event.
+    synced: HashMap<String, HashMap<&'c str, String>>,
+    /// The wire behind every `instance.port` and every signal of a fifo
+    /// or counter, by `instance.port`.
+    wires: HashMap<String, String>,
+}
+
+impl Lowering<'_> {
+    /// Reads async inputs through the synchronizers of `domain` from now on.
+    fn enter(&mut self, domain: &Domain) {
+        if let Some(synced) = self.synced.get(&domain.event()) {
+            for (pin, sync) in synced.iter() {
+                self.reads.insert(pin, sync.clone());
+            }
+        }
+    }
+
+    /// `text` as read by the design: memory words are replaced by the data
+    /// of their port, registered pins by their sample and instance ports by
+    /// their wires.
+    fn read(&self, text: &str) -> String {
+        let mut words = vec![];
+        let expr = text.parse::<Expr>().ok();
+        if let Some(expr) = &expr {
+            memory::reads(expr, &self.latencies, &mut words);
+        }
+        let text = match expr {
+            Some(expr) if !words.is_empty() => {
+                let data = |mem: &str, port: usize| {
+                    self.memories[mem].ports[port].data.last().unwrap().clone()
+                };
+                memory::replace_reads(&expr, &self.latencies, &self.ports, &data).to_string()
+            }
+            _ => text.to_string(),
+        };
+        self.flatten(&substitute(&text, &self.reads))
+    }
+
+    /// Rewrites `instance.port` references to the wires connected to them.
+    fn flatten(&self, text: &str) -> String {
+        PORT_REF
+            .replace_all(text, |capt: &regex::Captures| {
+                match self.wires.get(&capt[0]) {
+                    Some(wire) => wire.clone(),
+                    None => port_wire(&capt[1], &capt[2]),
+                }
+            })
+            .into_owned()
+    }
+
+    fn wire(&self, instance: &str, port: &str) -> &str {
+        &self.wires[&format!("{}.{}", instance, port)]
+    }
+}
+
+/// An `instance` declaration: its name, the module it copies, the ports of
+/// that module and the clocking inputs it adds.
+pub type Instance<'d> = (&'d str, &'d str, Vec<Port>, Vec<(&'d str, Role)>);
+
+/// Copies `module` as `instance`. Its clocks, resets and enables are
+/// `clocking`, which are wired to those of `domain`; an enable is held
+/// high if `domain` has none.
+fn instantiate(
+    code: &mut Code,
+    lowering: &Lowering,
+    (instance, module): (&str, &str),
+    ports: &[Port],
+    clocking: &[(&str, Role)],
+    domain: &Domain,
+) {
+    let mut connections: Vec<String> = clocking
+        .iter()
+        .map(|(port, role)| {
+            let wire = match role {
+                Role::Clock => domain.clock,
+                Role::Reset => domain.reset,
+                Role::Enable => domain.enable.unwrap_or("1'b1"),
+            };
+            format!(".{}({})", port, wire)
+        })
+        .collect();
+    for port in ports.iter() {
+        let wire = lowering.wire(instance, &port.name);
+        let kind = match port.direction {
+            Direction::Input => "reg",
+            _ => "wire",
+        };
+        if port.is_array() {
+            code.update(format!(
+                "
+{} [{}:{}]{}[{}:{}];",
+                kind, port.bits.start, port.bits.end, wire, port.array.start, port.array.end
+            ));
+        } else {
+            code.update(format!(
+                "
+{} [{}:{}]{};",
+                kind, port.bits.start, port.bits.end, wire
+            ));
+        }
+        connections.push(format!(".{}({})", port.name, wire));
+    }
+    code.update(format!(
+        "
+{} {}({});",
+        module,
+        instance,
+        connections.join(" , ")
+    ));
+}
+
+/// Declares the signals of a `fifo` or `counter`, named like the ports of
+/// an instance, and the copy of its submodule.
+fn instantiate_primitive(
+    code: &mut Code,
+    lowering: &Lowering,
+    module: &str,
+    primitive: &Primitive,
+    domain: &Domain,
+) {
+    let mut connections = vec![
+        format!(".clk({})", domain.rising()),
+        format!(".reset({})", domain.reset),
+    ];
+    let inputs = primitive.inputs().into_iter().map(|port| ("reg", port));
+    let outputs = primitive.statuses().into_iter().map(|port| ("wire", port));
+    for (kind, (port, width)) in inputs.chain(outputs) {
+        let wire = lowering.wire(primitive.name, port);
+        code.update(format!(
+            "
+{} [{}:0]{};",
+            kind,
+            width - 1,
+            wire
+        ));
+        connections.push(format!(".{}({})", port, wire));
+    }
+    let mut parameters = vec![format!(".WIDTH({})", primitive.width)];
+    if primitive.kind == Kind::Fifo {
+        parameters.push(format!(".DEPTH({})", primitive.depth));
+    }
+    code.update(format!(
+        "
+{}_{} #({}) {}({});",
+        module,
+        primitive.kind.name(),
+        parameters.join(" , "),
+        primitive.name,
+        connections.join(" , ")
+    ));
+}
+
+/// The submodule behind every `fifo` or every `counter` of `module`. A
+/// fifo shows its oldest word on `data`, or 0 while empty, ignores a `push` while full and a
+/// `pop` while empty; a counter counts up on every clock edge it is not
+/// cleared.
+fn define_primitive(module: &str, kind: Kind) -> String {
+    match kind {
+        Kind::Fifo => format!(
+            "
+
+module {module}_fifo #(parameter WIDTH = 8 , parameter DEPTH = 16)(input clk , input reset , input push , input [WIDTH-1:0]push_data , input pop , output [WIDTH-1:0]data , output empty , output full);
+localparam POINTER = DEPTH > 1 ? $clog2(DEPTH) : 1;
+reg [WIDTH-1:0]words[0:DEPTH-1];
+reg [POINTER-1:0]head;
+reg [POINTER-1:0]tail;
+reg [POINTER:0]count;
+wire write = push && !full;
+wire read = pop && !empty;
+assign data = count == 0 ? 0 : words[head];
+assign empty = count == 0;
+assign full = count == DEPTH;
+always @(posedge clk) begin
+if (reset) begin
+head <= 0;
+tail <= 0;
+count <= 0;
+end else begin
+if (write) begin
+words[tail] <= push_data;
+tail <= tail == DEPTH - 1 ? 0 : tail + 1;
+end
+if (read) head <= head == DEPTH - 1 ? 0 : head + 1;
+count <= count + write - read;
+end
+end
+endmodule"
+        ),
+        Kind::Counter => format!(
+            "
+
+module {module}_counter #(parameter WIDTH = 8)(input clk , input reset , input clear , output reg [WIDTH-1:0]value);
+always @(posedge clk)
+if (reset || clear) value <= 0;
+else value <= value + 1;
+endmodule"
+        ),
+    }
+}
+
+/// `port` declared as `kind`, as in `input [7:0]data`.
+fn declare_port(kind: &str, port: &Port) -> String {
+    let Port {
+        name, bits, array, ..
+    } = port;
+    match port.is_array() {
+        true => format!(
+            "{} [{}:{}]{}[{}:{}]",
+            kind, bits.start, bits.end, name, array.start, array.end
+        ),
+        false => format!("{} [{}:{}]{}", kind, bits.start, bits.end, name),
+    }
+}
+
+/// The header of `module`: the `clocking` inputs, then `ports`. Outputs are
+/// `reg` unless `wires`. A non-ANSI header only names the ports and
+/// declares them after it.
+fn declare_header(
+    module: &str,
+    clocking: &[(&str, Role)],
+    ports: &[Port],
+    (style, wires): (Header, bool),
+) -> String {
+    let mut declarations: Vec<(&str, String)> = clocking
+        .iter()
+        .map(|(name, _)| (*name, format!("input {}", name)))
+        .collect();
+    for port in ports.iter() {
+        let kind = match (port.direction, style, wires) {
+            (Direction::Input, ..) => "input",
+            (Direction::Inout, ..) => "inout",
+            (Direction::Output, Header::Ansi, false) => "output reg",
+            (Direction::Output, ..) => "output",
+        };
+        declarations.push((&port.name, declare_port(kind, port)));
+    }
+    match style {
+        Header::Ansi => {
+            let declarations: Vec<String> = declarations
+                .into_iter()
+                .map(|(_, declaration)| declaration)
+                .collect();
+            format!(
+                "
+module {}({});",
+                module,
+                declarations.join(" , ")
+            )
+        }
+        Header::NonAnsi => {
+            let names: Vec<&str> = declarations.iter().map(|(name, _)| *name).collect();
+            let mut out = format!(
+                "
+module {}({});",
+                module,
+                names.join(" , ")
+            );
+            for (_, declaration) in declarations.iter() {
+                out.push_str(&format!(
+                    "
+{};",
+                    declaration
+                ));
+            }
+            for port in ports.iter() {
+                if port.direction == Direction::Output && !wires {
+                    out.push_str(&format!(
+                        "
+{};",
+                        declare_port("reg", port)
+                    ));
+                }
+            }
+            out
+        }
+    }
+}
+
+/// A module named `module` with the ports of the chart, outputs as plain
+/// wires, that instantiates the chart compiled as `core`.
+pub fn wrap(
+    module: &str,
+    core: &str,
+    commands: &[Command],
+    nodes: &[Node],
+    style: Header,
+) -> String {
+    let clocking = clock::ports(commands, &node::machines(nodes));
+    let ports = library::command_ports(commands);
+    let mut out = format!(
+        "
+{}",
+        declare_header(module, &clocking, &ports, (style, true))
+    );
+    let names = clocking
+        .iter()
+        .map(|(name, _)| *name)
+        .chain(ports.iter().map(|port| port.name.as_str()));
+    let connections: Vec<String> = names.map(|name| format!(".{}({})", name, name)).collect();
+    out.push_str(&format!(
+        "
+{} core({});
+endmodule",
+        core,
+        connections.join(" , ")
+    ));
+    out
+}
+
+/// Lowers the parsed declarations and node graph to a Verilog module with
+/// one clocked block per clock, which walks the charts of the machines on
+/// that clock from their active states. `instances` are `(instance,
+/// module, ports, clocking)` for every `instance` declaration.
+pub fn emit_verilog(
+    module: &str,
+    commands: &[Command],
+    nodes: &[Node],
+    instances: &[Instance],
+    (style, naming, clocking): (Header, &Naming, &Domain),
+) -> Result<String, UnableToParseError> {
+    let mut code = Code::new(Names::new(
+        naming,
+        commands,
+        clocking,
+        VERILOG_KEYWORDS,
+        false,
+    ));
+    // The wires come first so that they keep their plain names unless the
+    // chart declares them.
+    let mut wires = HashMap::new();
+    for (instance, _, ports, _) in instances.iter() {
+        for port in ports.iter() {
+            let wire = code.fresh_name(&port_wire(instance, &port.name));
+            wires.insert(format!("{}.{}", instance, port.name), wire);
+        }
+    }
+    for primitive in primitive::primitives(commands) {
+        let signals = primitive.inputs().into_iter().chain(primitive.statuses());
+        for (signal, _) in signals {
+            let wire = code.fresh_name(&port_wire(primitive.name, signal));
+            wires.insert(format!("{}.{}", primitive.name, signal), wire);
+        }
+    }
+    let machines = node::machines(nodes);
+    let clocking = clock::ports(commands, &machines);
+    code.update(declare_header(
+        module,
+        &clocking,
+        &library::command_ports(commands),
+        (style, false),
+    ));
+    // Module-level logic that no single machine owns, such as wires and
+    // the samples of registered inouts, runs in the first machine's domain.
+    let main = clock::domain(commands, machines.first().copied().unwrap_or_default());
+    let domain_of = |machine: Option<&str>| match machine {
+        Some(machine) => clock::domain(commands, machine),
+        None => main,
+    };
+
+    for cmd in commands.iter() {
+        if let Command::Enum { members, .. } = cmd {
+            let width = enums::width(members.len());
+            for (value, member) in members.iter().enumerate() {
+                code.update(format!(
+                    "
+localparam [{}:0] {} = {}'d{};",
+                    width - 1,
+                    member,
+                    width,
+                    value
+                ));
+            }
+        }
+    }
+
+    let mut node_map = HashMap::new();
+    for node in nodes.iter() {
+        node_map.insert(node.get_name(), node);
+    }
+
+    // Every machine has its own state register; the unnamed one keeps the
+    // name charts without `machine =>` directives always had.
+    let mut state_regs = vec![];
+    for machine in machines.iter().copied() {
+        let state_count = nodes
+            .iter()
+            .filter(|node| node.machine == machine && node.node_type == NodeType::State)
+            .count() as u32;
+        let bit_count = state_count.ilog2() as u8;
+        let current_state_reg = match machine {
+            "" => code.fresh_name("currentState"),
+            machine => code.fresh_name(&format!("{}_currentState", machine)),
+        };
+        code.update(format!(
+            "
+reg [{bit_count}:0]{current_state_reg};",
+        ));
+        state_regs.push((machine, current_state_reg));
+    }
+
+    let controlled = node::controlled_pins(nodes);
+    let mut lowering = Lowering {
+        pins: HashMap::new(),
+        reads: HashMap::new(),
+        memories: HashMap::new(),
+        latencies: memory::latencies(commands),
+        ports: HashMap::new(),
+        synced: HashMap::new(),
+        wires,
+    };
+    for command in commands.iter() {
+        if let Command::Register {
+            reg_name,
+            bits,
+            array,
+            ..
+        } = command
+        {
+            if array.start == array.end && array.start == 0 {
+                code.update(format!(
+                    "
+reg [{} : {}]{};",
+                    bits.start, bits.end, reg_name
+                ));
+            } else {
+                code.update(format!(
+                    "
+reg [{} : {}]{}[{} : {}];",
+                    bits.start, bits.end, reg_name, array.start, array.end
+                ));
+            }
+        }
+        if let Command::Inout {
+            pin_name,
+            bits,
+            array,
+            registered,
+        } = command
+        {
+            let main_reg = code.fresh_name(&format!("{}_out", pin_name));
+            let write_reg = code.fresh_name(&format!("{}_write_reg", pin_name));
+            let read_reg = registered.then(|| code.fresh_name(&format!("{}_read_reg", pin_name)));
+            let is_array = array.start != array.end || array.start != 0;
+            for reg in [Some(&main_reg), read_reg.as_ref()].into_iter().flatten() {
+                if is_array {
+                    code.update(format!(
+                        "
+reg [{} : {}]{}[{} : {}];",
+                        bits.start, bits.end, reg, array.start, array.end
+                    ));
+                } else {
+                    code.update(format!(
+                        "
+reg [{} : {}]{};",
+                        bits.start, bits.end, reg
+                    ));
+                }
+            }
+            code.update(format!(
+                "
+reg {write_reg};"
+            ));
+            let released = format!("{{{}{{1'bz}}}}", bits.start.abs_diff(bits.end) + 1);
+            if is_array {
+                // Whole arrays cannot be assigned, so every element gets its
+                // own driver.
+                let i = code.fresh_name(&format!("{}_i", pin_name));
+                let block = code.fresh_name(&format!("{}_drive", pin_name));
+                let (low, high) = (array.start.min(array.end), array.start.max(array.end));
+                code.update(format!(
+                    "
+genvar {i};
+generate for ({i} = {low}; {i} <= {high}; {i} = {i} + 1) begin : {block}
+assign {pin_name}[{i}] = {write_reg} ? {main_reg}[{i}] : {released};"
+                ));
+                if let Some(read_reg) = &read_reg {
+                    code.update(format!(
+                        "
+always @({event})
+{read_reg}[{i}] <= {pin_name}[{i}];",
+                        event = main.event()
+                    ));
+                }
+                code.update(
+                    "
+end endgenerate"
+                        .to_string(),
+                );
+            } else {
+                code.update(format!(
+                    "
+assign {pin_name} = {write_reg} ? {main_reg} : {released};"
+                ));
+                if let Some(read_reg) = &read_reg {
+                    code.update(format!(
+                        "
+always @({event})
+{read_reg} <= {pin_name};",
+                        event = main.event()
+                    ));
+                }
+            }
+            if let Some(read_reg) = read_reg {
+                lowering.reads.insert(pin_name, read_reg);
+            }
+            lowering.pins.insert(
+                pin_name.clone(),
+                Pin {
+                    main_reg,
+                    write_reg,
+                    controlled: controlled.contains(pin_name.as_str()),
+                },
+            );
+        }
+    }
+
+    for (instance, sub, ports, clocking) in instances.iter() {
+        instantiate(
+            &mut code,
+            &lowering,
+            (instance, sub),
+            ports,
+            clocking,
+            &main,
+        );
+    }
+    let primitives = primitive::primitives(commands);
+    for primitive in primitives.iter() {
+        let owner = owner(
+            nodes,
+            |cmd| matches!(cmd, Command::Action { name, .. } if name == primitive.name),
+        );
+        instantiate_primitive(&mut code, &lowering, module, primitive, &domain_of(owner));
+    }
+
+    // Every domain reads the async inputs through synchronizers of its own.
+    let mut synchronized = false;
+    for machine in machines.iter() {
+        let domain = clock::domain(commands, machine);
+        if lowering.synced.contains_key(&domain.event()) {
+            continue;
+        }
+        let mut synced = HashMap::new();
+        for cmd in commands.iter() {
+            if let Command::Input {
+                pin_name,
+                bits,
+                asynchronous: true,
+                ..
+            } = cmd
+            {
+                let sync = code.fresh_name(&format!("{}_sync", pin_name));
+                let synchronizer = code.fresh_name(&format!("{}_synchronizer", pin_name));
+                code.update(format!(
+                    "
+wire [{}:{}]{};
+{}_sync #(.WIDTH({})) {}(.clk({}) , .d({}) , .q({}));",
+                    bits.start,
+                    bits.end,
+                    sync,
+                    module,
+                    bits.start.abs_diff(bits.end) as u32 + 1,
+                    synchronizer,
+                    domain.rising(),
+                    pin_name,
+                    sync
+                ));
+                synced.insert(pin_name.as_str(), sync);
+                synchronized = true;
+            }
+        }
+        lowering.synced.insert(domain.event(), synced);
+    }
+
+    let mut targets = memory::load_targets(commands, nodes);
+    let mut memory_domains = HashMap::new();
+    for cmd in commands.iter() {
+        if let Command::Memory { mem_name, .. } = cmd {
+            let owner = owner(nodes, |cmd| {
+                memory::accesses(cmd, &lowering.latencies)
+                    .iter()
+                    .any(|(mem, _)| mem == mem_name)
+            });
+            let domain = domain_of(owner);
+            let memory = declare_memory(
+                &mut code,
+                cmd,
+                &domain,
+                targets.remove(mem_name).unwrap_or_default(),
+            );
+            lowering.memories.insert(mem_name.clone(), memory);
+            memory_domains.insert(mem_name, domain);
+        }
+    }
+
+    // All wires are declared before any is assigned, since a wire may read
+    // one declared after it.
+    for cmd in commands.iter() {
+        if let Command::Wire {
+            wire_name, bits, ..
+        } = cmd
+        {
+            code.update(format!(
+                "
+wire [{}:{}]{};",
+                bits.start, bits.end, wire_name
+            ));
+        }
+    }
+    lowering.enter(&main);
+    for cmd in commands.iter() {
+        if let Command::Wire {
+            wire_name, value, ..
+        } = cmd
+        {
+            code.update(format!(
+                "
+assign {} = {};",
+                wire_name,
+                lowering.read(value)
+            ));
+        }
+    }
+
+    if !lowering.memories.is_empty() || !primitives.is_empty() {
+        drive_ports(&mut code, commands, nodes, &state_regs, &mut lowering)?;
+    }
+
+    for (machine, current_state_reg) in state_regs.iter() {
+        code.update(format!(
+            "
+always @(posedge {})
+{current_state_reg} = 0;
+",
+            clock::domain(commands, machine).reset
+        ));
+    }
+    for cmd in commands.iter() {
+        if let Command::Memory { mem_name, .. } = cmd {
+            for port in lowering.memories[mem_name].ports.iter() {
+                for tag in port.tags.iter() {
+                    code.update(format!(
+                        "
+always @(posedge {})
+{tag} = 0;
+",
+                        memory_domains[mem_name].reset
+                    ));
+                }
+            }
+        }
+    }
+    // Pins are driven from the clocked block of the machines writing them.
+    let pin_domains: HashMap<&str, Domain> = commands
+        .iter()
+        .filter_map(|cmd| match cmd {
+            Command::Inout { pin_name, .. } => {
+                let owner = owner(nodes, |cmd| match cmd {
+                    Command::RegisterTransfer { reg_name, .. } => {
+                        split_target(reg_name).0 == pin_name
+                    }
+                    Command::Drive { pin_name: driven } | Command::Release { pin_name: driven } => {
+                        driven == pin_name
+                    }
+                    _ => false,
+                });
+                Some((pin_name.as_str(), domain_of(owner)))
+            }
+            _ => None,
+        })
+        .collect();
+    // Pins under `drive` and `release` hold their enable, so a reset
+    // releases them.
+    for cmd in commands.iter() {
+        if let Command::Inout { pin_name, .. } = cmd {
+            let pin = &lowering.pins[pin_name];
+            if pin.controlled {
+                code.update(format!(
+                    "
+always @(posedge {})
+{} = 0;
+",
+                    pin_domains[pin_name.as_str()].reset,
+                    pin.write_reg
+                ));
+            }
+        }
+    }
+
+    //     for command in commands.iter() {
+    //         if let Command::Output {
+    //             pin_name,
+    //             bits,
+    //             array,
+    //         } = command
+    //         {
+    //             code.update(format!(
+    //                 "
+    // {pin_name} = 0;"
+    //             ))
+    //         }
+    //     }
+
+    // Machines on one clock share a clocked block so that a register
+    // several of them write has a single driver. Later non-blocking
+    // assignments win, so the machines go in reverse `priority` order.
+    let priority = commands
+        .iter()
+        .find_map(|cmd| match cmd {
+            Command::Priority { machines } => Some(machines.as_slice()),
+            _ => None,
+        })
+        .unwrap_or_default();
+    state_regs.sort_by_key(|(machine, _)| {
+        priority
+            .iter()
+            .position(|listed| listed == machine)
+            .map(std::cmp::Reverse)
+    });
+    let mut clocks: Vec<(Domain, Vec<&(&str, String)>)> = vec![];
+    for entry in state_regs.iter() {
+        let domain = clock::domain(commands, entry.0);
+        match clocks
+            .iter_mut()
+            .find(|(known, _)| known.same_clock(&domain))
+        {
+            Some((_, machines)) => machines.push(entry),
+            None => clocks.push((domain, vec![entry])),
+        }
+    }
+    if clocks.is_empty() {
+        clocks.push((main, vec![]));
+    }
+    let states = node_map_of(nodes);
+    for (domain, machines) in clocks.iter() {
+        code.update(format!(
+            "
+always @({}) begin",
+            domain.event()
+        ));
+        for cmd in commands.iter() {
+            if let Command::Inout { pin_name, .. } = cmd {
+                let pin = &lowering.pins[pin_name];
+                let pin_domain = &pin_domains[pin_name.as_str()];
+                if !pin.controlled && pin_domain.same_clock(domain) {
+                    code.update(gate(
+                        pin_domain.enable,
+                        format!(
+                            "
+{} <= 0;",
+                            pin.write_reg
+                        ),
+                    ));
+                }
+            }
+        }
+
+        // Reads with latency land before the states run, so that a transfer
+        // to the same register in this cycle wins.
+        for cmd in commands.iter() {
+            let Command::Memory { mem_name, .. } = cmd else {
+                continue;
+            };
+            let owner = &memory_domains[mem_name];
+            if !owner.same_clock(domain) {
+                continue;
+            }
+            let memory = &lowering.memories[mem_name];
+            let mut lands = String::new();
+            for port in memory.ports.iter() {
+                let Some(tag) = port.tags.last() else {
+                    continue;
+                };
+                lands.push_str(&format!(
+                    "
+{} <= 0;",
+                    port.tags[0]
+                ));
+                for stage in port.tags.windows(2) {
+                    lands.push_str(&format!(
+                        "
+{} <= {};",
+                        stage[1], stage[0]
+                    ));
+                }
+                for (idx, target) in memory.targets.iter().enumerate() {
+                    lands.push_str(&format!(
+                        "
+if ({} == {}) {} <= {};",
+                        tag,
+                        idx + 1,
+                        target,
+                        port.data.last().unwrap()
+                    ));
+                }
+            }
+            code.update(gate(owner.enable, lands));
+        }
+
+        for (machine, current_state_reg) in machines.iter() {
+            let machine_domain = clock::domain(commands, machine);
+            lowering.enter(&machine_domain);
+            if let Some(enable) = machine_domain.enable {
+                code.update(format!(
+                    "
+if ({}) begin",
+                    enable
+                ));
+            }
+            for node in nodes.iter() {
+                if node.machine == *machine && node.node_type == NodeType::State {
+                    code.update(format!(
+                        "
+if ({} == {}) begin",
+                        current_state_reg, node.id
+                    ));
+
+                    lowering.ports =
+                        memory::ports(&memory::state_accesses(node, &states, &lowering.latencies));
+                    let mut seen = HashSet::new();
+                    if !compile_node(
+                        &mut code,
+                        node,
+                        &node_map,
+                        &mut seen,
+                        current_state_reg,
+                        true,
+                        &lowering,
+                    ) {
+                        return Err(UnableToParseError::CircularDependency);
+                    }
+
+                    code.update(
+                        "
+end else"
+                            .to_string(),
+                    );
+                }
+            }
+            code.update(" begin".to_string());
+            code.update(format!(
+                "
+{} = 0;",
+                current_state_reg
+            ));
+            code.update(
+                "
+end"
+                .to_string(),
+            );
+            if machine_domain.enable.is_some() {
+                code.update(
+                    "
+end"
+                    .to_string(),
+                );
+            }
+        }
+        code.update(
+            "
+end"
+            .to_string(),
+        );
+    }
+    code.update(
+        "
+endmodule"
+            .to_string(),
+    );
+
+    let mut defined = vec![];
+    for primitive in primitives.iter() {
+        if !defined.contains(&primitive.kind) {
+            defined.push(primitive.kind);
+            code.update(define_primitive(module, primitive.kind));
+        }
+    }
+    if synchronized {
+        code.update(format!(
+            "
+
+module {module}_sync #(parameter WIDTH = 1)(input clk , input [WIDTH-1:0]d , output reg [WIDTH-1:0]q);
+reg [WIDTH-1:0]meta;
+always @(posedge clk) begin
+meta <= d;
+q <= meta;
+end
+endmodule"
+        ));
+    }
+
+    Ok(code.code)
+}
+
+fn node_map_of(nodes: &[Node]) -> HashMap<&str, &Node> {
+    nodes
+        .iter()
+        .map(|node| (node.node_name.as_str(), node))
+        .collect()
+}
+
+/// The machine of the first node with a command `uses` matches.
+fn owner(nodes: &[Node], uses: impl Fn(&Command) -> bool) -> Option<&str> {
+    nodes
+        .iter()
+        .find(|node| node.commands.iter().any(&uses))
+        .map(|node| node.machine.as_str())
+}
+
+/// `body` run only while `enable` is high, if there is one.
+fn gate(enable: Option<&str>, body: String) -> String {
+    match enable {
+        Some(enable) if !body.is_empty() => format!(
+            "
+if ({}) begin{}
+end",
+            enable, body
+        ),
+        _ => body,
+    }
+}
+
+/// Declares the memory `cmd` and its ports in the style synthesis tools
+/// map onto block RAM: the array is only written and read in one clocked
+/// block per port, at an address the state drives. Without latency the
+/// read is asynchronous, which maps onto distributed RAM instead.
+fn declare_memory(code: &mut Code, cmd: &Command, domain: &Domain, targets: Vec<String>) -> Memory {
+    let Command::Memory {
+        mem_name: mem,
+        bits,
+        depth,
+        ports,
+        latency,
+        init,
+    } = cmd
+    else {
+        unreachable!("only memories are declared as such")
+    };
+    let (depth, ports, latency) = (*depth, *ports, *latency);
+    let address_width = Expr::bits_for(depth.saturating_sub(1) as u64);
+    let tag_width = Expr::bits_for(targets.len() as u64);
+    code.update(format!(
+        "
+reg [{}:{}]{}[0:{}];",
+        bits.start,
+        bits.end,
+        mem,
+        depth.saturating_sub(1)
+    ));
+    if let Some(init) = init {
+        code.update(format!(
+            "
+initial $readmemh(\"{}\", {});",
+            init, mem
+        ));
+    }
+    let mut out = Memory {
+        ports: vec![],
+        targets,
+    };
+    for port in 0..ports {
+        let address = code.fresh_name(&format!("{}_address_{}", mem, port));
+        let write_enable = code.fresh_name(&format!("{}_write_enable_{}", mem, port));
+        let write_data = code.fresh_name(&format!("{}_write_data_{}", mem, port));
+        let stages: Vec<(String, String)> = (0..latency.max(1))
+            .map(|stage| {
+                let suffix = match stage {
+                    0 => port.to_string(),
+                    stage => format!("{}_{}", port, stage),
+                };
+                (
+                    code.fresh_name(&format!("{}_data_{}", mem, suffix)),
+                    code.fresh_name(&format!("{}_load_{}", mem, suffix)),
+                )
+            })
+            .collect();
+        code.update(format!(
+            "
+reg [{}:0]{};
+reg {};
+reg [{}:{}]{};",
+            address_width - 1,
+            address,
+            write_enable,
+            bits.start,
+            bits.end,
+            write_data
+        ));
+        let (data, tags): (Vec<String>, Vec<String>) = stages.into_iter().unzip();
+        if latency == 0 {
+            code.update(format!(
+                "
+wire [{}:{}]{};
+assign {} = {}[{}];
+always @({})
+if ({}) {}[{}] <= {};",
+                bits.start,
+                bits.end,
+                data[0],
+                data[0],
+                mem,
+                address,
+                domain.event(),
+                write_enable,
+                mem,
+                address,
+                write_data
+            ));
+            out.ports.push(MemoryPort {
+                address,
+                write_enable,
+                write_data,
+                data,
+                tags: vec![],
+            });
+            continue;
+        }
+        for (data, tag) in data.iter().zip(tags.iter()) {
+            code.update(format!(
+                "
+reg [{}:{}]{};
+reg [{}:0]{};",
+                bits.start,
+                bits.end,
+                data,
+                tag_width - 1,
+                tag
+            ));
+        }
+        code.update(format!(
+            "
+always @({}){} begin
+if ({}) {}[{}] <= {};
+{} <= {}[{}];",
+            domain.event(),
+            domain
+                .enable
+                .map(|enable| format!(" if ({})", enable))
+                .unwrap_or_default(),
+            write_enable,
+            mem,
+            address,
+            write_data,
+            data[0],
+            mem,
+            address
+        ));
+        for stage in data.windows(2) {
+            code.update(format!(
+                "
+{} <= {};",
+                stage[1], stage[0]
+            ));
+        }
+        code.update(
+            "
+end"
+            .to_string(),
+        );
+        out.ports.push(MemoryPort {
+            address,
+            write_enable,
+            write_data,
+            data,
+            tags,
+        });
+    }
+    out
+}
+
+/// Drives the address of every memory port from the current state, and
+/// its write enable and data, like the inputs of every fifo and counter,
+/// from the path taken through the chart.
+fn drive_ports(
+    code: &mut Code,
+    commands: &[Command],
+    nodes: &[Node],
+    state_regs: &[(&str, String)],
+    lowering: &mut Lowering,
+) -> Result<(), UnableToParseError> {
+    code.update(
+        "
+always @* begin"
+            .to_string(),
+    );
+    for cmd in commands.iter() {
+        let Command::Memory { mem_name, .. } = cmd else {
+            continue;
+        };
+        for port in lowering.memories[mem_name].ports.iter() {
+            for signal in [&port.address, &port.write_enable, &port.write_data] {
+                code.update(format!(
+                    "
+{} = 0;",
+                    signal
+                ));
+            }
+        }
+    }
+    for primitive in primitive::primitives(commands) {
+        for (input, _) in primitive.inputs() {
+            code.update(format!(
+                "
+{} = 0;",
+                lowering.wire(primitive.name, input)
+            ));
+        }
+    }
+    let transitions = collect_transitions(nodes)?;
+    let node_map = node_map_of(nodes);
+    for (machine, current_state_reg) in state_regs.iter() {
+        let domain = clock::domain(commands, machine);
+        lowering.enter(&domain);
+        // A disabled machine stays in its state without acting.
+        let enabled = domain
+            .enable
+            .map(|enable| format!("{} && ", enable))
+            .unwrap_or_default();
+        for node in nodes.iter() {
+            if node.machine != *machine || node.node_type != NodeType::State {
+                continue;
+            }
+            let accesses = memory::state_accesses(node, &node_map, &lowering.latencies);
+            lowering.ports = memory::ports(&accesses);
+            let lowering = &*lowering;
+            let drive = |cmd: &Command| match cmd {
+                Command::RegisterTransfer {
+                    reg_name,
+                    reg_value,
+                } => {
+                    let word = memory::word(&reg_name.parse().ok()?, &lowering.latencies)?;
+                    let port = &lowering.memories[&word.0].ports[lowering.ports[&word]];
+                    Some(format!(
+                        "
+{} = 1;
+{} = {};",
+                        port.write_enable,
+                        port.write_data,
+                        lowering.read(reg_value)
+                    ))
+                }
+                Command::Action {
+                    name,
+                    action,
+                    value,
+                } => {
+                    let mut out = format!(
+                        "
+{} = 1;",
+                        lowering.wire(name, action)
+                    );
+                    if let Some(value) = value {
+                        out.push_str(&format!(
+                            "
+{} = {};",
+                            lowering.wire(name, &format!("{}_data", action)),
+                            lowering.read(value)
+                        ));
+                    }
+                    Some(out)
+                }
+                _ => None,
+            };
+            // The state's own writes and actions happen on every path out
+            // of it.
+            let own: Vec<String> = node.commands.iter().filter_map(drive).collect();
+            let mut guarded = vec![];
+            for transition in transitions.iter().filter(|t| t.from == node.node_name) {
+                let driven: Vec<String> = transition.actions.iter().filter_map(drive).collect();
+                if driven.is_empty() {
+                    continue;
+                }
+                let guard: Vec<String> = transition
+                    .conditions
+                    .iter()
+                    .map(|(check, taken)| match taken {
+                        true if transition.conditions.len() == 1 => lowering.read(check),
+                        true => format!("({})", lowering.read(check)),
+                        false => format!("!({})", lowering.read(check)),
+                    })
+                    .collect();
+                guarded.push((guard, driven));
+            }
+            if accesses.is_empty() && own.is_empty() && guarded.is_empty() {
+                continue;
+            }
+            code.update(format!(
+                "
+if ({}{} == {}) begin",
+                enabled, current_state_reg, node.id
+            ));
+            for (mem, address) in accesses.iter() {
+                let port = lowering.ports[&(mem.clone(), address.clone())];
+                code.update(format!(
+                    "
+{} = {};",
+                    lowering.memories[mem].ports[port].address,
+                    lowering.read(address)
+                ));
+            }
+            code.update(own.concat());
+            for (guard, driven) in guarded {
+                if guard.is_empty() {
+                    code.update(driven.concat());
+                } else {
+                    code.update(format!(
+                        "
+if ({}) begin{}
+end",
+                        guard.join(" && "),
+                        driven.concat()
+                    ));
+                }
+            }
+            code.update(
+                "
+end"
+                .to_string(),
+            );
+        }
+    }
+    code.update(
+        "
+end
+"
+        .to_string(),
+    );
+    Ok(())
+}
+
+fn compile_node<'l>(
+    code: &mut Code,
+    node: &'l Node,
+    node_map: &'l HashMap<String, &'l Node>,
+    seen: &mut HashSet<&'l String>,
+    current_state_reg: &String,
+    full_compile: bool,
+    lowering: &Lowering,
+) -> bool {
+    if !full_compile && node.node_type == NodeType::State {
+        code.update(format!(
+            "
+{} <= {};",
+            current_state_reg, node.id
+        ));
+        return true;
+    }
+
+    if seen.contains(&node.node_name) {
+        return false;
+    }
+    seen.insert(&node.node_name);
+    if node.node_type == NodeType::Decision {
+        let mut check_cond = "0".to_string();
+        let mut yes_node = "".to_string();
+        let mut no_node = "".to_string();
+        for command in node.commands.iter() {
+            match command {
+                Command::Check { check } => {
+                    check_cond = check.to_string();
+                }
+                Command::Yes { next_node } => {
+                    yes_node = next_node.to_string();
+                }
+                Command::No { next_node } => {
+                    no_node = next_node.to_string();
+                }
+                _ => {}
+            }
+        }
+
+        code.update(format!(
+            "
+if ({}) begin",
+            lowering.read(&check_cond)
+        ));
+
+        if !compile_node(
+            code,
+            node_map.get(&yes_node).unwrap(),
+            node_map,
+            seen,
+            current_state_reg,
+            false,
+            lowering,
+        ) {
+            return false;
+        }
+
+        code.update(
+            "
+end else begin"
+                .to_string(),
+        );
+
+        if !compile_node(
+            code,
+            node_map.get(&no_node).unwrap(),
+            node_map,
+            seen,
+            current_state_reg,
+            false,
+            lowering,
+        ) {
+            return false;
+        }
+
+        code.update(
+            "
+end"
+            .to_string(),
+        );
+        return true;
+    }
+    let mut then_node = "".to_string();
+    for command in node.commands.iter() {
+        match command {
+            Command::RegisterTransfer {
+                reg_name,
+                reg_value,
+            } => {
+                let (base, select) = split_target(reg_name);
+                if let Some(((mem, address), target)) = memory::load(command, &lowering.latencies) {
+                    let memory = &lowering.memories[&mem];
+                    let port = lowering.ports[&(mem, address)];
+                    let tag = memory.targets.iter().position(|t| *t == target).unwrap() + 1;
+                    code.update(format!(
+                        "
+{} <= {};",
+                        memory.ports[port].tags[0], tag
+                    ));
+                } else if lowering.latencies.contains_key(base) {
+                    // Written through the memory's port, see `drive_ports`.
+                } else if let Some(pin) = lowering.pins.get(base) {
+                    code.update(format!(
+                        "
+{}{} <= {};",
+                        pin.main_reg,
+                        lowering.read(select),
+                        lowering.read(reg_value)
+                    ));
+                    if !pin.controlled {
+                        code.update(format!(
+                            "
+{} <= 1;",
+                            pin.write_reg
+                        ));
+                    }
+                } else {
+                    code.update(format!(
+                        "
+{} <= {};",
+                        lowering.flatten(reg_name),
+                        lowering.read(reg_value)
+                    ));
+                }
+            }
+            Command::Drive { pin_name } | Command::Release { pin_name } => {
+                if let Some(pin) = lowering.pins.get(pin_name) {
+                    code.update(format!(
+                        "
+{} <= {};",
+                        pin.write_reg,
+                        matches!(command, Command::Drive { .. }) as u8
+                    ));
+                }
+            }
+            Command::Then { next_node } => {
+                then_node = next_node.to_string();
+            }
+            _ => {}
+        }
+    }
+
+    compile_node(
+        code,
+        node_map.get(&then_node).unwrap(),
+        node_map,
+        seen,
+        current_state_reg,
+        false,
+        lowering,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{check_with, emit_verilog, parse, Header, Naming, Options};
+
+    fn verilog(source: &str) -> String {
+        emit_verilog(&parse(source).unwrap(), &Options::default()).unwrap()
+    }
+
+    #[test]
+    fn tristate_test() {
+        let verilog = verilog(
+            "
+bus => inout[7:0];
+pins => inout[1:0][3:0];
+.idle : state {
+    bus => 1;
+    pins[1] => 2;
+    then => idle;
+}
+",
+        );
+        assert!(verilog.contains("\nassign bus = bus_write_reg ? bus_out : {8{1'bz}};"));
+        assert!(verilog.contains(
+            " begin : pins_drive\nassign pins[pins_i] = pins_write_reg ? pins_out[pins_i] : {4{1'bz}};\nend endgenerate"
+        ));
+        // Without `drive` or `release` a pin is driven for the cycle after
+        // each transfer to it.
+        assert!(verilog
+            .contains("\nalways @(posedge clk) begin\nbus_write_reg <= 0;\npins_write_reg <= 0;"));
+        assert!(verilog.contains(
+            "\nbus_out <= 1;\nbus_write_reg <= 1;\npins_out[1] <= 2;\npins_write_reg <= 1;"
+        ));
+    }
+
+    #[test]
+    fn drive_test() {
+        let verilog = verilog(
+            "
+bus => inout[7:0];
+.idle : state {
+    drive => bus;
+    bus => 1;
+    then => done;
+}
+.done : state {
+    release => bus;
+    then => idle;
+}
+",
+        );
+        // Only `drive` and `release` change the enable, which a reset
+        // clears.
+        assert!(verilog.contains("\nalways @(posedge reset)\nbus_write_reg = 0;"));
+        assert!(verilog.contains("\nalways @(posedge clk) begin\nif (currentState == 0)"));
+        assert!(verilog.contains("\nbus_write_reg <= 1;\nbus_out <= 1;\ncurrentState <= 1;"));
+        assert!(verilog.contains("\nbus_write_reg <= 0;\ncurrentState <= 0;"));
+    }
+
+    #[test]
+    fn registered_inout_test() {
+        let verilog = verilog(
+            "
+bus => inout[7:0] registered;
+r0 => reg[7:0];
+.idle : state {
+    r0 => bus;
+    then => idle;
+}
+",
+        );
+        // The chart reads the sample taken at the last edge.
+        assert!(verilog.contains("\nalways @(posedge clk)\nbus_read_reg <= bus;"));
+        assert!(verilog.contains("\nr0 <= bus_read_reg;"));
+        assert!(!verilog.contains("<= bus;\ncurrentState"));
+    }
+
+    #[test]
+    fn memory_ports_test() {
+        let verilog = verilog(
+            "
+address => input[3:0];
+r0 => reg[7:0];
+mem => memory[7:0] depth 16 ports 2 init \"mem.hex\";
+.fill : state {
+    mem[address] => r0 + 1;
+    r0 => mem[address + 1];
+    then => fill;
+}
+",
+        );
+        assert!(verilog.contains("\nreg [7:0]mem[0:15];\ninitial $readmemh(\"mem.hex\", mem);"));
+        // Every port reads and writes in a clocked block of its own, at an
+        // address driven from the state.
+        assert!(verilog.contains(
+            "\nalways @(posedge clk) begin\nif (mem_write_enable_1) mem[mem_address_1] <= mem_write_data_1;\nmem_data_1 <= mem[mem_address_1];\nend"
+        ));
+        assert!(verilog.contains(
+            "\nif (currentState == 0) begin\nmem_address_0 = address;\nmem_address_1 = (address + 1);\nmem_write_enable_0 = 1;\nmem_write_data_0 = r0 + 1;\nend"
+        ));
+    }
+
+    #[test]
+    fn memory_latency_test() {
+        let verilog = verilog(
+            "
+r1 => reg[7:0];
+mem => memory[7:0] depth 16 latency 2;
+.fill : state {
+    r1 => mem[3];
+    then => fill;
+}
+",
+        );
+        // The word goes through a register per cycle of latency, and so
+        // does the tag of the register it loads.
+        assert!(
+            verilog.contains("\nmem_data_0 <= mem[mem_address_0];\nmem_data_0_1 <= mem_data_0;")
+        );
+        assert!(verilog
+            .contains("\nmem_load_0_1 <= mem_load_0;\nif (mem_load_0_1 == 1) r1 <= mem_data_0_1;"));
+        assert!(verilog.contains("\nif (currentState == 0) begin\nmem_load_0 <= 1;"));
+    }
+
+    #[test]
+    fn async_memory_test() {
+        let verilog = verilog(
+            "
+r0 => reg[7:0];
+lut => memory[3:0] depth 4 latency 0;
+.test : decision {
+    check => lut[r0[1:0]] == 0;
+    yes => fill;
+    no => store;
+}
+.fill : state {
+    r0 => r0 + 1;
+    then => test;
+}
+.store : conditional {
+    lut[r0[1:0]] => 3;
+    then => fill;
+}
+",
+        );
+        assert!(verilog.contains("\nassign lut_data_0 = lut[lut_address_0];"));
+        assert!(verilog.contains("\nif ((lut_data_0 == 0)) begin"));
+        // The write is on the path through `store` only.
+        assert!(verilog.contains(
+            "\nif (!((lut_data_0 == 0))) begin\nlut_write_enable_0 = 1;\nlut_write_data_0 = 3;\nend"
+        ));
+    }
+
+    #[test]
+    fn clock_test() {
+        let verilog = verilog(
+            "
+count => reg[3:0];
+clock => clk_a enable ce;
+clock bus => clk_b negedge reset rst_b;
+.idle : state {
+    count => count + 1;
+    then => idle;
+}
+machine => bus;
+.wait : state {
+    then => wait;
+}
+",
+        );
+        assert!(verilog.contains(
+            "\nmodule Top(input clk_a , input clk_b , input reset , input rst_b , input ce);"
+        ));
+        assert!(verilog.contains("\nalways @(posedge rst_b)\nbus_currentState = 0;"));
+        // A disabled machine holds its state.
+        assert!(verilog.contains("\nalways @(posedge clk_a) begin\nif (ce) begin\n"));
+        assert!(verilog.contains("\nalways @(negedge clk_b) begin\n"));
+    }
+
+    #[test]
+    fn synchronizer_test() {
+        let verilog = verilog(
+            "
+go => input async;
+a => reg;
+b => reg;
+clock => clk_a;
+clock bus => clk_b negedge;
+.idle : state {
+    a => go;
+    then => idle;
+}
+machine => bus;
+.wait : state {
+    b => go;
+    then => wait;
+}
+",
+        );
+        // Each clock reads `go` through a synchronizer of its own.
+        assert!(verilog.contains("(.clk(clk_a) , .d(go) , .q(go_sync));"));
+        assert!(verilog.contains("(.clk(~clk_b) , .d(go) , .q(go_sync_2));"));
+        assert!(verilog.contains("\na <= go_sync;"));
+        assert!(verilog.contains("\nb <= go_sync_2;"));
+        assert!(verilog.contains("\nmodule Top_sync #(parameter WIDTH = 1)"));
+    }
+
+    const STREAMS: &str = "
+rx => stream in[7:0];
+tx => stream out[7:0];
+.idle : state {
+    rx_ready => 1;
+    tx_valid => rx_valid;
+    tx_data => rx_data;
+    then => idle;
+}
+";
+
+    #[test]
+    fn stream_ports_test() {
+        assert!(verilog(STREAMS).contains(
+            "\nmodule Top(input clk , input reset , input [0:0]rx_valid , output reg [0:0]rx_ready , input [7:0]rx_data , output reg [0:0]tx_valid , input [0:0]tx_ready , output reg [7:0]tx_data);"
+        ));
+    }
+
+    #[test]
+    fn non_ansi_test() {
+        let options = Options {
+            clock: "aclk".to_string(),
+            reset: "arst".to_string(),
+            header: Header::NonAnsi,
+            ..Options::default()
+        };
+        let verilog = emit_verilog(&parse(STREAMS).unwrap(), &options).unwrap();
+        assert!(verilog.contains(
+            "module Top(aclk , arst , rx_valid , rx_ready , rx_data , tx_valid , tx_ready , tx_data);\ninput aclk;\ninput arst;\ninput [0:0]rx_valid;\noutput [0:0]rx_ready;"
+        ));
+        assert!(verilog.contains("\nreg [7:0]tx_data;"));
+        assert!(verilog.contains("\nalways @(posedge arst)\ncurrentState = 0;"));
+        assert!(verilog.contains("\nalways @(posedge aclk) begin"));
+    }
+
+    #[test]
+    fn wrapper_test() {
+        let options = Options {
+            wrapper: true,
+            ..Options::default()
+        };
+        let verilog = emit_verilog(&parse(STREAMS).unwrap(), &options).unwrap();
+        assert!(verilog.contains("module Top_core(input clk , input reset , "));
+        // The wrapper's outputs are wires.
+        assert!(verilog.contains("\nmodule Top(input clk , input reset , "));
+        assert!(verilog.contains(" , output [0:0]tx_valid , input [0:0]tx_ready , output [7:0]tx_data);\nTop_core core(.clk(clk) , .reset(reset) , .rx_valid(rx_valid) , "));
+        assert!(verilog.ends_with(" , .tx_data(tx_data));\nendmodule"));
+    }
+
+    #[test]
+    fn wire_name_test() {
+        let mut options = Options::default();
+        options.library.insert(
+            "multiplier",
+            parse(&std::fs::read_to_string("multiplier.asmc").unwrap()).unwrap(),
+        );
+        let design = parse(
+            "
+m_ready => reg;
+t_clear => reg;
+m => instance multiplier;
+t => counter[4];
+.idle : state {
+    m.a => 3;
+    m.b => 5;
+    m.start => 1;
+    t.clear();
+    m_ready => m.ready;
+    t_clear => t.value[0];
+    then => idle;
+}
+",
+        )
+        .unwrap();
+        assert_eq!(check_with(&design, &options), vec![]);
+        let verilog = emit_verilog(&design, &options).unwrap();
+        assert!(verilog.contains("\nwire [0:0]m_ready_2;"));
+        assert!(verilog.contains(".ready(m_ready_2)"));
+        assert!(verilog.contains("\nm_ready <= m_ready_2;"));
+        assert!(verilog.contains("\nreg [0:0]t_clear_2;"));
+        assert!(verilog.contains(".clear(t_clear_2)"));
+        assert!(verilog.contains("\nt_clear_2 = 1;"));
+
+        options.naming = Naming {
+            prefix: "asm_".to_string(),
+            suffix: String::new(),
+        };
+        let verilog = emit_verilog(&design, &options).unwrap();
+        assert!(verilog.contains(".ready(asm_m_ready)"));
+        assert!(verilog.contains(".clear(asm_t_clear)"));
+        assert!(verilog.contains("\nt_clear <= asm_t_value[0];"));
+    }
+
+    #[test]
+    fn primitive_test() {
+        let verilog = verilog(
+            "
+q => fifo[8] depth 16;
+t => counter[10];
+.idle : state {
+    q.pop();
+    t.clear();
+    then => idle;
+}
+",
+        );
+        assert!(verilog.contains(
+            "\nTop_fifo #(.WIDTH(8) , .DEPTH(16)) q(.clk(clk) , .reset(reset) , .push(q_push) , .push_data(q_push_data) , .pop(q_pop) , .data(q_data) , .empty(q_empty) , .full(q_full));"
+        ));
+        assert!(verilog.contains(
+            "\nTop_counter #(.WIDTH(10)) t(.clk(clk) , .reset(reset) , .clear(t_clear) , .value(t_value));"
+        ));
+        assert!(verilog.contains("\nmodule Top_fifo #(parameter WIDTH = 8 , parameter DEPTH = 16)"));
+        assert!(verilog.contains("\nmodule Top_counter #(parameter WIDTH = 8)"));
+    }
+
+    #[test]
+    fn action_test() {
+        let verilog = verilog(
+            "
+data => input[7:0];
+q => fifo[8] depth 16;
+.idle : state {
+    then => test;
+}
+.test : decision {
+    check => q.full;
+    yes => idle;
+    no => fill;
+}
+.fill : conditional {
+    q.push(data);
+    then => idle;
+}
+",
+        );
+        // Actions are driven for the cycle the path through them is taken.
+        assert!(verilog.contains("\nq_push = 0;\nq_push_data = 0;\nq_pop = 0;"));
+        assert!(verilog.contains(
+            "\nif (currentState == 0) begin\nif (!(q_full)) begin\nq_push = 1;\nq_push_data = data;\nend\nend"
+        ));
+    }
+
+    #[test]
+    fn fifo_test() {
+        let design = parse(
+            "
+q => fifo[8] depth 2;
+r0 => reg[7:0];
+.idle : state {
+    q.pop();
+    r0 => q.data;
+    then => idle;
+}
+",
+        )
+        .unwrap();
+        let verilog = emit_verilog(&design, &Options::default()).unwrap();
+        // The simulator reads an empty fifo as 0, not the last word popped.
+        assert!(verilog.contains("\nassign data = count == 0 ? 0 : words[head];"));
+    }
+}
@@ -0,0 +1,793 @@
+use crate::clock;
+use crate::command::{Command, UnableToParseError};
+use crate::expr::{BinaryOp, Expr, UnaryOp};
+use crate::naming::{self, Names, Naming, VHDL_KEYWORDS};
+use crate::node::{self, Node, NodeType};
+use crate::verilog_code_gen::Code;
+use crate::Diagnostic;
+use std::{
+    collections::{HashMap, HashSet},
+    ops::Range,
+};
+
+#[derive(PartialEq)]
+enum Kind {
+    Input,
+    Output,
+    Inout,
+    Register,
+    Wire,
+}
+
+struct Signal {
+    kind: Kind,
+    bits: Range<u8>,
+    array: Option<Range<u8>>,
+    /// Driven value and output enable of an inout pin.
+    shadow: Option<(String, String)>,
+    /// The register a `registered` inout is read through.
+    read: Option<String>,
+}
+
+impl Signal {
+    fn width(&self) -> u32 {
+        (self.bits.start as i32 - self.bits.end as i32).unsigned_abs() + 1
+    }
+    fn is_port(&self) -> bool {
+        self.kind != Kind::Register && self.kind != Kind::Wire
+    }
+}
+
+fn range(bits: &Range<u8>) -> String {
+    if bits.start >= bits.end {
+        format!("{} downto {}", bits.start, bits.end)
+    } else {
+        format!("{} to {}", bits.start, bits.end)
+    }
+}
+
+fn is_array(array: &Range<u8>) -> bool {
+    array.start != array.end || array.start != 0
+}
+
+/// VHDL does not allow consecutive underscores in identifiers.
+fn vhdl_name(code: &mut Code, name: &str) -> String {
+    code.fresh_name(&name.replace("__", "_"))
+}
+
+/// Functions the architecture calls or defines, besides the keywords.
+const MODEL_NAMES: &[&str] = &[
+    "to_u",
+    "sel",
+    "resize",
+    "shift_left",
+    "shift_right",
+    "to_integer",
+    "to_unsigned",
+    "unsigned",
+    "std_logic",
+    "std_logic_vector",
+    "rising_edge",
+];
+
+/// Fails if the chart uses a name the entity cannot declare, or two names
+/// that VHDL, ignoring case, takes for one.
+pub fn spellable(module: &str, commands: &[Command]) -> Result<(), Diagnostic> {
+    let words: Vec<&str> = VHDL_KEYWORDS.iter().chain(MODEL_NAMES).copied().collect();
+    naming::spellable(module, commands, &words, "VHDL", true)
+}
+
+fn state_literal(node: &Node) -> String {
+    format!("ST_{}", node.node_name)
+}
+
+fn resize(text: String, from: u32, to: u32) -> String {
+    if from == to {
+        text
+    } else {
+        format!("resize({}, {})", text, to)
+    }
+}
+
+struct Lowering<'l> {
+    code: Code,
+    signals: HashMap<String, Signal>,
+    node_map: HashMap<String, &'l Node>,
+    state_reg: String,
+    /// Inouts whose enable only `drive` and `release` change.
+    controlled: HashSet<&'l str>,
+}
+
+impl<'l> Lowering<'l> {
+    fn line(&mut self, depth: usize, text: String) {
+        self.code
+            .update(format!("\n{}{}", "    ".repeat(depth), text));
+    }
+
+    fn signal(&self, name: &str) -> Result<&Signal, UnableToParseError> {
+        self.signals
+            .get(name)
+            .ok_or(UnableToParseError::InvalidExpression)
+    }
+
+    fn width(&self, expr: &Expr) -> Result<u32, UnableToParseError> {
+        expr.width(&|name| {
+            self.signals
+                .get(name)
+                .map(|signal| (signal.width(), signal.array.is_some()))
+        })
+    }
+
+    fn index(&self, index: &Expr) -> Result<String, UnableToParseError> {
+        match index {
+            Expr::Number { value, .. } => Ok(value.to_string()),
+            _ => Ok(format!(
+                "to_integer({})",
+                self.vector(index, self.width(index)?)?
+            )),
+        }
+    }
+
+    /// Renders a selection of a declared name, returning the VHDL text, its
+    /// width and whether it still has to be converted from
+    /// `std_logic_vector`.
+    fn select(&self, expr: &Expr) -> Result<(String, u32, bool), UnableToParseError> {
+        match expr {
+            Expr::Ident(name) => {
+                let signal = self.signal(name)?;
+                Ok(match &signal.read {
+                    Some(read) => (read.clone(), signal.width(), false),
+                    None => (name.clone(), signal.width(), signal.is_port()),
+                })
+            }
+            Expr::Index(base, index) => {
+                let Expr::Ident(name) = base.as_ref() else {
+                    return Err(UnableToParseError::InvalidExpression);
+                };
+                let signal = self.signal(name)?;
+                let index = self.index(index)?;
+                let (name, is_port) = match &signal.read {
+                    Some(read) => (read, false),
+                    None => (name, signal.is_port()),
+                };
+                if signal.array.is_some() {
+                    Ok((format!("{}({})", name, index), signal.width(), is_port))
+                } else {
+                    Ok((format!("{}({} downto {})", name, index, index), 1, is_port))
+                }
+            }
+            Expr::Slice(base, high, low) => {
+                let (text, _, is_port) = self.select(base)?;
+                Ok((
+                    format!("{}({} downto {})", text, high, low),
+                    high.abs_diff(*low) + 1,
+                    is_port,
+                ))
+            }
+            _ => Err(UnableToParseError::InvalidExpression),
+        }
+    }
+
+    /// Renders `expr` as an `unsigned` of exactly `width` bits.
+    fn vector(&self, expr: &Expr, width: u32) -> Result<String, UnableToParseError> {
+        let own = self.width(expr)?;
+        Ok(match expr {
+            Expr::Ident(_) | Expr::Index(_, _) | Expr::Slice(_, _, _) => {
+                let (text, from, is_port) = self.select(expr)?;
+                let text = if is_port {
+                    format!("unsigned({})", text)
+                } else {
+                    text
+                };
+                resize(text, from, width)
+            }
+            Expr::Number { value, .. } => format!("to_unsigned({}, {})", value, width),
+            Expr::Unary(UnaryOp::LogicalNot, _) => {
+                resize(format!("to_u({})", self.condition(expr)?), 1, width)
+            }
+            Expr::Unary(UnaryOp::BitNot, inner) => {
+                format!("(not {})", self.vector(inner, width)?)
+            }
+            Expr::Unary(UnaryOp::Negate, inner) => format!(
+                "(to_unsigned(0, {}) - {})",
+                width,
+                self.vector(inner, width)?
+            ),
+            Expr::Binary(op, _, _) if op.is_boolean() => {
+                resize(format!("to_u({})", self.condition(expr)?), 1, width)
+            }
+            Expr::Binary(op @ (BinaryOp::Shl | BinaryOp::Shr), lhs, rhs) => {
+                let function = if *op == BinaryOp::Shl {
+                    "shift_left"
+                } else {
+                    "shift_right"
+                };
+                format!(
+                    "{}({}, {})",
+                    function,
+                    self.vector(lhs, width)?,
+                    self.index(rhs)?
+                )
+            }
+            Expr::Binary(BinaryOp::Mul, lhs, rhs) => format!(
+                "resize({} * {}, {})",
+                self.vector(lhs, width)?,
+                self.vector(rhs, width)?,
+                width
+            ),
+            Expr::Binary(op, lhs, rhs) => {
+                let operator = match op {
+                    BinaryOp::Div => "/",
+                    BinaryOp::Mod => "mod",
+                    BinaryOp::Add => "+",
+                    BinaryOp::Sub => "-",
+                    BinaryOp::BitAnd => "and",
+                    BinaryOp::BitXor => "xor",
+                    _ => "or",
+                };
+                format!(
+                    "({} {} {})",
+                    self.vector(lhs, width)?,
+                    operator,
+                    self.vector(rhs, width)?
+                )
+            }
+            Expr::Ternary(cond, yes, no) => format!(
+                "sel({}, {}, {})",
+                self.condition(cond)?,
+                self.vector(yes, width)?,
+                self.vector(no, width)?
+            ),
+            Expr::Concat(parts) => {
+                let mut texts = vec![];
+                for part in parts.iter() {
+                    texts.push(self.vector(part, self.width(part)?)?);
+                }
+                resize(format!("({})", texts.join(" & ")), own, width)
+            }
+        })
+    }
+
+    /// Renders `expr` as a VHDL `boolean`.
+    fn condition(&self, expr: &Expr) -> Result<String, UnableToParseError> {
+        Ok(match expr {
+            Expr::Unary(UnaryOp::LogicalNot, inner) => {
+                format!("(not {})", self.condition(inner)?)
+            }
+            Expr::Binary(op @ (BinaryOp::LogicalAnd | BinaryOp::LogicalOr), lhs, rhs) => {
+                format!(
+                    "({} {} {})",
+                    self.condition(lhs)?,
+                    if *op == BinaryOp::LogicalAnd {
+                        "and"
+                    } else {
+                        "or"
+                    },
+                    self.condition(rhs)?
+                )
+            }
+            Expr::Binary(op, lhs, rhs) if op.is_boolean() => {
+                let width = self.width(lhs)?.max(self.width(rhs)?);
+                let operator = match op {
+                    BinaryOp::Lt => "<",
+                    BinaryOp::Le => "<=",
+                    BinaryOp::Gt => ">",
+                    BinaryOp::Ge => ">=",
+                    BinaryOp::Eq => "=",
+                    _ => "/=",
+                };
+                format!(
+                    "({} {} {})",
+                    self.vector(lhs, width)?,
+                    operator,
+                    self.vector(rhs, width)?
+                )
+            }
+            _ => format!("({} /= 0)", self.vector(expr, self.width(expr)?)?),
+        })
+    }
+
+    fn transfer(
+        &mut self,
+        depth: usize,
+        reg_name: &str,
+        reg_value: &str,
+    ) -> Result<(), UnableToParseError> {
+        let target: Expr = reg_name.parse()?;
+        let value: Expr = reg_value.parse()?;
+        let base = target
+            .idents()
+            .first()
+            .map(|name| name.to_string())
+            .ok_or(UnableToParseError::InvalidExpression)?;
+        let (text, width, is_port) = self.select(&target)?;
+        let rendered = self.vector(&value, width)?;
+        let signal = self.signal(&base)?;
+        let (shadow, shown) = (signal.shadow.clone(), signal.read.clone());
+        if let Some((driven, enable)) = shadow {
+            let text = text.replacen(shown.as_ref().unwrap_or(&base), &driven, 1);
+            self.line(depth, format!("{} <= {};", text, rendered));
+            if !self.controlled.contains(base.as_str()) {
+                self.line(depth, format!("{} <= '1';", enable));
+            }
+        } else if is_port {
+            self.line(
+                depth,
+                format!("{} <= std_logic_vector({});", text, rendered),
+            );
+        } else {
+            self.line(depth, format!("{} <= {};", text, rendered));
+        }
+        Ok(())
+    }
+
+    fn lookup(&self, name: &str) -> Result<&'l Node, UnableToParseError> {
+        self.node_map
+            .get(name)
+            .copied()
+            .ok_or(UnableToParseError::UndefinedNode)
+    }
+
+    fn compile_node(
+        &mut self,
+        node: &'l Node,
+        seen: &mut HashSet<&'l String>,
+        depth: usize,
+        full_compile: bool,
+    ) -> Result<(), UnableToParseError> {
+        if !full_compile && node.node_type == NodeType::State {
+            let state_reg = self.state_reg.clone();
+            self.line(depth, format!("{} <= {};", state_reg, state_literal(node)));
+            return Ok(());
+        }
+        if !seen.insert(&node.node_name) {
+            return Err(UnableToParseError::CircularDependency);
+        }
+
+        if node.node_type == NodeType::Decision {
+            let mut check_cond = "0";
+            let mut yes_node = "";
+            let mut no_node = "";
+            for command in node.commands.iter() {
+                match command {
+                    Command::Check { check } => check_cond = check,
+                    Command::Yes { next_node } => yes_node = next_node,
+                    Command::No { next_node } => no_node = next_node,
+                    _ => {}
+                }
+            }
+            let cond = self.condition(&check_cond.parse()?)?;
+            self.line(depth, format!("if {} then", cond));
+            self.compile_node(self.lookup(yes_node)?, seen, depth + 1, false)?;
+            self.line(depth, "else".to_string());
+            self.compile_node(self.lookup(no_node)?, seen, depth + 1, false)?;
+            self.line(depth, "end if;".to_string());
+        } else {
+            let mut then_node = "";
+            for command in node.commands.iter() {
+                match command {
+                    Command::RegisterTransfer {
+                        reg_name,
+                        reg_value,
+                    } => self.transfer(depth, reg_name, reg_value)?,
+                    Command::Drive { pin_name } | Command::Release { pin_name } => {
+                        let level = match command {
+                            Command::Drive { .. } => "'1'",
+                            _ => "'0'",
+                        };
+                        if let Some((_, enable)) = self.signal(pin_name)?.shadow.clone() {
+                            self.line(depth, format!("{} <= {};", enable, level));
+                        }
+                    }
+                    Command::Then { next_node } => then_node = next_node,
+                    _ => {}
+                }
+            }
+            self.compile_node(self.lookup(then_node)?, seen, depth, false)?;
+        }
+        seen.remove(&node.node_name);
+        Ok(())
+    }
+}
+
+/// Lowers the chart to a VHDL-2008 entity and architecture with one
+/// clocked process, reading `std_logic_vector` ports as `unsigned`.
+pub fn emit_vhdl(
+    module: &str,
+    commands: &[Command],
+    nodes: &[Node],
+    naming: &Naming,
+) -> Result<String, UnableToParseError> {
+    let mut lowering = Lowering {
+        code: Code::new(Names::new(
+            naming,
+            commands,
+            &clock::DEFAULT,
+            VHDL_KEYWORDS,
+            true,
+        )),
+        signals: HashMap::new(),
+        node_map: HashMap::new(),
+        state_reg: String::new(),
+        controlled: node::controlled_pins(nodes),
+    };
+    for node in nodes.iter() {
+        lowering.node_map.insert(node.get_name(), node);
+    }
+
+    let mut port_types = vec![];
+    let mut ports = vec![
+        "clk : in std_logic".to_string(),
+        "reset : in std_logic".to_string(),
+    ];
+    let mut declarations = vec![];
+    let mut drivers = vec![];
+    let mut samples = vec![];
+    let mut resets = vec![];
+    for cmd in commands.iter() {
+        let (name, bits, array, kind) = match cmd {
+            Command::Input {
+                pin_name,
+                bits,
+                array,
+                ..
+            } => (pin_name, bits, array, Kind::Input),
+            Command::Output {
+                pin_name,
+                bits,
+                array,
+                ..
+            } => (pin_name, bits, array, Kind::Output),
+            Command::Inout {
+                pin_name,
+                bits,
+                array,
+                ..
+            } => (pin_name, bits, array, Kind::Inout),
+            Command::Register {
+                reg_name,
+                bits,
+                array,
+                ..
+            } => (reg_name, bits, array, Kind::Register),
+            _ => continue,
+        };
+        let element = if kind == Kind::Register {
+            format!("unsigned({})", range(bits))
+        } else {
+            format!("std_logic_vector({})", range(bits))
+        };
+        let ty = if is_array(array) {
+            let type_name = format!("{}_t", name);
+            let declaration = format!(
+                "type {} is array ({}) of {};",
+                type_name,
+                range(array),
+                element
+            );
+            if kind == Kind::Register {
+                declarations.push(declaration);
+            } else {
+                port_types.push(declaration);
+            }
+            type_name
+        } else {
+            element
+        };
+        let mut shadow = None;
+        let mut read = None;
+        match kind {
+            Kind::Input => ports.push(format!("{} : in {}", name, ty)),
+            Kind::Output => ports.push(format!("{} : out {}", name, ty)),
+            Kind::Register | Kind::Wire => declarations.push(format!("signal {} : {};", name, ty)),
+            Kind::Inout => {
+                ports.push(format!("{} : inout {}", name, ty));
+                let driven = vhdl_name(&mut lowering.code, &format!("{}_out", name));
+                let enable = vhdl_name(&mut lowering.code, &format!("{}_write_reg", name));
+                let registered = matches!(
+                    cmd,
+                    Command::Inout {
+                        registered: true,
+                        ..
+                    }
+                );
+                let read_reg = registered
+                    .then(|| vhdl_name(&mut lowering.code, &format!("{}_read_reg", name)));
+                let unsigned = format!("unsigned({})", range(bits));
+                if is_array(array) {
+                    let driven_type = vhdl_name(&mut lowering.code, &format!("{}_driven_t", name));
+                    let block = vhdl_name(&mut lowering.code, &format!("{}_drive", name));
+                    declarations.push(format!(
+                        "type {} is array ({}) of {};",
+                        driven_type,
+                        range(array),
+                        unsigned
+                    ));
+                    declarations.push(format!("signal {} : {};", driven, driven_type));
+                    drivers.push(format!(
+                        "{block} : for i in {} generate\n        {name}(i) <= std_logic_vector({driven}(i)) when {enable} = '1' else (others => 'Z');\n    end generate;",
+                        range(array)
+                    ));
+                    if let Some(read_reg) = &read_reg {
+                        declarations.push(format!("signal {} : {};", read_reg, driven_type));
+                        samples.push(format!(
+                            "for i in {} loop\n                {read_reg}(i) <= unsigned({name}(i));\n            end loop;",
+                            range(array)
+                        ));
+                    }
+                } else {
+                    declarations.push(format!("signal {} : {};", driven, unsigned));
+                    drivers.push(format!(
+                        "{name} <= std_logic_vector({driven}) when {enable} = '1' else (others => 'Z');"
+                    ));
+                    if let Some(read_reg) = &read_reg {
+                        declarations.push(format!("signal {} : {};", read_reg, unsigned));
+                        samples.push(format!("{read_reg} <= unsigned({name});"));
+                    }
+                }
+                declarations.push(format!("signal {} : std_logic;", enable));
+                // Pins under `drive` and `release` hold their enable until
+                // a reset; the others are driven for one cycle per transfer.
+                if lowering.controlled.contains(name.as_str()) {
+                    resets.push(format!("{} <= '0';", enable));
+                } else {
+                    samples.push(format!("{} <= '0';", enable));
+                }
+                shadow = Some((driven, enable));
+                read = read_reg;
+            }
+        }
+        lowering.signals.insert(
+            name.clone(),
+            Signal {
+                kind,
+                bits: bits.clone(),
+                array: if is_array(array) {
+                    Some(array.clone())
+                } else {
+                    None
+                },
+                shadow,
+                read,
+            },
+        );
+    }
+
+    // Wires are concurrent assignments; every signal is known by now, so
+    // they may read each other in any order.
+    for cmd in commands.iter() {
+        if let Command::Wire {
+            wire_name, bits, ..
+        } = cmd
+        {
+            declarations.push(format!("signal {} : unsigned({});", wire_name, range(bits)));
+            lowering.signals.insert(
+                wire_name.clone(),
+                Signal {
+                    kind: Kind::Wire,
+                    bits: bits.clone(),
+                    array: None,
+                    shadow: None,
+                    read: None,
+                },
+            );
+        }
+    }
+    for cmd in commands.iter() {
+        if let Command::Wire {
+            wire_name, value, ..
+        } = cmd
+        {
+            let width = lowering.signal(wire_name)?.width();
+            let rendered = lowering.vector(&value.parse()?, width)?;
+            drivers.push(format!("{} <= {};", wire_name, rendered));
+        }
+    }
+
+    let states: Vec<&Node> = nodes
+        .iter()
+        .filter(|node| node.node_type == NodeType::State)
+        .collect();
+    let state_type = vhdl_name(&mut lowering.code, "state_t");
+    lowering.state_reg = vhdl_name(&mut lowering.code, "currentState");
+    let reset_state = states
+        .first()
+        .map(|node| state_literal(node))
+        .ok_or(UnableToParseError::UndefinedNode)?;
+
+    let mut code =
+        String::from("library ieee;\nuse ieee.std_logic_1164.all;\nuse ieee.numeric_std.all;\n");
+    if !port_types.is_empty() {
+        code.push_str(&format!(
+            "\npackage {module}_types is\n    {}\nend package {module}_types;\n\nlibrary ieee;\nuse ieee.std_logic_1164.all;\nuse ieee.numeric_std.all;\nuse work.{module}_types.all;\n",
+            port_types.join("\n    ")
+        ));
+    }
+    code.push_str(&format!(
+        "\nentity {module} is\n    port (\n        {}\n    );\nend entity {module};\n",
+        ports.join(";\n        ")
+    ));
+    code.push_str(&format!(
+        "\narchitecture rtl of {module} is\n    type {} is ({});\n    signal {} : {};",
+        state_type,
+        states
+            .iter()
+            .map(|node| state_literal(node))
+            .collect::<Vec<_>>()
+            .join(", "),
+        lowering.state_reg,
+        state_type
+    ));
+    for declaration in declarations.iter() {
+        code.push_str(&format!("\n    {}", declaration));
+    }
+    code.push_str(
+        "
+
+    function to_u(b : boolean) return unsigned is
+    begin
+        if b then
+            return \"1\";
+        else
+            return \"0\";
+        end if;
+    end function;
+
+    function sel(c : boolean; a, b : unsigned) return unsigned is
+    begin
+        if c then
+            return a;
+        else
+            return b;
+        end if;
+    end function;
+begin",
+    );
+    for driver in drivers.iter() {
+        code.push_str(&format!("\n    {}", driver));
+    }
+    code.push_str(&format!(
+        "
+
+    process (clk, reset)
+    begin
+        if reset = '1' then
+            {} <= {};",
+        lowering.state_reg, reset_state
+    ));
+    for reset in resets.iter() {
+        code.push_str(&format!("\n            {}", reset));
+    }
+    code.push_str("\n        elsif rising_edge(clk) then");
+    for sample in samples.iter() {
+        code.push_str(&format!("\n            {}", sample));
+    }
+
+    lowering.code.code = code;
+    let state_reg = lowering.state_reg.clone();
+    lowering.line(3, format!("case {} is", state_reg));
+    for state in states.iter() {
+        lowering.line(4, format!("when {} =>", state_literal(state)));
+        lowering.compile_node(state, &mut HashSet::new(), 5, true)?;
+    }
+    lowering.line(3, "end case;".to_string());
+    lowering.code.update(
+        "
+        end if;
+    end process;
+end architecture rtl;
+"
+        .to_string(),
+    );
+    Ok(lowering.code.code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn compile(declarations: &[&str], nodes: Vec<Node>) -> String {
+        let commands: Vec<Command> = declarations
+            .iter()
+            .map(|cmd| cmd.parse().unwrap())
+            .collect();
+        emit_vhdl("Top", &commands, &nodes, &Naming::default()).unwrap()
+    }
+
+    #[test]
+    fn multiplier_step_test() {
+        let vhdl = compile(
+            &[
+                "a => input[3:0]",
+                "res => output[7:0]",
+                "r0 => reg[3:0]",
+                "r1 => reg[7:0]",
+            ],
+            vec![
+                Node::try_parse("main", "state", "r0 => a; r1 => r1 << 1; then => check;").unwrap(),
+                Node::try_parse(
+                    "check",
+                    "decision",
+                    "check => r0 == 0; yes => done; no => main;",
+                )
+                .unwrap(),
+                Node::try_parse("done", "conditional", "res => r1 + r0; then => main;").unwrap(),
+            ],
+        );
+        assert!(vhdl.contains("a : in std_logic_vector(3 downto 0);"));
+        assert!(vhdl.contains("res : out std_logic_vector(7 downto 0)"));
+        assert!(vhdl.contains("signal r0 : unsigned(3 downto 0);"));
+        assert!(vhdl.contains("r0 <= unsigned(a);"));
+        assert!(vhdl.contains("r1 <= shift_left(r1, 1);"));
+        assert!(vhdl.contains("if (resize(r0, 32) = to_unsigned(0, 32)) then"));
+        assert!(vhdl.contains("res <= std_logic_vector((r1 + resize(r0, 8)));"));
+        assert!(vhdl.contains("when ST_main =>"));
+    }
+
+    #[test]
+    fn memory_and_inout_test() {
+        let vhdl = compile(
+            &[
+                "address => input[3:0]",
+                "data => inout[3:0]",
+                "mem => reg[15:0][3:0]",
+            ],
+            vec![Node::try_parse(
+                "main",
+                "state",
+                "mem[address] => data; data => mem[2]; then => main;",
+            )
+            .unwrap()],
+        );
+        assert!(vhdl.contains("type mem_t is array (15 downto 0) of unsigned(3 downto 0);"));
+        assert!(vhdl.contains("mem(to_integer(unsigned(address))) <= unsigned(data);"));
+        assert!(vhdl.contains("when data_write_reg = '1'"));
+        assert!(vhdl.contains("<= mem(2);"));
+        assert!(!vhdl.contains("__"));
+    }
+
+    #[test]
+    fn literal_width_test() {
+        // Unsized numbers are 32 bits, as in the Verilog of the chart.
+        let vhdl = compile(
+            &["a => reg[3:0]", "hit => output"],
+            vec![
+                Node::try_parse("main", "state", "then => check;").unwrap(),
+                Node::try_parse(
+                    "check",
+                    "decision",
+                    "check => a + a == 14; yes => found; no => main;",
+                )
+                .unwrap(),
+                Node::try_parse("found", "conditional", "hit => 1; then => main;").unwrap(),
+            ],
+        );
+        assert!(vhdl.contains("if ((resize(a, 32) + resize(a, 32)) = to_unsigned(14, 32)) then"));
+    }
+
+    #[test]
+    fn names_test() {
+        let parse = |declarations: &[&str]| -> Vec<Command> {
+            declarations
+                .iter()
+                .map(|cmd| cmd.parse().unwrap())
+                .collect()
+        };
+        assert_eq!(
+            spellable("Top", &parse(&["next => reg"]))
+                .unwrap_err()
+                .message,
+            "`next` is reserved in VHDL, rename it"
+        );
+        assert!(spellable("Top", &parse(&["OUT => output"])).is_err());
+        assert!(spellable("Top", &parse(&["sel => reg"])).is_err());
+        assert_eq!(
+            spellable("Top", &parse(&["count => reg", "Count => output"]))
+                .unwrap_err()
+                .message,
+            "`count` and `Count` are the same name in VHDL, rename one"
+        );
+        assert!(spellable("top", &parse(&["Top => reg"])).is_err());
+        assert!(spellable("Top", &parse(&["count => reg", "total => output"])).is_ok());
+    }
+}
@@ -0,0 +1,306 @@
+use crate::command::{Command, Timeout};
+use crate::naming::Names;
+use crate::node::{Node, NodeType};
+use crate::Design;
+use std::collections::HashMap;
+
+/// Cycles the counter has to reach for `cmd`, if it needs one. A plain
+/// `wait_until` polls its check without counting.
+fn count_to(cmd: &Command) -> Option<u32> {
+    match cmd {
+        Command::Wait { cycles } if *cycles > 1 => Some(cycles - 1),
+        Command::WaitUntil {
+            timeout: Some(Timeout { cycles, .. }),
+            ..
+        } => Some(cycles.saturating_sub(1)),
+        _ => None,
+    }
+}
+
+fn counter(machine: &str) -> String {
+    match machine {
+        "" => "wait_count".to_string(),
+        machine => format!("{}_wait_count", machine),
+    }
+}
+
+/// The registers [`lower`] declares, named by `names`: one counter per
+/// machine that waits, wide enough for its longest wait. A machine is in
+/// one state at a time, so its waits can share it.
+fn wait_registers(design: &Design, names: &mut Names) -> Vec<(String, Command)> {
+    let mut out = vec![];
+    for machine in design.machines() {
+        let longest = design
+            .nodes
+            .iter()
+            .filter(|node| node.machine == machine)
+            .flat_map(|node| node.commands.iter())
+            .filter_map(count_to)
+            .max();
+        if let Some(longest) = longest {
+            out.push((
+                machine.to_string(),
+                Command::Register {
+                    reg_name: names.fresh(&counter(machine)),
+                    bits: longest.max(1).ilog2() as u8..0,
+                    array: 0..0,
+                    enum_name: None,
+                },
+            ));
+        }
+    }
+    out
+}
+
+fn conditional(machine: &str, name: String, commands: Vec<Command>) -> Node {
+    Node {
+        node_name: name,
+        node_type: NodeType::Conditional,
+        commands,
+        machine: machine.to_string(),
+        origin: None,
+        id: 0,
+    }
+}
+
+fn decision(machine: &str, name: String, check: String, yes: String, no: String) -> Node {
+    Node {
+        node_name: name,
+        node_type: NodeType::Decision,
+        commands: vec![
+            Command::Check { check },
+            Command::Yes { next_node: yes },
+            Command::No { next_node: no },
+        ],
+        machine: machine.to_string(),
+        origin: None,
+        id: 0,
+    }
+}
+
+/// Rewrites `wait` and `wait_until` into a decision after the state that
+/// either loops back to it, counting, or leaves it and clears the counter.
+/// The counter is compared with `>=` so a count left over from a reset in
+/// the middle of a wait ends that wait early instead of wrapping around.
+pub fn lower(design: &Design, names: &mut Names) -> Design {
+    let mut lowered = design.clone();
+    let mut counters = HashMap::new();
+    for (machine, cmd) in wait_registers(design, names) {
+        if let Command::Register { reg_name, .. } = &cmd {
+            counters.insert(machine, reg_name.clone());
+        }
+        lowered.commands.push(cmd);
+    }
+    let mut added = vec![];
+    for node in lowered.nodes.iter_mut() {
+        let Some(wait) = node
+            .commands
+            .iter()
+            .find(|cmd| matches!(cmd, Command::Wait { .. } | Command::WaitUntil { .. }))
+            .cloned()
+        else {
+            continue;
+        };
+        let machine = node.machine.clone();
+        let count = counters.get(&machine).cloned().unwrap_or_default();
+        let state = node.node_name.clone();
+        let named = |suffix: &str| format!("{}_{}", state, suffix);
+        node.commands.retain(|cmd| *cmd != wait);
+        let Some(Command::Then { next_node }) = node
+            .commands
+            .iter_mut()
+            .find(|cmd| matches!(cmd, Command::Then { .. }))
+        else {
+            continue;
+        };
+        let then = std::mem::replace(next_node, named("wait"));
+
+        let clear = |name: String, next_node: String| {
+            conditional(
+                &machine,
+                name,
+                vec![
+                    Command::RegisterTransfer {
+                        reg_name: count.clone(),
+                        reg_value: "0".to_string(),
+                    },
+                    Command::Then { next_node },
+                ],
+            )
+        };
+        let more = conditional(
+            &machine,
+            named("wait_more"),
+            vec![
+                Command::RegisterTransfer {
+                    reg_name: count.clone(),
+                    reg_value: format!("{} + 1", count),
+                },
+                Command::Then {
+                    next_node: state.clone(),
+                },
+            ],
+        );
+        match (&wait, count_to(&wait)) {
+            (Command::Wait { .. }, None) => *next_node = then,
+            (Command::Wait { .. }, Some(last)) => {
+                added.push(decision(
+                    &machine,
+                    named("wait"),
+                    format!("{} >= {}", count, last),
+                    named("wait_done"),
+                    named("wait_more"),
+                ));
+                added.push(clear(named("wait_done"), then));
+                added.push(more);
+            }
+            (Command::WaitUntil { check, .. }, None) => {
+                added.push(decision(
+                    &machine,
+                    named("wait"),
+                    check.clone(),
+                    then,
+                    state.clone(),
+                ));
+            }
+            (
+                Command::WaitUntil {
+                    check,
+                    timeout: Some(timeout),
+                },
+                Some(last),
+            ) => {
+                added.push(decision(
+                    &machine,
+                    named("wait"),
+                    check.clone(),
+                    named("wait_done"),
+                    named("wait_timeout_check"),
+                ));
+                added.push(decision(
+                    &machine,
+                    named("wait_timeout_check"),
+                    format!("{} >= {}", count, last),
+                    named("wait_timeout"),
+                    named("wait_more"),
+                ));
+                added.push(clear(named("wait_done"), then));
+                added.push(clear(named("wait_timeout"), timeout.next_node.clone()));
+                added.push(more);
+            }
+            _ => {}
+        }
+    }
+    lowered.nodes.extend(added);
+    lowered
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::DEFAULT;
+    use crate::{parse, Naming};
+
+    fn names(design: &Design, naming: &Naming) -> Names {
+        Names::new(naming, &design.commands, &DEFAULT, &[], false)
+    }
+
+    #[test]
+    fn lower_test() {
+        let design = parse(
+            "
+ack => input;
+.idle : state {
+    wait => 3;
+    then => poll;
+}
+.poll : state {
+    wait_until => ack timeout 20 => idle;
+    then => poll;
+}
+",
+        )
+        .unwrap();
+        assert_eq!(
+            lower(&design, &mut names(&design, &Naming::default())).to_string(),
+            "ack => input;
+wait_count => reg[4:0];
+
+.idle : state {
+    then => idle_wait;
+}
+
+.poll : state {
+    then => poll_wait;
+}
+
+.idle_wait : decision {
+    check => wait_count >= 2;
+    yes => idle_wait_done;
+    no => idle_wait_more;
+}
+
+.idle_wait_done : conditional {
+    wait_count => 0;
+    then => poll;
+}
+
+.idle_wait_more : conditional {
+    wait_count => wait_count + 1;
+    then => idle;
+}
+
+.poll_wait : decision {
+    check => ack;
+    yes => poll_wait_done;
+    no => poll_wait_timeout_check;
+}
+
+.poll_wait_timeout_check : decision {
+    check => wait_count >= 19;
+    yes => poll_wait_timeout;
+    no => poll_wait_more;
+}
+
+.poll_wait_done : conditional {
+    wait_count => 0;
+    then => poll;
+}
+
+.poll_wait_timeout : conditional {
+    wait_count => 0;
+    then => idle;
+}
+
+.poll_wait_more : conditional {
+    wait_count => wait_count + 1;
+    then => poll;
+}
+"
+        );
+    }
+
+    #[test]
+    fn counter_name_test() {
+        let design = parse(
+            "
+wait_count => reg;
+.idle : state {
+    wait => 2;
+    then => idle;
+}
+",
+        )
+        .unwrap();
+        let counter = |naming: &Naming| {
+            let lowered = lower(&design, &mut names(&design, naming));
+            lowered.commands.last().unwrap().to_string()
+        };
+        assert_eq!(counter(&Naming::default()), "wait_count_2 => reg");
+        let naming = Naming {
+            prefix: "asm_".to_string(),
+            suffix: String::new(),
+        };
+        assert_eq!(counter(&naming), "asm_wait_count => reg");
+    }
+}